@@ -18,7 +18,11 @@
 use std::sync::{Arc, Mutex};
 
 use anyhow::anyhow;
-use ftdi_tools::{i2c::FtdiI2c, list_all_device, mpsse::FtdiMpsse};
+use ftdi_tools::{
+    Interface,
+    i2c::{FtdiI2c, I2cConfig, I2cProbe},
+    mpsse::FtdiMpsse,
+};
 use lm75::Lm75;
 
 /// 主函数 - 程序入口点
@@ -36,28 +40,25 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     // 扫描系统中所有可用的 FTDI 设备
-    let devices = list_all_device();
-    // 确保至少找到一个 FTDI 设备，否则程序会 panic
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个 FTDI 设备的第一个接口，初始化 MPSSE 模式
     // MPSSE (Multi-Protocol Synchronous Serial Engine) 支持 SPI/I2C/JTAG 等协议
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, devices[0].interface[0])?;
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
     // 使用 Arc<Mutex<>> 包装以支持线程安全的共享访问
     let mtx = Arc::new(Mutex::new(mpsse));
 
     // 创建 I2C 主控制器实例，默认配置为 100kHz
-    let mut i2c = FtdiI2c::new(mtx)?;
+    let mut i2c = FtdiI2c::new(mtx, I2cConfig::new())?;
 
     // 扫描 I2C 总线上的所有设备地址 (0x00 - 0x7F)
     // 这个操作会对每个地址发送 START + 地址 + 读/写位 + ACK/NACK
-    let addr_set = i2c.scan();
+    // 返回地址以及应答的访问方式 (读、写或两者)
+    let addr_set = i2c.scan(0..0x80, I2cProbe::Both)?;
     // 以十六进制格式显示扫描到的设备地址
     println!("i2c detect:{:#x?}", addr_set);
 
     // 使用扫描到的第一个设备地址创建 LM75 传感器实例
     // LM75 默认地址通常为 0x48-0x4F，取决于 A0/A1/A2 引脚的连接
-    let mut lm75 = Lm75::new(i2c, addr_set[0]);
+    let mut lm75 = Lm75::new(i2c, addr_set[0].0);
 
     // 读取温度数据，返回值为摄氏度
     // LM75 的温度分辨率为 0.5°C，温度范围 -55°C 到 +125°C
@@ -25,7 +25,11 @@ use std::{
     time::Duration,
 };
 
-use ftdi_tools::{i2c::FtdiI2c, list_all_device, mpsse::FtdiMpsse};
+use ftdi_tools::{
+    Interface,
+    i2c::{FtdiI2c, I2cConfig, I2cProbe},
+    mpsse::FtdiMpsse,
+};
 use sht31::prelude::*;
 
 /// 主函数 - 程序入口点
@@ -42,23 +46,18 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     // 获取系统中所有可用的 FTDI 设备列表
-    let devices = list_all_device();
-    // 检查是否找到可用的 FTDI 设备
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个可用的 FTDI 设备的第一个接口
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, devices[0].interface[0])?;
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
     // 将 MPSSE 控制器包装在线程安全的互斥锁中
     let mtx = Arc::new(Mutex::new(mpsse));
 
     // 创建 I2C 主控制器，默认时钟频率 100kHz
-    let mut i2c = FtdiI2c::new(mtx)?;
     // 启用快速模式，提高数据传输效率
     // 快速模式会将多个操作打包在一个 MPSSE 命令中
-    i2c.enbale_fast(true);
+    let mut i2c = FtdiI2c::new(mtx, I2cConfig::new().fast(true))?;
 
-    // 扫描 I2C 总线以查找连接的设备
-    let addr_set = i2c.scan();
+    // 扫描 I2C 总线以查找连接的设备，零长度写探测不会给设备带来副作用
+    let addr_set = i2c.scan(0..0x80, I2cProbe::Write)?;
     // 输出扫描结果，显示所有在线设备的 I2C 地址
     println!("i2c detect:{:#x?}", addr_set);
 
@@ -22,11 +22,10 @@
 use std::time::Instant;
 
 use ftdi_tools::{
+    Interface,
     jtag::{JtagDetectTdi, JtagDetectTdo},
-    list_all_device,
     mpsse::FtdiMpsse,
 };
-use itertools::Itertools;
 
 /// 主函数 - JTAG 设备检测程序入口
 ///
@@ -43,38 +42,21 @@ fn main() -> anyhow::Result<()> {
     let now = Instant::now();
 
     // 扫描系统中所有可用的 FTDI 设备
-    let devices = list_all_device();
-    // 确保至少找到一个可用的 FTDI 设备
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个 FTDI 设备的第一个接口，初始化 MPSSE 模式
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, devices[0].interface[0])?;
-
-    // 创建存储没有找到 TDI 的引脚组合的向量
-    // 这些组合将在第二阶段用于 TDI 检测
-    let mut notdi: Vec<_> = Vec::new();
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
 
     // =========================
     // 阶段1: TDO 检测阶段
     // =========================
     // 遍历所有可能的 TCK 和 TMS 引脚组合 (8 个引脚中选择 2 个的排列)
-    let mut jtag = JtagDetectTdo::new(mpsse);
-    for couple in (0..8).permutations(2) {
-        let tck = couple[0]; // TCK (测试时钟) 引脚编号
-        let tms = couple[1]; // TMS (测试模式选择) 引脚编号
-
-        // 创建 TDO 检测器实例
-        // 这个检测器会尝试在指定的 TCK/TMS 组合下查找 TDO 信号
-        jtag.set_pins(tck, tms);
+    let jtag = JtagDetectTdo::new(mpsse);
+    // 如果 JTAG 经过缓冲器接到了 ACBUS，可以调用 jtag.include_upper(true) 把高位引脚也加入检测
+    // 引脚编号 0-7 为低位引脚，8 以上为高位引脚
 
-        // 注意: 在实际应用中，这里可以添加控制电平转换芯片的代码
-        // 例如设置 TCK 和 TMS 为输出，其他引脚为输入
-
-        // 执行 JTAG 链扫描，查找可能的 TDO 引脚
-        jtag.scan()?
-            .into_iter()
-            .for_each(|tdo| notdi.push((tck, tms, tdo))); // 将找到的组合保存起来
-    }
+    // scan_all 会把多个组合打包进同一个 MPSSE 命令缓冲区，
+    // 比逐个调用 set_pins + scan 快得多
+    // 返回没有找到 TDI 的引脚组合 (tck, tms, tdo)，这些组合将在第二阶段用于 TDI 检测
+    let notdi = jtag.scan_all()?;
 
     // =========================
     // 阶段2: TDI 检测阶段
@@ -85,8 +67,8 @@ fn main() -> anyhow::Result<()> {
         // 注意: 在实际应用中，这里可以添加控制电平转换芯片的代码
         // 例如设置 TDO 为输入，其他引脚为输出
 
-        // 遍历所有可能的 TDI 引脚 (0-7)
-        for tdi in 0..8 {
+        // 遍历所有可能的 TDI 引脚
+        for tdi in 0..jtag.pin_count() {
             // 跳过与已经使用的引脚冲突的情况
             if tdi == tck || tdi == tms || tdi == tdo {
                 continue;
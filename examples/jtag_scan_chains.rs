@@ -24,7 +24,11 @@ use std::{
     time::Instant,
 };
 
-use ftdi_tools::{jtag::FtdiJtag, list_all_device, mpsse::FtdiMpsse};
+use ftdi_tools::{
+    Interface,
+    jtag::{FtdiJtag, JtagDevice},
+    mpsse::FtdiMpsse,
+};
 
 /// 主函数 - JTAG 链扫描程序入口
 ///
@@ -42,13 +46,9 @@ fn main() -> anyhow::Result<()> {
     let now = Instant::now();
 
     // 扫描系统中所有可用的 FTDI 设备
-    let devices = list_all_device();
-    // 验证至少找到一个 FTDI 设备
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个可用的 FTDI 设备的第一个接口
     // 初始化 MPSSE 模式以支持 JTAG 通信
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, devices[0].interface[0])?;
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
     // 将 MPSSE 控制器包装在线程安全的互斥锁中以支持多线程访问
     let mtx = Arc::new(Mutex::new(mpsse));
 
@@ -57,12 +57,26 @@ fn main() -> anyhow::Result<()> {
     let mut jtag = FtdiJtag::new(mtx)?;
 
     // 执行 JTAG 链扫描操作
-    // scan_with(true) 表示在 TDI 线上发送逻辑 1，这会触发设备返回其 IDCODE
-    let ids = jtag.scan_with(true)?;
+    // scan_decoded(true) 表示在 TDI 线上发送逻辑 1，这会触发设备返回其 IDCODE
+    // scan_decoded 会将 IDCODE 解析为厂商、器件号和版本
+    let devices = jtag.scan_decoded(true)?;
 
-    // 以十六进制格式显示扫描结果
+    // 逐个显示扫描结果
     // 每个 ID 都是 32 位的设备标识符，包含厂商、设备类型等信息
-    println!("Scan Result:{ids:#x?}");
+    for (idx, device) in devices.iter().enumerate() {
+        match device {
+            JtagDevice::Device {
+                idcode,
+                manufacturer,
+                part,
+                version,
+            } => println!(
+                "TAP{idx}: {idcode:#010x} manufacturer {} part {part:#06x} version {version}",
+                manufacturer.name().unwrap_or("unknown")
+            ),
+            JtagDevice::Bypass => println!("TAP{idx}: bypass"),
+        }
+    }
 
     // 输出扫描操作的总耗时
     println!("Finish Scan Using {:?}", now.elapsed());
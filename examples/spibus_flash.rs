@@ -1,7 +1,7 @@
 //! SPI Flash 存储器操作示例 (使用 SPI Bus 模式)
 //!
 //! 此示例演示如何使用 FTDI 芯片通过 SPI 接口与 Flash 存储器进行通信。
-//! 使用 embedded-hal-bus 库的 RefCellDevice 来管理片选信号。
+//! 使用 FtdiGpioCsDevice 管理片选信号，片选与数据在同一条 MPSSE 命令中发送。
 //!
 //! SPI Flash 常见型号:
 //! - W25Q32/64/128 系列 (Winbond)
@@ -26,17 +26,11 @@
 //! RUST_LOG=info cargo run --example spibus_flash
 //! ```
 
-use std::{
-    cell::RefCell,
-    sync::{Arc, Mutex},
-};
+use std::sync::{Arc, Mutex};
 
 use anyhow::anyhow;
 use eh1::spi::SpiDevice;
-use embedded_hal_bus::spi::RefCellDevice;
-use ftdi_tools::{
-    Interface, Pin, gpio::FtdiOutputPin, list_all_device, mpsse::FtdiMpsse, spi::FtdiSpi,
-};
+use ftdi_tools::{Interface, Pin, mpsse::FtdiMpsse, spi::FtdiSpi};
 use spi_flash::{Error, Flash, FlashAccess};
 
 /// Flash 设备包装结构体
@@ -83,27 +77,18 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     // 获取系统中所有可用的 FTDI 设备
-    let devices = list_all_device();
-    // 验证至少存在一个可用的 FTDI 设备
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个 FTDI 设备的接口 A
     // 接口 A 通常具有最完整的 MPSSE 功能支持
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, Interface::A)?;
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
     // 使用 Arc<Mutex<>> 包装以支持多线程安全访问
     let mtx = Arc::new(Mutex::new(mpsse));
 
-    // 创建 SPI 控制器并将其包装在 RefCell 中
-    // RefCell 允许在运行时进行内部可变性检查
-    let spi = RefCell::new(FtdiSpi::new(mtx.clone())?);
-
-    // 创建 GPIO 输出引脚用于控制 Flash 的片选 (CS) 信号
-    // Pin::Lower(3) 对应 FTDI AD3 引脚
-    let cs = FtdiOutputPin::new(mtx.clone(), Pin::Lower(3))?;
+    // 创建 SPI 控制器
+    let spi = FtdiSpi::new(mtx.clone())?;
 
     // 创建 SPI 设备实例，结合 SPI 总线和片选控制
-    // RefCellDevice::new_no_delay 创建一个没有延时的 SPI 设备实例
-    let mut flash_device = FlashDevice(RefCellDevice::new_no_delay(&spi, cs)?);
+    // Pin::Lower(3) 对应 FTDI AD3 引脚，每次传输只需一次 USB 往返
+    let mut flash_device = FlashDevice(spi.device(Pin::Lower(3))?);
 
     // 初始化 Flash 存储器接口
     let mut flash = Flash::new(&mut flash_device);
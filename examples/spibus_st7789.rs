@@ -5,7 +5,7 @@ use std::{
 };
 
 use anyhow::anyhow;
-use eh1::digital::OutputPin;
+use eh1::digital::{OutputPin, PinState};
 use embedded_graphics::{
     mono_font::{MonoTextStyle, ascii::FONT_10X20},
     prelude::*,
@@ -14,8 +14,7 @@ use embedded_graphics::{
 };
 use embedded_hal_bus::spi::RefCellDevice;
 use ftdi_tools::{
-    Interface, Pin, delay::Delay, gpio::FtdiOutputPin, list_all_device, mpsse::FtdiMpsse,
-    spi::FtdiSpiTx,
+    Interface, Pin, delay::Delay, gpio::FtdiOutputPin, mpsse::FtdiMpsse, spi::FtdiSpiTx,
 };
 use mipidsi::{
     Builder, TestImage,
@@ -29,20 +28,16 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     // 扫描并获取所有连接的 FTDI 设备
-    let devices = list_all_device();
-    // 验证系统中存在可用的 FTDI 设备
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个 FTDI 设备的接口 A
     // 接口 A 通常是主接口，支持全部 MPSSE 功能
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, Interface::A)?;
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
     // 使用线程安全的 Arc<Mutex<>> 包装 MPSSE 控制器
     let mtx = Arc::new(Mutex::new(mpsse));
 
     // 创建 FtdiSpiDevice 实例
     // 这个设备封装了 SPI 总线和片选控制，提供了完整的 SpiDevice 实现
     let spibus = RefCell::new(FtdiSpiTx::new(mtx.clone())?);
-    let cs = FtdiOutputPin::new(mtx.clone(), Pin::Lower(3))?;
+    let cs = FtdiOutputPin::new_with_state(mtx.clone(), Pin::Lower(3), PinState::High)?;
     let spidevice = RefCellDevice::new_no_delay(&spibus, cs)?;
     let rst = FtdiOutputPin::new(mtx.clone(), Pin::Lower(4))?;
     let dc = FtdiOutputPin::new(mtx.clone(), Pin::Lower(5))?;
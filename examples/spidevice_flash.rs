@@ -28,7 +28,11 @@ use std::sync::{Arc, Mutex};
 
 use anyhow::anyhow;
 use eh1::spi::SpiDevice;
-use ftdi_tools::{Interface, list_all_device, mpsse::FtdiMpsse, spi::FtdiSpiDevice};
+use ftdi_tools::{
+    Interface,
+    mpsse::FtdiMpsse,
+    spi::{FtdiSpiDevice, SpiDeviceConfig},
+};
 use spi_flash::{Error, Flash, FlashAccess};
 
 /// Flash 设备适配器结构体
@@ -75,19 +79,15 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     // 扫描并获取所有连接的 FTDI 设备
-    let devices = list_all_device();
-    // 验证系统中存在可用的 FTDI 设备
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个 FTDI 设备的接口 A
     // 接口 A 通常是主接口，支持全部 MPSSE 功能
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, Interface::A)?;
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
     // 使用线程安全的 Arc<Mutex<>> 包装 MPSSE 控制器
     let mtx = Arc::new(Mutex::new(mpsse));
 
     // 创建 FtdiSpiDevice 实例
     // 这个设备封装了 SPI 总线和片选控制，提供了完整的 SpiDevice 实现
-    let spidevice = FtdiSpiDevice::new(mtx)?;
+    let spidevice = FtdiSpiDevice::new(mtx, SpiDeviceConfig::new())?;
 
     // 将 SPI 设备包装成 Flash 访问接口
     let mut flash_device = FlashDevice(spidevice);
@@ -31,7 +31,7 @@
 use std::sync::{Arc, Mutex};
 
 use ftdi_tools::{
-    list_all_device,
+    Interface,
     mpsse::FtdiMpsse,
     swd::{FtdiSwd, SwdAddr},
 };
@@ -50,13 +50,9 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     // 获取系统中所有可用的 FTDI 设备列表
-    let devices = list_all_device();
-    // 验证至少存在一个 FTDI 设备可供使用
-    assert!(!devices.is_empty(), "Not found Ftdi devices");
-
     // 打开第一个 FTDI 设备的第一个接口
     // 初始化 MPSSE 模式以支持 SWD 通信协议
-    let mpsse = FtdiMpsse::open(&devices[0].usb_device, devices[0].interface[0])?;
+    let mpsse = FtdiMpsse::open_first(Interface::A)?;
     // 使用线程安全的互斥锁包装 MPSSE 控制器
     let mtx = Arc::new(Mutex::new(mpsse));
 
@@ -0,0 +1,28 @@
+//! 生成 Linux udev 规则示例
+//!
+//! 默认打印所有已知 FTDI VID/PID 的 udev 规则，加 `--install` 时写入
+//! `/etc/udev/rules.d/99-ftdi-tools.rules` 并重新加载 udev (需要 root 权限)。
+//!
+//! 运行方式:
+//! ```bash
+//! cargo run --example udev_rules > 99-ftdi-tools.rules
+//! sudo -E cargo run --example udev_rules -- --install
+//! ```
+
+use ftdi_tools::udev_rules;
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    if std::env::args().any(|arg| arg == "--install") {
+        #[cfg(target_os = "linux")]
+        {
+            ftdi_tools::install_udev_rules()?;
+            println!("已安装 {}, 请重新插拔设备", ftdi_tools::UDEV_RULES_PATH);
+        }
+        #[cfg(not(target_os = "linux"))]
+        anyhow::bail!("--install 仅支持 Linux");
+    } else {
+        print!("{}", udev_rules());
+    }
+    Ok(())
+}
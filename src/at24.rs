@@ -0,0 +1,234 @@
+//! 24xx series I2C EEPROMs.
+//!
+//! A write that runs past the end of a page rolls over to the start of the same page, so
+//! writing with a page size larger than the real one silently corrupts data. Take the
+//! geometry from [`At24Part`] or let [`At24::detect_page_size`] find the page size by
+//! provoking such a roll-over. An optional WP pin is released only while writing.
+use crate::{FtdiError, gpio::FtdiOutputPin, i2c::FtdiI2c, i2c::FtdiI2cError};
+use eh1::{digital::OutputPin, i2c::I2c};
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+/// Longest write cycle of the 24xx parts is 5 ms, with margin for slow ones
+const WRITE_CYCLE_TIMEOUT: Duration = Duration::from_millis(20);
+/// Largest page of any 24xx part
+const MAX_PAGE_SIZE: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum At24Error {
+    #[error(transparent)]
+    I2c(#[from] FtdiI2cError),
+    #[error(transparent)]
+    FtdiInner(#[from] FtdiError),
+    #[error("{len} bytes at {offset:#x} exceed the EEPROM size")]
+    OutOfRange { offset: usize, len: usize },
+    #[error("EEPROM still busy after the write cycle timeout")]
+    WriteTimeout,
+    #[error("Page size detection failed, the read back does not show a roll-over")]
+    NoRollOver,
+}
+
+/// Geometry of a 24xx EEPROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct At24Part {
+    /// Capacity in bytes
+    pub size: usize,
+    pub page_size: usize,
+    /// Bytes of the word address, higher address bits go into the device address
+    pub addr_bytes: usize,
+}
+
+impl At24Part {
+    pub const AT24C01: Self = Self::new(128, 8, 1);
+    pub const AT24C02: Self = Self::new(256, 8, 1);
+    pub const AT24C04: Self = Self::new(512, 16, 1);
+    pub const AT24C08: Self = Self::new(1024, 16, 1);
+    pub const AT24C16: Self = Self::new(2048, 16, 1);
+    pub const AT24C32: Self = Self::new(4096, 32, 2);
+    pub const AT24C64: Self = Self::new(8192, 32, 2);
+    pub const AT24C128: Self = Self::new(16384, 64, 2);
+    pub const AT24C256: Self = Self::new(32768, 64, 2);
+    pub const AT24C512: Self = Self::new(65536, 128, 2);
+    pub const AT24CM01: Self = Self::new(131072, 256, 2);
+    pub const AT24CM02: Self = Self::new(262144, 256, 2);
+
+    pub const fn new(size: usize, page_size: usize, addr_bytes: usize) -> Self {
+        Self {
+            size,
+            page_size,
+            addr_bytes,
+        }
+    }
+    /// Bytes reachable through one device address
+    const fn block_size(&self) -> usize {
+        1 << (8 * self.addr_bytes)
+    }
+}
+
+/// 24xx EEPROM on an I2C bus
+pub struct At24 {
+    i2c: FtdiI2c,
+    /// Device address with the block select bits cleared, usually 0x50
+    addr: u8,
+    part: At24Part,
+    /// Write protect, held high except while writing
+    wp: Option<FtdiOutputPin>,
+}
+
+impl At24 {
+    pub fn new(i2c: FtdiI2c, addr: u8, part: At24Part) -> Self {
+        Self {
+            i2c,
+            addr,
+            part,
+            wp: None,
+        }
+    }
+    /// Protects the EEPROM with `wp` and releases it only around writes
+    pub fn with_write_protect(mut self, mut wp: FtdiOutputPin) -> Result<Self, At24Error> {
+        wp.set_high()?;
+        self.wp = Some(wp);
+        Ok(self)
+    }
+    pub fn part(&self) -> At24Part {
+        self.part
+    }
+    pub fn into_inner(self) -> (FtdiI2c, Option<FtdiOutputPin>) {
+        (self.i2c, self.wp)
+    }
+    fn check_range(&self, offset: usize, len: usize) -> Result<(), At24Error> {
+        if offset + len > self.part.size {
+            return Err(At24Error::OutOfRange { offset, len });
+        }
+        Ok(())
+    }
+    /// Device address and word address bytes of `offset`
+    fn address(&self, offset: usize) -> (u8, Vec<u8>) {
+        let block = self.part.block_size();
+        let addr = self.addr | (offset / block) as u8;
+        let word = (offset % block).to_be_bytes();
+        (addr, word[word.len() - self.part.addr_bytes..].to_vec())
+    }
+    pub fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), At24Error> {
+        self.check_range(offset, buf.len())?;
+        // The address counter does not carry into the block select bits
+        for range in chunks(offset, buf.len(), self.part.block_size()) {
+            let (addr, word) = self.address(range.start);
+            self.i2c.write_read(
+                addr,
+                &word,
+                &mut buf[range.start - offset..range.end - offset],
+            )?;
+        }
+        Ok(())
+    }
+    /// Writes `data` page by page, waiting for each write cycle to finish
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), At24Error> {
+        self.check_range(offset, data.len())?;
+        if let Some(wp) = &mut self.wp {
+            wp.set_low()?;
+        }
+        let result = chunks(offset, data.len(), self.part.page_size).try_for_each(|range| {
+            self.write_page(range.start, &data[range.start - offset..range.end - offset])
+        });
+        if let Some(wp) = &mut self.wp {
+            wp.set_high()?;
+        }
+        result
+    }
+    fn write_page(&mut self, offset: usize, data: &[u8]) -> Result<(), At24Error> {
+        let (addr, mut frame) = self.address(offset);
+        frame.extend_from_slice(data);
+        self.i2c.write(addr, &frame)?;
+        // The EEPROM does not acknowledge its address during the write cycle
+        let start = Instant::now();
+        while !self.i2c.probe(addr)? {
+            if start.elapsed() > WRITE_CYCLE_TIMEOUT {
+                return Err(At24Error::WriteTimeout);
+            }
+        }
+        Ok(())
+    }
+    /// Finds the page size by writing past the end of the first page, and keeps it
+    ///
+    /// The bytes the write touched are read before and restored afterwards, also when the
+    /// detection fails, which then keeps the old page size. `addr_bytes` of the part must
+    /// be right, its `page_size` is ignored.
+    pub fn detect_page_size(&mut self) -> Result<usize, At24Error> {
+        let probe_len = MAX_PAGE_SIZE.min(self.part.size);
+        let mut original = vec![0; probe_len];
+        self.read(0, &mut original)?;
+        let old_page_size = self.part.page_size;
+        let detected = self.probe_page_size(probe_len);
+        // Without a page size the bytes are restored one by one, which can not wrap
+        self.part.page_size = *detected.as_ref().unwrap_or(&1);
+        let restored = self.write(0, &original);
+        if detected.is_err() {
+            self.part.page_size = old_page_size;
+        }
+        let page_size = detected?;
+        restored?;
+        log::info!("EEPROM page size is {page_size} bytes");
+        Ok(page_size)
+    }
+    /// Writes a pattern of `probe_len` bytes in a single write, it wraps within the first
+    /// page whatever its size
+    fn probe_page_size(&mut self, probe_len: usize) -> Result<usize, At24Error> {
+        let pattern: Vec<u8> = (0..probe_len).map(|idx| idx as u8).collect();
+        self.part.page_size = probe_len;
+        self.write(0, &pattern)?;
+        let mut readback = vec![0; probe_len];
+        self.read(0, &mut readback)?;
+        rolled_page_size(&pattern, &readback).ok_or(At24Error::NoRollOver)
+    }
+}
+
+/// Splits `offset..offset + len` at multiples of `boundary`
+fn chunks(offset: usize, len: usize, boundary: usize) -> impl Iterator<Item = Range<usize>> {
+    let end = offset + len;
+    let mut start = offset;
+    std::iter::from_fn(move || {
+        if start >= end {
+            return None;
+        }
+        let chunk = start..end.min((start / boundary + 1) * boundary);
+        start = chunk.end;
+        Some(chunk)
+    })
+}
+
+/// Page size for which writing `pattern` from the start of a page leaves `readback`
+///
+/// A page of `n` bytes only keeps the last `n` bytes of the pattern, rolled over to its start.
+/// The pattern bytes are distinct, so the first byte read back tells where that tail starts.
+fn rolled_page_size(pattern: &[u8], readback: &[u8]) -> Option<usize> {
+    let first = pattern.iter().position(|&byte| byte == readback[0])?;
+    (readback[..pattern.len() - first] == pattern[first..]).then_some(pattern.len() - first)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chunks, rolled_page_size};
+
+    #[test]
+    fn split_at_pages() {
+        assert_eq!(
+            chunks(6, 20, 8).collect::<Vec<_>>(),
+            [6..8, 8..16, 16..24, 24..26]
+        );
+        assert!(chunks(8, 8, 8).eq(std::iter::once(8..16)));
+        assert_eq!(chunks(3, 0, 8).count(), 0);
+    }
+    #[test]
+    fn page_size_from_roll_over() {
+        let pattern: Vec<u8> = (0..=255).collect();
+        // 16 byte page: the last 16 bytes of the pattern overwrite the first page
+        let mut readback = vec![0xFF; 256];
+        readback[..16].copy_from_slice(&pattern[240..]);
+        assert_eq!(rolled_page_size(&pattern, &readback), Some(16));
+        assert_eq!(rolled_page_size(&pattern, &pattern), Some(256));
+        assert_eq!(rolled_page_size(&pattern, &[0x42; 256]), None);
+    }
+}
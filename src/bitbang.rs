@@ -0,0 +1,263 @@
+//! Bit-banged protocols on any GPIO pins.
+//!
+//! A [`Sequence`] describes a transfer bit by bit: pin levels to change, the edges at which the
+//! changes reach the pins and the pins to sample. [`BitBang::run`] compiles it into GPIO
+//! commands and sends them in one batch, so a whole frame costs a single USB round trip.
+//!
+//! ```no_run
+//! # use ftdi_tools::{Pin, bitbang::{BitBang, Sequence}};
+//! # fn demo(bitbang: &BitBang) -> Result<(), ftdi_tools::FtdiError> {
+//! let (clk, data) = (Pin::Upper(0), Pin::Upper(1));
+//! let mut seq = Sequence::new();
+//! for bit in [true, false, true, true] {
+//!     seq.set(data, bit).edge(clk, true).sample(data).edge(clk, false);
+//! }
+//! let samples = bitbang.run(&seq)?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    FtdiError, Pin,
+    gpio::UsedPin,
+    mpsse::{FtdiMpsse, PinUsage},
+    mpsse_cmd::MpsseCmdBuilder,
+};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Set(Pin, bool),
+    Direction(Pin, bool),
+    Commit,
+    Sample(Pin),
+    Hold(usize),
+}
+
+/// Bit-level description of a transfer, built once and run any number of times
+#[derive(Debug, Clone, Default)]
+pub struct Sequence {
+    ops: Vec<Op>,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Changes the level of an output, it reaches the pin at the next edge or sample
+    pub fn set(&mut self, pin: Pin, level: bool) -> &mut Self {
+        self.ops.push(Op::Set(pin, level));
+        self
+    }
+    /// Makes `pin` an output, e.g. to drive a shared data line after a turnaround
+    pub fn output(&mut self, pin: Pin) -> &mut Self {
+        self.ops.push(Op::Direction(pin, true));
+        self
+    }
+    /// Makes `pin` an input, which also releases an open drain line
+    pub fn input(&mut self, pin: Pin) -> &mut Self {
+        self.ops.push(Op::Direction(pin, false));
+        self
+    }
+    /// Writes every pending change to the pins at once
+    pub fn commit(&mut self) -> &mut Self {
+        self.ops.push(Op::Commit);
+        self
+    }
+    /// Moves a clock to `level` together with every pending change
+    pub fn edge(&mut self, clock: Pin, level: bool) -> &mut Self {
+        self.set(clock, level).commit()
+    }
+    /// Commits pending changes, then reads `pin`
+    pub fn sample(&mut self, pin: Pin) -> &mut Self {
+        self.ops.push(Op::Sample(pin));
+        self
+    }
+    /// Commits pending changes, then keeps the levels for `count` more GPIO commands
+    ///
+    /// Each command lasts at least 50 ns, which stretches slow protocols without a USB round trip.
+    pub fn hold(&mut self, count: usize) -> &mut Self {
+        self.ops.push(Op::Hold(count));
+        self
+    }
+    /// Appends the operations of `other`
+    pub fn extend(&mut self, other: &Sequence) -> &mut Self {
+        self.ops.extend_from_slice(&other.ops);
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+    fn pins(&self) -> impl Iterator<Item = Pin> + '_ {
+        self.ops.iter().filter_map(|op| match *op {
+            Op::Set(pin, _) | Op::Direction(pin, _) | Op::Sample(pin) => Some(pin),
+            Op::Commit | Op::Hold(_) => None,
+        })
+    }
+    /// Builds the commands, returning the pins sampled in order
+    fn compile(&self, levels: &mut Levels, cmd: &mut MpsseCmdBuilder) -> Vec<Pin> {
+        let mut samples = Vec::new();
+        for op in &self.ops {
+            match *op {
+                Op::Set(pin, level) => levels.set(pin, level),
+                Op::Direction(pin, output) => levels.direction(pin, output),
+                Op::Commit => levels.emit(cmd),
+                Op::Sample(pin) => {
+                    levels.emit(cmd);
+                    match pin {
+                        Pin::Lower(_) => cmd.gpio_lower(),
+                        Pin::Upper(_) => cmd.gpio_upper(),
+                    };
+                    samples.push(pin);
+                }
+                Op::Hold(count) => {
+                    levels.emit(cmd);
+                    for _ in 0..count {
+                        cmd.set_gpio_lower(levels.lower[0], levels.lower[1]);
+                    }
+                }
+            }
+        }
+        levels.emit(cmd);
+        samples
+    }
+}
+
+/// Pins owned by a bit-banged protocol
+pub struct BitBang {
+    /// Thread-safe handle to FTDI MPSSE controller
+    mtx: Arc<Mutex<FtdiMpsse>>,
+    pins: Vec<UsedPin>,
+}
+
+impl BitBang {
+    /// Takes `outputs`, driven low, and `inputs`. Sequences may switch the direction of any of them.
+    pub fn new(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        outputs: &[Pin],
+        inputs: &[Pin],
+    ) -> Result<Self, FtdiError> {
+        let pins = outputs
+            .iter()
+            .chain(inputs)
+            .map(|&pin| UsedPin::new(mtx.clone(), pin, PinUsage::BitBang))
+            .collect::<Result<_, _>>()?;
+        let this = Self { mtx, pins };
+        let mut seq = Sequence::new();
+        for &pin in outputs {
+            seq.set(pin, false).output(pin);
+        }
+        for &pin in inputs {
+            seq.input(pin);
+        }
+        this.run(&seq)?;
+        Ok(this)
+    }
+    /// Runs `seq` and returns its samples in order, `true` for high
+    pub fn run(&self, seq: &Sequence) -> Result<Vec<bool>, FtdiError> {
+        if let Some(pin) = seq
+            .pins()
+            .find(|pin| !self.pins.iter().any(|p| **p == *pin))
+        {
+            return Err(FtdiError::PinFault(format!(
+                "{pin} is not part of the bit-bang pins"
+            )));
+        }
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        let mut levels = Levels::new(&lock);
+        let mut cmd = MpsseCmdBuilder::new();
+        let samples = seq.compile(&mut levels, &mut cmd);
+        let response = lock.exec(cmd)?;
+        levels.store(&mut lock);
+        Ok(samples
+            .iter()
+            .zip(response)
+            .map(|(pin, bank)| bank & pin.mask() != 0)
+            .collect())
+    }
+}
+
+/// Pin levels and directions of both banks while commands are built
+struct Levels {
+    lower: [u8; 2],
+    upper: [u8; 2],
+    /// Banks changed since they were last emitted
+    dirty: [bool; 2],
+}
+impl Levels {
+    fn new(lock: &FtdiMpsse) -> Self {
+        Self {
+            lower: [lock.lower.value, lock.lower.direction],
+            upper: [lock.upper.value, lock.upper.direction],
+            dirty: [false; 2],
+        }
+    }
+    fn bank(&mut self, pin: Pin) -> (&mut [u8; 2], &mut bool) {
+        match pin {
+            Pin::Lower(_) => (&mut self.lower, &mut self.dirty[0]),
+            Pin::Upper(_) => (&mut self.upper, &mut self.dirty[1]),
+        }
+    }
+    fn set(&mut self, pin: Pin, level: bool) {
+        let (bank, dirty) = self.bank(pin);
+        let value = if level {
+            bank[0] | pin.mask()
+        } else {
+            bank[0] & !pin.mask()
+        };
+        *dirty |= value != bank[0];
+        bank[0] = value;
+    }
+    fn direction(&mut self, pin: Pin, output: bool) {
+        let (bank, dirty) = self.bank(pin);
+        let direction = if output {
+            bank[1] | pin.mask()
+        } else {
+            bank[1] & !pin.mask()
+        };
+        *dirty |= direction != bank[1];
+        bank[1] = direction;
+    }
+    fn emit(&mut self, cmd: &mut MpsseCmdBuilder) {
+        if self.dirty[0] {
+            cmd.set_gpio_lower(self.lower[0], self.lower[1]);
+        }
+        if self.dirty[1] {
+            cmd.set_gpio_upper(self.upper[0], self.upper[1]);
+        }
+        self.dirty = [false; 2];
+    }
+    fn store(&self, lock: &mut FtdiMpsse) {
+        [lock.lower.value, lock.lower.direction] = self.lower;
+        [lock.upper.value, lock.upper.direction] = self.upper;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Levels, Sequence};
+    use crate::{Pin, mpsse_cmd::MpsseCmdBuilder};
+
+    #[test]
+    fn batches_changes_per_edge() {
+        let (clk, mosi, miso) = (Pin::Lower(0), Pin::Lower(1), Pin::Upper(2));
+        let mut seq = Sequence::new();
+        seq.set(mosi, true)
+            .edge(clk, true)
+            .sample(miso)
+            .edge(clk, false);
+        let mut levels = Levels {
+            lower: [0x00, 0x03],
+            upper: [0x00, 0x00],
+            dirty: [false; 2],
+        };
+        let mut cmd = MpsseCmdBuilder::new();
+        assert_eq!(seq.compile(&mut levels, &mut cmd), [miso]);
+        let mut expected = MpsseCmdBuilder::new();
+        expected
+            .set_gpio_lower(0x03, 0x03)
+            .gpio_upper()
+            .set_gpio_lower(0x02, 0x03);
+        assert_eq!(cmd.destruct(), expected.destruct());
+        assert_eq!(levels.lower, [0x02, 0x03]);
+    }
+}
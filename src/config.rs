@@ -0,0 +1,322 @@
+//! Bench setups described in a TOML or JSON file.
+//!
+//! A file names the adapter, the protocols to open and the GPIO pins by their use:
+//!
+//! ```toml
+//! serial = "FT6ZK1AB"
+//! interface = "A"
+//!
+//! [i2c]
+//! frequency = 400_000
+//! timeout_ms = 50
+//!
+//! [pins.reset]
+//! pin = "AC0"
+//! direction = "output"
+//! initial = true
+//!
+//! [pins.ready]
+//! pin = "AC1"
+//! direction = "input"
+//! ```
+//!
+//! [`BenchConfig::open`] then returns a [`Bench`] holding the opened objects.
+//! Protocols share AD0 to AD3, so only one of them can be opened per interface.
+//! Needs the `config` feature.
+use crate::{
+    FtdiError, Interface, Pin,
+    gpio::{FtdiInputPin, FtdiOutputPin},
+    i2c::{self, FtdiI2c, FtdiI2cError},
+    jtag::FtdiJtag,
+    list_all_device,
+    mpsse::FtdiMpsse,
+    spi::{FtdiSpi, FtdiSpiError},
+    swd::{FtdiSwd, FtdiSwdError},
+};
+use eh1::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+use serde::{Deserialize, Deserializer, de::Error as _};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid TOML config: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Invalid JSON config: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid YAML script: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("Unsupported file extension")]
+    UnknownFormat,
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+    #[error(transparent)]
+    FtdiInner(#[from] FtdiError),
+    #[error(transparent)]
+    I2c(#[from] FtdiI2cError),
+    #[error(transparent)]
+    Spi(#[from] FtdiSpiError),
+    #[error(transparent)]
+    Swd(#[from] FtdiSwdError),
+}
+
+/// Contents of a bench config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BenchConfig {
+    /// Serial number of the adapter, the first FTDI device found when missing
+    pub serial: Option<String>,
+    #[serde(default = "default_interface")]
+    pub interface: Interface,
+    pub spi: Option<SpiConfig>,
+    pub i2c: Option<I2cConfig>,
+    pub jtag: Option<JtagConfig>,
+    pub swd: Option<SwdConfig>,
+    /// GPIO pins by name
+    #[serde(default)]
+    pub pins: BTreeMap<String, PinConfig>,
+}
+
+fn default_interface() -> Interface {
+    Interface::A
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpiConfig {
+    pub frequency: Option<usize>,
+    /// SPI mode, 0 to 3
+    #[serde(default)]
+    pub mode: u8,
+    /// Least significant bit first
+    #[serde(default)]
+    pub lsb: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct I2cConfig {
+    /// SCL frequency, 100 kHz when missing
+    pub frequency: Option<usize>,
+    #[serde(default)]
+    pub fast: bool,
+    /// See [`FtdiI2c::set_timeout`]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JtagConfig {
+    pub frequency: Option<usize>,
+    #[serde(default)]
+    pub adaptive_clock: bool,
+    /// See [`FtdiJtag::set_tdo_neg_read`]
+    #[serde(default)]
+    pub tdo_neg_read: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwdConfig {
+    pub frequency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinConfig {
+    /// Datasheet name like `AD4` or `AC0`
+    #[serde(deserialize_with = "pin_name")]
+    pub pin: Pin,
+    pub direction: Direction,
+    /// Level an output starts with
+    #[serde(default)]
+    pub initial: bool,
+}
+
+fn pin_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pin, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(D::Error::custom)
+}
+
+impl BenchConfig {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(text)?)
+    }
+    pub fn from_json(text: &str) -> Result<Self, ConfigError> {
+        Ok(serde_json::from_str(text)?)
+    }
+    /// Reads a file, its extension selects TOML or JSON
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => Err(ConfigError::UnknownFormat),
+        }
+    }
+    /// Opens the adapter and everything the config describes
+    pub fn open(&self) -> Result<Bench, ConfigError> {
+        let device = list_all_device()
+            .into_iter()
+            .find(|device| {
+                self.serial
+                    .as_deref()
+                    .is_none_or(|serial| device.usb_device.serial_number() == Some(serial))
+            })
+            .ok_or_else(|| {
+                FtdiError::OpenFailed(match &self.serial {
+                    Some(serial) => format!("No FTDI device with serial {serial}"),
+                    None => "No FTDI device found".to_string(),
+                })
+            })?;
+        let mpsse = Arc::new(Mutex::new(FtdiMpsse::open(
+            &device.usb_device,
+            self.interface,
+        )?));
+        let set_frequency = |frequency: Option<usize>| -> Result<(), FtdiError> {
+            if let Some(frequency) = frequency {
+                FtdiMpsse::lock(&mpsse)?.set_frequency(frequency)?;
+            }
+            Ok(())
+        };
+        let mut bench = Bench {
+            mpsse: mpsse.clone(),
+            spi: None,
+            i2c: None,
+            jtag: None,
+            swd: None,
+            outputs: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+        };
+        if let Some(config) = &self.spi {
+            let mut spi = FtdiSpi::new(mpsse.clone())?;
+            let mode = match config.mode {
+                0 => MODE_0,
+                1 => MODE_1,
+                2 => MODE_2,
+                3 => MODE_3,
+                mode => return Err(ConfigError::Invalid(format!("SPI mode {mode}"))),
+            };
+            spi.set_mode(mode, config.lsb)?;
+            set_frequency(config.frequency)?;
+            bench.spi = Some(spi);
+        }
+        if let Some(config) = &self.i2c {
+            let mut settings = i2c::I2cConfig::new()
+                .fast(config.fast)
+                .timeout(config.timeout_ms.map(Duration::from_millis));
+            if let Some(frequency) = config.frequency {
+                settings = settings.frequency(frequency);
+            }
+            bench.i2c = Some(FtdiI2c::new(mpsse.clone(), settings)?);
+        }
+        if let Some(config) = &self.jtag {
+            let mut jtag = FtdiJtag::new(mpsse.clone())?;
+            jtag.adaptive_clock(config.adaptive_clock)?;
+            jtag.set_tdo_neg_read(config.tdo_neg_read);
+            set_frequency(config.frequency)?;
+            bench.jtag = Some(jtag);
+        }
+        if let Some(config) = &self.swd {
+            bench.swd = Some(FtdiSwd::new(mpsse.clone())?);
+            set_frequency(config.frequency)?;
+        }
+        for (name, config) in &self.pins {
+            match config.direction {
+                Direction::Output => {
+                    let pin = FtdiOutputPin::new_with_state(
+                        mpsse.clone(),
+                        config.pin,
+                        config.initial.into(),
+                    )?;
+                    bench.outputs.insert(name.clone(), pin);
+                }
+                Direction::Input => {
+                    let pin = FtdiInputPin::new(mpsse.clone(), config.pin)?;
+                    bench.inputs.insert(name.clone(), pin);
+                }
+            }
+        }
+        Ok(bench)
+    }
+}
+
+/// Objects opened from a [`BenchConfig`]
+pub struct Bench {
+    pub mpsse: Arc<Mutex<FtdiMpsse>>,
+    pub spi: Option<FtdiSpi>,
+    pub i2c: Option<FtdiI2c>,
+    pub jtag: Option<FtdiJtag>,
+    pub swd: Option<FtdiSwd>,
+    outputs: BTreeMap<String, FtdiOutputPin>,
+    inputs: BTreeMap<String, FtdiInputPin>,
+}
+
+impl Bench {
+    /// Output pin named in the config
+    pub fn output(&mut self, name: &str) -> Option<&mut FtdiOutputPin> {
+        self.outputs.get_mut(name)
+    }
+    /// Input pin named in the config
+    pub fn input(&mut self, name: &str) -> Option<&mut FtdiInputPin> {
+        self.inputs.get_mut(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BenchConfig, Direction};
+    use crate::{Interface, Pin};
+
+    #[test]
+    fn toml_and_json() {
+        let toml = BenchConfig::from_toml(
+            r#"
+            serial = "FT6ZK1AB"
+            [i2c]
+            frequency = 400_000
+            [pins.reset]
+            pin = "ac0"
+            direction = "output"
+            initial = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(toml.serial.as_deref(), Some("FT6ZK1AB"));
+        assert_eq!(toml.interface, Interface::A);
+        assert_eq!(toml.i2c.unwrap().frequency, Some(400_000));
+        let reset = &toml.pins["reset"];
+        assert_eq!(reset.pin, Pin::Upper(0));
+        assert_eq!(reset.direction, Direction::Output);
+        assert!(reset.initial);
+
+        let json = BenchConfig::from_json(
+            r#"{"interface": "B", "spi": {"mode": 2}, "pins": {"irq": {"pin": "BD5", "direction": "input"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(json.interface, Interface::B);
+        assert_eq!(json.spi.unwrap().mode, 2);
+        assert_eq!(json.pins["irq"].pin, Pin::Lower(5));
+    }
+    #[test]
+    fn rejects_typos() {
+        assert!(BenchConfig::from_toml("[i2c]\nfrequncy = 1").is_err());
+        assert!(BenchConfig::from_toml("[pins.x]\npin = \"AE0\"\ndirection = \"input\"").is_err());
+        assert!(BenchConfig::from_toml("[pins.x]\npin = \"AD8\"\ndirection = \"input\"").is_err());
+    }
+}
@@ -1,15 +1,136 @@
+use crate::{FtdiError, mpsse::FtdiMpsse, mpsse_cmd::MpsseCmdBuilder};
 use eh1::delay::DelayNs;
-use std::time::Duration;
+use std::{
+    hint,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
+/// Host delay, sleeps
+///
+/// Sleeping overshoots by the scheduler granularity: tens of microseconds on Linux, up to
+/// 15 ms on Windows. [`Delay::precise`] and [`Delay::spin`] busy-wait instead.
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Delay;
+impl Delay {
+    /// Sleeps for the coarse part of every delay and busy-waits the rest
+    ///
+    /// Measures how far sleeps overshoot, which takes a few sleeps of 1 ms.
+    pub fn precise(self) -> SpinDelay {
+        const PROBE: Duration = Duration::from_millis(1);
+        let overshoot = (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                sleep(PROBE);
+                start.elapsed().saturating_sub(PROBE)
+            })
+            .max()
+            .unwrap_or_default();
+        SpinDelay {
+            spin: overshoot + overshoot / 4,
+        }
+    }
+    /// Busy-waits throughout every delay
+    pub const fn spin(self) -> SpinDelay {
+        SpinDelay {
+            spin: Duration::MAX,
+        }
+    }
+}
 impl DelayNs for Delay {
     fn delay_ns(&mut self, ns: u32) {
-        std::thread::sleep(Duration::from_nanos(ns as u64));
+        sleep(Duration::from_nanos(ns as u64));
     }
     fn delay_us(&mut self, us: u32) {
-        std::thread::sleep(Duration::from_micros(us as u64));
+        sleep(Duration::from_micros(us as u64));
     }
     fn delay_ms(&mut self, ms: u32) {
-        std::thread::sleep(Duration::from_millis(ms as u64));
+        sleep(Duration::from_millis(ms as u64));
+    }
+}
+
+/// Host delay busy-waiting the final part of every delay, see [`Delay::precise`]
+///
+/// Converting a [`Delay`] gives one that only sleeps.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpinDelay {
+    /// Final part of a delay that is busy-waited
+    spin: Duration,
+}
+impl SpinDelay {
+    fn wait(&self, delay: Duration) {
+        let deadline = Instant::now() + delay;
+        if delay > self.spin {
+            sleep(delay - self.spin);
+        }
+        while Instant::now() < deadline {
+            hint::spin_loop();
+        }
+    }
+}
+impl From<Delay> for SpinDelay {
+    fn from(_: Delay) -> Self {
+        Self::default()
+    }
+}
+impl DelayNs for SpinDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.wait(Duration::from_nanos(ns as u64));
+    }
+    fn delay_us(&mut self, us: u32) {
+        self.wait(Duration::from_micros(us as u64));
+    }
+    fn delay_ms(&mut self, ms: u32) {
+        self.wait(Duration::from_millis(ms as u64));
+    }
+}
+
+/// Longest delay clocked by a single command batch, keeps long delays clear of USB timeouts
+const MAX_CLOCKED: Duration = Duration::from_millis(100);
+
+/// Delay timed by the MPSSE clock instead of the host
+///
+/// The device clocks without data for the requested time, so the delay sits exactly between
+/// the commands before and after it. TCK/SCK (AD0) toggles meanwhile, so only use it where
+/// clock pulses are harmless, like SPI with CS released or JTAG in Run-Test/Idle, not I2C.
+/// Adaptive clocking stretches the delay. Only FTx232H devices can clock without data.
+pub struct MpsseDelay {
+    /// Thread-safe handle to FTDI MPSSE controller
+    mtx: Arc<Mutex<FtdiMpsse>>,
+}
+
+impl MpsseDelay {
+    pub fn new(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Self, FtdiError> {
+        let chip_type = FtdiMpsse::lock(&mtx)?.chip_type;
+        if !chip_type.supports_three_phase() {
+            return Err(FtdiError::UnsupportedChip(chip_type));
+        }
+        Ok(Self { mtx })
+    }
+    /// Returns once the device has clocked for at least `delay`
+    pub fn delay(&self, delay: Duration) -> Result<(), FtdiError> {
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let mut bits = lock.clock_bits_for(delay);
+        let max_bits = lock.clock_bits_for(MAX_CLOCKED).max(1);
+        while bits > 0 {
+            let chunk = bits.min(max_bits);
+            let mut cmd = MpsseCmdBuilder::new();
+            // The read is answered after the clocking, which makes exec wait for it
+            cmd.clock_bits(chunk).gpio_lower();
+            lock.exec(cmd)?;
+            bits -= chunk;
+        }
+        Ok(())
+    }
+}
+
+impl DelayNs for MpsseDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        let delay = Duration::from_nanos(ns as u64);
+        if let Err(e) = self.delay(delay) {
+            log::warn!("Clocked delay failed, sleeping instead: {e}");
+            sleep(delay);
+        }
     }
 }
@@ -0,0 +1,133 @@
+//! DMX512 transmitter on top of [`FtdiUart`].
+//!
+//! A frame is a break, a mark after break, the start code 0 and up to 512 channel slots,
+//! sent at 250 kbaud with 2 stop bits. Wire TXD to an RS-485 transceiver with its driver enabled.
+//!
+//! Break and mark after break are set with USB control requests, so both last
+//! a few hundred microseconds instead of the minimum 88µs and 8µs, well within the standard.
+use crate::{
+    FtdiError,
+    uart::{DataBits, FtdiUart, Parity, StopBits},
+};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle, sleep},
+    time::Duration,
+};
+
+const BAUD_RATE: u32 = 250_000;
+/// Start, 8 data bits and 2 stop bits at 250 kbaud.
+const SLOT_TIME: Duration = Duration::from_micros(44);
+const MAX_CHANNELS: usize = 512;
+const START_CODE: u8 = 0;
+
+/// Sends DMX512 frames from the calling thread
+pub struct DmxTransmitter {
+    uart: FtdiUart,
+}
+
+impl DmxTransmitter {
+    /// Switches the uart to 250 kbaud 8N2
+    pub fn new(mut uart: FtdiUart) -> Result<Self, FtdiError> {
+        uart.set_baud_rate(BAUD_RATE)?;
+        uart.set_framing(DataBits::Eight, Parity::None, StopBits::Two)?;
+        uart.set_break(false)?;
+        Ok(Self { uart })
+    }
+    /// Restores 1 stop bit and releases the uart
+    pub fn into_inner(mut self) -> Result<FtdiUart, FtdiError> {
+        self.uart
+            .set_framing(DataBits::Eight, Parity::None, StopBits::One)?;
+        Ok(self.uart)
+    }
+    /// Sends one frame with `channels` as slots 1 to `channels.len()`
+    ///
+    /// Returns once the last slot has left the chip.
+    pub fn send_frame(&mut self, channels: &[u8]) -> Result<(), FtdiError> {
+        assert!(
+            channels.len() <= MAX_CHANNELS,
+            "a frame has at most {MAX_CHANNELS} channels"
+        );
+        self.uart.set_break(true)?;
+        sleep(Duration::from_micros(100));
+        self.uart.set_break(false)?;
+        let mut frame = Vec::with_capacity(channels.len() + 1);
+        frame.push(START_CODE);
+        frame.extend_from_slice(channels);
+        self.uart.write(&frame)?;
+        // The write completes when the chip has buffered the frame, the next break
+        // must not cut off slots still being shifted out.
+        sleep(SLOT_TIME * frame.len() as u32);
+        Ok(())
+    }
+    /// Keeps sending frames from a channel buffer in a background thread
+    pub fn refresh(self) -> DmxRefresh {
+        let channels = Arc::new(Mutex::new([0; MAX_CHANNELS]));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let channels = channels.clone();
+            let stop = stop.clone();
+            let mut this = self;
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let frame = *channels.lock().unwrap();
+                    this.send_frame(&frame)?;
+                }
+                Ok(this)
+            })
+        };
+        DmxRefresh {
+            channels,
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Background refresh started by [`DmxTransmitter::refresh`]
+///
+/// All 512 channels are sent in every frame, about 40 frames per second.
+/// Dropping it stops the refresh.
+pub struct DmxRefresh {
+    channels: Arc<Mutex<[u8; MAX_CHANNELS]>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<DmxTransmitter, FtdiError>>>,
+}
+
+impl DmxRefresh {
+    /// Sets channel 1 to 512, the next frame carries the new value
+    pub fn set(&self, channel: usize, value: u8) {
+        self.set_range(channel, &[value]);
+    }
+    /// Sets consecutive channels starting at `first`, all of them change in the same frame
+    pub fn set_range(&self, first: usize, values: &[u8]) {
+        assert!(
+            first >= 1 && first - 1 + values.len() <= MAX_CHANNELS,
+            "channels are numbered 1 to {MAX_CHANNELS}"
+        );
+        self.channels.lock().unwrap()[first - 1..first - 1 + values.len()].copy_from_slice(values);
+    }
+    /// Stops after the current frame and returns the transmitter
+    ///
+    /// Fails with the error that ended the refresh early, if any.
+    pub fn stop(mut self) -> Result<DmxTransmitter, FtdiError> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread
+            .take()
+            .unwrap()
+            .join()
+            .expect("DMX refresh thread panicked")
+    }
+}
+
+impl Drop for DmxRefresh {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
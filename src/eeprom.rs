@@ -0,0 +1,683 @@
+//! FTDI configuration EEPROM access.
+//!
+//! The EEPROM is kept as a raw image and fields are decoded on demand, so
+//! bits this module does not know about survive a read-modify-write cycle.
+//! The checksum is recomputed by [`FtdiEeprom::write`].
+//!
+//! Layout reference: libftdi `ftdi_eeprom_build`/`ftdi_eeprom_decode`.
+//!
+//! [`dump_to_file`] archives the EEPROM of an adapter before experiments,
+//! [`restore_from_file`] writes it back.
+//!
+//! Chips without MPSSE, like the FT232R and FT-X series, are accessed through
+//! [`FtdiEeprom::read_device`] and [`FtdiEeprom::write_device`].
+use crate::{
+    ChipType, FtdiError, Interface,
+    ftdaye::FtdiContext,
+    mpsse::{self, FtdiMpsse},
+};
+use std::{fs, path::Path};
+
+/// Largest EEPROM the FTDI chips can address (93C56/93C66 are used as 256 bytes).
+const MAX_EEPROM_SIZE: usize = 256;
+/// 93C46 size, smaller EEPROMs wrap around when read past the end.
+const MIN_EEPROM_SIZE: usize = 128;
+
+/// Output drive strength of an I/O group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriveStrength {
+    Ma4 = 0,
+    Ma8 = 1,
+    Ma12 = 2,
+    Ma16 = 3,
+}
+impl DriveStrength {
+    const fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => DriveStrength::Ma4,
+            1 => DriveStrength::Ma8,
+            2 => DriveStrength::Ma12,
+            _ => DriveStrength::Ma16,
+        }
+    }
+}
+
+/// GPIO bank of an interface, matching [`crate::Pin::Lower`] and [`crate::Pin::Upper`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoBank {
+    Lower,
+    Upper,
+}
+
+/// I/O cell options shared by all pins of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoCellConfig {
+    /// Output drive strength.
+    pub drive: DriveStrength,
+    /// Slow slew rate on outputs.
+    pub slow_slew: bool,
+    /// Schmitt trigger on inputs.
+    pub schmitt: bool,
+}
+impl IoCellConfig {
+    const SLOW_SLEW: u8 = 1 << 2;
+    const SCHMITT: u8 = 1 << 3;
+    const fn from_nibble(nibble: u8) -> Self {
+        Self {
+            drive: DriveStrength::from_bits(nibble),
+            slow_slew: nibble & Self::SLOW_SLEW != 0,
+            schmitt: nibble & Self::SCHMITT != 0,
+        }
+    }
+    const fn to_nibble(self) -> u8 {
+        let mut nibble = self.drive as u8;
+        if self.slow_slew {
+            nibble |= Self::SLOW_SLEW;
+        }
+        if self.schmitt {
+            nibble |= Self::SCHMITT;
+        }
+        nibble
+    }
+}
+
+/// EEPROM byte holding bmAttributes of the USB configuration descriptor.
+const USB_ATTRIBUTES_OFFSET: usize = 0x08;
+/// EEPROM byte holding bMaxPower, in units of 2 mA.
+const MAX_POWER_OFFSET: usize = 0x09;
+/// Highest current a USB 2.0 device may draw from the bus.
+const MAX_BUS_POWER_MA: u16 = 500;
+
+/// USB power characteristics the device reports to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbPowerConfig {
+    /// Largest current drawn from the bus in mA, at most 500, rounded down to even values.
+    pub max_power_ma: u16,
+    /// The device has its own supply.
+    pub self_powered: bool,
+    /// The device may wake a suspended host.
+    pub remote_wakeup: bool,
+}
+impl UsbPowerConfig {
+    /// Reserved bit of bmAttributes, always set.
+    const RESERVED: u8 = 1 << 7;
+    const SELF_POWERED: u8 = 1 << 6;
+    const REMOTE_WAKEUP: u8 = 1 << 5;
+}
+
+/// EEPROM byte holding the UART signal inversion flags of FT232R and FT-X chips.
+const INVERT_OFFSET: usize = 0x0B;
+
+/// UART signals inverted by the chip, as configured in the EEPROM.
+///
+/// Only the FT232R and FT-X series have these flags, the H series chips can not invert.
+#[bitfield_struct::bitfield(u8, order = Lsb)]
+#[derive(PartialEq, Eq)]
+pub struct SignalInversion {
+    pub txd: bool,
+    pub rxd: bool,
+    pub rts: bool,
+    pub cts: bool,
+    pub dtr: bool,
+    pub dsr: bool,
+    pub dcd: bool,
+    pub ri: bool,
+}
+
+/// Number of FT232H ACBUS pins with a configurable function.
+pub const ACBUS_PINS: usize = 10;
+/// EEPROM byte holding the functions of ACBUS0 and ACBUS1, one nibble per pin.
+const ACBUS_OFFSET: usize = 0x18;
+
+/// Function of an FT232H ACBUS pin, as configured in the EEPROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcbusFunction {
+    Tristate,
+    TxLed,
+    RxLed,
+    TxRxLed,
+    PwrEn,
+    Sleep,
+    Drive0,
+    Drive1,
+    /// GPIO in CBUS bit-bang mode (ACBUS5, ACBUS6, ACBUS8 and ACBUS9 only).
+    IoMode,
+    TxDen,
+    Clk30,
+    Clk15,
+    Clk7_5,
+    /// Value not defined by FTDI, e.g. a blank EEPROM.
+    Unknown(u8),
+}
+impl AcbusFunction {
+    const fn from_nibble(nibble: u8) -> Self {
+        match nibble {
+            0 => AcbusFunction::Tristate,
+            1 => AcbusFunction::TxLed,
+            2 => AcbusFunction::RxLed,
+            3 => AcbusFunction::TxRxLed,
+            4 => AcbusFunction::PwrEn,
+            5 => AcbusFunction::Sleep,
+            6 => AcbusFunction::Drive0,
+            7 => AcbusFunction::Drive1,
+            8 => AcbusFunction::IoMode,
+            9 => AcbusFunction::TxDen,
+            10 => AcbusFunction::Clk30,
+            11 => AcbusFunction::Clk15,
+            12 => AcbusFunction::Clk7_5,
+            x => AcbusFunction::Unknown(x),
+        }
+    }
+    const fn to_nibble(self) -> u8 {
+        match self {
+            AcbusFunction::Tristate => 0,
+            AcbusFunction::TxLed => 1,
+            AcbusFunction::RxLed => 2,
+            AcbusFunction::TxRxLed => 3,
+            AcbusFunction::PwrEn => 4,
+            AcbusFunction::Sleep => 5,
+            AcbusFunction::Drive0 => 6,
+            AcbusFunction::Drive1 => 7,
+            AcbusFunction::IoMode => 8,
+            AcbusFunction::TxDen => 9,
+            AcbusFunction::Clk30 => 10,
+            AcbusFunction::Clk15 => 11,
+            AcbusFunction::Clk7_5 => 12,
+            AcbusFunction::Unknown(x) => x & 0x0F,
+        }
+    }
+    /// Whether the pin is driven by the chip itself and can not be used as GPIO.
+    pub const fn is_repurposed(self) -> bool {
+        !matches!(
+            self,
+            AcbusFunction::Tristate | AcbusFunction::IoMode | AcbusFunction::Unknown(_)
+        )
+    }
+}
+
+/// Decodes the ACBUS function nibbles, `bytes` starting at EEPROM byte 0x18.
+pub(crate) fn acbus_functions(bytes: &[u8]) -> [AcbusFunction; ACBUS_PINS] {
+    std::array::from_fn(|idx| AcbusFunction::from_nibble((bytes[idx / 2] >> (idx % 2 * 4)) & 0x0F))
+}
+
+/// EEPROM words holding the ACBUS functions.
+pub(crate) const ACBUS_WORDS: std::ops::Range<u16> =
+    (ACBUS_OFFSET / 2) as u16..(ACBUS_OFFSET / 2 + ACBUS_PINS.div_ceil(4)) as u16;
+
+/// Raw image of the FTDI configuration EEPROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FtdiEeprom {
+    chip_type: ChipType,
+    data: Vec<u8>,
+}
+
+impl FtdiEeprom {
+    /// Reads the whole EEPROM of the device.
+    ///
+    /// The EEPROM size is detected by looking for the wrap around of a 93C46.
+    pub fn read(mpsse: &FtdiMpsse) -> Result<Self, FtdiError> {
+        Self::read_words(mpsse.chip_type, |addr| mpsse.read_eeprom_word(addr))
+    }
+
+    /// Writes the image back to the device, updating the checksum first.
+    ///
+    /// The FT230X is not supported yet.
+    pub fn write(&mut self, mpsse: &FtdiMpsse) -> Result<(), FtdiError> {
+        self.write_words(mpsse.chip_type, |addr, word| {
+            mpsse.write_eeprom_word(addr, word)
+        })
+    }
+
+    /// Like [`FtdiEeprom::read`], for any FTDI chip, without switching it to MPSSE.
+    ///
+    /// The first interface is claimed meanwhile, a kernel driver bound to it is detached.
+    pub fn read_device(usb_device: &nusb::DeviceInfo) -> Result<Self, FtdiError> {
+        let (chip_type, ft) = open_eeprom(usb_device)?;
+        Self::read_words(chip_type, |addr| ft.read_eeprom_word(addr))
+    }
+
+    /// Like [`FtdiEeprom::write`], for any FTDI chip, without switching it to MPSSE.
+    pub fn write_device(&mut self, usb_device: &nusb::DeviceInfo) -> Result<(), FtdiError> {
+        let (chip_type, ft) = open_eeprom(usb_device)?;
+        self.write_words(chip_type, |addr, word| ft.write_eeprom_word(addr, word))
+    }
+
+    fn read_words(
+        chip_type: ChipType,
+        mut read_word: impl FnMut(u16) -> Result<u16, FtdiError>,
+    ) -> Result<Self, FtdiError> {
+        let mut data = Vec::with_capacity(MAX_EEPROM_SIZE);
+        for addr in 0..(MAX_EEPROM_SIZE / 2) as u16 {
+            data.extend_from_slice(&read_word(addr)?.to_le_bytes());
+        }
+        if data[..MIN_EEPROM_SIZE] == data[MIN_EEPROM_SIZE..] {
+            data.truncate(MIN_EEPROM_SIZE);
+        }
+        Self::from_bytes(chip_type, data)
+    }
+
+    fn write_words(
+        &mut self,
+        chip_type: ChipType,
+        mut write_word: impl FnMut(u16, u16) -> Result<(), FtdiError>,
+    ) -> Result<(), FtdiError> {
+        if chip_type != self.chip_type {
+            return Err(FtdiError::Other(
+                "Eeprom image belongs to another chip type",
+            ));
+        }
+        // the FT-X checksum is not verified against a real MTP dump yet
+        if chip_type == ChipType::FT230X {
+            return Err(FtdiError::UnsupportedChip(chip_type));
+        }
+        self.update_checksum();
+        for (addr, word) in self.data.chunks(2).enumerate() {
+            write_word(addr as u16, u16::from_le_bytes([word[0], word[1]]))?;
+        }
+        Ok(())
+    }
+
+    /// Creates an image from raw bytes, e.g. a previous dump.
+    pub fn from_bytes(chip_type: ChipType, data: Vec<u8>) -> Result<Self, FtdiError> {
+        if data.len() != MIN_EEPROM_SIZE && data.len() != MAX_EEPROM_SIZE {
+            return Err(FtdiError::Other("Eeprom image must be 128 or 256 bytes"));
+        }
+        Ok(Self { chip_type, data })
+    }
+
+    /// Raw EEPROM bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn chip_type(&self) -> ChipType {
+        self.chip_type
+    }
+
+    /// Whether the stored checksum matches the content.
+    pub fn checksum_valid(&self) -> bool {
+        let stored = u16::from_le_bytes([
+            self.data[self.data.len() - 2],
+            self.data[self.data.len() - 1],
+        ]);
+        stored == checksum(self.chip_type, &self.data)
+    }
+
+    fn update_checksum(&mut self) {
+        let len = self.data.len();
+        let checksum = checksum(self.chip_type, &self.data).to_le_bytes();
+        self.data[len - 2..].copy_from_slice(&checksum);
+    }
+
+    /// Byte offset and nibble shift of the I/O cell options of a group.
+    fn io_cell_location(
+        &self,
+        interface: Interface,
+        bank: IoBank,
+    ) -> Result<(usize, u8), FtdiError> {
+        const GROUP_0_1: usize = 0x0C;
+        const GROUP_2_3: usize = 0x0D;
+        let location = match (self.chip_type, interface, bank) {
+            (ChipType::FT232H, Interface::A, IoBank::Lower) => Some((GROUP_0_1, 0)),
+            (ChipType::FT232H, Interface::A, IoBank::Upper) => Some((GROUP_2_3, 0)),
+            (ChipType::FT2232H, Interface::A, IoBank::Lower) => Some((GROUP_0_1, 0)),
+            (ChipType::FT2232H, Interface::A, IoBank::Upper) => Some((GROUP_0_1, 4)),
+            (ChipType::FT2232H, Interface::B, IoBank::Lower) => Some((GROUP_2_3, 0)),
+            (ChipType::FT2232H, Interface::B, IoBank::Upper) => Some((GROUP_2_3, 4)),
+            (ChipType::FT4232H, Interface::A, IoBank::Lower) => Some((GROUP_0_1, 0)),
+            (ChipType::FT4232H, Interface::B, IoBank::Lower) => Some((GROUP_0_1, 4)),
+            (ChipType::FT4232H, Interface::C, IoBank::Lower) => Some((GROUP_2_3, 0)),
+            (ChipType::FT4232H, Interface::D, IoBank::Lower) => Some((GROUP_2_3, 4)),
+            (ChipType::FT232H | ChipType::FT2232H | ChipType::FT4232H, _, _) => None,
+            (chip_type, _, _) => return Err(FtdiError::UnsupportedChip(chip_type)),
+        };
+        location.ok_or_else(|| {
+            FtdiError::PinFault(format!(
+                "{:?} Interface::{interface:?} do not has {bank:?} bank",
+                self.chip_type
+            ))
+        })
+    }
+
+    /// Slew, Schmitt and drive options of a GPIO bank.
+    pub fn io_cell(&self, interface: Interface, bank: IoBank) -> Result<IoCellConfig, FtdiError> {
+        let (offset, shift) = self.io_cell_location(interface, bank)?;
+        Ok(IoCellConfig::from_nibble(
+            (self.data[offset] >> shift) & 0x0F,
+        ))
+    }
+
+    /// Changes the slew, Schmitt and drive options of a GPIO bank.
+    ///
+    /// Takes effect after [`FtdiEeprom::write`] and a power cycle of the device.
+    pub fn set_io_cell(
+        &mut self,
+        interface: Interface,
+        bank: IoBank,
+        config: IoCellConfig,
+    ) -> Result<(), FtdiError> {
+        let (offset, shift) = self.io_cell_location(interface, bank)?;
+        self.data[offset] &= !(0x0F << shift);
+        self.data[offset] |= config.to_nibble() << shift;
+        Ok(())
+    }
+
+    /// Power settings of the USB configuration descriptor.
+    pub fn usb_power(&self) -> UsbPowerConfig {
+        let attributes = self.data[USB_ATTRIBUTES_OFFSET];
+        UsbPowerConfig {
+            max_power_ma: self.data[MAX_POWER_OFFSET] as u16 * 2,
+            self_powered: attributes & UsbPowerConfig::SELF_POWERED != 0,
+            remote_wakeup: attributes & UsbPowerConfig::REMOTE_WAKEUP != 0,
+        }
+    }
+
+    /// Changes the power settings of the USB configuration descriptor.
+    ///
+    /// Takes effect after [`FtdiEeprom::write`] and a power cycle of the device.
+    pub fn set_usb_power(&mut self, config: UsbPowerConfig) -> Result<(), FtdiError> {
+        if config.max_power_ma > MAX_BUS_POWER_MA {
+            return Err(FtdiError::Other("USB max power is at most 500 mA"));
+        }
+        let mut attributes = self.data[USB_ATTRIBUTES_OFFSET] | UsbPowerConfig::RESERVED;
+        for (bit, set) in [
+            (UsbPowerConfig::SELF_POWERED, config.self_powered),
+            (UsbPowerConfig::REMOTE_WAKEUP, config.remote_wakeup),
+        ] {
+            if set {
+                attributes |= bit;
+            } else {
+                attributes &= !bit;
+            }
+        }
+        self.data[USB_ATTRIBUTES_OFFSET] = attributes;
+        self.data[MAX_POWER_OFFSET] = (config.max_power_ma / 2) as u8;
+        Ok(())
+    }
+
+    fn check_invert_support(&self) -> Result<(), FtdiError> {
+        match self.chip_type {
+            ChipType::R | ChipType::FT230X => Ok(()),
+            chip_type => Err(FtdiError::UnsupportedChip(chip_type)),
+        }
+    }
+
+    /// UART signal inversion flags, FT232R and FT-X images only.
+    ///
+    /// Read these chips with [`FtdiEeprom::read_device`], they have no MPSSE.
+    pub fn signal_inversion(&self) -> Result<SignalInversion, FtdiError> {
+        self.check_invert_support()?;
+        Ok(SignalInversion::from_bits(self.data[INVERT_OFFSET]))
+    }
+
+    /// Changes the UART signal inversion flags, FT232R and FT-X images only.
+    ///
+    /// Takes effect after [`FtdiEeprom::write_device`] and a power cycle of the device.
+    pub fn set_signal_inversion(&mut self, inversion: SignalInversion) -> Result<(), FtdiError> {
+        self.check_invert_support()?;
+        self.data[INVERT_OFFSET] = inversion.into_bits();
+        Ok(())
+    }
+
+    /// Functions of the FT232H ACBUS0-ACBUS9 pins.
+    pub fn acbus_functions(&self) -> Result<[AcbusFunction; ACBUS_PINS], FtdiError> {
+        if self.chip_type != ChipType::FT232H {
+            return Err(FtdiError::UnsupportedChip(self.chip_type));
+        }
+        Ok(acbus_functions(&self.data[ACBUS_OFFSET..]))
+    }
+
+    /// Changes the function of an FT232H ACBUS pin.
+    ///
+    /// Takes effect after [`FtdiEeprom::write`] and a power cycle of the device.
+    pub fn set_acbus_function(
+        &mut self,
+        idx: usize,
+        function: AcbusFunction,
+    ) -> Result<(), FtdiError> {
+        if self.chip_type != ChipType::FT232H {
+            return Err(FtdiError::UnsupportedChip(self.chip_type));
+        }
+        if idx >= ACBUS_PINS {
+            return Err(FtdiError::PinFault(format!("FT232H do not has ACBUS{idx}")));
+        }
+        let shift = idx % 2 * 4;
+        let byte = &mut self.data[ACBUS_OFFSET + idx / 2];
+        *byte &= !(0x0F << shift);
+        *byte |= function.to_nibble() << shift;
+        Ok(())
+    }
+}
+
+/// Reads the EEPROM of `mpsse` and saves it to `path`
+///
+/// With the `config` feature a `.json` path gets the decoded fields next to the raw bytes,
+/// any other path gets the raw image.
+pub fn dump_to_file(mpsse: &FtdiMpsse, path: impl AsRef<Path>) -> Result<FtdiEeprom, FtdiError> {
+    let path = path.as_ref();
+    let eeprom = FtdiEeprom::read(mpsse)?;
+    let contents = match path.extension() {
+        #[cfg(feature = "config")]
+        Some(ext) if ext == "json" => eeprom.to_json()?.into_bytes(),
+        _ => eeprom.data.clone(),
+    };
+    fs::write(path, contents).map_err(|e| backup_error(path, e))?;
+    Ok(eeprom)
+}
+
+/// Writes an image saved by [`dump_to_file`] back to the EEPROM of `mpsse`
+///
+/// The image must come from the same chip type, JSON files record it, raw files are
+/// assumed to match.
+pub fn restore_from_file(
+    mpsse: &FtdiMpsse,
+    path: impl AsRef<Path>,
+) -> Result<FtdiEeprom, FtdiError> {
+    let path = path.as_ref();
+    let contents = fs::read(path).map_err(|e| backup_error(path, e))?;
+    let mut eeprom = match path.extension() {
+        #[cfg(feature = "config")]
+        Some(ext) if ext == "json" => {
+            let text = String::from_utf8(contents).map_err(|e| backup_error(path, e))?;
+            FtdiEeprom::from_json(&text)?
+        }
+        _ => FtdiEeprom::from_bytes(mpsse.chip_type, contents)?,
+    };
+    eeprom.write(mpsse)?;
+    Ok(eeprom)
+}
+
+fn backup_error(path: &Path, e: impl std::fmt::Display) -> FtdiError {
+    FtdiError::EepromBackup(format!("{}: {e}", path.display()))
+}
+
+#[cfg(feature = "config")]
+impl FtdiEeprom {
+    /// Decoded fields for reading, only `chip_type` and `data` are used to restore
+    fn to_json(&self) -> Result<String, FtdiError> {
+        let power = self.usb_power();
+        let mut json = serde_json::json!({
+            "chip_type": format!("{:?}", self.chip_type),
+            "checksum_valid": self.checksum_valid(),
+            "usb_power": {
+                "max_power_ma": power.max_power_ma,
+                "self_powered": power.self_powered,
+                "remote_wakeup": power.remote_wakeup,
+            },
+            "data": self.data.iter().map(|byte| format!("{byte:02x}")).collect::<String>(),
+        });
+        if let Ok(functions) = self.acbus_functions() {
+            json["acbus_functions"] = functions.map(|f| format!("{f:?}")).to_vec().into();
+        }
+        if let Ok(inversion) = self.signal_inversion() {
+            json["signal_inversion"] = format!("{inversion:?}").into();
+        }
+        serde_json::to_string_pretty(&json).map_err(|e| FtdiError::EepromBackup(e.to_string()))
+    }
+    fn from_json(text: &str) -> Result<Self, FtdiError> {
+        let invalid = |what: &str| FtdiError::EepromBackup(format!("invalid {what}"));
+        let json: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| FtdiError::EepromBackup(e.to_string()))?;
+        let chip_type = json["chip_type"]
+            .as_str()
+            .ok_or_else(|| invalid("chip_type"))?;
+        let chip_type = [
+            ChipType::FT2232D,
+            ChipType::FT2232H,
+            ChipType::FT4232H,
+            ChipType::FT232H,
+            ChipType::R,
+            ChipType::FT230X,
+        ]
+        .into_iter()
+        .find(|known| format!("{known:?}") == chip_type)
+        .ok_or_else(|| invalid("chip_type"))?;
+        let hex = json["data"].as_str().ok_or_else(|| invalid("data"))?;
+        let data = (0..hex.len())
+            .step_by(2)
+            .map(|idx| {
+                hex.get(idx..idx + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| invalid("data"))?;
+        Self::from_bytes(chip_type, data)
+    }
+}
+
+/// Claims the first interface of `usb_device` for the EEPROM control requests.
+fn open_eeprom(usb_device: &nusb::DeviceInfo) -> Result<(ChipType, FtdiContext), FtdiError> {
+    let chip_type = mpsse::identify(usb_device);
+    if chip_type == ChipType::Unknown {
+        return Err(FtdiError::UnsupportedChip(chip_type));
+    }
+    let handle = usb_device
+        .open()?
+        .detach_and_claim_interface(Interface::A.interface_number())?;
+    let ft = FtdiContext::new(handle, Interface::A, chip_type.max_packet_size());
+    Ok((chip_type, ft))
+}
+
+/// FTDI EEPROM checksum, stored in the last word.
+///
+/// The FT-X user area, words 0x12 to 0x3F, is left out like libftdi does, the
+/// factory configuration words from 0x40 on are part of a complete dump.
+fn checksum(chip_type: ChipType, data: &[u8]) -> u16 {
+    const FT_X_USER_AREA: std::ops::Range<usize> = 0x12..0x40;
+    data[..data.len() - 2]
+        .chunks(2)
+        .enumerate()
+        .filter(|(addr, _)| chip_type != ChipType::FT230X || !FT_X_USER_AREA.contains(addr))
+        .fold(0xAAAA, |checksum: u16, (_, word)| {
+            (checksum ^ u16::from_le_bytes([word[0], word[1]])).rotate_left(1)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AcbusFunction, DriveStrength, FtdiEeprom, IoBank, IoCellConfig, SignalInversion,
+        UsbPowerConfig,
+    };
+    use crate::{ChipType, FtdiError, Interface};
+
+    #[test]
+    fn checksum_roundtrip() {
+        let mut eeprom = FtdiEeprom::from_bytes(ChipType::FT2232H, vec![0; 256]).unwrap();
+        assert!(!eeprom.checksum_valid());
+        eeprom.update_checksum();
+        assert!(eeprom.checksum_valid());
+    }
+
+    #[test]
+    fn ft_x_checksum() {
+        let mut eeprom = FtdiEeprom::from_bytes(ChipType::FT230X, vec![0; 256]).unwrap();
+        eeprom.update_checksum();
+        assert!(eeprom.checksum_valid());
+        // the user area is not covered
+        eeprom.data[0x12 * 2] = 0x55;
+        eeprom.data[0x3F * 2 + 1] = 0x55;
+        assert!(eeprom.checksum_valid());
+        eeprom.data[0x40 * 2] = 0x55;
+        assert!(!eeprom.checksum_valid());
+        // not written until the checksum is confirmed against a real device
+        assert!(matches!(
+            eeprom.write_words(ChipType::FT230X, |_, _| Ok(())),
+            Err(FtdiError::UnsupportedChip(ChipType::FT230X))
+        ));
+    }
+
+    #[test]
+    fn io_cell_nibbles() {
+        let mut eeprom = FtdiEeprom::from_bytes(ChipType::FT2232H, vec![0; 256]).unwrap();
+        let config = IoCellConfig {
+            drive: DriveStrength::Ma12,
+            slow_slew: true,
+            schmitt: false,
+        };
+        eeprom
+            .set_io_cell(Interface::A, IoBank::Upper, config)
+            .unwrap();
+        assert_eq!(eeprom.as_bytes()[0x0C], 0x60);
+        assert_eq!(eeprom.io_cell(Interface::A, IoBank::Upper).unwrap(), config);
+        assert!(eeprom.io_cell(Interface::C, IoBank::Lower).is_err());
+    }
+
+    #[test]
+    fn usb_power_bits() {
+        let mut eeprom = FtdiEeprom::from_bytes(ChipType::FT232H, vec![0; 256]).unwrap();
+        let config = UsbPowerConfig {
+            max_power_ma: 90,
+            self_powered: true,
+            remote_wakeup: false,
+        };
+        eeprom.set_usb_power(config).unwrap();
+        assert_eq!(eeprom.as_bytes()[0x08..0x0A], [0xC0, 45]);
+        assert_eq!(eeprom.usb_power(), config);
+        assert!(
+            eeprom
+                .set_usb_power(UsbPowerConfig {
+                    max_power_ma: 510,
+                    ..config
+                })
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn inversion_flags() {
+        let mut eeprom = FtdiEeprom::from_bytes(ChipType::R, vec![0; 128]).unwrap();
+        let inversion = SignalInversion::new().with_txd(true).with_rts(true);
+        eeprom.set_signal_inversion(inversion).unwrap();
+        assert_eq!(eeprom.as_bytes()[0x0B], 0x05);
+        assert_eq!(eeprom.signal_inversion().unwrap(), inversion);
+        let eeprom = FtdiEeprom::from_bytes(ChipType::FT232H, vec![0; 256]).unwrap();
+        assert!(eeprom.signal_inversion().is_err());
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn json_backup_roundtrip() {
+        let mut data: Vec<u8> = (0..=255).collect();
+        data[0x08] = 0x80;
+        let eeprom = FtdiEeprom::from_bytes(ChipType::FT232H, data).unwrap();
+        let json = eeprom.to_json().unwrap();
+        assert!(json.contains("\"acbus_functions\""));
+        assert_eq!(FtdiEeprom::from_json(&json).unwrap(), eeprom);
+        assert!(FtdiEeprom::from_json(&json.replace("FT232H", "FT999")).is_err());
+    }
+
+    #[test]
+    fn acbus_nibbles() {
+        let mut eeprom = FtdiEeprom::from_bytes(ChipType::FT232H, vec![0; 256]).unwrap();
+        eeprom.set_acbus_function(3, AcbusFunction::TxDen).unwrap();
+        eeprom.set_acbus_function(8, AcbusFunction::IoMode).unwrap();
+        assert_eq!(eeprom.as_bytes()[0x19], 0x90);
+        assert_eq!(eeprom.as_bytes()[0x1C], 0x08);
+        let functions = eeprom.acbus_functions().unwrap();
+        assert_eq!(functions[3], AcbusFunction::TxDen);
+        assert_eq!(functions[8], AcbusFunction::IoMode);
+        assert_eq!(functions[9], AcbusFunction::Tristate);
+        assert_eq!(super::ACBUS_WORDS, 0x0C..0x0F);
+    }
+}
@@ -0,0 +1,556 @@
+//! Flash ESP32 chips through their ROM serial bootloader.
+//!
+//! The bootloader talks SLIP framed command packets over [`FtdiUart`].
+//! The chip is put into download mode by holding IO0 low while EN rises,
+//! either with the DTR/RTS auto reset circuit found on most development boards
+//! or with two GPIOs of another MPSSE interface wired to EN and IO0.
+//!
+//! Only the original ESP32 ROM and flasher stubs are handled, later chips
+//! expect extra fields in some commands.
+use crate::{FtdiError, gpio::FtdiOutputPin, stats::Event, uart::FtdiUart};
+use eh1::digital::OutputPin;
+use std::{thread::sleep, time::Duration};
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+// Bootloader commands
+const FLASH_BEGIN: u8 = 0x02;
+const FLASH_DATA: u8 = 0x03;
+const FLASH_END: u8 = 0x04;
+const MEM_BEGIN: u8 = 0x05;
+const MEM_END: u8 = 0x06;
+const MEM_DATA: u8 = 0x07;
+const SYNC: u8 = 0x08;
+const READ_REG: u8 = 0x0A;
+const SPI_ATTACH: u8 = 0x0D;
+const CHANGE_BAUDRATE: u8 = 0x0F;
+const SPI_FLASH_MD5: u8 = 0x13;
+
+/// Seed of the checksum over the data of *_DATA commands.
+const CHECKSUM_SEED: u8 = 0xEF;
+const ROM_FLASH_BLOCK: usize = 0x400;
+const STUB_FLASH_BLOCK: usize = 0x4000;
+const RAM_BLOCK: usize = 0x1800;
+/// Erase granularity of the SPI flash, interrupted writes resume at a multiple of it.
+const FLASH_SECTOR: usize = 0x1000;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Flash erase in FLASH_BEGIN takes up to this long per MiB.
+const ERASE_TIMEOUT_PER_MB: Duration = Duration::from_secs(30);
+/// SPI_FLASH_MD5 reads flash at no less than this many seconds per MiB.
+const MD5_TIMEOUT_PER_MB: Duration = Duration::from_secs(8);
+
+#[derive(Debug, thiserror::Error)]
+pub enum EspError {
+    #[error(transparent)]
+    FtdiInner(#[from] FtdiError),
+    #[error("No response to command {0:#04x}")]
+    Timeout(u8),
+    #[error("Command {command:#04x} failed with status {status:#04x}, error {error:#04x}")]
+    Failed { command: u8, status: u8, error: u8 },
+    #[error("Bad response: {0}")]
+    BadResponse(&'static str),
+}
+
+/// How EN and IO0 are driven to enter the bootloader
+pub enum EspReset {
+    /// Auto reset circuit of development boards: RTS pulls EN low, DTR pulls IO0 low.
+    DtrRts,
+    /// GPIOs wired directly to the EN and IO0 pins.
+    Gpio {
+        en: FtdiOutputPin,
+        io0: FtdiOutputPin,
+    },
+    /// The chip is already in download mode.
+    None,
+}
+
+/// Step of [`EspFlasher::write_flash_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashPhase {
+    /// Comparing flash with the image, only in [`EspFlasher::update_flash`]
+    Verify,
+    Erase,
+    Write,
+}
+
+/// Progress of [`EspFlasher::write_flash_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashProgress {
+    pub phase: FlashPhase,
+    /// Bytes of the image the chip has acknowledged, counted from its start
+    pub done: usize,
+    /// Length of the whole image
+    pub total: usize,
+}
+
+impl FlashProgress {
+    /// Where a write interrupted after this point can be resumed, see [`EspFlasher::write_flash_with`]
+    pub fn resume_from(&self) -> usize {
+        self.done / FLASH_SECTOR * FLASH_SECTOR
+    }
+}
+
+/// Flasher stub loaded into RAM in place of the ROM bootloader
+///
+/// Segments and entry point are taken from the stub images shipped with esptool.
+pub struct EspStub<'a> {
+    pub text: &'a [u8],
+    pub text_start: u32,
+    pub data: &'a [u8],
+    pub data_start: u32,
+    pub entry: u32,
+}
+
+/// ROM serial bootloader client
+pub struct EspFlasher {
+    uart: FtdiUart,
+    reset: EspReset,
+    /// Whether a flasher stub replaced the ROM bootloader
+    stub: bool,
+}
+
+impl EspFlasher {
+    pub fn new(uart: FtdiUart, reset: EspReset) -> Self {
+        Self {
+            uart,
+            reset,
+            stub: false,
+        }
+    }
+    /// Resets the chip into download mode, syncs and attaches the SPI flash
+    pub fn connect(&mut self) -> Result<(), EspError> {
+        self.enter_bootloader()?;
+        self.sync()?;
+        // SPI_ATTACH with default pins
+        self.command(SPI_ATTACH, &[0; 8], 0, DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+    /// Releases the reset lines and the uart
+    pub fn into_inner(self) -> (FtdiUart, EspReset) {
+        (self.uart, self.reset)
+    }
+    /// Resets the chip to run the application
+    pub fn hard_reset(&mut self) -> Result<(), EspError> {
+        match &mut self.reset {
+            EspReset::DtrRts => {
+                self.uart.set_rts(true)?;
+                sleep(Duration::from_millis(100));
+                self.uart.set_rts(false)?;
+            }
+            EspReset::Gpio { en, io0 } => {
+                io0.set_high()?;
+                en.set_low()?;
+                sleep(Duration::from_millis(100));
+                en.set_high()?;
+            }
+            EspReset::None => (),
+        }
+        Ok(())
+    }
+    fn enter_bootloader(&mut self) -> Result<(), EspError> {
+        match &mut self.reset {
+            EspReset::DtrRts => {
+                // Same sequence as the esptool classic reset
+                self.uart.set_dtr(false)?;
+                self.uart.set_rts(true)?;
+                sleep(Duration::from_millis(100));
+                self.uart.set_dtr(true)?;
+                self.uart.set_rts(false)?;
+                sleep(Duration::from_millis(50));
+                self.uart.set_dtr(false)?;
+            }
+            EspReset::Gpio { en, io0 } => {
+                en.set_low()?;
+                io0.set_low()?;
+                sleep(Duration::from_millis(100));
+                en.set_high()?;
+                sleep(Duration::from_millis(50));
+                io0.set_high()?;
+            }
+            EspReset::None => (),
+        }
+        Ok(())
+    }
+    /// Sends SYNC until the bootloader has detected the baud rate
+    pub fn sync(&mut self) -> Result<(), EspError> {
+        let mut data = vec![0x07, 0x07, 0x12, 0x20];
+        data.extend_from_slice(&[0x55; 32]);
+        self.uart.clear_rx()?;
+        for _ in 0..10 {
+            match self.command(SYNC, &data, 0, Duration::from_millis(100)) {
+                Ok(_) => {
+                    // The ROM answers a SYNC several times
+                    while self.read_frame(Duration::from_millis(10)).is_ok() {}
+                    return Ok(());
+                }
+                Err(EspError::Timeout(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(EspError::Timeout(SYNC))
+    }
+    /// Reads a 32-bit register
+    pub fn read_reg(&mut self, addr: u32) -> Result<u32, EspError> {
+        self.command(READ_REG, &addr.to_le_bytes(), 0, DEFAULT_TIMEOUT)
+    }
+    /// Switches the bootloader and the uart to another baud rate
+    pub fn change_baud(&mut self, baud_rate: u32) -> Result<(), EspError> {
+        // The ROM ignores the current rate, the stub needs it
+        let current = if self.stub { self.uart.baud_rate() } else { 0 };
+        let data = [baud_rate.to_le_bytes(), current.to_le_bytes()].concat();
+        self.command(CHANGE_BAUDRATE, &data, 0, DEFAULT_TIMEOUT)?;
+        self.uart.set_baud_rate(baud_rate)?;
+        sleep(Duration::from_millis(50));
+        self.uart.clear_rx()?;
+        Ok(())
+    }
+    /// Loads a flasher stub into RAM and runs it
+    ///
+    /// The stub flashes with larger blocks and erases faster than the ROM.
+    pub fn load_stub(&mut self, stub: &EspStub) -> Result<(), EspError> {
+        for (segment, start) in [(stub.text, stub.text_start), (stub.data, stub.data_start)] {
+            let blocks = segment.len().div_ceil(RAM_BLOCK);
+            let params = [segment.len() as u32, blocks as u32, RAM_BLOCK as u32, start];
+            self.command(MEM_BEGIN, &words(&params), 0, DEFAULT_TIMEOUT)?;
+            for (seq, block) in segment.chunks(RAM_BLOCK).enumerate() {
+                self.data_command(MEM_DATA, seq, block, DEFAULT_TIMEOUT)?;
+            }
+        }
+        // 0 runs the entry point
+        self.command(MEM_END, &words(&[0, stub.entry]), 0, DEFAULT_TIMEOUT)?;
+        let greeting = self.read_frame(DEFAULT_TIMEOUT)?;
+        if greeting != b"OHAI" {
+            return Err(EspError::BadResponse("stub did not start"));
+        }
+        self.stub = true;
+        Ok(())
+    }
+    /// Erases and writes `data` to flash at `offset`, `offset` must be 4 KiB aligned
+    pub fn write_flash(&mut self, offset: u32, data: &[u8]) -> Result<(), EspError> {
+        self.write_flash_with(offset, data, 0, |_| ())
+    }
+    /// Like [`EspFlasher::write_flash`], reporting progress before the erase and after every block
+    ///
+    /// Only `data[resume_from..]` is erased and written, so a write that failed part way can
+    /// continue from the [`FlashProgress::resume_from`] of the last progress it reported,
+    /// after reconnecting. `resume_from` must be a multiple of 4 KiB.
+    pub fn write_flash_with(
+        &mut self,
+        offset: u32,
+        data: &[u8],
+        resume_from: usize,
+        mut progress: impl FnMut(FlashProgress),
+    ) -> Result<(), EspError> {
+        assert!(
+            resume_from.is_multiple_of(FLASH_SECTOR) && resume_from <= data.len(),
+            "resume_from must be a 4 KiB multiple within data"
+        );
+        let block_size = if self.stub {
+            STUB_FLASH_BLOCK
+        } else {
+            ROM_FLASH_BLOCK
+        };
+        let report = |phase, done| FlashProgress {
+            phase,
+            done,
+            total: data.len(),
+        };
+        let remaining = &data[resume_from..];
+        let blocks = remaining.len().div_ceil(block_size);
+        let params = [
+            remaining.len() as u32,
+            blocks as u32,
+            block_size as u32,
+            offset + resume_from as u32,
+        ];
+        let erase_timeout = ERASE_TIMEOUT_PER_MB.mul_f32(remaining.len() as f32 / (1 << 20) as f32);
+        progress(report(FlashPhase::Erase, resume_from));
+        self.command(
+            FLASH_BEGIN,
+            &words(&params),
+            0,
+            erase_timeout.max(DEFAULT_TIMEOUT),
+        )?;
+        let mut done = resume_from;
+        for (seq, block) in remaining.chunks(block_size).enumerate() {
+            // The last block is padded with erased flash
+            let len = block.len();
+            let mut block = block.to_vec();
+            block.resize(block_size, 0xFF);
+            self.data_command(FLASH_DATA, seq, &block, DEFAULT_TIMEOUT)?;
+            log::debug!("Flash {}/{} blocks", seq + 1, blocks);
+            done += len;
+            progress(report(FlashPhase::Write, done));
+        }
+        Ok(())
+    }
+    /// Writes only the 4 KiB sectors of `data` that differ from the flash at `offset`
+    ///
+    /// Every sector is compared by the MD5 the chip computes, which is much faster than erasing
+    /// and writing it when most of an image is unchanged. Returns the number of bytes written.
+    pub fn update_flash(
+        &mut self,
+        offset: u32,
+        data: &[u8],
+        mut progress: impl FnMut(FlashProgress),
+    ) -> Result<usize, EspError> {
+        let mut stale = Vec::new();
+        for (idx, sector) in data.chunks(FLASH_SECTOR).enumerate() {
+            let start = idx * FLASH_SECTOR;
+            progress(FlashProgress {
+                phase: FlashPhase::Verify,
+                done: start,
+                total: data.len(),
+            });
+            if self.flash_md5(offset + start as u32, sector.len() as u32)? != md5(sector) {
+                stale.push(start..start + sector.len());
+            }
+        }
+        // Neighbouring sectors are written in one go
+        let mut runs: Vec<std::ops::Range<usize>> = Vec::new();
+        for range in stale {
+            match runs.last_mut() {
+                Some(run) if run.end == range.start => run.end = range.end,
+                _ => runs.push(range),
+            }
+        }
+        log::info!(
+            "{} of {} bytes differ",
+            runs.iter().map(|run| run.len()).sum::<usize>(),
+            data.len()
+        );
+        let mut written = 0;
+        for run in runs {
+            self.write_flash_with(offset + run.start as u32, &data[run.clone()], 0, |p| {
+                progress(FlashProgress {
+                    done: run.start + p.done,
+                    total: data.len(),
+                    ..p
+                })
+            })?;
+            written += run.len();
+        }
+        Ok(written)
+    }
+    /// MD5 digest of `len` bytes of flash at `offset`, computed by the chip
+    pub fn flash_md5(&mut self, offset: u32, len: u32) -> Result<[u8; 16], EspError> {
+        let timeout = MD5_TIMEOUT_PER_MB.mul_f32(len as f32 / (1 << 20) as f32);
+        let (_, body) = self.command_body(
+            SPI_FLASH_MD5,
+            &words(&[offset, len, 0, 0]),
+            0,
+            timeout.max(DEFAULT_TIMEOUT),
+        )?;
+        // The stub sends the raw digest, the ROM sends it as hex
+        let digest = if self.stub {
+            body.get(..16).and_then(|digest| digest.try_into().ok())
+        } else {
+            body.get(..32)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u128::from_str_radix(hex, 16).ok())
+                .map(u128::to_be_bytes)
+        };
+        digest.ok_or(EspError::BadResponse("malformed MD5 digest"))
+    }
+    /// Finishes flashing, `reboot` runs the new application
+    pub fn flash_end(&mut self, reboot: bool) -> Result<(), EspError> {
+        // 0 reboots, 1 stays in the bootloader
+        let stay = (!reboot) as u32;
+        self.command(FLASH_END, &stay.to_le_bytes(), 0, DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+    fn data_command(
+        &mut self,
+        command: u8,
+        seq: usize,
+        block: &[u8],
+        timeout: Duration,
+    ) -> Result<u32, EspError> {
+        let mut data = words(&[block.len() as u32, seq as u32, 0, 0]);
+        data.extend_from_slice(block);
+        let checksum = block.iter().fold(CHECKSUM_SEED, |acc, x| acc ^ x);
+        self.command(command, &data, checksum as u32, timeout)
+    }
+    /// Sends a command and returns the value field of its response
+    fn command(
+        &mut self,
+        command: u8,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<u32, EspError> {
+        Ok(self.command_body(command, data, checksum, timeout)?.0)
+    }
+    /// Sends a command and returns the value field and the data of its response
+    fn command_body(
+        &mut self,
+        command: u8,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<(u32, Vec<u8>), EspError> {
+        // direction 0 = request
+        let mut packet = vec![0x00, command];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(data);
+        self.uart.write(&slip_encode(&packet))?;
+        // Skip responses left over from earlier commands
+        for _ in 0..100 {
+            let response = match self.read_frame(timeout) {
+                Ok(response) => response,
+                Err(EspError::Timeout(_)) => {
+                    self.uart.record(Event::Timeout);
+                    return Err(EspError::Timeout(command));
+                }
+                Err(e) => return Err(e),
+            };
+            // direction, command, size, value, data with status bytes at the end
+            // The ROM appends 4 status bytes and the stub 2, both start with status and error
+            let status_len = if self.stub { 2 } else { 4 };
+            if response.len() < 8 + status_len || response[0] != 0x01 || response[1] != command {
+                continue;
+            }
+            let value = u32::from_le_bytes(response[4..8].try_into().unwrap());
+            let (body, status) = response[8..].split_at(response.len() - 8 - status_len);
+            if status[0] != 0 {
+                return Err(EspError::Failed {
+                    command,
+                    status: status[0],
+                    error: status[1],
+                });
+            }
+            return Ok((value, body.to_vec()));
+        }
+        Err(EspError::BadResponse("no response to the command"))
+    }
+    /// Reads and decodes one SLIP frame
+    fn read_frame(&mut self, timeout: Duration) -> Result<Vec<u8>, EspError> {
+        let mut byte = [0];
+        // Wait for the frame start
+        loop {
+            if self.uart.read(&mut byte, timeout)? == 0 {
+                return Err(EspError::Timeout(0));
+            }
+            if byte[0] == SLIP_END {
+                break;
+            }
+        }
+        let mut frame = Vec::new();
+        loop {
+            if self.uart.read(&mut byte, timeout)? == 0 {
+                return Err(EspError::Timeout(0));
+            }
+            match byte[0] {
+                // Back to back frames share the END byte
+                SLIP_END if frame.is_empty() => (),
+                SLIP_END => return Ok(frame),
+                SLIP_ESC => {
+                    if self.uart.read(&mut byte, timeout)? == 0 {
+                        return Err(EspError::Timeout(0));
+                    }
+                    match byte[0] {
+                        SLIP_ESC_END => frame.push(SLIP_END),
+                        SLIP_ESC_ESC => frame.push(SLIP_ESC),
+                        _ => return Err(EspError::BadResponse("invalid SLIP escape")),
+                    }
+                }
+                x => frame.push(x),
+            }
+        }
+    }
+}
+
+fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = vec![SLIP_END];
+    for &byte in packet {
+        match byte {
+            SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            x => frame.push(x),
+        }
+    }
+    frame.push(SLIP_END);
+    frame
+}
+
+fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// MD5 as in RFC 1321, to compare with the digests of SPI_FLASH_MD5
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let constants: [u32; 64] =
+        std::array::from_fn(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32);
+    let mut message = data.to_vec();
+    message.push(0x80);
+    // Zeros up to the 64 bit length that ends the last block
+    message.resize((data.len() + 9).next_multiple_of(64) - 8, 0);
+    message.extend_from_slice(&(data.len() as u64 * 8).to_le_bytes());
+    let mut state = [0x67452301u32, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in message.chunks(64) {
+        let m: [u32; 16] = std::array::from_fn(|i| {
+            u32::from_le_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap())
+        });
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(m[g])
+                .rotate_left(SHIFTS[i / 16 * 4 + i % 4]);
+            (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 16];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlashPhase, FlashProgress, md5, slip_encode};
+
+    #[test]
+    fn slip() {
+        assert_eq!(
+            slip_encode(&[0x01, 0xC0, 0xDB, 0x02]),
+            [0xC0, 0x01, 0xDB, 0xDC, 0xDB, 0xDD, 0x02, 0xC0]
+        );
+    }
+    #[test]
+    fn resume_at_sector() {
+        let progress = FlashProgress {
+            phase: FlashPhase::Write,
+            done: 0x5400,
+            total: 0x8000,
+        };
+        assert_eq!(progress.resume_from(), 0x5000);
+    }
+    #[test]
+    fn md5_digest() {
+        let hex = |data: &[u8]| md5(data).map(|b| format!("{b:02x}")).concat();
+        assert_eq!(hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hex(b"The quick brown fox jumps over the lazy dog"),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        // Padding spills into an extra block
+        assert_eq!(hex(&[b'a'; 56]), "3b0c8ac703f828b04c6c197006d17218");
+    }
+}
@@ -0,0 +1,224 @@
+//! Load a bitstream into the configuration SRAM of common FPGAs.
+//!
+//! | Family          | Interface | Bitstream     |
+//! |-----------------|-----------|---------------|
+//! | Xilinx 7-series | JTAG      | `.bit`/`.bin` |
+//! | Lattice ECP5    | JTAG      | `.bit`        |
+//! | Lattice iCE40   | SPI slave | `.bin`        |
+//!
+//! The JTAG flows expect the FPGA to be the only TAP on the chain.
+//! Configuration is volatile, the FPGA loses it on power cycle or PROGRAM.
+use crate::{
+    FtdiError,
+    gpio::{FtdiInputPin, FtdiOutputPin},
+    jtag::FtdiJtag,
+    spi::{FtdiSpi, FtdiSpiError},
+};
+use eh1::{
+    digital::{InputPin, OutputPin},
+    spi::SpiBus,
+};
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// Bitstream bytes shifted per USB transfer.
+const CHUNK_SIZE: usize = 0x1_0000;
+
+/// Removes the header of a Xilinx `.bit` file, a `.bin` file is returned unchanged.
+///
+/// The header is a list of tagged fields, the bitstream is the payload of field `e`.
+pub fn xilinx_bit_payload(data: &[u8]) -> Result<&[u8], FtdiError> {
+    const MAGIC: [u8; 13] = [
+        0x00, 0x09, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x00, 0x00, 0x01,
+    ];
+    let Some(mut rest) = data.strip_prefix(&MAGIC) else {
+        return Ok(data);
+    };
+    let malformed = || FtdiError::FpgaConfigFailed("malformed .bit header".into());
+    loop {
+        let (&key, tail) = rest.split_first().ok_or_else(malformed)?;
+        if key == b'e' {
+            let len = tail.get(..4).ok_or_else(malformed)?;
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            return tail.get(4..4 + len).ok_or_else(malformed);
+        }
+        // a: design name, b: part, c: date, d: time
+        let len = tail.get(..2).ok_or_else(malformed)?;
+        let len = u16::from_be_bytes(len.try_into().unwrap()) as usize;
+        rest = tail.get(2 + len..).ok_or_else(malformed)?;
+    }
+}
+
+/// Configures a Xilinx 7-series FPGA through JTAG (UG470, JTAG configuration flow).
+///
+/// `bitstream` is the content of a `.bit` or `.bin` file.
+pub fn load_xilinx7(jtag: &mut FtdiJtag, bitstream: &[u8]) -> Result<(), FtdiError> {
+    const IR_LEN: usize = 6;
+    const CFG_IN: u8 = 0x05;
+    const JPROGRAM: u8 = 0x0B;
+    const JSTART: u8 = 0x0C;
+    const BYPASS: u8 = 0x3F;
+    // IR capture: [5] DONE, [4] INIT_COMPLETE, [1:0] 0b01
+    const INIT_COMPLETE: u8 = 1 << 4;
+    const DONE: u8 = 1 << 5;
+
+    let bitstream = xilinx_bit_payload(bitstream)?;
+    jtag.goto_idle()?;
+    shift_ir(jtag, JPROGRAM, IR_LEN)?;
+    // Configuration memory is cleared before INIT_COMPLETE rises
+    let start = Instant::now();
+    while shift_ir(jtag, BYPASS, IR_LEN)? & INIT_COMPLETE == 0 {
+        if start.elapsed() > Duration::from_millis(100) {
+            return Err(FtdiError::FpgaConfigFailed(
+                "INIT_COMPLETE not set after JPROGRAM".into(),
+            ));
+        }
+        sleep(Duration::from_millis(1));
+    }
+    shift_ir(jtag, CFG_IN, IR_LEN)?;
+    shift_dr_bitstream(jtag, bitstream)?;
+    shift_ir(jtag, JSTART, IR_LEN)?;
+    // Startup sequence needs at least 2000 TCK cycles
+    jtag.idle_cycles(2000)?;
+    let status = shift_ir(jtag, BYPASS, IR_LEN)?;
+    if status & DONE == 0 {
+        return Err(FtdiError::FpgaConfigFailed(format!(
+            "DONE not set, IR capture {status:#04x}"
+        )));
+    }
+    Ok(())
+}
+
+/// Configures a Lattice ECP5 FPGA through JTAG (FPGA-TN-02039, SRAM programming).
+///
+/// `bitstream` is the content of a `.bit` file.
+pub fn load_ecp5(jtag: &mut FtdiJtag, bitstream: &[u8]) -> Result<(), FtdiError> {
+    const IR_LEN: usize = 8;
+    const ISC_ENABLE: u8 = 0xC6;
+    const ISC_ERASE: u8 = 0x0E;
+    const ISC_DISABLE: u8 = 0x26;
+    const LSC_RESET_CRC: u8 = 0x3B;
+    const LSC_READ_STATUS: u8 = 0x3C;
+    const LSC_BITSTREAM_BURST: u8 = 0x7A;
+    const BYPASS: u8 = 0xFF;
+    // Status register
+    const DONE: u32 = 1 << 8;
+    const FAIL: u32 = 1 << 13;
+    const BSE_ERROR_SHIFT: u32 = 23;
+
+    jtag.goto_idle()?;
+    shift_ir(jtag, ISC_ENABLE, IR_LEN)?;
+    shift_dr(jtag, &[0x00], 8)?;
+    jtag.idle_cycles(1000)?;
+    // Erase SRAM
+    shift_ir(jtag, ISC_ERASE, IR_LEN)?;
+    shift_dr(jtag, &[0x01], 8)?;
+    sleep(Duration::from_millis(10));
+    jtag.idle_cycles(1000)?;
+    shift_ir(jtag, LSC_RESET_CRC, IR_LEN)?;
+    jtag.idle_cycles(1000)?;
+    shift_ir(jtag, LSC_BITSTREAM_BURST, IR_LEN)?;
+    shift_dr_bitstream(jtag, bitstream)?;
+    jtag.idle_cycles(100)?;
+    shift_ir(jtag, ISC_DISABLE, IR_LEN)?;
+    jtag.idle_cycles(1000)?;
+    shift_ir(jtag, BYPASS, IR_LEN)?;
+    jtag.idle_cycles(1000)?;
+
+    shift_ir(jtag, LSC_READ_STATUS, IR_LEN)?;
+    let status = shift_dr(jtag, &[0; 4], 32)?;
+    let status = u32::from_le_bytes(status[..4].try_into().unwrap());
+    if status & DONE == 0 || status & FAIL != 0 {
+        return Err(FtdiError::FpgaConfigFailed(format!(
+            "status {status:#010x}, bitstream error {}",
+            (status >> BSE_ERROR_SHIFT) & 0b111
+        )));
+    }
+    Ok(())
+}
+
+/// Configures a Lattice iCE40 FPGA in SPI slave mode (TN1248).
+///
+/// `ss` drives SPI_SS of the FPGA, it must be low while CRESET_B rises
+/// or the FPGA boots from its own flash instead.
+pub fn load_ice40(
+    spi: &mut FtdiSpi,
+    ss: &mut FtdiOutputPin,
+    creset: &mut FtdiOutputPin,
+    cdone: &mut FtdiInputPin,
+    bitstream: &[u8],
+) -> Result<(), FtdiSpiError> {
+    ss.set_low()?;
+    creset.set_low()?;
+    sleep(Duration::from_millis(1));
+    creset.set_high()?;
+    // Internal configuration memory is cleared within 1200us
+    sleep(Duration::from_millis(2));
+    // 8 dummy clocks with SPI_SS high
+    ss.set_high()?;
+    spi.write(&[0u8])?;
+    ss.set_low()?;
+    for chunk in bitstream.chunks(CHUNK_SIZE) {
+        spi.write(chunk)?;
+    }
+    ss.set_high()?;
+    // CDONE rises within 49 clocks, then at least 100 clocks to start user I/O
+    spi.write(&[0u8; 7])?;
+    if !cdone.is_high()? {
+        return Err(FtdiError::FpgaConfigFailed("CDONE not set".into()).into());
+    }
+    spi.write(&[0u8; 13])?;
+    Ok(())
+}
+
+/// Loads IR and returns the captured IR value, ends in Run-Test/Idle.
+fn shift_ir(jtag: &mut FtdiJtag, ir: u8, len: usize) -> Result<u8, FtdiError> {
+    let capture = jtag.shift_ir_raw(&[ir], len, true)?;
+    jtag.update()?;
+    Ok(capture[0])
+}
+
+/// Shifts a short DR value and returns the captured bits, ends in Run-Test/Idle.
+fn shift_dr(jtag: &mut FtdiJtag, dr: &[u8], len: usize) -> Result<Vec<u8>, FtdiError> {
+    let capture = jtag.shift_dr_raw(dr, len, true)?;
+    jtag.update()?;
+    Ok(capture)
+}
+
+/// Streams a configuration bitstream through DR, ends in Run-Test/Idle.
+///
+/// Bitstreams are sent MSB first per byte while JTAG shifts LSB first.
+fn shift_dr_bitstream(jtag: &mut FtdiJtag, bitstream: &[u8]) -> Result<(), FtdiError> {
+    let chunks = bitstream.len().div_ceil(CHUNK_SIZE);
+    for (idx, chunk) in bitstream.chunks(CHUNK_SIZE).enumerate() {
+        let data: Vec<u8> = chunk.iter().map(|byte| byte.reverse_bits()).collect();
+        jtag.shift_dr_raw(&data, data.len() * 8, idx + 1 == chunks)?;
+        log::debug!(
+            "Bitstream {}/{} bytes",
+            idx * CHUNK_SIZE + chunk.len(),
+            bitstream.len()
+        );
+    }
+    jtag.update()
+}
+
+#[cfg(test)]
+mod test {
+    use super::xilinx_bit_payload;
+
+    #[test]
+    fn bit_header() {
+        let mut bit = vec![
+            0x00, 0x09, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x00, 0x00, 0x01,
+        ];
+        bit.extend_from_slice(b"a\x00\x04top\x00");
+        bit.extend_from_slice(b"b\x00\x0c7a35tcpg236\x00");
+        bit.extend_from_slice(b"e\x00\x00\x00\x04");
+        bit.extend_from_slice(&[0xAA, 0x99, 0x55, 0x66]);
+        assert_eq!(xilinx_bit_payload(&bit).unwrap(), [0xAA, 0x99, 0x55, 0x66]);
+        let bin = [0xFF, 0xFF, 0xAA, 0x99];
+        assert_eq!(xilinx_bit_payload(&bin).unwrap(), bin);
+    }
+}
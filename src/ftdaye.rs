@@ -1,7 +1,30 @@
-use crate::{FtdiError, Interface};
-use futures_lite::future::{block_on, zip};
-use nusb::transfer::{Control, ControlType, Recipient, RequestBuffer};
-use std::time::Duration;
+use crate::{
+    FtdiError, Interface, mpsse_cmd,
+    stats::{Event, Stats, StatsCell},
+};
+use futures_lite::future::{block_on, or, zip};
+use nusb::transfer::{Control, ControlType, Queue, Recipient, RequestBuffer, TransferError};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    sync::{
+        Condvar, Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Bulk requests kept in flight at the same time in each direction.
+const QUEUE_DEPTH: usize = 4;
+/// Largest bulk IN request, it completes early on the first short packet.
+const IN_TRANSFER_SIZE: usize = 0x4000;
+/// Largest data of one bulk OUT transfer, the OS splits it into packets.
+const OUT_TRANSFER_SIZE: usize = 0x1_0000;
+/// Commands of [`FtdiContext::pipelined`] in flight at the same time.
+const PIPELINE_DEPTH: usize = 2;
 
 #[repr(C)]
 #[expect(unused)]
@@ -24,6 +47,14 @@ pub(crate) struct FtdiContext {
     /// FTDI device interface
     interface: Interface,
     max_packet_size: usize,
+    /// Bulk IN requests, completed in submission order
+    read_queue: RefCell<Queue<RequestBuffer>>,
+    /// Bulk OUT requests, completed in submission order
+    write_queue: RefCell<Queue<Vec<u8>>>,
+    /// Recovery events of this interface
+    stats: StatsCell,
+    /// Set once a transfer found the device unplugged
+    gone: Cell<bool>,
 }
 
 impl FtdiContext {
@@ -33,36 +64,231 @@ impl FtdiContext {
         max_packet_size: usize,
     ) -> Self {
         Self {
+            read_queue: RefCell::new(handle.bulk_in_queue(interface.read_ep())),
+            write_queue: RefCell::new(handle.bulk_out_queue(interface.write_ep())),
             handle,
             interface,
             max_packet_size,
+            stats: StatsCell::default(),
+            gone: Cell::new(false),
+        }
+    }
+    /// Runs `f` unless the device is already gone, and remembers when it finds it gone.
+    ///
+    /// Without this every call would wait for its own transfer to fail after an unplug.
+    fn transfer<T>(&self, f: impl FnOnce() -> Result<T, FtdiError>) -> Result<T, FtdiError> {
+        if self.gone.get() {
+            return Err(FtdiError::DeviceGone);
         }
+        let result = f();
+        if let Err(FtdiError::DeviceGone) = result {
+            self.gone.set(true);
+        }
+        result
+    }
+    /// Whether a transfer found the device unplugged
+    pub(crate) fn is_gone(&self) -> bool {
+        self.gone.get()
+    }
+    pub(crate) fn record(&self, event: Event) {
+        self.stats.record(event);
     }
-    pub(crate) fn into_mpsse(mut self, mask: u8) -> Result<Self, FtdiError> {
+    pub(crate) fn stats(&self) -> Stats {
+        self.stats.get()
+    }
+    pub(crate) fn reset_stats(&self) {
+        self.stats.reset();
+    }
+    pub(crate) fn into_mpsse(mut self, mask: u8, latency_timer: u8) -> Result<Self, FtdiError> {
+        self.init_mpsse(mask, latency_timer)?;
+        Ok(self)
+    }
+    fn init_mpsse(&mut self, mask: u8, latency_timer: u8) -> Result<(), FtdiError> {
         self.usb_reset()?;
         self.usb_purge_buffers()?;
-        self.set_latency_timer(16)?;
-        self.set_bitmode(mask, BitMode::Mpsse)?;
+        self.set_latency_timer(latency_timer)?;
+        self.set_bitmode(mask, BitMode::Mpsse)
+    }
+    /// Switches to a new handle of the same interface after a replug, the stats are kept.
+    pub(crate) fn reopen_mpsse(
+        &mut self,
+        handle: nusb::Interface,
+        mask: u8,
+        latency_timer: u8,
+    ) -> Result<(), FtdiError> {
+        self.read_queue = RefCell::new(handle.bulk_in_queue(self.interface.read_ep()));
+        self.write_queue = RefCell::new(handle.bulk_out_queue(self.interface.write_ep()));
+        self.handle = handle;
+        self.gone.set(false);
+        self.init_mpsse(mask, latency_timer)
+    }
+    pub(crate) fn into_uart(mut self) -> Result<Self, FtdiError> {
+        // 8 data bits, no parity, 1 stop bit
+        const SIO_SET_DATA_8N1: u16 = 8;
+
+        self.usb_reset()?;
+        self.usb_purge_buffers()?;
+        // Short latency so that small responses are not held back
+        self.set_latency_timer(2)?;
+        self.set_bitmode(0, BitMode::Reset)?;
+        self.set_line_property(SIO_SET_DATA_8N1)?;
         Ok(self)
     }
+    /// Data bits in bits 0-7, parity in bits 8-10, stop bits in bits 11-13, break in bit 14.
+    pub(crate) fn set_line_property(&mut self, value: u16) -> Result<(), FtdiError> {
+        const SIO_SET_DATA_REQUEST: u8 = 0x04;
+
+        self.sio_write(SIO_SET_DATA_REQUEST, value)
+    }
+    /// Baud rate divisor as encoded in AN232B-05, the upper bits go to the high byte of index.
+    pub(crate) fn set_baud_divisor(&mut self, encoded: u32) -> Result<(), FtdiError> {
+        const SIO_SET_BAUDRATE_REQUEST: u8 = 0x03;
+
+        let index = (((encoded >> 16) as u16) << 8) | self.interface.index();
+        self.sio_write_index(SIO_SET_BAUDRATE_REQUEST, encoded as u16, index)
+    }
+    /// Drives DTR and RTS, `true` asserts the line which is a low level.
+    pub(crate) fn set_modem_ctrl(
+        &mut self,
+        dtr: Option<bool>,
+        rts: Option<bool>,
+    ) -> Result<(), FtdiError> {
+        const SIO_SET_MODEM_CTRL_REQUEST: u8 = 0x01;
+        const DTR: u16 = 1 << 0;
+        const RTS: u16 = 1 << 1;
+
+        let mut value = 0;
+        for (line, state) in [(DTR, dtr), (RTS, rts)] {
+            if let Some(state) = state {
+                // high byte selects the lines to change
+                value |= line << 8;
+                if state {
+                    value |= line;
+                }
+            }
+        }
+        self.sio_write(SIO_SET_MODEM_CTRL_REQUEST, value)
+    }
+    /// Discards whatever is left in both chip buffers.
+    pub(crate) fn purge(&mut self) -> Result<(), FtdiError> {
+        self.usb_purge_buffers()
+    }
+    /// Flow control mode in the high byte of index, XON/XOFF characters in value.
+    pub(crate) fn set_flow_ctrl(&mut self, mode: u8, value: u16) -> Result<(), FtdiError> {
+        const SIO_SET_FLOW_CTRL_REQUEST: u8 = 0x02;
+
+        let index = ((mode as u16) << 8) | self.interface.index();
+        self.sio_write_index(SIO_SET_FLOW_CTRL_REQUEST, value, index)
+    }
+    /// A received `char` sends the buffered data at once instead of at the latency timer.
+    pub(crate) fn set_event_char(&mut self, char: Option<u8>) -> Result<(), FtdiError> {
+        const SIO_SET_EVENT_CHAR_REQUEST: u8 = 0x06;
+
+        self.sio_write(SIO_SET_EVENT_CHAR_REQUEST, Self::special_char(char))
+    }
+    /// Characters received with a parity error are replaced by `char`.
+    pub(crate) fn set_error_char(&mut self, char: Option<u8>) -> Result<(), FtdiError> {
+        const SIO_SET_ERROR_CHAR_REQUEST: u8 = 0x07;
+
+        self.sio_write(SIO_SET_ERROR_CHAR_REQUEST, Self::special_char(char))
+    }
+    /// Character in the low byte, enable in bit 8.
+    fn special_char(char: Option<u8>) -> u16 {
+        match char {
+            Some(char) => 0x100 | char as u16,
+            None => 0,
+        }
+    }
+    /// Modem status byte followed by line status byte, as in the IN packet headers.
+    pub(crate) fn poll_modem_status(&self) -> Result<[u8; 2], FtdiError> {
+        const SIO_POLL_MODEM_STATUS_REQUEST: u8 = 0x05;
+
+        let mut buf = [0; 2];
+        let len = self.transfer(|| {
+            Ok(self.handle.control_in_blocking(
+                Control {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Device,
+                    request: SIO_POLL_MODEM_STATUS_REQUEST,
+                    value: 0,
+                    index: self.interface.index(),
+                },
+                &mut buf,
+                Duration::from_secs(1),
+            )?)
+        })?;
+        if len != buf.len() {
+            return Err(FtdiError::Other("Modem status length not correct"));
+        }
+        Ok(buf)
+    }
+    pub(crate) fn purge_rx(&mut self) -> Result<(), FtdiError> {
+        self.record(Event::Purge);
+        self.usb_purge_rx_buffer()
+    }
     fn sio_write(&mut self, request: u8, value: u16) -> Result<(), FtdiError> {
-        self.handle
-            .control_out_blocking(
+        self.sio_write_index(request, value, self.interface.index())
+    }
+    fn sio_write_index(&mut self, request: u8, value: u16, index: u16) -> Result<(), FtdiError> {
+        self.transfer(|| {
+            Ok(self.handle.control_out_blocking(
                 Control {
                     control_type: ControlType::Vendor,
                     recipient: Recipient::Device,
                     request,
                     value,
-                    index: self.interface.index(),
+                    index,
                 },
                 &[],
                 Duration::from_secs(1),
-            )
-            .map_err(std::io::Error::from)?;
+            )?)
+        })?;
 
         Ok(())
     }
 
+    pub(crate) fn read_eeprom_word(&self, addr: u16) -> Result<u16, FtdiError> {
+        const SIO_READ_EEPROM_REQUEST: u8 = 0x90;
+
+        let mut buf = [0; 2];
+        let len = self.transfer(|| {
+            Ok(self.handle.control_in_blocking(
+                Control {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Device,
+                    request: SIO_READ_EEPROM_REQUEST,
+                    value: 0,
+                    index: addr,
+                },
+                &mut buf,
+                Duration::from_secs(1),
+            )?)
+        })?;
+        if len != buf.len() {
+            return Err(FtdiError::Other("Eeprom read length not correct"));
+        }
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub(crate) fn write_eeprom_word(&self, addr: u16, value: u16) -> Result<(), FtdiError> {
+        const SIO_WRITE_EEPROM_REQUEST: u8 = 0x91;
+
+        self.transfer(|| {
+            Ok(self.handle.control_out_blocking(
+                Control {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Device,
+                    request: SIO_WRITE_EEPROM_REQUEST,
+                    value,
+                    index: addr,
+                },
+                &[],
+                Duration::from_secs(1),
+            )?)
+        })?;
+        Ok(())
+    }
+
     fn usb_reset(&mut self) -> Result<(), FtdiError> {
         const SIO_RESET_REQUEST: u8 = 0;
         const SIO_RESET_SIO: u16 = 0;
@@ -88,6 +314,7 @@ impl FtdiContext {
     }
 
     fn usb_purge_buffers(&mut self) -> Result<(), FtdiError> {
+        self.record(Event::Purge);
         self.usb_purge_tx_buffer()?;
         self.usb_purge_rx_buffer()?;
 
@@ -110,50 +337,456 @@ impl FtdiContext {
 
         Ok(())
     }
-    pub(crate) async fn async_write(&self, data: Vec<u8>) -> Result<(), FtdiError> {
-        self.handle
-            .bulk_out(self.interface.write_ep(), data)
-            .await
-            .into_result()
-            .map_err(std::io::Error::from)?;
-        Ok(())
+    /// Bulk IN stream with its own requests, for reading from another thread.
+    ///
+    /// Do not mix it with [`FtdiContext::write_read`], both would take each other's responses.
+    pub(crate) fn bulk_reader(&self) -> BulkReader {
+        BulkReader {
+            queue: self.handle.bulk_in_queue(self.interface.read_ep()),
+            max_packet_size: self.max_packet_size,
+        }
+    }
+    /// Bulk OUT stream with its own requests, for writing from another thread.
+    pub(crate) fn bulk_writer(&self) -> BulkWriter {
+        BulkWriter {
+            queue: self.handle.bulk_out_queue(self.interface.write_ep()),
+        }
     }
-    pub(crate) async fn async_read(&self, data: &mut [u8]) -> Result<(), FtdiError> {
+    pub(crate) fn write(&self, data: Vec<u8>) -> Result<(), FtdiError> {
+        self.transfer(|| {
+            let mut queue = self.write_queue.borrow_mut();
+            block_on(Self::queued_write(
+                &mut queue,
+                data.chunks(OUT_TRANSFER_SIZE),
+            ))
+        })
+    }
+    /// Writes `write` and fills `read`, giving up with [`FtdiError::Timeout`] at `deadline`.
+    ///
+    /// After a timeout the chip may still hold part of the commands and the response,
+    /// purge it before it is used again.
+    pub(crate) fn write_read(
+        &self,
+        write: &[u8],
+        read: &mut [u8],
+        deadline: Option<Instant>,
+    ) -> Result<(), FtdiError> {
+        self.transfer(|| self.queued_write_read(write, read, deadline))
+    }
+    fn queued_write_read(
+        &self,
+        write: &[u8],
+        read: &mut [u8],
+        deadline: Option<Instant>,
+    ) -> Result<(), FtdiError> {
+        let mut write_queue = self.write_queue.borrow_mut();
+        let mut read_queue = self.read_queue.borrow_mut();
+        let transfer = async {
+            Some(
+                zip(
+                    // A failed transfer never leaves half a command in the chip
+                    Self::queued_write(
+                        &mut write_queue,
+                        mpsse_cmd::split_commands(write, OUT_TRANSFER_SIZE).into_iter(),
+                    ),
+                    Self::queued_read(&mut read_queue, self.max_packet_size, read),
+                )
+                .await,
+            )
+        };
+        let timer = async {
+            match deadline {
+                Some(deadline) => {
+                    wait_until(deadline).await;
+                    None
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let Some((write_result, read_result)) = block_on(or(transfer, timer)) else {
+            // Abandoned requests must not complete into the next transfer
+            write_queue.cancel_all();
+            while write_queue.pending() > 0 {
+                let _ = block_on(write_queue.next_complete());
+            }
+            read_queue.cancel_all();
+            while read_queue.pending() > 0 {
+                let _ = block_on(read_queue.next_complete());
+            }
+            self.record(Event::Timeout);
+            return Err(FtdiError::Timeout);
+        };
+        write_result?;
+        if let Err(FtdiError::BadMpsseCommand(_)) = read_result {
+            self.record(Event::BadCommand);
+        }
+        read_result
+    }
+    /// Sends the commands `next` yields and passes their responses to `done` in order.
+    ///
+    /// `next` returns the command and the length of its response. The following command is
+    /// already queued on bulk OUT while the response of the previous one is read, so the
+    /// chip does not idle for a USB round trip between them.
+    pub(crate) fn pipelined<E: From<FtdiError>>(
+        &self,
+        mut next: impl FnMut() -> Result<Option<(Vec<u8>, usize)>, E>,
+        mut done: impl FnMut(Vec<u8>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.gone.get() {
+            return Err(FtdiError::DeviceGone.into());
+        }
+        let mut write_queue = self.write_queue.borrow_mut();
+        let mut read_queue = self.read_queue.borrow_mut();
+        // OUT transfers and response length of each command in flight, oldest first
+        let mut in_flight = VecDeque::new();
+        // Response data not claimed by a command yet, the next one may already have started
+        let mut received = VecDeque::new();
+        let mut exhausted = false;
+        let mut run = || -> Result<(), E> {
+            loop {
+                while !exhausted && in_flight.len() < PIPELINE_DEPTH {
+                    let Some((cmd, read_len)) = next()? else {
+                        exhausted = true;
+                        break;
+                    };
+                    let chunks = mpsse_cmd::split_commands(&cmd, OUT_TRANSFER_SIZE);
+                    in_flight.push_back((chunks.len(), read_len));
+                    for chunk in chunks {
+                        write_queue.submit(chunk.to_vec());
+                    }
+                }
+                let Some((transfers, read_len)) = in_flight.pop_front() else {
+                    return Ok(());
+                };
+                let response = self.transfer(|| {
+                    while received.len() < read_len {
+                        while read_queue.pending() < QUEUE_DEPTH {
+                            read_queue.submit(RequestBuffer::new(IN_TRANSFER_SIZE));
+                        }
+                        let response = block_on(read_queue.next_complete()).into_result()?;
+                        Self::parse_packets(&response, self.max_packet_size, |_, data| {
+                            received.extend(data)
+                        })
+                        .inspect_err(|e| {
+                            if let FtdiError::BadMpsseCommand(_) = e {
+                                self.record(Event::BadCommand);
+                            }
+                        })?;
+                    }
+                    for _ in 0..transfers {
+                        block_on(write_queue.next_complete()).into_result()?;
+                    }
+                    Ok(received.drain(..read_len).collect())
+                })?;
+                done(response)?;
+            }
+        };
+        let result = run();
+        if result.is_ok() && !received.is_empty() {
+            log::warn!("Discard {} unexpected bytes", received.len());
+        }
+        // Abandoned requests must not complete into the next transfer
+        write_queue.cancel_all();
+        while write_queue.pending() > 0 {
+            let _ = block_on(write_queue.next_complete());
+        }
+        read_queue.cancel_all();
+        while read_queue.pending() > 0 {
+            let _ = block_on(read_queue.next_complete());
+        }
+        result
+    }
+    /// Submits one transfer per chunk, with several of them in flight at once.
+    async fn queued_write<'a>(
+        queue: &mut Queue<Vec<u8>>,
+        mut chunks: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<(), FtdiError> {
+        let mut result = Ok(());
+        loop {
+            while queue.pending() < QUEUE_DEPTH {
+                let Some(chunk) = chunks.next() else {
+                    break;
+                };
+                queue.submit(chunk.to_vec());
+            }
+            if queue.pending() == 0 {
+                break;
+            }
+            if let Err(e) = queue.next_complete().await.into_result() {
+                // Later chunks would leave the command stream torn apart
+                queue.cancel_all();
+                while queue.pending() > 0 {
+                    let _ = queue.next_complete().await;
+                }
+                result = Err(e.into());
+                break;
+            }
+        }
+        result
+    }
+    /// Keeps up to `QUEUE_DEPTH` IN requests in flight until `data` is filled.
+    ///
+    /// Each request spans several packets, every packet starts with two modem status bytes.
+    async fn queued_read(
+        queue: &mut Queue<RequestBuffer>,
+        max_packet_size: usize,
+        data: &mut [u8],
+    ) -> Result<(), FtdiError> {
+        let payload = max_packet_size - 2;
+        let max_packets = IN_TRANSFER_SIZE / max_packet_size;
+        // Payload capacity of the requests in flight, oldest first
+        let mut in_flight = VecDeque::new();
         let mut read_len = 0;
+        let mut result = Ok(());
         while read_len < data.len() {
-            let result = self
-                .handle
-                .bulk_in(
-                    self.interface.read_ep(),
-                    RequestBuffer::new(self.max_packet_size),
-                )
-                .await
-                .into_result()
-                .map_err(std::io::Error::from)?;
-            if result.len() < 2 {
+            // Short packets may need more requests than this estimate
+            while queue.pending() < QUEUE_DEPTH {
+                let expected = read_len + in_flight.iter().sum::<usize>();
+                if expected >= data.len() {
+                    break;
+                }
+                let packets = (data.len() - expected).div_ceil(payload).min(max_packets);
+                queue.submit(RequestBuffer::new(packets * max_packet_size));
+                in_flight.push_back(packets * payload);
+            }
+            let response = queue.next_complete().await.into_result();
+            in_flight.pop_front();
+            let response = match response {
+                Ok(response) => response,
+                Err(e) => {
+                    result = Err(e.into());
+                    break;
+                }
+            };
+            if let Err(e) = Self::parse_packets(&response, max_packet_size, |_, packet_data| {
+                let len = packet_data.len().min(data.len() - read_len);
+                data[read_len..read_len + len].copy_from_slice(&packet_data[..len]);
+                read_len += len;
+            }) {
+                result = Err(e);
+                break;
+            }
+        }
+        // Requests still in flight only carry status bytes once all data has arrived
+        queue.cancel_all();
+        while queue.pending() > 0 {
+            if let Ok(response) = queue.next_complete().await.into_result() {
+                let mut unexpected = 0;
+                let _ = Self::parse_packets(&response, max_packet_size, |_, packet_data| {
+                    unexpected += packet_data.len()
+                });
+                if unexpected > 0 {
+                    log::warn!("Discard {unexpected} unexpected bytes");
+                }
+            }
+        }
+        result
+    }
+    /// Strips the status bytes of every packet in a multi-packet response.
+    ///
+    /// `f` gets the line status byte and the data of each packet.
+    fn parse_packets(
+        response: &[u8],
+        max_packet_size: usize,
+        mut f: impl FnMut(u8, &[u8]),
+    ) -> Result<(), FtdiError> {
+        for packet in response.chunks(max_packet_size) {
+            if packet.len() < 2 {
                 return Err(FtdiError::Other("Usb bulkin length not correct"));
             }
-            let (response_status, response_data) = result.split_at(2);
+            let (response_status, response_data) = packet.split_at(2);
             if response_status[0] == 0xFA {
                 return Err(FtdiError::BadMpsseCommand(response_status[1]));
             }
-            let (_, read_buf) = data.split_at_mut(read_len);
-            let (read_buf, _) = read_buf.split_at_mut(response_data.len());
-            read_buf.copy_from_slice(response_data);
-            read_len += response_data.len()
+            f(response_status[1], response_data);
         }
         Ok(())
     }
-    pub(crate) async fn async_write_read(
-        &self,
-        write: Vec<u8>,
-        read: &mut [u8],
-    ) -> Result<(), FtdiError> {
-        let (write_result, read_result) = zip(self.async_write(write), self.async_read(read)).await;
-        write_result?;
-        read_result
+}
+
+impl From<TransferError> for FtdiError {
+    fn from(e: TransferError) -> Self {
+        match e {
+            TransferError::Disconnected => FtdiError::DeviceGone,
+            e => FtdiError::Usb(e.into()),
+        }
     }
-    pub(crate) fn write_read(&self, write: Vec<u8>, read: &mut [u8]) -> Result<(), FtdiError> {
-        block_on(self.async_write_read(write, read))
+}
+
+/// Data of one bulk IN response
+pub(crate) struct RxChunk {
+    pub(crate) data: Vec<u8>,
+    /// Data length up to the end of each packet that reported receive errors, and its line status
+    pub(crate) errors: Vec<(usize, u8)>,
+}
+
+pub(crate) struct BulkReader {
+    queue: Queue<RequestBuffer>,
+    max_packet_size: usize,
+}
+impl BulkReader {
+    /// Waits for the next response and returns its data, possibly nothing.
+    ///
+    /// The chip answers every latency timer period, so this never waits longer
+    /// than that, even when no data has arrived.
+    pub(crate) fn next(&mut self) -> Result<RxChunk, FtdiError> {
+        // Overrun, parity error, framing error and break
+        const LINE_RX_ERRORS: u8 = 0x1E;
+
+        while self.queue.pending() < QUEUE_DEPTH {
+            self.queue.submit(RequestBuffer::new(IN_TRANSFER_SIZE));
+        }
+        let response = block_on(self.queue.next_complete()).into_result()?;
+        let mut data = Vec::with_capacity(response.len());
+        let mut errors = Vec::new();
+        FtdiContext::parse_packets(&response, self.max_packet_size, |line, packet_data| {
+            data.extend_from_slice(packet_data);
+            if line & LINE_RX_ERRORS != 0 {
+                errors.push((data.len(), line));
+            }
+        })?;
+        Ok(RxChunk { data, errors })
+    }
+}
+impl Drop for BulkReader {
+    fn drop(&mut self) {
+        self.queue.cancel_all();
+        while self.queue.pending() > 0 {
+            let _ = block_on(self.queue.next_complete());
+        }
+    }
+}
+
+pub(crate) struct BulkWriter {
+    queue: Queue<Vec<u8>>,
+}
+impl BulkWriter {
+    /// Sends all of `data`, with several transfers in flight for large writes.
+    pub(crate) fn write(&mut self, data: &[u8]) -> Result<(), FtdiError> {
+        block_on(FtdiContext::queued_write(
+            &mut self.queue,
+            data.chunks(OUT_TRANSFER_SIZE),
+        ))
+    }
+}
+
+/// Completes at `deadline`, a thread shared by all timers wakes the task once it has passed.
+///
+/// Dropping the future before removes its deadline from the shared thread.
+pub(crate) fn wait_until(deadline: Instant) -> WaitUntil {
+    WaitUntil { deadline, id: None }
+}
+
+pub(crate) struct WaitUntil {
+    deadline: Instant,
+    /// Set while the deadline is registered with the timer thread
+    id: Option<u64>,
+}
+impl Future for WaitUntil {
+    type Output = ();
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            self.cancel();
+            return Poll::Ready(());
+        }
+        let timers = Timers::get();
+        let id = *self
+            .id
+            .get_or_insert_with(|| timers.next_id.fetch_add(1, Ordering::Relaxed));
+        timers
+            .entries
+            .lock()
+            .unwrap()
+            .insert((self.deadline, id), cx.waker().clone());
+        timers.changed.notify_one();
+        Poll::Pending
+    }
+}
+impl WaitUntil {
+    fn cancel(&mut self) {
+        if let Some(id) = self.id.take() {
+            Timers::get()
+                .entries
+                .lock()
+                .unwrap()
+                .remove(&(self.deadline, id));
+        }
+    }
+}
+impl Drop for WaitUntil {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Deadlines of the pending [`WaitUntil`] futures
+struct Timers {
+    entries: Mutex<BTreeMap<(Instant, u64), Waker>>,
+    /// Signalled when an entry was added, it may be the earliest now
+    changed: Condvar,
+    next_id: AtomicU64,
+}
+impl Timers {
+    fn get() -> &'static Timers {
+        static TIMERS: OnceLock<Timers> = OnceLock::new();
+        TIMERS.get_or_init(|| {
+            thread::Builder::new()
+                .name("ftdi-timer".to_string())
+                .spawn(|| Timers::get().run())
+                .expect("failed to spawn the timer thread");
+            Timers {
+                entries: Mutex::new(BTreeMap::new()),
+                changed: Condvar::new(),
+                next_id: AtomicU64::new(0),
+            }
+        })
+    }
+    fn run(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            while let Some(entry) = entries.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                expired.push(entry.remove());
+            }
+            if !expired.is_empty() {
+                drop(entries);
+                expired.into_iter().for_each(Waker::wake);
+                entries = self.entries.lock().unwrap();
+                continue;
+            }
+            entries = match entries.keys().next() {
+                Some(&(deadline, _)) => {
+                    self.changed
+                        .wait_timeout(entries, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.changed.wait(entries).unwrap(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Timers, wait_until};
+    use futures_lite::future::{block_on, poll_once};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn timer_cancelled_on_drop() {
+        let far = Instant::now() + Duration::from_secs(3600);
+        assert_eq!(block_on(poll_once(wait_until(far))), None);
+        let entries = Timers::get().entries.lock().unwrap();
+        assert!(entries.keys().all(|&(deadline, _)| deadline != far));
+        drop(entries);
+
+        let start = Instant::now();
+        block_on(wait_until(start + Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
     }
 }
@@ -3,9 +3,10 @@ use crate::{
     mpsse::{FtdiMpsse, PinUsage},
     mpsse_cmd::MpsseCmdBuilder,
 };
+use eh1::digital::PinState;
 use std::{
     ops::Deref,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, PoisonError},
 };
 
 pub(crate) struct UsedPin {
@@ -16,8 +17,11 @@ pub(crate) struct UsedPin {
 }
 impl Drop for UsedPin {
     fn drop(&mut self) {
-        let mut lock = self.mtx.lock().unwrap();
-        lock.free_pin(self.pin);
+        // Runs while unwinding too, so neither a poisoned lock nor a gone device may panic
+        let mut lock = self.mtx.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = lock.free_pin(self.pin) {
+            log::warn!("Failed to release {}: {e}", self.pin);
+        }
     }
 }
 impl Deref for UsedPin {
@@ -33,7 +37,7 @@ impl UsedPin {
         usage: PinUsage,
     ) -> Result<Self, FtdiError> {
         {
-            let mut lock = mtx.lock().unwrap();
+            let mut lock = FtdiMpsse::lock(&mtx)?;
             lock.alloc_pin(pin, usage)?;
         }
         Ok(Self { mtx, pin })
@@ -51,28 +55,60 @@ pub struct FtdiOutputPin {
 }
 
 impl FtdiOutputPin {
+    /// Drives the pin with the level it was last left at, usually low
     pub fn new(mtx: Arc<Mutex<FtdiMpsse>>, pin: Pin) -> Result<Self, FtdiError> {
+        Self::open(mtx, pin, None)
+    }
+    /// Drives the pin with `state` from the start, without a glitch to the previous level
+    pub fn new_with_state(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        pin: Pin,
+        state: PinState,
+    ) -> Result<Self, FtdiError> {
+        Self::open(mtx, pin, Some(state))
+    }
+    fn open(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        pin: Pin,
+        state: Option<PinState>,
+    ) -> Result<Self, FtdiError> {
         let this = Self {
             mtx: mtx.clone(),
             pin: UsedPin::new(mtx.clone(), pin, PinUsage::Output)?,
         };
         {
-            let mut lock = mtx.lock().unwrap();
+            let mut lock = FtdiMpsse::lock(&mtx)?;
             let mut cmd = MpsseCmdBuilder::new();
-            match pin {
-                Pin::Lower(_) => {
-                    lock.lower.direction |= pin.mask();
-                    cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
-                }
-                Pin::Upper(_) => {
-                    lock.upper.direction |= pin.mask();
-                    cmd.set_gpio_upper(lock.upper.value, lock.upper.direction);
-                }
+            let bank = match pin {
+                Pin::Lower(_) => &mut lock.lower,
+                Pin::Upper(_) => &mut lock.upper,
+            };
+            match state {
+                Some(PinState::High) => bank.value |= pin.mask(),
+                Some(PinState::Low) => bank.value &= !pin.mask(),
+                None => {}
             }
+            bank.direction |= pin.mask();
+            match pin {
+                Pin::Lower(_) => cmd.set_gpio_lower(lock.lower.value, lock.lower.direction),
+                Pin::Upper(_) => cmd.set_gpio_upper(lock.upper.value, lock.upper.direction),
+            };
             lock.exec(cmd)?;
         }
         Ok(this)
     }
+    /// Drives several output pins of the same FTDI interface at once
+    ///
+    /// See [`FtdiMpsse::set_pins`]. Fails with [`FtdiError::PinFault`] if the pins belong
+    /// to different interfaces.
+    pub fn set_all(pins: &[(&FtdiOutputPin, bool)]) -> Result<(), FtdiError> {
+        let Some((first, _)) = pins.first() else {
+            return Ok(());
+        };
+        same_interface(pins.iter().map(|(pin, _)| &pin.mtx))?;
+        let pins: Vec<(Pin, bool)> = pins.iter().map(|&(pin, state)| (*pin.pin, state)).collect();
+        FtdiMpsse::lock(&first.mtx)?.set_pins(&pins)
+    }
 }
 
 impl eh1::digital::Error for FtdiError {
@@ -87,7 +123,7 @@ impl eh1::digital::ErrorType for FtdiOutputPin {
 
 impl eh1::digital::OutputPin for FtdiOutputPin {
     fn set_low(&mut self) -> Result<(), FtdiError> {
-        let mut lock = self.mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
         match *self.pin {
             Pin::Lower(_) => {
@@ -105,7 +141,7 @@ impl eh1::digital::OutputPin for FtdiOutputPin {
     }
 
     fn set_high(&mut self) -> Result<(), FtdiError> {
-        let mut lock = self.mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
         match *self.pin {
             Pin::Lower(_) => {
@@ -139,7 +175,7 @@ impl FtdiInputPin {
             mtx: mtx.clone(),
             pin: UsedPin::new(mtx.clone(), pin, PinUsage::Input)?,
         };
-        let mut lock = mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
         match pin {
             Pin::Lower(_) => {
@@ -155,8 +191,17 @@ impl FtdiInputPin {
         Ok(this)
     }
 
+    /// Reads several input pins of the same FTDI interface with one USB round trip
+    pub fn read_all(pins: &[&FtdiInputPin]) -> Result<Vec<bool>, FtdiError> {
+        let Some(first) = pins.first() else {
+            return Ok(Vec::new());
+        };
+        same_interface(pins.iter().map(|pin| &pin.mtx))?;
+        let pins: Vec<Pin> = pins.iter().map(|pin| *pin.pin).collect();
+        FtdiMpsse::lock(&first.mtx)?.read_pins(&pins)
+    }
     pub(crate) fn get(&self) -> Result<bool, FtdiError> {
-        let lock = self.mtx.lock().unwrap();
+        let lock = FtdiMpsse::lock(&self.mtx)?;
 
         let mut cmd = MpsseCmdBuilder::new();
         match *self.pin {
@@ -182,3 +227,32 @@ impl eh1::digital::InputPin for FtdiInputPin {
         self.get().map(|res| !res)
     }
 }
+
+fn same_interface<'a, T: 'a>(mut mtxs: impl Iterator<Item = &'a Arc<T>>) -> Result<(), FtdiError> {
+    let Some(first) = mtxs.next() else {
+        return Ok(());
+    };
+    if !mtxs.all(|mtx| Arc::ptr_eq(mtx, first)) {
+        return Err(FtdiError::PinFault(
+            "pins must belong to the same interface".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::same_interface;
+    use crate::FtdiError;
+    use std::sync::Arc;
+
+    #[test]
+    fn mixed_interfaces() {
+        let (a, b) = (Arc::new(0u8), Arc::new(0u8));
+        assert!(same_interface([&a, &a.clone()].into_iter()).is_ok());
+        assert!(matches!(
+            same_interface([&a, &b].into_iter()),
+            Err(FtdiError::PinFault(_))
+        ));
+    }
+}
@@ -0,0 +1,110 @@
+//! Heartbeat for external hardware watchdogs.
+//!
+//! A background thread toggles an output pin, a watchdog on the rig resets or powers down
+//! the target once the edges stop. They stop when the process dies, and with
+//! [`Heartbeat::start_supervised`] also when the application stops calling [`Heartbeat::kick`],
+//! e.g. because its main loop hangs.
+use crate::{FtdiError, gpio::FtdiOutputPin};
+use eh1::digital::{OutputPin, PinState};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Toggles a pin from a background thread, see the [module docs](self)
+///
+/// Dropping it stops the toggling and leaves the pin low.
+pub struct Heartbeat {
+    stop: Arc<AtomicBool>,
+    /// When [`Heartbeat::kick`] was last called
+    last_kick: Arc<Mutex<Instant>>,
+    thread: Option<JoinHandle<Result<FtdiOutputPin, FtdiError>>>,
+}
+
+impl Heartbeat {
+    /// Toggles `pin` for as long as the process runs, `period` is one full low-high cycle
+    pub fn start(pin: FtdiOutputPin, period: Duration) -> Self {
+        Self::spawn(pin, period, None)
+    }
+    /// Like [`Heartbeat::start`], but pauses while [`Heartbeat::kick`] was not called within `timeout`
+    pub fn start_supervised(pin: FtdiOutputPin, period: Duration, timeout: Duration) -> Self {
+        Self::spawn(pin, period, Some(timeout))
+    }
+    fn spawn(mut pin: FtdiOutputPin, period: Duration, timeout: Option<Duration>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let last_kick = Arc::new(Mutex::new(Instant::now()));
+        let thread = {
+            let stop = stop.clone();
+            let last_kick = last_kick.clone();
+            thread::spawn(move || {
+                let mut level = PinState::Low;
+                let mut healthy = true;
+                while !stop.load(Ordering::Relaxed) {
+                    let elapsed = last_kick.lock().unwrap().elapsed();
+                    if timeout.is_some_and(|timeout| elapsed > timeout) {
+                        if healthy {
+                            log::warn!("Heartbeat paused, no kick for {elapsed:?}");
+                        }
+                        healthy = false;
+                    } else {
+                        healthy = true;
+                        level = !level;
+                        pin.set_state(level)?;
+                    }
+                    // Unparked early by stop
+                    thread::park_timeout(period / 2);
+                }
+                Ok(pin)
+            })
+        };
+        Self {
+            stop,
+            last_kick,
+            thread: Some(thread),
+        }
+    }
+    /// Tells a supervised heartbeat that the application is still alive
+    pub fn kick(&self) {
+        *self.last_kick.lock().unwrap() = Instant::now();
+    }
+    /// Whether the thread still runs, it ends early when driving the pin failed
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+    /// Stops toggling, drives the pin low and returns it
+    ///
+    /// Fails with the error that ended the heartbeat early, if any.
+    pub fn stop(mut self) -> Result<FtdiOutputPin, FtdiError> {
+        self.stop.store(true, Ordering::Relaxed);
+        let thread = self.thread.take().unwrap();
+        thread.thread().unpark();
+        let mut pin = thread.join().expect("heartbeat thread panicked")?;
+        pin.set_low()?;
+        Ok(pin)
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.stop.store(true, Ordering::Relaxed);
+        thread.thread().unpark();
+        match thread.join() {
+            Ok(Ok(mut pin)) => {
+                if let Err(e) = pin.set_low() {
+                    log::warn!("Failed to drive the heartbeat pin low: {e}");
+                }
+            }
+            Ok(Err(e)) => log::warn!("Heartbeat ended with: {e}"),
+            Err(_) => log::warn!("Heartbeat thread panicked"),
+        }
+    }
+}
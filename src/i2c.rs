@@ -1,12 +1,20 @@
 use self::cmd::I2cCmdBuilder;
 use crate::{
-    ChipType, FtdiError, Pin,
+    FtdiError, Pin,
     gpio::UsedPin,
     mpsse::{FtdiMpsse, PinUsage},
     mpsse_cmd::MpsseCmdBuilder,
+    stats::Event,
 };
 use eh1::i2c::{ErrorKind, NoAcknowledgeSource, Operation, SevenBitAddress};
-use std::sync::{Arc, Mutex};
+use std::{
+    ops::Deref,
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum FtdiI2cError {
@@ -14,65 +22,331 @@ pub enum FtdiI2cError {
     FtdiInner(#[from] FtdiError),
     #[error("Slave not ack.")]
     NoAck(NoAcknowledgeSource),
+    #[error("Bus stuck, transaction timed out")]
+    /// The transaction hit the timeout set by [`FtdiI2c::set_timeout`], the bus was recovered.
+    BusStuck,
+    #[error("Bus busy, SCL or SDA held low before the start condition")]
+    /// The check set by [`FtdiI2c::set_busy_check`] found the bus in use, nothing was sent.
+    BusBusy,
 }
-/// Inter-Integrated Circuit (I2C) master controller using FTDI MPSSE
+/// Access used by [`FtdiI2c::scan`] to probe an address
 ///
-/// Implements I2C bus communication with support for start/stop conditions and clock stretching
-pub struct FtdiI2c {
+/// Also reports which access was acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2cProbe {
+    /// 1-byte read, the byte is not acknowledged so the device lets go of SDA before the stop
+    Read,
+    /// Zero-length write, free of side effects for most devices
+    Write,
+    /// Both accesses
+    Both,
+}
+const GENERAL_CALL_ADDR: u8 = 0x00;
+/// Second byte of a general call asking devices to reset
+const GENERAL_CALL_RESET: u8 = 0x06;
+/// Reserved address 1111 100x of the Device ID protocol
+const DEVICE_ID_ADDR: u8 = 0x7C;
+
+/// Answer of the Device ID protocol, see [`FtdiI2c::device_id`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2cDeviceId {
+    /// 12-bit manufacturer code assigned by NXP
+    pub manufacturer: u16,
+    /// 9-bit part identification
+    pub part: u16,
+    /// 3-bit die revision
+    pub revision: u8,
+}
+impl I2cDeviceId {
+    fn from_bytes(bytes: [u8; 3]) -> Self {
+        let id = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        Self {
+            manufacturer: (id >> 12) as u16,
+            part: ((id >> 3) & 0x1FF) as u16,
+            revision: (id & 0x7) as u8,
+        }
+    }
+}
+
+/// SCL waveform of data and ACK bits, see [`FtdiI2c::set_scl_timing`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SclTiming {
+    /// SCL frequency in Hertz
+    pub frequency: usize,
+    /// SCL low time of one bit
+    pub low: Duration,
+    /// SCL high time of one bit
+    pub high: Duration,
+}
+
+/// Settings of an [`FtdiI2c`] handle, applied once by [`FtdiI2c::new`]
+///
+/// The defaults are 100 kHz, one transaction per command batch, 3 commands per
+/// start and stop condition, bits shifted by the MPSSE, no direction pin, no timeout
+/// and no bus-busy check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2cConfig {
+    frequency: usize,
+    fast: bool,
+    start_stop_cmds: usize,
+    scl_holds: Option<(usize, usize)>,
+    direction_pin: Option<Pin>,
+    timeout: Option<Duration>,
+    busy_check: Option<Duration>,
+}
+impl Default for I2cConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 100_000,
+            fast: false,
+            start_stop_cmds: 3,
+            scl_holds: None,
+            direction_pin: None,
+            timeout: None,
+            busy_check: None,
+        }
+    }
+}
+impl I2cConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// SCL frequency in Hertz, see [`FtdiI2c::set_frequency`]
+    pub fn frequency(mut self, frequency_hz: usize) -> Self {
+        self.frequency = frequency_hz;
+        self
+    }
+    /// Sends a whole transaction in one command batch and checks the ACKs afterwards
+    ///
+    /// Much faster, but a NACKed byte does not stop the transaction early.
+    pub fn fast(mut self, enable: bool) -> Self {
+        self.fast = enable;
+        self
+    }
+    /// MPSSE commands per start, repeated start and stop condition, more make them longer
+    pub fn start_stop_len(mut self, start_stop_cmds: usize) -> Self {
+        self.start_stop_cmds = start_stop_cmds;
+        self
+    }
+    /// See [`FtdiI2c::set_scl_holds`]
+    pub fn scl_holds(mut self, low: usize, high: usize) -> Self {
+        self.scl_holds = Some((low, high));
+        self
+    }
+    /// Pin switching an external SDA buffer, shared by all handles of the bus
+    pub fn direction_pin(mut self, pin: Pin) -> Self {
+        self.direction_pin = Some(pin);
+        self
+    }
+    /// See [`FtdiI2c::set_timeout`]
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// See [`FtdiI2c::set_busy_check`]
+    pub fn busy_check(mut self, wait: Option<Duration>) -> Self {
+        self.busy_check = wait;
+        self
+    }
+}
+
+/// Phases of one bit period and how many of them SCL is low and high
+#[derive(Debug, Clone, Copy)]
+struct SclShape {
+    phases: u32,
+    low: u32,
+    high: u32,
+}
+impl SclShape {
+    const THREE_PHASE: Self = Self {
+        phases: 3,
+        low: 2,
+        high: 1,
+    };
+    const TWO_PHASE: Self = Self {
+        phases: 2,
+        low: 1,
+        high: 1,
+    };
+    /// Shortest period meeting both minimums
+    fn min_period(self, min_low: Duration, min_high: Duration) -> Duration {
+        let period = |min: Duration, share: u32| {
+            (min.as_nanos() * self.phases as u128).div_ceil(share as u128)
+        };
+        Duration::from_nanos(period(min_low, self.low).max(period(min_high, self.high)) as u64)
+    }
+}
+
+/// Pins of an I2C bus, shared by all [`FtdiI2c`] handles on it
+pub(crate) struct I2cBus {
     _pins: [UsedPin; 3],
     /// Thread-safe handle to FTDI MPSSE controller
     mtx: Arc<Mutex<FtdiMpsse>>,
-    /// Length of start, repeated start, and stop conditions in MPSSE commands
-    /// More commands increase the duration of these conditions
-    start_stop_cmds: usize,
     /// Optional direction pin for SDA line direction control (if used)
-    direction_pin: Option<UsedPin>,
-    enable_fast: bool,
+    direction_pin: OnceLock<UsedPin>,
+    /// MPSSE clock last set by one of the handles, 0 if unknown
+    mpsse_frequency: AtomicUsize,
+    /// Whether 3-phase clocking was already turned off again
+    closed: bool,
 }
 
-impl Drop for FtdiI2c {
-    fn drop(&mut self) {
-        let lock = self.mtx.lock().unwrap();
-        if lock.chip_type != ChipType::FT2232D {
+impl I2cBus {
+    /// Turns 3-phase clocking off, only the first call sends the command
+    fn close(&mut self) -> Result<(), FtdiError> {
+        if std::mem::replace(&mut self.closed, true) {
+            return Ok(());
+        }
+        let lock = self.mtx.lock().unwrap_or_else(PoisonError::into_inner);
+        if lock.chip_type.supports_three_phase() {
             let mut cmd = MpsseCmdBuilder::new();
             cmd.enable_3phase_data_clocking(false);
-            lock.exec(cmd).unwrap();
+            lock.exec(cmd)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for I2cBus {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::warn!("Failed to close the I2C bus: {e}");
         }
     }
 }
 
+/// Inter-Integrated Circuit (I2C) master controller using FTDI MPSSE
+///
+/// Implements I2C bus communication with support for start/stop conditions and clock stretching
+///
+/// Several handles can share one bus, e.g. one per driver, see [`FtdiI2c::new`].
+/// Transactions of different handles never interleave, each one holds the
+/// MPSSE from its start to its stop condition.
+pub struct FtdiI2c {
+    bus: Arc<I2cBus>,
+    /// Thread-safe handle to FTDI MPSSE controller
+    mtx: Arc<Mutex<FtdiMpsse>>,
+    /// Length of start, repeated start, and stop conditions in MPSSE commands
+    /// More commands increase the duration of these conditions
+    start_stop_cmds: usize,
+    /// Extra GPIO commands SCL stays low and high in every bit, `None` shifts bits with the MPSSE
+    scl_holds: Option<(usize, usize)>,
+    enable_fast: bool,
+    /// MPSSE clock giving `scl_frequency`, set again when another handle changed it
+    mpsse_frequency: usize,
+    /// SCL frequency actually generated, in Hertz
+    scl_frequency: usize,
+    /// Whether the chip spreads a bit over three clock phases
+    three_phase: bool,
+    /// Longest time one transaction may take
+    timeout: Option<Duration>,
+    /// Longest wait for an idle bus before the start condition
+    busy_check: Option<Duration>,
+}
+
 impl FtdiI2c {
     const SLAVE_ACK_MASK: u8 = 1 << 0;
     const SLAVE_NOT_ACK: u8 = Self::SLAVE_ACK_MASK;
-    pub fn new(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Self, FtdiI2cError> {
-        let this = Self {
-            _pins: [
-                UsedPin::new(mtx.clone(), Pin::Lower(0), PinUsage::I2c)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(1), PinUsage::I2c)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(2), PinUsage::I2c)?,
-            ],
-            mtx: mtx.clone(),
-            start_stop_cmds: 3,
-            direction_pin: None,
-            enable_fast: false,
-        };
-        {
-            let lock = mtx.lock().unwrap();
-            if lock.chip_type != ChipType::FT2232D {
-                let mut cmd = MpsseCmdBuilder::new();
-                cmd.enable_3phase_data_clocking(true);
-                lock.exec(cmd)?;
+    /// Opens the I2C bus on AD0 to AD2, or adds a handle to it if it is already open
+    ///
+    /// Every handle has its own settings, only the direction pin is shared and fails
+    /// if the bus already has one. The pins are released when the last handle is dropped.
+    pub fn new(mtx: Arc<Mutex<FtdiMpsse>>, config: I2cConfig) -> Result<Self, FtdiI2cError> {
+        let bus = FtdiMpsse::lock(&mtx)?.i2c_bus.upgrade();
+        let bus = match bus {
+            Some(bus) => bus,
+            None => {
+                let bus = Arc::new(I2cBus {
+                    _pins: [
+                        UsedPin::new(mtx.clone(), Pin::Lower(0), PinUsage::I2c)?,
+                        UsedPin::new(mtx.clone(), Pin::Lower(1), PinUsage::I2c)?,
+                        UsedPin::new(mtx.clone(), Pin::Lower(2), PinUsage::I2c)?,
+                    ],
+                    mtx: mtx.clone(),
+                    direction_pin: OnceLock::new(),
+                    mpsse_frequency: AtomicUsize::new(0),
+                    closed: false,
+                });
+                let mut lock = FtdiMpsse::lock(&mtx)?;
+                if lock.chip_type.supports_three_phase() {
+                    let mut cmd = MpsseCmdBuilder::new();
+                    cmd.enable_3phase_data_clocking(true);
+                    lock.exec(cmd)?;
+                }
+                lock.i2c_bus = Arc::downgrade(&bus);
+                bus
             }
+        };
+        let three_phase = FtdiMpsse::lock(&mtx)?.chip_type.supports_three_phase();
+        let mut this = Self {
+            bus,
+            mtx,
+            start_stop_cmds: config.start_stop_cmds,
+            scl_holds: config.scl_holds,
+            enable_fast: config.fast,
+            mpsse_frequency: 0,
+            scl_frequency: 0,
+            three_phase,
+            timeout: config.timeout,
+            busy_check: config.busy_check,
+        };
+        if let Some(pin) = config.direction_pin {
+            this.set_direction_pin(pin)?;
         }
-        log::info!("IIC default 100Khz");
-        this.set_frequency(100_000)?;
+        this.set_frequency(config.frequency)?;
         Ok(this)
     }
+    /// Another handle on the same bus, starting with the settings of this one
+    pub fn share(&self) -> Self {
+        Self {
+            bus: self.bus.clone(),
+            mtx: self.mtx.clone(),
+            start_stop_cmds: self.start_stop_cmds,
+            scl_holds: self.scl_holds,
+            enable_fast: self.enable_fast,
+            mpsse_frequency: self.mpsse_frequency,
+            scl_frequency: self.scl_frequency,
+            three_phase: self.three_phase,
+            timeout: self.timeout,
+            busy_check: self.busy_check,
+        }
+    }
+    /// Drops this handle, and closes the bus when it was the last one
+    ///
+    /// Dropping closes the bus as well but only logs a failure, e.g. of an unplugged device.
+    pub fn shutdown(self) -> Result<(), FtdiI2cError> {
+        if let Some(mut bus) = Arc::into_inner(self.bus) {
+            bus.close()?;
+        }
+        Ok(())
+    }
+    fn direction_pin(&self) -> Option<&Pin> {
+        self.bus.direction_pin.get().map(Deref::deref)
+    }
+    fn cmd<'a>(&self, lock: &'a MutexGuard<FtdiMpsse>) -> I2cCmdBuilder<'a> {
+        I2cCmdBuilder::new(lock, self.direction_pin(), self.scl_holds)
+    }
+    /// Programs the clock of this handle if another handle has changed it
+    fn select_frequency(&self, lock: &FtdiMpsse) -> Result<(), FtdiError> {
+        let previous = self
+            .bus
+            .mpsse_frequency
+            .swap(self.mpsse_frequency, Ordering::Relaxed);
+        if previous != self.mpsse_frequency
+            && let Err(e) = lock.set_frequency(self.mpsse_frequency)
+        {
+            self.bus.mpsse_frequency.store(0, Ordering::Relaxed);
+            return Err(e);
+        }
+        Ok(())
+    }
 
-    pub fn set_direction_pin(&mut self, pin: Pin) -> Result<(), FtdiI2cError> {
-        self.direction_pin = Some(UsedPin::new(self.mtx.clone(), pin, PinUsage::I2c)?);
-        let mut lock = self.mtx.lock().unwrap();
-        match self.direction_pin.as_deref().unwrap() {
+    fn set_direction_pin(&mut self, pin: Pin) -> Result<(), FtdiI2cError> {
+        let used_pin = UsedPin::new(self.mtx.clone(), pin, PinUsage::I2c)?;
+        if self.bus.direction_pin.set(used_pin).is_err() {
+            return Err(FtdiError::PinFault("I2C direction pin is already set".to_string()).into());
+        }
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        match pin {
             Pin::Lower(_) => {
                 lock.lower.direction |= pin.mask();
             }
@@ -82,36 +356,222 @@ impl FtdiI2c {
         }
         Ok(())
     }
-    pub fn enbale_fast(&mut self, enable: bool) {
-        self.enable_fast = enable;
+    /// Sets the SCL frequency
+    ///
+    /// # Returns
+    /// The SCL frequency actually generated, see [`FtdiI2c::scl_frequency`]
+    ///
+    /// # Notes
+    /// Except on the FT2232D, 3-phase data clocking spreads one SCL period
+    /// over three MPSSE clock phases, so the MPSSE clock is set to 3/2 of SCL.
+    pub fn set_frequency(&mut self, frequency_hz: usize) -> Result<usize, FtdiI2cError> {
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let three_phase = self.three_phase;
+        let mpsse_frequency = if three_phase {
+            frequency_hz * 3 / 2
+        } else {
+            frequency_hz
+        };
+        let actual = lock.set_frequency(mpsse_frequency)?;
+        self.mpsse_frequency = mpsse_frequency;
+        self.bus
+            .mpsse_frequency
+            .store(mpsse_frequency, Ordering::Relaxed);
+        self.scl_frequency = if three_phase { actual * 2 / 3 } else { actual };
+        log::info!("IIC SCL set to {}Hz", self.scl_frequency);
+        Ok(self.scl_frequency)
     }
-
-    pub fn set_stop_start_len(&mut self, start_stop_cmds: usize) {
-        self.start_stop_cmds = start_stop_cmds
+    /// SCL frequency actually generated, after divisor rounding, in Hertz
+    pub fn scl_frequency(&self) -> usize {
+        self.scl_frequency
     }
-
-    pub fn set_frequency(&self, frequency_hz: usize) -> Result<(), FtdiI2cError> {
-        let lock = self.mtx.lock().unwrap();
-        if lock.chip_type == ChipType::FT2232D {
-            lock.set_frequency(frequency_hz)?;
+    /// Sets the fastest SCL whose low and high times are at least `min_low` and `min_high`
+    ///
+    /// The MPSSE shifts every bit with a fixed waveform: SCL is high for 1/3 of the
+    /// period with 3-phase clocking, and for 1/2 on the FT2232D. So the ratio can not be
+    /// chosen, only the period. On slow edges add the rise time to `min_high`.
+    pub fn set_scl_timing(
+        &mut self,
+        min_low: Duration,
+        min_high: Duration,
+    ) -> Result<SclTiming, FtdiI2cError> {
+        let shape = self.scl_shape();
+        let period = shape.min_period(min_low, min_high);
+        let frequency = (1_000_000_000 / period.as_nanos().max(1)).max(1) as usize;
+        self.set_frequency(frequency)?;
+        Ok(self.scl_timing())
+    }
+    /// SCL waveform currently generated
+    pub fn scl_timing(&self) -> SclTiming {
+        let shape = self.scl_shape();
+        let period = Duration::from_nanos(1_000_000_000 / self.scl_frequency.max(1) as u64);
+        SclTiming {
+            frequency: self.scl_frequency,
+            low: period * shape.low / shape.phases,
+            high: period * shape.high / shape.phases,
+        }
+    }
+    /// Clocks every bit with GPIO commands instead of the MPSSE shift commands
+    ///
+    /// SCL is low for `2 + low` and high for `1 + high` GPIO commands of a bit, so tLOW
+    /// and tHIGH no longer follow the fixed ratio of [`FtdiI2c::set_scl_timing`], e.g. to
+    /// meet fast-mode minimums on a heavily loaded bus. SDA is sampled at the end of the
+    /// high time. Much slower than shifting and independent of the SCL frequency.
+    /// `None` (the default) shifts the bits again.
+    pub fn set_scl_holds(&mut self, holds: Option<(usize, usize)>) {
+        self.scl_holds = holds;
+    }
+    fn scl_shape(&self) -> SclShape {
+        if self.three_phase {
+            SclShape::THREE_PHASE
         } else {
-            lock.set_frequency(frequency_hz * 3 / 2)?;
+            SclShape::TWO_PHASE
         }
+    }
+
+    /// Limits the time one transaction may take, `None` waits forever (the default)
+    ///
+    /// A transaction running out of time is aborted, the bus is recovered with
+    /// [`FtdiI2c::recover_bus`] and [`FtdiI2cError::BusStuck`] is returned.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+    /// Samples SCL and SDA before every transaction, `None` skips the check (the default)
+    ///
+    /// While another master or a stuck slave holds a line low the transaction waits up
+    /// to `wait`, then fails with [`FtdiI2cError::BusBusy`] without driving the lines.
+    /// `Some(Duration::ZERO)` fails on the first sample.
+    pub fn set_busy_check(&mut self, wait: Option<Duration>) {
+        self.busy_check = wait;
+    }
+    /// Waits for SCL and SDA to be high, if the busy check is on
+    fn wait_bus_idle(&self, lock: &FtdiMpsse) -> Result<(), FtdiI2cError> {
+        let Some(wait) = self.busy_check else {
+            return Ok(());
+        };
+        let deadline = Instant::now() + wait;
+        // SDA is read back on AD2
+        while lock.read_pins(&[Pin::Lower(0), Pin::Lower(2)])? != [true, true] {
+            if Instant::now() >= deadline {
+                log::warn!("I2C bus busy for {wait:?}");
+                return Err(FtdiI2cError::BusBusy);
+            }
+        }
+        Ok(())
+    }
+    /// Frees a bus held by a slave stuck in the middle of a byte
+    ///
+    /// Discards what is left in the chip, then clocks SCL up to 9 times with SDA released
+    /// so the slave can finish its byte and let SDA go, and ends with a stop condition.
+    pub fn recover_bus(&mut self) -> Result<(), FtdiI2cError> {
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        lock.purge()?;
+        self.select_frequency(&lock)?;
+        let mut cmd = self.cmd(&lock);
+        cmd.recover(self.start_stop_cmds);
+        cmd.exec_until(None)?;
         Ok(())
     }
 
-    pub fn scan(&mut self) -> Vec<u8> {
-        let mut addr_set = Vec::new();
-        for addr in 0..128 {
-            let write_response = self.transaction(addr, &mut [Operation::Write(&[])]);
-            let read_response = self.transaction(addr, &mut [Operation::Read(&mut [])]);
-            if write_response.is_ok() || read_response.is_ok() {
-                addr_set.push(addr);
+    /// Probe every address of `addrs` and return the ones acknowledged
+    ///
+    /// Each address is reported together with the access that was acknowledged, write-only
+    /// devices like some OLED controllers only answer [`I2cProbe::Write`] and read-only ones
+    /// only [`I2cProbe::Read`]. A read probe moves the address counter of EEPROMs (0x50-0x57).
+    pub fn scan(
+        &mut self,
+        addrs: impl IntoIterator<Item = u8>,
+        probe: I2cProbe,
+    ) -> Result<Vec<(u8, I2cProbe)>, FtdiI2cError> {
+        // Every probe is a complete start, address, stop sequence, so all of them go in one batch
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        self.select_frequency(&lock)?;
+        let mut probes = Vec::new();
+        let mut cmds = Vec::new();
+        let mut samples = Vec::new();
+        for addr in addrs {
+            let reads = match probe {
+                I2cProbe::Read => &[true][..],
+                I2cProbe::Write => &[false][..],
+                I2cProbe::Both => &[false, true][..],
+            };
+            for &read in reads {
+                let mut cmd = self.cmd(&lock);
+                cmd.start(self.start_stop_cmds).i2c_addr(addr, read);
+                if read {
+                    // a stop right after the address fails while the device pulls SDA low for a 0 bit
+                    cmd.i2c_read_byte(false);
+                }
+                cmd.end(self.start_stop_cmds);
+                let (cmd, sampled) = cmd.into_parts();
+                probes.push((addr, read));
+                cmds.push(cmd);
+                samples.push(sampled);
+            }
+        }
+        let responses = lock.exec_many(cmds)?;
+        let mut addr_set: Vec<(u8, I2cProbe)> = Vec::new();
+        for (((addr, read), sampled), response) in probes.into_iter().zip(samples).zip(responses) {
+            let response = cmd::pack_samples(&sampled, response);
+            if (response[0] & Self::SLAVE_ACK_MASK) == Self::SLAVE_NOT_ACK {
+                continue;
+            }
+            let found = if read {
+                I2cProbe::Read
+            } else {
+                I2cProbe::Write
+            };
+            match addr_set.last_mut() {
+                Some((last, kind)) if *last == addr => *kind = I2cProbe::Both,
+                _ => addr_set.push((addr, found)),
             }
         }
-        addr_set
+        Ok(addr_set)
     }
 
+    /// Whether a device acknowledges `addr`, checked with a zero-length write
+    ///
+    /// Only start, address and stop go out, the SMBus quick command with the write bit.
+    /// Unlike a read probe this leaves no device waiting to send data.
+    pub fn probe(&mut self, addr: u8) -> Result<bool, FtdiI2cError> {
+        match self.run(addr, &mut [Operation::Write(&[])]) {
+            Ok(()) => Ok(true),
+            Err(FtdiI2cError::NoAck(NoAcknowledgeSource::Address)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+    /// Writes `data` to the general call address 0, every device taking part listens
+    pub fn general_call(&mut self, data: &[u8]) -> Result<(), FtdiI2cError> {
+        self.run(GENERAL_CALL_ADDR, &mut [Operation::Write(data)])
+    }
+    /// Broadcasts the general call software reset, devices supporting it reset and reload their address
+    pub fn software_reset(&mut self) -> Result<(), FtdiI2cError> {
+        self.general_call(&[GENERAL_CALL_RESET])
+    }
+    /// Reads the Device ID of the device at `addr` with the reserved Device ID address
+    ///
+    /// Fails with [`FtdiI2cError::NoAck`] if the device does not support it.
+    pub fn device_id(&mut self, addr: u8) -> Result<I2cDeviceId, FtdiI2cError> {
+        let mut id = [0; 3];
+        self.run(
+            DEVICE_ID_ADDR,
+            &mut [Operation::Write(&[addr << 1]), Operation::Read(&mut id)],
+        )?;
+        Ok(I2cDeviceId::from_bytes(id))
+    }
+    /// Runs a transaction, `Operation::Write(&[])` only sends the address
+    fn run(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), FtdiI2cError> {
+        let result = if self.enable_fast {
+            self.transaction_fast(address, operations)
+        } else {
+            self.transaction(address, operations)
+        };
+        if let Err(FtdiI2cError::FtdiInner(FtdiError::Timeout)) = result {
+            self.recover_bus()?;
+            return Err(FtdiI2cError::BusStuck);
+        }
+        result
+    }
     fn transaction(
         &mut self,
         address: u8,
@@ -119,33 +579,36 @@ impl FtdiI2c {
     ) -> Result<(), FtdiI2cError> {
         // lock at the start to prevent GPIO from being modified while we build
         // the MPSSE command
-        let lock = self.mtx.lock().unwrap();
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        self.select_frequency(&lock)?;
+        self.wait_bus_idle(&lock)?;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
 
         // start
-        let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+        let mut cmd = self.cmd(&lock);
         cmd.start(self.start_stop_cmds);
-        lock.exec(cmd)?;
+        cmd.exec_until(deadline)?;
 
         let mut prev_op_was_a_read = false;
         for (op_idx, operation) in operations.iter_mut().enumerate() {
             match operation {
                 Operation::Read(buffer) => {
                     if op_idx == 0 || !prev_op_was_a_read {
-                        let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+                        let mut cmd = self.cmd(&lock);
                         if op_idx != 0 {
                             cmd.restart(self.start_stop_cmds); // repeated start
                         }
                         cmd.i2c_addr(address, true); // (Address+Read)+Ack
-                        let response = lock.exec(cmd)?;
+                        let response = cmd.exec_until(deadline)?;
                         if (response[0] & Self::SLAVE_ACK_MASK) == Self::SLAVE_NOT_ACK {
-                            let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+                            let mut cmd = self.cmd(&lock);
                             cmd.end(self.start_stop_cmds);
-                            lock.exec(cmd)?;
+                            cmd.exec_until(deadline)?;
                             return Err(FtdiI2cError::NoAck(NoAcknowledgeSource::Address));
                         }
                     }
 
-                    let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+                    let mut cmd = self.cmd(&lock);
                     for idx in 0..buffer.len() {
                         if idx == buffer.len() - 1 {
                             cmd.i2c_read_byte(false); // NMAK: Master Not Ack
@@ -153,36 +616,36 @@ impl FtdiI2c {
                             cmd.i2c_read_byte(true); // MAK: Master Ack
                         }
                     }
-                    let response = lock.exec(cmd)?;
+                    let response = cmd.exec_until(deadline)?;
                     buffer.copy_from_slice(&response);
 
                     prev_op_was_a_read = true;
                 }
                 Operation::Write(bytes) => {
                     if op_idx == 0 || prev_op_was_a_read {
-                        let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+                        let mut cmd = self.cmd(&lock);
                         if op_idx != 0 {
                             cmd.restart(self.start_stop_cmds); // repeated start
                         }
                         cmd.i2c_addr(address, false); // (Address+Write)+Ack
-                        let response = lock.exec(cmd)?;
+                        let response = cmd.exec_until(deadline)?;
                         if (response[0] & Self::SLAVE_ACK_MASK) == Self::SLAVE_NOT_ACK {
-                            let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+                            let mut cmd = self.cmd(&lock);
                             cmd.end(self.start_stop_cmds);
-                            lock.exec(cmd)?;
+                            cmd.exec_until(deadline)?;
                             return Err(FtdiI2cError::NoAck(NoAcknowledgeSource::Address));
                         }
                     }
                     for idx in 0..bytes.len() {
-                        let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+                        let mut cmd = self.cmd(&lock);
                         cmd.i2c_write_byte(bytes[idx]);
-                        let response = lock.exec(cmd)?;
+                        let response = cmd.exec_until(deadline)?;
                         if (response[0] & Self::SLAVE_ACK_MASK) == Self::SLAVE_NOT_ACK
                             && idx != bytes.len() - 1
                         {
-                            let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+                            let mut cmd = self.cmd(&lock);
                             cmd.end(self.start_stop_cmds);
-                            lock.exec(cmd)?;
+                            cmd.exec_until(deadline)?;
                             return Err(FtdiI2cError::NoAck(NoAcknowledgeSource::Data));
                         }
                     }
@@ -192,9 +655,9 @@ impl FtdiI2c {
         }
 
         // stop
-        let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+        let mut cmd = self.cmd(&lock);
         cmd.end(self.start_stop_cmds);
-        lock.exec(cmd)?;
+        cmd.exec_until(deadline)?;
 
         Ok(())
     }
@@ -205,10 +668,13 @@ impl FtdiI2c {
     ) -> Result<(), FtdiI2cError> {
         // lock at the start to prevent GPIO from being modified while we build
         // the MPSSE command
-        let lock = self.mtx.lock().unwrap();
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        self.select_frequency(&lock)?;
+        self.wait_bus_idle(&lock)?;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
 
         // start
-        let mut cmd = I2cCmdBuilder::new(&lock, self.direction_pin.as_deref());
+        let mut cmd = self.cmd(&lock);
         cmd.start(self.start_stop_cmds);
 
         let mut prev_op_was_a_read = false;
@@ -245,7 +711,7 @@ impl FtdiI2c {
             }
         }
         cmd.end(self.start_stop_cmds);
-        let response = lock.exec(cmd)?;
+        let response = cmd.exec_until(deadline)?;
 
         // parse response
         prev_op_was_a_read = false;
@@ -292,6 +758,7 @@ impl eh1::i2c::Error for FtdiI2cError {
     fn kind(&self) -> ErrorKind {
         match self {
             FtdiI2cError::NoAck(x) => ErrorKind::NoAcknowledge(*x),
+            FtdiI2cError::BusBusy => ErrorKind::Bus,
             _ => ErrorKind::Other,
         }
     }
@@ -316,11 +783,11 @@ impl eh1::i2c::I2c for FtdiI2c {
         address: SevenBitAddress,
         operations: &mut [Operation<'_>],
     ) -> Result<(), Self::Error> {
-        if self.enable_fast {
-            self.transaction_fast(address, operations)
-        } else {
-            self.transaction(address, operations)
+        let result = self.run(address, operations);
+        if let Err(FtdiI2cError::NoAck(_)) = result {
+            FtdiMpsse::lock(&self.mtx)?.record(Event::I2cNack);
         }
+        result
     }
 }
 
@@ -331,28 +798,91 @@ mod cmd {
     const IS_LSB: bool = false;
     const DATA_BITS: usize = 8;
     const ACK_BITS: usize = 1;
+    /// SDA is read back on AD2
+    const SDA_IN: u8 = Pin::Lower(2).mask();
 
-    use crate::{Pin, mpsse::FtdiMpsse, mpsse_cmd::MpsseCmdBuilder};
-    use std::sync::MutexGuard;
+    use crate::{FtdiError, Pin, mpsse::FtdiMpsse, mpsse_cmd::MpsseCmdBuilder};
+    use std::{sync::MutexGuard, time::Instant};
     pub(super) struct I2cCmdBuilder<'a> {
         cmd: MpsseCmdBuilder,
         lock: &'a MutexGuard<'a, FtdiMpsse>,
         direction_pin: Option<Pin>,
+        /// Extra GPIO commands SCL stays low and high, bits are shifted when `None`
+        scl_holds: Option<(usize, usize)>,
+        /// Number of GPIO samples making up each byte of the response
+        sampled: Vec<usize>,
     }
-    impl<'a> From<I2cCmdBuilder<'a>> for MpsseCmdBuilder {
-        fn from(value: I2cCmdBuilder<'a>) -> Self {
-            value.cmd
+    /// Folds the SDA samples of GPIO clocked bits into the bytes the shift commands return
+    ///
+    /// Every group of `sampled` becomes one byte, MSB first. A response without GPIO
+    /// samples is returned as it is.
+    pub(super) fn pack_samples(sampled: &[usize], response: Vec<u8>) -> Vec<u8> {
+        if sampled.is_empty() {
+            return response;
         }
+        let mut samples = response.into_iter();
+        sampled
+            .iter()
+            .map(|&bits| {
+                samples.by_ref().take(bits).fold(0, |byte, sample| {
+                    (byte << 1) | u8::from(sample & SDA_IN != 0)
+                })
+            })
+            .collect()
     }
     impl<'a> I2cCmdBuilder<'a> {
-        pub(super) fn new(lock: &'a MutexGuard<FtdiMpsse>, direction_pin: Option<&Pin>) -> Self {
+        pub(super) fn new(
+            lock: &'a MutexGuard<FtdiMpsse>,
+            direction_pin: Option<&Pin>,
+            scl_holds: Option<(usize, usize)>,
+        ) -> Self {
+            let mut cmd = MpsseCmdBuilder::new();
+            // Bit phases repeat the pin state left by the previous phase, the timed
+            // conditions hold their states instead
+            cmd.coalesce_gpio(true);
             I2cCmdBuilder {
-                cmd: MpsseCmdBuilder::new(),
+                cmd,
                 lock,
                 direction_pin: direction_pin.copied(),
+                scl_holds,
+                sampled: Vec::new(),
             }
         }
+        /// The commands and the sample groups to pass to [`pack_samples`] with their response
+        pub(super) fn into_parts(self) -> (MpsseCmdBuilder, Vec<usize>) {
+            (self.cmd, self.sampled)
+        }
+        /// Runs the commands, the response holds one byte per shifted or sampled byte
+        pub(super) fn exec_until(self, deadline: Option<Instant>) -> Result<Vec<u8>, FtdiError> {
+            let lock = self.lock;
+            let (cmd, sampled) = self.into_parts();
+            Ok(pack_samples(&sampled, lock.exec_until(cmd, deadline)?))
+        }
         fn i2c_out(&mut self, scl: bool, sda: bool) -> &mut Self {
+            self.i2c_drive(scl, sda, false)
+        }
+        /// Drives SCL and SDA for `count` commands, part of the timing of a condition
+        fn i2c_hold(&mut self, scl: bool, sda: bool, count: usize) -> &mut Self {
+            for _ in 0..count {
+                self.i2c_drive(scl, sda, true);
+            }
+            self
+        }
+        fn set_lower(&mut self, state: u8, direction: u8, hold: bool) {
+            if hold {
+                self.cmd.hold_gpio_lower(state, direction);
+            } else {
+                self.cmd.set_gpio_lower(state, direction);
+            }
+        }
+        fn set_upper(&mut self, state: u8, direction: u8, hold: bool) {
+            if hold {
+                self.cmd.hold_gpio_upper(state, direction);
+            } else {
+                self.cmd.set_gpio_upper(state, direction);
+            }
+        }
+        fn i2c_drive(&mut self, scl: bool, sda: bool, hold: bool) -> &mut Self {
             let lower_value = self.lock.lower.value;
             let lower_direction = self.lock.lower.direction;
             let upper_value = self.lock.upper.value;
@@ -362,67 +892,100 @@ mod cmd {
             if let Some(pin) = self.direction_pin {
                 match pin {
                     Pin::Lower(_) => {
-                        self.cmd.set_gpio_lower(
+                        self.set_lower(
                             lower_value | pin.mask() | scl | sda,
                             lower_direction | SCL | SDA,
+                            hold,
                         );
                     }
                     Pin::Upper(_) => {
-                        self.cmd
-                            .set_gpio_lower(lower_value | scl | sda, lower_direction | SCL | SDA);
-                        self.cmd
-                            .set_gpio_upper(upper_value | pin.mask(), upper_direction);
+                        self.set_lower(lower_value | scl | sda, lower_direction | SCL | SDA, hold);
+                        self.set_upper(upper_value | pin.mask(), upper_direction, hold);
                     }
                 }
             } else {
-                self.cmd
-                    .set_gpio_lower(lower_value | scl | sda, lower_direction | SCL | SDA);
+                self.set_lower(lower_value | scl | sda, lower_direction | SCL | SDA, hold);
             }
             self
         }
         fn i2c_in(&mut self) -> &mut Self {
+            self.i2c_release_sda(false, false)
+        }
+        /// Drives SCL and releases SDA
+        fn i2c_release_sda(&mut self, scl: bool, hold: bool) -> &mut Self {
             let lower_value = self.lock.lower.value;
             let lower_direction = self.lock.lower.direction;
             let upper_value = self.lock.upper.value;
             let upper_direction = self.lock.upper.direction;
             if let Some(Pin::Upper(_)) = self.direction_pin {
-                self.cmd.set_gpio_upper(upper_value, upper_direction);
+                self.set_upper(upper_value, upper_direction, hold);
             }
-            self.cmd.set_gpio_lower(lower_value, lower_direction | SCL);
+            let scl = if scl { SCL } else { 0 };
+            self.set_lower(lower_value | scl, lower_direction | SCL, hold);
             self
         }
+        // Repeated states stretch the setup and hold times of the conditions
         pub(super) fn start(&mut self, count: usize) -> &mut Self {
-            for _ in 0..count {
-                self.i2c_out(true, true);
-            }
-            for _ in 0..count {
-                self.i2c_out(true, false);
-            }
-            for _ in 0..count {
-                self.i2c_out(false, false);
-            }
-            self
+            self.i2c_hold(true, true, count)
+                .i2c_hold(true, false, count)
+                .i2c_hold(false, false, count)
         }
         pub(super) fn restart(&mut self, count: usize) -> &mut Self {
-            for _ in 0..count {
-                self.i2c_out(false, true);
-            }
-            self.start(count)
+            self.i2c_hold(false, true, count).start(count)
         }
         pub(super) fn end(&mut self, count: usize) -> &mut Self {
-            for _ in 0..count {
-                self.i2c_out(false, false);
+            self.i2c_hold(false, false, count)
+                .i2c_hold(true, false, count)
+                .i2c_hold(true, true, count)
+        }
+        pub(super) fn recover(&mut self, count: usize) -> &mut Self {
+            for _ in 0..9 {
+                for _ in 0..count {
+                    self.i2c_release_sda(false, true);
+                }
+                for _ in 0..count {
+                    self.i2c_release_sda(true, true);
+                }
             }
-            for _ in 0..count {
-                self.i2c_out(true, false);
+            self.end(count)
+        }
+        /// Drives SDA for one GPIO clocked bit, SCL is low before and after it
+        fn gpio_bit_out(&mut self, sda: bool, (low, high): (usize, usize)) -> &mut Self {
+            self.i2c_hold(false, sda, 1 + low)
+                .i2c_hold(true, sda, 1 + high)
+                .i2c_hold(false, sda, 1)
+        }
+        /// Releases SDA for one GPIO clocked bit and samples it before SCL falls
+        fn gpio_bit_in(&mut self, (low, high): (usize, usize)) -> &mut Self {
+            for _ in 0..=low {
+                self.i2c_release_sda(false, true);
             }
-            for _ in 0..count {
-                self.i2c_out(true, true);
+            for _ in 0..=high {
+                self.i2c_release_sda(true, true);
+            }
+            self.cmd.gpio_lower();
+            self.i2c_release_sda(false, true)
+        }
+        fn gpio_byte_out(&mut self, value: u8, holds: (usize, usize)) -> &mut Self {
+            for idx in (0..DATA_BITS).rev() {
+                self.gpio_bit_out(value & (1 << idx) != 0, holds);
             }
             self
         }
+        fn gpio_bits_in(&mut self, bits: usize, holds: (usize, usize)) -> &mut Self {
+            for _ in 0..bits {
+                self.gpio_bit_in(holds);
+            }
+            self.sampled.push(bits);
+            self
+        }
         pub(super) fn i2c_addr(&mut self, addr: u8, is_read: bool) -> &mut Self {
             let addr = if is_read { (addr << 1) | 1 } else { addr << 1 };
+            if let Some(holds) = self.scl_holds {
+                return self
+                    .gpio_byte_out(addr, holds)
+                    .gpio_bits_in(ACK_BITS, holds);
+            }
             self.cmd
                 .shift_bits_out(TCK_INIT_VALUE, IS_LSB, addr, DATA_BITS);
             self.i2c_in()
@@ -431,6 +994,11 @@ mod cmd {
             self
         }
         pub(super) fn i2c_read_byte(&mut self, m_ack: bool) -> &mut Self {
+            if let Some(holds) = self.scl_holds {
+                return self
+                    .gpio_bits_in(DATA_BITS, holds)
+                    .gpio_bit_out(!m_ack, holds);
+            }
             let m_ack = if m_ack { 0 } else { 0xff };
             self.i2c_in()
                 .cmd
@@ -441,6 +1009,11 @@ mod cmd {
             self
         }
         pub(super) fn i2c_write_byte(&mut self, value: u8) -> &mut Self {
+            if let Some(holds) = self.scl_holds {
+                return self
+                    .gpio_byte_out(value, holds)
+                    .gpio_bits_in(ACK_BITS, holds);
+            }
             self.i2c_out(false, false)
                 .cmd
                 .shift_bits_out(TCK_INIT_VALUE, IS_LSB, value, DATA_BITS);
@@ -451,3 +1024,51 @@ mod cmd {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{I2cDeviceId, SclShape, cmd::pack_samples};
+    use std::time::Duration;
+
+    #[test]
+    fn device_id() {
+        assert_eq!(
+            I2cDeviceId::from_bytes([0x00, 0x0D, 0x0B]),
+            I2cDeviceId {
+                manufacturer: 0x000,
+                part: 0x1A1,
+                revision: 3
+            }
+        );
+    }
+
+    #[test]
+    fn scl_period() {
+        // Fast-mode minimums, tLOW 1.3us and tHIGH 0.6us
+        let low = Duration::from_nanos(1300);
+        let high = Duration::from_nanos(600);
+        assert_eq!(
+            SclShape::THREE_PHASE.min_period(low, high),
+            Duration::from_nanos(1950)
+        );
+        assert_eq!(
+            SclShape::TWO_PHASE.min_period(low, high),
+            Duration::from_nanos(2600)
+        );
+        // 200ns rise time eating into the high phase
+        assert_eq!(
+            SclShape::THREE_PHASE.min_period(low, high + Duration::from_nanos(200)),
+            Duration::from_nanos(2400)
+        );
+    }
+
+    #[test]
+    fn gpio_samples() {
+        // SDA read back on AD2, SCL high on AD0
+        let ack = [0b001];
+        let byte = [0b101, 0b001, 0b101, 0b101, 0b001, 0b001, 0b101, 0b001];
+        let response = [&ack[..], &byte[..], &ack[..]].concat();
+        assert_eq!(pack_samples(&[1, 8, 1], response), [0x00, 0xB2, 0x00]);
+        assert_eq!(pack_samples(&[], vec![0x12, 0x34]), [0x12, 0x34]);
+    }
+}
@@ -0,0 +1,399 @@
+//! Firmware images for the flash programmers.
+//!
+//! An [`Image`] holds data at absolute addresses, read from a raw binary, Intel HEX or the
+//! loadable segments of an ELF file. Programs are usually linked for the address their flash
+//! is mapped at, [`Image::rebase`] moves them to offsets within the flash chip and
+//! [`Image::flatten`] gives the contiguous block a flash write takes:
+//!
+//! ```no_run
+//! # use ftdi_tools::image::Image;
+//! # fn demo(flasher: &mut ftdi_tools::esp::EspFlasher) -> Result<(), Box<dyn std::error::Error>> {
+//! let mut image = Image::load("firmware.hex", 0)?;
+//! image.rebase(0x3F40_0000, 0)?;
+//! if let Some((offset, data)) = image.flatten(0xFF) {
+//!     flasher.write_flash(offset, &data)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::{fmt::Write, path::Path};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageError {
+    #[error("Failed to read image: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Intel HEX line {line}: {reason}")]
+    Hex { line: usize, reason: &'static str },
+    #[error("Invalid ELF: {0}")]
+    Elf(&'static str),
+    #[error("Data at {0:#x} overlaps other data")]
+    Overlap(u32),
+    #[error("Data at {0:#x} does not fit the address space")]
+    OutOfRange(u64),
+}
+
+/// Contiguous data starting at `addr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    fn end(&self) -> u64 {
+        self.addr as u64 + self.data.len() as u64
+    }
+}
+
+/// Data at absolute addresses, segments are sorted, disjoint and never adjacent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    segments: Vec<Segment>,
+}
+
+impl Image {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+    /// Raw binary placed at `addr`
+    pub fn from_bin(data: &[u8], addr: u32) -> Result<Self, ImageError> {
+        let mut image = Self::new();
+        image.insert(addr, data)?;
+        Ok(image)
+    }
+    /// Parses Intel HEX, with 16 and 32 bit extended addresses
+    pub fn from_ihex(text: &str) -> Result<Self, ImageError> {
+        let mut image = Self::new();
+        let mut base = 0u32;
+        for (idx, line) in text.lines().enumerate() {
+            let err = |reason| ImageError::Hex {
+                line: idx + 1,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let hex = line.strip_prefix(':').ok_or(err("missing ':'"))?;
+            if hex.len() % 2 != 0 || hex.len() < 10 {
+                return Err(err("truncated record"));
+            }
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| err("invalid hex digit"))?;
+            let len = bytes[0] as usize;
+            if bytes.len() != len + 5 {
+                return Err(err("length does not match"));
+            }
+            if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+                return Err(err("bad checksum"));
+            }
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+            let data = &bytes[4..4 + len];
+            let value = || match data {
+                &[high, low] => Ok(u16::from_be_bytes([high, low]) as u32),
+                _ => Err(err("address record needs 2 bytes")),
+            };
+            match bytes[3] {
+                0x00 => image.insert(base.wrapping_add(offset), data)?,
+                0x01 => break,
+                0x02 => base = value()? << 4,
+                0x04 => base = value()? << 16,
+                // Start addresses do not matter for flashing
+                0x03 | 0x05 => (),
+                _ => return Err(err("unknown record type")),
+            }
+        }
+        Ok(image)
+    }
+    /// Takes the loadable segments of an ELF file at their physical (load) addresses
+    pub fn from_elf(file: &[u8]) -> Result<Self, ImageError> {
+        let elf = Elf::new(file)?;
+        let mut image = Self::new();
+        for idx in 0..elf.phnum {
+            let ph = elf.phoff + idx * elf.phentsize;
+            // PT_LOAD
+            if elf.u32(ph)? != 1 {
+                continue;
+            }
+            let (offset, paddr, filesz) = if elf.is_64 {
+                (elf.u64(ph + 8)?, elf.u64(ph + 24)?, elf.u64(ph + 32)?)
+            } else {
+                (
+                    elf.u32(ph + 4)? as u64,
+                    elf.u32(ph + 12)? as u64,
+                    elf.u32(ph + 16)? as u64,
+                )
+            };
+            // Zero-initialised data is not stored
+            if filesz == 0 {
+                continue;
+            }
+            let data = elf.bytes(offset, filesz)?;
+            let addr = u32::try_from(paddr).map_err(|_| ImageError::OutOfRange(paddr))?;
+            image.insert(addr, data)?;
+        }
+        Ok(image)
+    }
+    /// Reads an ELF file by its magic, Intel HEX by a `.hex` or `.ihex` extension and anything
+    /// else as raw binary placed at `base`
+    pub fn load(path: impl AsRef<Path>, base: u32) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let file = std::fs::read(path)?;
+        if file.starts_with(b"\x7FELF") {
+            return Self::from_elf(&file);
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("hex" | "ihex") => {
+                let text = String::from_utf8(file).map_err(|_| ImageError::Hex {
+                    line: 0,
+                    reason: "not text",
+                })?;
+                Self::from_ihex(&text)
+            }
+            _ => Self::from_bin(&file, base),
+        }
+    }
+    /// Adds `data` at `addr`, it may not overlap data already in the image
+    pub fn insert(&mut self, addr: u32, data: &[u8]) -> Result<(), ImageError> {
+        let end = addr as u64 + data.len() as u64;
+        if end > 1 << 32 {
+            return Err(ImageError::OutOfRange(end));
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        let idx = self.segments.partition_point(|s| s.addr < addr);
+        if idx > 0 && self.segments[idx - 1].end() > addr as u64 {
+            return Err(ImageError::Overlap(addr));
+        }
+        if let Some(next) = self.segments.get(idx)
+            && (next.addr as u64) < end
+        {
+            return Err(ImageError::Overlap(next.addr));
+        }
+        // Intel HEX records mostly continue the previous segment
+        let idx = if idx > 0 && self.segments[idx - 1].end() == addr as u64 {
+            self.segments[idx - 1].data.extend_from_slice(data);
+            idx - 1
+        } else {
+            self.segments.insert(
+                idx,
+                Segment {
+                    addr,
+                    data: data.to_vec(),
+                },
+            );
+            idx
+        };
+        if let Some(next) = self.segments.get(idx + 1)
+            && next.addr as u64 == self.segments[idx].end()
+        {
+            let next = self.segments.remove(idx + 1);
+            self.segments[idx].data.extend(next.data);
+        }
+        Ok(())
+    }
+    /// Moves data linked at `from` and above to start at `to`
+    ///
+    /// E.g. `rebase(0x0800_0000, 0)` maps an STM32 image to offsets within its flash.
+    pub fn rebase(&mut self, from: u32, to: u32) -> Result<(), ImageError> {
+        for segment in &mut self.segments {
+            let addr = (segment.addr as u64 + to as u64)
+                .checked_sub(from as u64)
+                .ok_or(ImageError::OutOfRange(segment.addr as u64))?;
+            let end = addr + segment.data.len() as u64;
+            if end > 1 << 32 {
+                return Err(ImageError::OutOfRange(end));
+            }
+            segment.addr = addr as u32;
+        }
+        Ok(())
+    }
+    /// Start address and data from the first to the last byte, gaps filled with `fill`
+    pub fn flatten(&self, fill: u8) -> Option<(u32, Vec<u8>)> {
+        let start = self.segments.first()?.addr;
+        let end = self.segments.last()?.end();
+        let mut data = vec![fill; (end - start as u64) as usize];
+        for segment in &self.segments {
+            let offset = (segment.addr - start) as usize;
+            data[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        Some((start, data))
+    }
+    /// Intel HEX text with 16 data bytes per record
+    pub fn to_ihex(&self) -> String {
+        fn record(text: &mut String, kind: u8, offset: u16, data: &[u8]) {
+            let mut bytes = vec![data.len() as u8];
+            bytes.extend_from_slice(&offset.to_be_bytes());
+            bytes.push(kind);
+            bytes.extend_from_slice(data);
+            let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_sub(*b));
+            bytes.push(checksum);
+            text.push(':');
+            for byte in bytes {
+                write!(text, "{byte:02X}").unwrap();
+            }
+            text.push('\n');
+        }
+        let mut text = String::new();
+        let mut upper = None;
+        for segment in &self.segments {
+            let mut addr = segment.addr;
+            let mut data = segment.data.as_slice();
+            while !data.is_empty() {
+                if upper != Some(addr >> 16) {
+                    upper = Some(addr >> 16);
+                    record(&mut text, 0x04, 0, &((addr >> 16) as u16).to_be_bytes());
+                }
+                // Records do not cross a 64 KiB boundary
+                let room = 0x1_0000 - (addr & 0xFFFF) as usize;
+                let (chunk, rest) = data.split_at(data.len().min(16).min(room));
+                record(&mut text, 0x00, addr as u16, chunk);
+                addr = addr.wrapping_add(chunk.len() as u32);
+                data = rest;
+            }
+        }
+        record(&mut text, 0x01, 0, &[]);
+        text
+    }
+}
+
+/// Header fields of an ELF file needed to find its program headers
+struct Elf<'a> {
+    file: &'a [u8],
+    is_64: bool,
+    is_be: bool,
+    phoff: u64,
+    phentsize: u64,
+    phnum: u64,
+}
+
+impl<'a> Elf<'a> {
+    fn new(file: &'a [u8]) -> Result<Self, ImageError> {
+        if !file.starts_with(b"\x7FELF") || file.len() < 0x34 {
+            return Err(ImageError::Elf("not an ELF file"));
+        }
+        let is_64 = match file[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(ImageError::Elf("unknown class")),
+        };
+        let is_be = match file[5] {
+            1 => false,
+            2 => true,
+            _ => return Err(ImageError::Elf("unknown byte order")),
+        };
+        let mut elf = Self {
+            file,
+            is_64,
+            is_be,
+            phoff: 0,
+            phentsize: 0,
+            phnum: 0,
+        };
+        if is_64 {
+            elf.phoff = elf.u64(0x20)?;
+            elf.phentsize = elf.u16(0x36)? as u64;
+            elf.phnum = elf.u16(0x38)? as u64;
+        } else {
+            elf.phoff = elf.u32(0x1C)? as u64;
+            elf.phentsize = elf.u16(0x2A)? as u64;
+            elf.phnum = elf.u16(0x2C)? as u64;
+        }
+        Ok(elf)
+    }
+    fn bytes(&self, offset: u64, len: u64) -> Result<&'a [u8], ImageError> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.file.get(offset as usize..end as usize))
+            .ok_or(ImageError::Elf("truncated file"))
+    }
+    fn u16(&self, offset: u64) -> Result<u16, ImageError> {
+        let bytes = self.bytes(offset, 2)?.try_into().unwrap();
+        Ok(if self.is_be {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+    fn u32(&self, offset: u64) -> Result<u32, ImageError> {
+        let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+        Ok(if self.is_be {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+    fn u64(&self, offset: u64) -> Result<u64, ImageError> {
+        let bytes = self.bytes(offset, 8)?.try_into().unwrap();
+        Ok(if self.is_be {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Image, ImageError, Segment};
+
+    #[test]
+    fn ihex_round_trip() {
+        let text = ":020000040800F2\n\
+                    :0400000001020304F2\n\
+                    :02000400AABB95\n\
+                    :00000001FF\n";
+        let image = Image::from_ihex(text).unwrap();
+        assert_eq!(
+            image.segments(),
+            [Segment {
+                addr: 0x0800_0000,
+                data: vec![1, 2, 3, 4, 0xAA, 0xBB]
+            }]
+        );
+        assert_eq!(Image::from_ihex(&image.to_ihex()).unwrap(), image);
+        assert!(matches!(
+            Image::from_ihex(":0400000001020304F3"),
+            Err(ImageError::Hex { line: 1, .. })
+        ));
+    }
+    #[test]
+    fn elf_load_segments() {
+        // ELF32 little endian, one PT_LOAD at paddr 0x0800_0000 and one without file data
+        let mut elf = vec![0; 0x80];
+        elf[..6].copy_from_slice(b"\x7FELF\x01\x01");
+        elf[0x1C..0x20].copy_from_slice(&0x34u32.to_le_bytes());
+        elf[0x2A..0x2C].copy_from_slice(&32u16.to_le_bytes());
+        elf[0x2C..0x2E].copy_from_slice(&2u16.to_le_bytes());
+        for (ph, filesz) in [(0x34, 4u32), (0x54, 0)] {
+            elf[ph..ph + 4].copy_from_slice(&1u32.to_le_bytes());
+            elf[ph + 4..ph + 8].copy_from_slice(&0x78u32.to_le_bytes());
+            elf[ph + 12..ph + 16].copy_from_slice(&0x0800_0000u32.to_le_bytes());
+            elf[ph + 16..ph + 20].copy_from_slice(&filesz.to_le_bytes());
+        }
+        elf[0x78..0x7C].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        let mut image = Image::from_elf(&elf).unwrap();
+        image.rebase(0x0800_0000, 0x1000).unwrap();
+        assert_eq!(
+            image.flatten(0xFF),
+            Some((0x1000, vec![0xDE, 0xAD, 0xBE, 0xEF]))
+        );
+    }
+    #[test]
+    fn flatten_fills_gaps() {
+        let mut image = Image::from_bin(&[1, 2], 0x10).unwrap();
+        image.insert(0x14, &[3]).unwrap();
+        assert!(matches!(
+            image.insert(0x11, &[0]),
+            Err(ImageError::Overlap(0x11))
+        ));
+        assert_eq!(image.flatten(0xFF), Some((0x10, vec![1, 2, 0xFF, 0xFF, 3])));
+    }
+}
@@ -1,11 +1,16 @@
+use super::JtagDevice;
 use crate::{
     ChipType, FtdiError, Pin,
+    bitbang::{BitBang, Sequence},
     gpio::{FtdiOutputPin, UsedPin},
     mpsse::{FtdiMpsse, PinUsage},
     mpsse_cmd::MpsseCmdBuilder,
 };
 use eh1::digital::OutputPin;
-use std::sync::{Arc, Mutex};
+use std::{
+    cell::{Cell, RefCell},
+    sync::{Arc, Mutex, PoisonError},
+};
 
 const TCK_MASK: u8 = Pin::Lower(0).mask();
 const TDI_MASK: u8 = Pin::Lower(1).mask();
@@ -14,26 +19,52 @@ const TDO_MASK: u8 = Pin::Lower(2).mask();
 const TMS_MASK: u8 = Pin::Lower(3).mask();
 // TCK(AD0) must be init with value 0.
 // TDI(AD1) only can output on second edge.
-// TDO(AD2) only can sample on first edge.
+// TDO(AD2) samples on first edge, or second edge with `FtdiJtag::set_tdo_neg_read`.
 // according to AN108-2.2.
 // https://ftdichip.com/Support/Documents/AppNotes/AN_108_Command_Processor_for_MPSSE_and_MCU_Host_Bus_Emulation_Modes.pdf
 const TCK_INIT_VALUE: bool = false;
 const IS_LSB: bool = true;
 
+/// TAP controller state as tracked by [`FtdiJtag`]
+///
+/// Only the states the controller can be left in are tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JtagState {
+    /// Not known yet, the next operation starts with a reset
+    Unknown,
+    RunTestIdle,
+    ShiftDr,
+    Exit1Dr,
+    ShiftIr,
+    Exit1Ir,
+}
+
 /// JTAG (Joint Test Action Group) interface controller
 /// Implements JTAG state machine management and data transfer operations
 pub struct FtdiJtag {
-    _pins: [UsedPin; 4],
+    engine: Engine,
     /// Thread-safe handle to FTDI MPSSE controller
     mtx: Arc<Mutex<FtdiMpsse>>,
     /// Whether adaptive clocking (RTCK) is enabled
     adaptive_clocking_pin: Option<UsedPin>,
     /// Optional custom pin assignments for JTAG signals
     direction: Option<[FtdiOutputPin; 4]>,
+    /// Current TAP state, updated by every operation
+    state: Cell<JtagState>,
+    /// Whether TDO is sampled on the falling TCK edge
+    tdo_neg_read: bool,
+    /// Whether IR scans of the instruction already loaded are skipped
+    ir_cache: bool,
+    /// Length and bits of the instruction loaded by the last IR scan, if known
+    ir: RefCell<Option<(usize, Vec<u8>)>>,
+    /// IR lengths of the TAPs, TDO side first, when IR captures are checked
+    ir_lens: Option<Vec<usize>>,
 }
 impl Drop for FtdiJtag {
     fn drop(&mut self) {
-        self.adaptive_clock(false).unwrap();
+        if let Err(e) = self.adaptive_clock(false) {
+            log::warn!("Failed to turn adaptive clocking off: {e}");
+        }
     }
 }
 impl FtdiJtag {
@@ -52,19 +83,51 @@ impl FtdiJtag {
     /// - TDO: Lower(2) - Test Data Out
     /// - TMS: Lower(3) - Test Mode Select
     pub fn new(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Self, FtdiError> {
-        let this = Self {
-            _pins: [
-                UsedPin::new(mtx.clone(), Pin::Lower(0), PinUsage::Jtag)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(1), PinUsage::Jtag)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(2), PinUsage::Jtag)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(3), PinUsage::Jtag)?,
-            ],
-            mtx: mtx.clone(),
-            adaptive_clocking_pin: None,
-            direction: None,
-        };
+        Self::new_with_pins(
+            mtx,
+            Pin::Lower(0),
+            Pin::Lower(1),
+            Pin::Lower(2),
+            Pin::Lower(3),
+        )
+    }
+    /// Creates a JTAG interface on any four pins
+    ///
+    /// The MPSSE only shifts JTAG on AD0 to AD3 in this order, other pins are bit-banged
+    /// with one GPIO command per TCK edge, which is much slower. Adaptive clocking needs
+    /// the MPSSE pins.
+    pub fn new_with_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        tck: Pin,
+        tdi: Pin,
+        tdo: Pin,
+        tms: Pin,
+    ) -> Result<Self, FtdiError> {
+        let mpsse_pins = [Pin::Lower(0), Pin::Lower(1), Pin::Lower(2), Pin::Lower(3)];
+        if [tck, tdi, tdo, tms] != mpsse_pins {
+            log::info!("Bit-bang JTAG on TCK {tck}, TDI {tdi}, TDO {tdo}, TMS {tms}");
+            let bitbang = BitBang::new(mtx.clone(), &[tck, tdi, tms], &[tdo])?;
+            return Ok(Self::with_engine(
+                mtx,
+                Engine::BitBang {
+                    bitbang,
+                    pins: JtagPins { tck, tdi, tdo, tms },
+                },
+            ));
+        }
+        let this = Self::with_engine(
+            mtx.clone(),
+            Engine::Mpsse {
+                _pins: [
+                    UsedPin::new(mtx.clone(), tck, PinUsage::Jtag)?,
+                    UsedPin::new(mtx.clone(), tdi, PinUsage::Jtag)?,
+                    UsedPin::new(mtx.clone(), tdo, PinUsage::Jtag)?,
+                    UsedPin::new(mtx.clone(), tms, PinUsage::Jtag)?,
+                ],
+            },
+        );
         {
-            let mut lock = mtx.lock().unwrap();
+            let mut lock = FtdiMpsse::lock(&mtx)?;
             // Set TCK, TDI, TMS as output pins (0x0b = 00001011)
             lock.lower.direction |= TCK_MASK | TDI_MASK | TMS_MASK;
             // TCK must initialize to low (AN108-2.2)
@@ -77,6 +140,33 @@ impl FtdiJtag {
         }
         Ok(this)
     }
+    fn with_engine(mtx: Arc<Mutex<FtdiMpsse>>, engine: Engine) -> Self {
+        Self {
+            engine,
+            mtx,
+            adaptive_clocking_pin: None,
+            direction: None,
+            state: Cell::new(JtagState::Unknown),
+            tdo_neg_read: false,
+            ir_cache: true,
+            ir: RefCell::new(None),
+            ir_lens: None,
+        }
+    }
+    /// Runs `cmd` on the MPSSE or bit-bangs it, the response is laid out the same way
+    fn exec(&self, cmd: JtagCmdBuilder) -> Result<Vec<u8>, FtdiError> {
+        match &self.engine {
+            Engine::Mpsse { .. } => FtdiMpsse::lock(&self.mtx)?.exec(cmd),
+            Engine::BitBang { bitbang, pins } => {
+                let (seq, groups) = cmd.bitbang(pins);
+                Ok(pack_samples(&bitbang.run(&seq)?, &groups))
+            }
+        }
+    }
+    /// Turns adaptive clocking off and drops the JTAG, returning what dropping would only log
+    pub fn shutdown(mut self) -> Result<(), FtdiError> {
+        self.adaptive_clock(false)
+    }
     /// Enables/disables adaptive clocking (RTCK)
     ///
     /// # Arguments
@@ -88,8 +178,14 @@ impl FtdiJtag {
         if self.adaptive_clocking_pin.is_some() == state {
             return Ok(());
         }
+        if let Engine::BitBang { .. } = self.engine {
+            return Err(FtdiError::Other(
+                "Adaptive clocking needs JTAG on the MPSSE pins.",
+            ));
+        }
         {
-            let lock = self.mtx.lock().unwrap();
+            // Also called from drop, possibly while unwinding
+            let lock = self.mtx.lock().unwrap_or_else(PoisonError::into_inner);
             if lock.chip_type == ChipType::FT2232D {
                 return Ok(());
             }
@@ -98,28 +194,28 @@ impl FtdiJtag {
             lock.exec(cmd)?;
         }
         if state {
-            log::info!("Use {:?} as RTCK.", Pin::Lower(7));
+            log::info!("Use {} as RTCK.", Pin::Lower(7));
             self.adaptive_clocking_pin = Some(UsedPin::new(
                 self.mtx.clone(),
                 Pin::Lower(7),
                 PinUsage::Jtag,
             )?);
         } else {
-            log::info!("Free {:?}.", Pin::Lower(7));
+            log::info!("Free {}.", Pin::Lower(7));
             self.adaptive_clocking_pin = None;
         }
         Ok(())
     }
-    /// Configures custom JTAG pin assignments
+    /// Drives the direction pins of level shifters in front of the JTAG signals
     ///
     /// # Arguments
-    /// * `tck` - Test Clock pin
-    /// * `tdi` - Test Data Input pin
-    /// * `tdo` - Test Data Output pin
-    /// * `tms` - Test Mode Select pin
+    /// * `tck` - TCK buffer direction pin
+    /// * `tdi` - TDI buffer direction pin
+    /// * `tdo` - TDO buffer direction pin
+    /// * `tms` - TMS buffer direction pin
     ///
     /// # Important
-    /// Must be called after initialization, original default pins will be overridden
+    /// The JTAG signals stay where they are, use [`FtdiJtag::new_with_pins`] to move them
     pub fn with_direction(
         &mut self,
         tck: Pin,
@@ -138,19 +234,148 @@ impl FtdiJtag {
         self.direction = Some([tck, tdi, tdo, tms]);
         Ok(())
     }
+    /// Samples TDO on the falling TCK edge instead of the rising edge
+    ///
+    /// Targets that return TDO late, because they are slow or behind level shifters,
+    /// may only read back stable data this way at higher TCK rates.
+    pub fn set_tdo_neg_read(&mut self, enable: bool) {
+        self.tdo_neg_read = enable;
+    }
+    /// Skip the IR scan of [`FtdiJtag::write`], [`FtdiJtag::read`] and [`FtdiJtag::write_read`]
+    /// when the instruction is still loaded from the previous call, enabled by default
+    ///
+    /// Repeated polling of one register then costs a DR scan only. Without the cache every
+    /// call resets the TAP and loads the instruction again.
+    pub fn set_ir_cache(&mut self, enable: bool) {
+        self.ir_cache = enable;
+        self.invalidate_ir();
+    }
+    /// Checks the bits captured by every IR scan of [`FtdiJtag::write`], [`FtdiJtag::read`]
+    /// and [`FtdiJtag::write_read`], `None` turns the check off (the default)
+    ///
+    /// `ir_lens` are the IR lengths of the TAPs, the one next to TDO first. Each TAP must
+    /// capture `...01`, the first one that does not is reported as [`FtdiError::IrCapture`],
+    /// which usually points at a wrong chain order, a wrong IR length or bad wiring.
+    pub fn set_ir_check(&mut self, ir_lens: Option<&[usize]>) {
+        self.ir_lens = ir_lens.map(<[usize]>::to_vec);
+    }
+    /// Forgets the loaded instruction, e.g. after the target was reset behind our back
+    pub fn invalidate_ir(&self) {
+        self.ir.borrow_mut().take();
+    }
     pub fn goto_idle(&mut self) -> Result<(), FtdiError> {
-        let mut cmd = JtagCmdBuilder::new();
+        self.invalidate_ir();
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
         cmd.jtag_any2idle();
-        let lock = self.mtx.lock().unwrap();
-        lock.exec(cmd)?;
+        self.exec(cmd)?;
+        self.state.set(JtagState::RunTestIdle);
+        Ok(())
+    }
+    /// Current TAP state
+    pub fn state(&self) -> JtagState {
+        self.state.get()
+    }
+    /// Moves from Exit1-DR/IR through Update-DR/IR to Run-Test/Idle
+    ///
+    /// Unlike [`FtdiJtag::goto_idle`] this does not reset the TAP, so the instruction is kept.
+    pub fn update(&mut self) -> Result<(), FtdiError> {
+        match self.state.get() {
+            JtagState::RunTestIdle => return Ok(()),
+            JtagState::Exit1Dr | JtagState::Exit1Ir => (),
+            _ => return Err(FtdiError::Other("Jtag update is only possible from Exit1.")),
+        }
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
+        cmd.jtag_dr_exit2idle();
+        self.exec(cmd)?;
+        self.state.set(JtagState::RunTestIdle);
+        Ok(())
+    }
+    /// Clocks `cycles` TCK cycles in Run-Test/Idle
+    ///
+    /// A TAP in Exit1 is updated first, an unknown TAP is reset.
+    pub fn idle_cycles(&mut self, cycles: usize) -> Result<(), FtdiError> {
+        match self.state.get() {
+            JtagState::RunTestIdle => (),
+            JtagState::Exit1Dr | JtagState::Exit1Ir => self.update()?,
+            JtagState::Unknown => self.goto_idle()?,
+            JtagState::ShiftDr | JtagState::ShiftIr => {
+                return Err(FtdiError::Other(
+                    "Jtag shift state can only be left with the last bit.",
+                ));
+            }
+        }
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
+        for _ in 0..cycles / 7 {
+            cmd.jtag_idle_cycle();
+        }
+        cmd.tms(true, 0, cycles % 7, false);
+        self.exec(cmd)?;
         Ok(())
     }
+    /// Shifts `bits_count` bits of `tdi` through DR and returns the TDO bits, LSB first
+    ///
+    /// The TAP is moved to Shift-DR first if needed and stays there afterwards,
+    /// so long registers can be shifted in chunks of any size.
+    /// If `exit` is set the last bit is clocked with TMS high and the TAP ends in Exit1-DR,
+    /// from where [`FtdiJtag::update`] completes the scan.
+    pub fn shift_dr_raw(
+        &mut self,
+        tdi: &[u8],
+        bits_count: usize,
+        exit: bool,
+    ) -> Result<Vec<u8>, FtdiError> {
+        self.shift_raw(false, tdi, bits_count, exit)
+    }
+    /// Same as [`FtdiJtag::shift_dr_raw`] for the instruction register
+    pub fn shift_ir_raw(
+        &mut self,
+        tdi: &[u8],
+        bits_count: usize,
+        exit: bool,
+    ) -> Result<Vec<u8>, FtdiError> {
+        self.shift_raw(true, tdi, bits_count, exit)
+    }
+    fn shift_raw(
+        &mut self,
+        ir: bool,
+        tdi: &[u8],
+        bits_count: usize,
+        exit: bool,
+    ) -> Result<Vec<u8>, FtdiError> {
+        assert!(
+            tdi.len() * 8 >= bits_count,
+            "tdi should have at least {bits_count} bits"
+        );
+        if ir {
+            self.invalidate_ir();
+        }
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
+        cmd.jtag_goto_shift(self.state.get(), ir)?;
+        if bits_count == 0 {
+            self.exec(cmd)?;
+            self.state.set(Self::shift_state(ir, false));
+            return Ok(Vec::new());
+        }
+        cmd.jtag_shift_raw(tdi, bits_count, exit);
+        let mut response = self.exec(cmd)?;
+        self.state.set(Self::shift_state(ir, exit));
+        JtagCmdBuilder::jtag_parse_raw_shift(&mut response, bits_count, exit);
+        Ok(response)
+    }
+    fn shift_state(ir: bool, exit: bool) -> JtagState {
+        match (ir, exit) {
+            (false, false) => JtagState::ShiftDr,
+            (false, true) => JtagState::Exit1Dr,
+            (true, false) => JtagState::ShiftIr,
+            (true, true) => JtagState::Exit1Ir,
+        }
+    }
     pub fn scan_with(&mut self, tdi: bool) -> Result<Vec<u32>, FtdiError> {
         const ID_LEN: usize = 32;
-        let mut cmd = JtagCmdBuilder::new();
+        self.invalidate_ir();
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
         cmd.jtag_any2idle().jtag_idle2dr();
-        let lock = self.mtx.lock().unwrap();
-        lock.exec(cmd)?;
+        self.exec(cmd)?;
         let tdi = if tdi { vec![0xff; 4] } else { vec![0; 4] };
         // 移入0并读取TDO，持续直到检测到连续32个0
         let mut idcodes = Vec::new();
@@ -159,9 +384,9 @@ impl FtdiJtag {
         let mut consecutive_zeros = 0;
 
         'outer: loop {
-            let mut cmd = MpsseCmdBuilder::new();
-            cmd.shift_bytes(TCK_INIT_VALUE, IS_LSB, &tdi);
-            let response = lock.exec(cmd)?;
+            let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
+            cmd.bytes(Some(&tdi), tdi.len(), true);
+            let response = self.exec(cmd)?;
             let tdos: Vec<_> = response
                 .iter()
                 .flat_map(|&byte| (0..8).map(move |i| (byte >> i) & 1 == 1))
@@ -192,37 +417,61 @@ impl FtdiJtag {
             }
         }
         // 退出Shift-DR状态
-        drop(lock);
         self.goto_idle()?;
         Ok(idcodes)
     }
+    /// Scans the chain like [`FtdiJtag::scan_with`] and decodes every IDCODE
+    pub fn scan_decoded(&mut self, tdi: bool) -> Result<Vec<JtagDevice>, FtdiError> {
+        let idcodes = self.scan_with(tdi)?;
+        Ok(idcodes.into_iter().map(JtagDevice::decode).collect())
+    }
+    /// Counts the TAPs in the chain, including those without an IDCODE register
+    ///
+    /// Loads BYPASS into every TAP by filling the whole IR with ones, then measures how many
+    /// TCK cycles a marker bit takes through the one bit bypass registers.
+    /// The TAPs are reset afterwards.
+    pub fn count_devices(&mut self) -> Result<usize, FtdiError> {
+        // Upper bounds of the total IR length and of the chain length
+        const MAX_IR_BITS: usize = 1024;
+        const MAX_DEVICES: usize = 256;
+        self.goto_idle()?;
+        self.shift_ir_raw(&[0xff; MAX_IR_BITS / 8], MAX_IR_BITS, true)?;
+        self.update()?;
+        // Flush the bypass registers with zeros, then follow the first one
+        let mut tdi = vec![0x00; MAX_DEVICES / 8];
+        tdi.resize(MAX_DEVICES * 2 / 8, 0xff);
+        let tdo = self.shift_dr_raw(&tdi, MAX_DEVICES * 2, true)?;
+        self.goto_idle()?;
+        let bit = |idx: usize| (tdo[idx / 8] >> (idx % 8)) & 1 != 0;
+        if (0..MAX_DEVICES).any(bit) {
+            return Err(FtdiError::Other(
+                "Jtag TDO is not low after flushing the chain.",
+            ));
+        }
+        (MAX_DEVICES..MAX_DEVICES * 2)
+            .position(bit)
+            .ok_or(FtdiError::Other(
+                "Jtag marker bit did not come out of the chain.",
+            ))
+    }
     pub fn write(&self, ir: &[u8], irlen: usize, dr: &[u8], drlen: usize) -> Result<(), FtdiError> {
-        log::warn!("Not test");
-        let mut cmd = JtagCmdBuilder::new();
-
-        cmd.jtag_any2idle();
-        cmd.jtag_idle2ir()
-            .jtag_shift_write(ir, irlen)
-            .jtag_ir_exit2dr()
-            .jtag_shift_write(dr, drlen)
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
+        let loaded = self.goto_dr(&mut cmd, ir, irlen)?;
+        cmd.jtag_shift_write(dr, drlen)
             .jtag_dr_exit2idle()
             .jtag_idle_cycle();
-        let lock = self.mtx.lock().unwrap();
-        lock.exec(cmd)?;
+        let mut response = self.exec(cmd)?;
+        self.loaded(loaded, &mut response)?;
         Ok(())
     }
     pub fn read(&self, ir: &[u8], irlen: usize, drlen: usize) -> Result<Vec<u8>, FtdiError> {
-        log::warn!("Not test");
-        let mut cmd = JtagCmdBuilder::new();
-        cmd.jtag_any2idle();
-        cmd.jtag_idle2ir()
-            .jtag_shift_write(ir, irlen)
-            .jtag_ir_exit2dr()
-            .jtag_shift_read(drlen)
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
+        let loaded = self.goto_dr(&mut cmd, ir, irlen)?;
+        cmd.jtag_shift_read(drlen)
             .jtag_dr_exit2idle()
             .jtag_idle_cycle();
-        let lock = self.mtx.lock().unwrap();
-        let mut response = lock.exec(cmd)?;
+        let mut response = self.exec(cmd)?;
+        self.loaded(loaded, &mut response)?;
         let len = JtagCmdBuilder::jtag_parse_single_shift(&mut response, drlen);
 
         if response.len() > len {
@@ -237,17 +486,13 @@ impl FtdiJtag {
         dr: &[u8],
         drlen: usize,
     ) -> Result<Vec<u8>, FtdiError> {
-        log::warn!("Not test");
-        let mut cmd = JtagCmdBuilder::new();
-        cmd.jtag_any2idle();
-        cmd.jtag_idle2ir()
-            .jtag_shift_write(ir, irlen)
-            .jtag_ir_exit2dr()
-            .jtag_shift(dr, drlen)
+        let mut cmd = JtagCmdBuilder::new(self.tdo_neg_read);
+        let loaded = self.goto_dr(&mut cmd, ir, irlen)?;
+        cmd.jtag_shift(dr, drlen)
             .jtag_dr_exit2idle()
             .jtag_idle_cycle();
-        let lock = self.mtx.lock().unwrap();
-        let mut response = lock.exec(cmd)?;
+        let mut response = self.exec(cmd)?;
+        self.loaded(loaded, &mut response)?;
         let len = JtagCmdBuilder::jtag_parse_single_shift(&mut response, drlen);
 
         if response.len() > len {
@@ -255,75 +500,405 @@ impl FtdiJtag {
         }
         Ok(response)
     }
+    /// Moves to Shift-DR with the instruction `ir` loaded
+    ///
+    /// Returns the instruction when it had to be shifted, pass it to [`FtdiJtag::loaded`]
+    /// once the command went through.
+    fn goto_dr(
+        &self,
+        cmd: &mut JtagCmdBuilder,
+        ir: &[u8],
+        irlen: usize,
+    ) -> Result<Option<(usize, Vec<u8>)>, FtdiError> {
+        let instruction = (irlen, ir_bits(ir, irlen)?);
+        let hit = self.ir_cache
+            && self.state.get() == JtagState::RunTestIdle
+            && self.ir.borrow().as_ref() == Some(&instruction);
+        if hit {
+            cmd.jtag_idle2dr();
+            return Ok(None);
+        }
+        // A failed command leaves an unknown instruction behind
+        self.invalidate_ir();
+        cmd.jtag_any2idle().jtag_idle2ir();
+        if self.ir_lens.is_some() {
+            cmd.jtag_shift(ir, irlen);
+        } else {
+            cmd.jtag_shift_write(ir, irlen);
+        }
+        cmd.jtag_ir_exit2dr();
+        Ok(Some(instruction))
+    }
+    /// Records the state after a scan started with [`FtdiJtag::goto_dr`]
+    ///
+    /// A checked IR capture is taken off the front of `response`.
+    fn loaded(
+        &self,
+        instruction: Option<(usize, Vec<u8>)>,
+        response: &mut Vec<u8>,
+    ) -> Result<(), FtdiError> {
+        self.state.set(JtagState::RunTestIdle);
+        let Some(instruction) = instruction else {
+            return Ok(());
+        };
+        if let Some(ir_lens) = &self.ir_lens {
+            let irlen = instruction.0;
+            // Whole bytes, the remaining bits if any, and the last bit clocked with TMS
+            let len = (irlen - 1) / 8 + ((irlen - 1) % 8 != 0) as usize + 1;
+            let mut capture: Vec<u8> = response.drain(..len).collect();
+            JtagCmdBuilder::jtag_parse_single_shift(&mut capture, irlen);
+            check_ir_capture(&capture, irlen, ir_lens)?;
+        }
+        if self.ir_cache {
+            *self.ir.borrow_mut() = Some(instruction);
+        }
+        Ok(())
+    }
+}
+
+/// JTAG pins of [`FtdiJtag`] when they are bit-banged
+struct JtagPins {
+    tck: Pin,
+    tdi: Pin,
+    tdo: Pin,
+    tms: Pin,
+}
+
+/// How [`FtdiJtag`] drives its pins
+enum Engine {
+    /// TCK, TDI, TDO and TMS on AD0 to AD3, clocked by the MPSSE
+    Mpsse { _pins: [UsedPin; 4] },
+    /// Any other pins, one GPIO command per edge
+    BitBang { bitbang: BitBang, pins: JtagPins },
 }
 
-struct JtagCmdBuilder(MpsseCmdBuilder);
+/// One MPSSE JTAG command, kept as an operation so it can also be bit-banged
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JtagOp {
+    /// `len` TMS bits, LSB first, with TDI held at `tdi`
+    Tms {
+        tdi: bool,
+        tms: u8,
+        len: usize,
+        read: bool,
+    },
+    /// `len` whole bytes, `None` leaves TDI alone and only reads
+    Bytes {
+        tdi: Option<Vec<u8>>,
+        len: usize,
+        read: bool,
+    },
+    /// The low `len` bits of `tdi`, `None` leaves TDI alone and only reads
+    Bits {
+        tdi: Option<u8>,
+        len: usize,
+        read: bool,
+    },
+}
+
+/// The `irlen` instruction bits of `ir` with the unused high bits cleared
+fn ir_bits(ir: &[u8], irlen: usize) -> Result<Vec<u8>, FtdiError> {
+    if irlen == 0 {
+        return Err(FtdiError::Other("Jtag instruction length must not be 0."));
+    }
+    let Some(bits) = ir.get(..irlen.div_ceil(8)) else {
+        return Err(FtdiError::Other(
+            "Jtag instruction is shorter than its length.",
+        ));
+    };
+    let mut bits = bits.to_vec();
+    let tail_bits = irlen % 8;
+    if tail_bits != 0 {
+        *bits.last_mut().unwrap() &= (1 << tail_bits) - 1;
+    }
+    Ok(bits)
+}
+
+/// Checks that every TAP of the `irlen` captured IR bits starts with `01`, LSB first
+fn check_ir_capture(capture: &[u8], irlen: usize, ir_lens: &[usize]) -> Result<(), FtdiError> {
+    if ir_lens.iter().sum::<usize>() != irlen {
+        return Err(FtdiError::Other(
+            "Jtag IR lengths of the check do not add up to the instruction length.",
+        ));
+    }
+    let bit = |idx: usize| (capture[idx / 8] >> (idx % 8)) & 1 != 0;
+    let mut offset = 0;
+    for (tap, &len) in ir_lens.iter().enumerate() {
+        if !bit(offset) || (len > 1 && bit(offset + 1)) {
+            let captured =
+                (0..len.min(64)).fold(0, |value, idx| value | ((bit(offset + idx) as u64) << idx));
+            log::warn!("IR capture of TAP {tap} is {captured:#b}");
+            return Err(FtdiError::IrCapture { tap, captured });
+        }
+        offset += len;
+    }
+    Ok(())
+}
+
+/// Packs bit-banged TDO samples like the MPSSE returns them
+///
+/// Every read command answers one byte per 8 bits, the bits shifted in from the MSB.
+fn pack_samples(samples: &[bool], groups: &[usize]) -> Vec<u8> {
+    let mut samples = samples.iter();
+    groups
+        .iter()
+        .map(|&len| {
+            samples
+                .by_ref()
+                .take(len)
+                .enumerate()
+                .fold(0, |byte, (idx, &bit)| {
+                    byte | ((bit as u8) << (8 - len + idx))
+                })
+        })
+        .collect()
+}
+
+struct JtagCmdBuilder {
+    tdo_neg_read: bool,
+    ops: Vec<JtagOp>,
+}
 impl From<JtagCmdBuilder> for MpsseCmdBuilder {
     fn from(value: JtagCmdBuilder) -> Self {
-        value.0
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.tdo_neg_read(value.tdo_neg_read);
+        for op in value.ops {
+            match op {
+                JtagOp::Tms {
+                    tdi,
+                    tms,
+                    len,
+                    read: true,
+                } => cmd.clock_tms(tdi, tms, len),
+                JtagOp::Tms { tdi, tms, len, .. } => cmd.clock_tms_out(tdi, tms, len),
+                JtagOp::Bytes { tdi: None, len, .. } => {
+                    cmd.shift_bytes_in(TCK_INIT_VALUE, IS_LSB, len)
+                }
+                JtagOp::Bytes {
+                    tdi: Some(tdi),
+                    read: true,
+                    ..
+                } => cmd.shift_bytes(TCK_INIT_VALUE, IS_LSB, &tdi),
+                JtagOp::Bytes { tdi: Some(tdi), .. } => {
+                    cmd.shift_bytes_out(TCK_INIT_VALUE, IS_LSB, &tdi)
+                }
+                JtagOp::Bits { tdi: None, len, .. } => {
+                    cmd.shift_bits_in(TCK_INIT_VALUE, IS_LSB, len)
+                }
+                JtagOp::Bits {
+                    tdi: Some(tdi),
+                    len,
+                    read: true,
+                } => cmd.shift_bits(TCK_INIT_VALUE, IS_LSB, tdi, len),
+                JtagOp::Bits {
+                    tdi: Some(tdi),
+                    len,
+                    ..
+                } => cmd.shift_bits_out(TCK_INIT_VALUE, IS_LSB, tdi, len),
+            };
+        }
+        cmd
     }
 }
 impl JtagCmdBuilder {
-    fn new() -> Self {
-        JtagCmdBuilder(MpsseCmdBuilder::new())
+    fn new(tdo_neg_read: bool) -> Self {
+        JtagCmdBuilder {
+            tdo_neg_read,
+            ops: Vec::new(),
+        }
     }
-    fn jtag_any2idle(&mut self) -> &mut Self {
-        self.0.clock_tms_out(true, 0b0001_1111, 6);
+    fn tms(&mut self, tdi: bool, tms: u8, len: usize, read: bool) -> &mut Self {
+        if len != 0 {
+            self.ops.push(JtagOp::Tms {
+                tdi,
+                tms,
+                len,
+                read,
+            });
+        }
         self
     }
-    fn jtag_idle_cycle(&mut self) -> &mut Self {
-        self.0.clock_tms_out(true, 0, 7);
+    fn bytes(&mut self, tdi: Option<&[u8]>, len: usize, read: bool) -> &mut Self {
+        if len != 0 {
+            self.ops.push(JtagOp::Bytes {
+                tdi: tdi.map(<[u8]>::to_vec),
+                len,
+                read: read || tdi.is_none(),
+            });
+        }
         self
     }
-    fn jtag_idle2ir(&mut self) -> &mut Self {
-        self.0.clock_tms_out(true, 0b0000_0011, 4);
+    fn bits(&mut self, tdi: Option<u8>, len: usize, read: bool) -> &mut Self {
+        if len != 0 {
+            self.ops.push(JtagOp::Bits {
+                tdi,
+                len,
+                read: read || tdi.is_none(),
+            });
+        }
         self
     }
+    /// Bit-banged equivalent of the command, with the number of samples per response byte
+    ///
+    /// TDO is sampled before the rising TCK edge, whatever `tdo_neg_read` says.
+    fn bitbang(&self, pins: &JtagPins) -> (Sequence, Vec<usize>) {
+        let mut seq = Sequence::new();
+        let mut groups = Vec::new();
+        let mut clock = |tdi: Option<bool>, tms: Option<bool>, read: bool| {
+            if let Some(tdi) = tdi {
+                seq.set(pins.tdi, tdi);
+            }
+            if let Some(tms) = tms {
+                seq.set(pins.tms, tms);
+            }
+            seq.commit();
+            if read {
+                seq.sample(pins.tdo);
+            }
+            seq.edge(pins.tck, true).edge(pins.tck, false);
+        };
+        let bit = |byte: u8, idx: usize| (byte >> idx) & 1 != 0;
+        for op in &self.ops {
+            match op {
+                JtagOp::Tms {
+                    tdi,
+                    tms,
+                    len,
+                    read,
+                } => {
+                    for idx in 0..*len {
+                        clock(Some(*tdi), Some(bit(*tms, idx)), *read);
+                    }
+                    if *read {
+                        groups.push(*len);
+                    }
+                }
+                JtagOp::Bytes { tdi, len, read } => {
+                    for byte_idx in 0..*len {
+                        let byte = tdi.as_ref().map(|tdi| tdi[byte_idx]);
+                        for idx in 0..8 {
+                            clock(byte.map(|byte| bit(byte, idx)), None, *read);
+                        }
+                        if *read {
+                            groups.push(8);
+                        }
+                    }
+                }
+                JtagOp::Bits { tdi, len, read } => {
+                    for idx in 0..*len {
+                        clock(tdi.map(|byte| bit(byte, idx)), None, *read);
+                    }
+                    if *read {
+                        groups.push(*len);
+                    }
+                }
+            }
+        }
+        (seq, groups)
+    }
+    fn jtag_any2idle(&mut self) -> &mut Self {
+        self.tms(true, 0b0001_1111, 6, false)
+    }
+    fn jtag_idle_cycle(&mut self) -> &mut Self {
+        self.tms(true, 0, 7, false)
+    }
+    fn jtag_idle2ir(&mut self) -> &mut Self {
+        self.tms(true, 0b0000_0011, 4, false)
+    }
     fn jtag_ir_exit2dr(&mut self) -> &mut Self {
-        self.0.clock_tms_out(true, 0b0000_0011, 4);
-        self
+        self.tms(true, 0b0000_0011, 4, false)
     }
     fn jtag_idle2dr(&mut self) -> &mut Self {
-        self.0.clock_tms_out(true, 0b0000_0001, 3);
-        self
+        self.tms(true, 0b0000_0001, 3, false)
     }
     fn jtag_dr_exit2idle(&mut self) -> &mut Self {
-        self.0.clock_tms_out(true, 0b0000_0001, 2);
-        self
+        self.tms(true, 0b0000_0001, 2, false)
     }
     fn jtag_shift(&mut self, data: &[u8], bits_count: usize) -> &mut Self {
         assert!(bits_count != 0);
         let bytes_count = (bits_count - 1) >> 3;
         let remain_bits = (bits_count - 1) & 0b111;
         let last_bit = data[bytes_count] >> remain_bits == 1;
-        self.0
-            .shift_bytes(TCK_INIT_VALUE, IS_LSB, &data[0..bytes_count])
-            .shift_bits(TCK_INIT_VALUE, IS_LSB, data[bytes_count], remain_bits)
-            .clock_tms(last_bit, 0b0000_0001, 1);
-        self
+        self.bytes(Some(&data[0..bytes_count]), bytes_count, true)
+            .bits(Some(data[bytes_count]), remain_bits, true)
+            .tms(last_bit, 0b0000_0001, 1, true)
     }
     fn jtag_shift_write(&mut self, data: &[u8], bits_count: usize) -> &mut Self {
         assert!(bits_count != 0);
         let bytes_count = (bits_count - 1) >> 3;
         let remain_bits = (bits_count - 1) & 0b111;
         let last_bit = data[bytes_count] >> remain_bits == 1;
-        self.0
-            .shift_bytes_out(TCK_INIT_VALUE, IS_LSB, &data[0..bytes_count])
-            .shift_bits_out(TCK_INIT_VALUE, IS_LSB, data[bytes_count], remain_bits)
-            .clock_tms_out(last_bit, 0b0000_0001, 1);
-        self
+        self.bytes(Some(&data[0..bytes_count]), bytes_count, false)
+            .bits(Some(data[bytes_count]), remain_bits, false)
+            .tms(last_bit, 0b0000_0001, 1, false)
     }
     fn jtag_shift_read(&mut self, bits_count: usize) -> &mut Self {
         assert!(bits_count != 0);
         let bytes_count = (bits_count - 1) >> 3;
         let remain_bits = (bits_count - 1) & 0b111;
         let last_bit = Default::default(); // the last bit of tdi when shift2exit
-        self.0
-            .shift_bytes_in(TCK_INIT_VALUE, IS_LSB, bytes_count)
-            .shift_bits_in(TCK_INIT_VALUE, IS_LSB, remain_bits)
-            .clock_tms(last_bit, 0b0000_0001, 1);
+        self.bytes(None, bytes_count, true)
+            .bits(None, remain_bits, true)
+            .tms(last_bit, 0b0000_0001, 1, true)
+    }
+    // TMS sequences, LSB first, from the tracked state to Shift-DR/IR.
+    fn jtag_goto_shift(&mut self, from: JtagState, ir: bool) -> Result<&mut Self, FtdiError> {
+        match (from, ir) {
+            (JtagState::Unknown, false) => {
+                self.jtag_any2idle().jtag_idle2dr();
+            }
+            (JtagState::Unknown, true) => {
+                self.jtag_any2idle().jtag_idle2ir();
+            }
+            (JtagState::RunTestIdle, false) => {
+                self.jtag_idle2dr();
+            }
+            (JtagState::RunTestIdle, true) => {
+                self.jtag_idle2ir();
+            }
+            (JtagState::ShiftDr, false) | (JtagState::ShiftIr, true) => (),
+            (JtagState::ShiftDr, true) | (JtagState::ShiftIr, false) => {
+                return Err(FtdiError::Other(
+                    "Jtag shift state can only be left with the last bit.",
+                ));
+            }
+            // Exit1 -> Pause -> Exit2 -> Shift
+            (JtagState::Exit1Dr, false) | (JtagState::Exit1Ir, true) => {
+                self.tms(true, 0b0000_0010, 3, false);
+            }
+            // Exit1-DR -> Update-DR -> Select-DR -> Select-IR -> Capture-IR -> Shift-IR
+            (JtagState::Exit1Dr, true) => {
+                self.tms(true, 0b0000_0111, 5, false);
+            }
+            // Exit1-IR -> Update-IR -> Select-DR -> Capture-DR -> Shift-DR
+            (JtagState::Exit1Ir, false) => {
+                self.jtag_ir_exit2dr();
+            }
+        }
+        Ok(self)
+    }
+    fn jtag_shift_raw(&mut self, data: &[u8], bits_count: usize, exit: bool) -> &mut Self {
+        if exit {
+            return self.jtag_shift(data, bits_count);
+        }
+        let bytes_count = bits_count >> 3;
+        let remain_bits = bits_count & 0b111;
+        self.bytes(Some(&data[0..bytes_count]), bytes_count, true);
+        if remain_bits != 0 {
+            self.bits(Some(data[bytes_count]), remain_bits, true);
+        }
         self
     }
+    fn jtag_parse_raw_shift(response: &mut Vec<u8>, bits_count: usize, exit: bool) {
+        if exit {
+            let len = Self::jtag_parse_single_shift(response, bits_count);
+            response.truncate(len);
+        } else if bits_count & 0b111 != 0 {
+            // bits are shifted in from the MSB
+            let last = response.len() - 1;
+            response[last] >>= 8 - (bits_count & 0b111);
+        }
+    }
     fn jtag_parse_single_shift(response: &mut [u8], bits_count: usize) -> usize {
         assert!(bits_count != 0);
         let bytes_count = (bits_count - 1) >> 3;
@@ -337,3 +912,47 @@ impl JtagCmdBuilder {
         bytes_count + 1
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{JtagCmdBuilder, JtagPins, check_ir_capture, ir_bits, pack_samples};
+    use crate::{FtdiError, Pin};
+
+    #[test]
+    fn instruction_bits() {
+        assert_eq!(ir_bits(&[0xFF, 0xFF], 10).unwrap(), [0xFF, 0x03]);
+        assert_eq!(ir_bits(&[0xFA], 4).unwrap(), [0x0A]);
+        assert!(matches!(ir_bits(&[0xFF], 0), Err(FtdiError::Other(_))));
+        assert!(matches!(ir_bits(&[0xFF], 9), Err(FtdiError::Other(_))));
+    }
+
+    #[test]
+    fn bitbang_matches_mpsse_layout() {
+        // 3 data bits, then the last bit with TMS high
+        assert_eq!(
+            pack_samples(&[true, false, true, true], &[3, 1]),
+            [0b1010_0000, 0b1000_0000]
+        );
+        let pins = JtagPins {
+            tck: Pin::Upper(0),
+            tdi: Pin::Upper(1),
+            tdo: Pin::Upper(2),
+            tms: Pin::Upper(3),
+        };
+        let mut cmd = JtagCmdBuilder::new(false);
+        cmd.jtag_shift(&[0xA5, 0x01], 9);
+        let (_, groups) = cmd.bitbang(&pins);
+        assert_eq!(groups, [8, 1]);
+    }
+    #[test]
+    fn ir_capture_pattern() {
+        // TAP 0 with 4 bits captures 0b0001, TAP 1 with 5 bits 0b10101
+        let capture = [0b0101_0001, 0b1];
+        assert!(check_ir_capture(&capture, 9, &[4, 5]).is_ok());
+        assert!(matches!(
+            check_ir_capture(&capture, 9, &[5, 4]),
+            Err(FtdiError::IrCapture { tap: 1, .. })
+        ));
+        assert!(check_ir_capture(&capture, 9, &[4, 4]).is_err());
+    }
+}
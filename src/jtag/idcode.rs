@@ -0,0 +1,95 @@
+/// JEP106 manufacturer identity, bits [11:1] of an IDCODE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jep106 {
+    /// Number of continuation codes (0x7F) preceding the identity.
+    pub bank: u8,
+    /// Identity code without the parity bit.
+    pub id: u8,
+}
+impl Jep106 {
+    // (bank, id, name), only the vendors commonly seen on JTAG chains.
+    const KNOWN: &[(u8, u8, &'static str)] = &[
+        (0, 0x01, "AMD"),
+        (0, 0x04, "Fujitsu"),
+        (0, 0x09, "Intel"),
+        (0, 0x0E, "Freescale (Motorola)"),
+        (0, 0x15, "NXP (Philips)"),
+        (0, 0x17, "Texas Instruments"),
+        (0, 0x1F, "Atmel"),
+        (0, 0x20, "STMicroelectronics"),
+        (0, 0x21, "Lattice"),
+        (0, 0x29, "Microchip"),
+        (0, 0x34, "Cypress"),
+        (0, 0x41, "Infineon"),
+        (0, 0x49, "Xilinx"),
+        (0, 0x65, "Analog Devices"),
+        (0, 0x6E, "Altera"),
+        (4, 0x3B, "ARM"),
+        (9, 0x09, "SiFive"),
+    ];
+    /// Manufacturer name, if it is in the built-in table.
+    pub fn name(self) -> Option<&'static str> {
+        Self::KNOWN
+            .iter()
+            .find(|(bank, id, _)| *bank == self.bank && *id == self.id)
+            .map(|(_, _, name)| *name)
+    }
+}
+
+/// One device found on a JTAG chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JtagDevice {
+    /// Device reporting an IDCODE.
+    Device {
+        idcode: u32,
+        manufacturer: Jep106,
+        part: u16,
+        version: u8,
+    },
+    /// Device without IDCODE, it selected BYPASS after reset.
+    Bypass,
+}
+impl JtagDevice {
+    /// Decode a value returned by [`FtdiJtag::scan_with`](super::FtdiJtag::scan_with),
+    /// where bypassed devices are reported as 0.
+    pub fn decode(idcode: u32) -> Self {
+        if idcode & 1 == 0 {
+            return JtagDevice::Bypass;
+        }
+        // IDCODE: [31:28] version, [27:12] part, [11:8] bank, [7:1] id, [0] 1
+        JtagDevice::Device {
+            idcode,
+            manufacturer: Jep106 {
+                bank: ((idcode >> 8) & 0x0F) as u8,
+                id: ((idcode >> 1) & 0x7F) as u8,
+            },
+            part: (idcode >> 12) as u16,
+            version: (idcode >> 28) as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Jep106, JtagDevice};
+
+    #[test]
+    fn decode_idcode() {
+        // Cortex-M4 SW-DP/JTAG-DP
+        assert_eq!(
+            JtagDevice::decode(0x4BA0_0477),
+            JtagDevice::Device {
+                idcode: 0x4BA0_0477,
+                manufacturer: Jep106 { bank: 4, id: 0x3B },
+                part: 0xBA00,
+                version: 4,
+            }
+        );
+        // XC7A35T
+        let JtagDevice::Device { manufacturer, .. } = JtagDevice::decode(0x0362_D093) else {
+            panic!("not a device");
+        };
+        assert_eq!(manufacturer.name(), Some("Xilinx"));
+        assert_eq!(JtagDevice::decode(0), JtagDevice::Bypass);
+    }
+}
@@ -1,11 +1,17 @@
 use crate::{FtdiError, mpsse::FtdiMpsse, mpsse_cmd::MpsseCmdBuilder};
 
 const ID_LEN: usize = 32;
+/// Pin index of the detectors: 0-7 are the lower pins, 8 and up are the upper pins.
+const UPPER_OFFSET: usize = 8;
 pub struct JtagDetectTdo {
     /// Thread-safe handle to FTDI MPSSE controller
     mpsse: FtdiMpsse,
-    tck_mask: u8,
-    tms_mask: u8,
+    /// Whether the upper pins are driven and sampled too
+    upper: bool,
+    /// Pins the detection may use, see [`JtagDetectTdo::set_candidates`]
+    candidates: u16,
+    tck_mask: u16,
+    tms_mask: u16,
 }
 impl From<JtagDetectTdo> for FtdiMpsse {
     fn from(value: JtagDetectTdo) -> Self {
@@ -19,38 +25,51 @@ impl JtagDetectTdo {
     ///
     /// Parameters:
     ///
-    /// tck & tms are all lower pins index
+    /// tck & tms are pin indexes, see [`JtagDetectTdo::include_upper`]
     pub fn new(mpsse: impl Into<FtdiMpsse>) -> Self {
         // all pins default set to low
         Self {
             mpsse: mpsse.into(),
+            upper: false,
+            candidates: u16::MAX,
             tck_mask: 0,
             tms_mask: 0,
         }
     }
+    /// Also detect on the upper pins, they are indexed from 8 up to [`JtagDetectTdo::pin_count`]
+    pub fn include_upper(&mut self, enable: bool) -> Result<(), FtdiError> {
+        self.upper = enable && check_upper(&self.mpsse)?;
+        Ok(())
+    }
+    /// Number of pin indexes the detector can use
+    pub fn pin_count(&self) -> usize {
+        pin_count(&self.mpsse, self.upper)
+    }
+    /// Restricts detection to the pin indexes in `pins`, all pins are candidates by default
+    ///
+    /// Other pins are never driven or reported, which keeps detection off circuitry that is
+    /// not JTAG and makes [`JtagDetectTdo::scan_all`] try fewer pairs.
+    pub fn set_candidates(&mut self, pins: &[usize]) -> Result<(), FtdiError> {
+        self.candidates = candidate_mask(pins)?;
+        Ok(())
+    }
+    fn usable(&self) -> u16 {
+        usable_mask(&self.mpsse, self.upper) & self.candidates
+    }
     pub fn set_pins(&mut self, tck: usize, tms: usize) {
         self.tck_mask = 1 << tck;
         self.tms_mask = 1 << tms;
     }
-    fn shift_dr(&self, len: usize) -> Result<Vec<u8>, FtdiError> {
-        let direction = self.tck_mask | self.tms_mask;
+    fn shift_dr(&self, len: usize) -> Result<Vec<u16>, FtdiError> {
         let mut cmd = MpsseCmdBuilder::new();
-        for _ in 0..len {
-            cmd.set_gpio_lower(0, direction) // TCK0,TMS0,
-                .set_gpio_lower(self.tck_mask, direction) // TCK1,TMS0,
-                .gpio_lower();
-        }
+        push_shift_dr(&mut cmd, self.upper, self.tck_mask, self.tms_mask, len);
         let response = self.mpsse.exec(cmd)?;
-        Ok(response)
+        Ok(parse_gpio(&response, self.upper))
     }
     /// Scans JTAG chain to identify connected devices through TDO pins
     ///
     /// # Returns
-    /// Result containing 8-element vector (one per TDO pin) of detected IDCODE vectors
-    /// Each inner vector contains Option<u32> where:
-    /// - Some(u32): Valid 32-bit IDCODE detected
-    /// - None: Bypass detected
-    /// - Empty: No devices detected on that pin
+    /// Result containing the indexes of the pins on which an IDCODE was detected
     ///
     /// # Protocol Flow
     /// 1. Resets JTAG state machine to Shift-DR
@@ -63,54 +82,99 @@ impl JtagDetectTdo {
         if mask.count_ones() != 2 {
             return Err(FtdiError::Other("tck cannot be same to tms."));
         }
-        let mut tdo_pins = Vec::new();
-        reset2dr(&self.mpsse, self.tck_mask, self.tms_mask)?;
+        let usable = self.usable();
+        check_pins(mask, usable)?;
+        reset2dr(&self.mpsse, self.upper, self.tck_mask, self.tms_mask)?;
         let read = self.shift_dr(ID_LEN * 2)?;
-        // println!("read_buf{read:?}");
-        for i in 0..8 {
-            if 1 << i == self.tck_mask || 1 << i == self.tms_mask {
-                continue;
-            }
-            let mut current_id = 0;
-            let mut bit_count = 0;
-            let mut consecutive_bypass = 0;
-            let tdos: Vec<_> = read.iter().map(|&x| (x >> i) & 1 == 1).collect();
+        Ok(find_tdo(&read, self.pin_count(), mask | !usable))
+    }
+    /// Tries every TCK/TMS pair and returns the `(tck, tms, tdo)` combinations with an IDCODE
+    ///
+    /// Several pairs are sent in one MPSSE buffer, which is much faster than calling
+    /// [`JtagDetectTdo::scan`] for each pair.
+    pub fn scan_all(&self) -> Result<Vec<(usize, usize, usize)>, FtdiError> {
+        // Keeps the responses of one buffer well within the chip's transmit buffer.
+        const PAIRS_PER_BUFFER: usize = 8;
+        let usable = self.usable();
+        let pin_count = self.pin_count();
+        let pins: Vec<usize> = (0..pin_count).filter(|i| usable & (1 << i) != 0).collect();
+        let pairs: Vec<(usize, usize)> = pins
+            .iter()
+            .flat_map(|&tck| {
+                pins.iter()
+                    .filter(move |&&tms| tms != tck)
+                    .map(move |&tms| (tck, tms))
+            })
+            .collect();
 
-            for tdo_val in tdos {
-                // Bypass detection - no device present
-                if bit_count == 0 && !tdo_val {
-                    consecutive_bypass += 1;
-                } else {
-                    // Accumulate IDCODE bits (LSB first)
-                    current_id = (current_id >> 1) | if tdo_val { 0x8000_0000 } else { 0 };
-                    bit_count += 1;
-                    consecutive_bypass = 0;
-                }
-                // Exit on 32 consecutive bypass bits
-                if consecutive_bypass == ID_LEN {
-                    break;
+        let mut found = Vec::new();
+        for chunk in pairs.chunks(PAIRS_PER_BUFFER) {
+            let mut cmd = MpsseCmdBuilder::new();
+            for &(tck, tms) in chunk {
+                push_reset2dr(&mut cmd, self.upper, 1 << tck, 1 << tms);
+                push_shift_dr(&mut cmd, self.upper, 1 << tck, 1 << tms, ID_LEN * 2);
+            }
+            let response = parse_gpio(&self.mpsse.exec(cmd)?, self.upper);
+            for (&(tck, tms), read) in chunk.iter().zip(response.chunks_exact(ID_LEN * 2)) {
+                let skip = (1 << tck) | (1 << tms) | !usable;
+                for tdo in find_tdo(read, pin_count, skip) {
+                    found.push((tck, tms, tdo));
                 }
-                // Store completed 32-bit IDCODE
-                if bit_count == ID_LEN {
-                    // Terminate on invalid IDCODE (all 1s)
-                    if current_id != u32::MAX {
-                        tdo_pins.push(i);
-                    }
-                    break;
+            }
+        }
+        Ok(found)
+    }
+}
+// Pins of `skip` are never reported as TDO.
+fn find_tdo(read: &[u16], pin_count: usize, skip: u16) -> Vec<usize> {
+    let mut tdo_pins = Vec::new();
+    for i in 0..pin_count {
+        if skip & (1 << i) != 0 {
+            continue;
+        }
+        let mut current_id = 0;
+        let mut bit_count = 0;
+        let mut consecutive_bypass = 0;
+        let tdos: Vec<_> = read.iter().map(|&x| (x >> i) & 1 == 1).collect();
+
+        for tdo_val in tdos {
+            // Bypass detection - no device present
+            if bit_count == 0 && !tdo_val {
+                consecutive_bypass += 1;
+            } else {
+                // Accumulate IDCODE bits (LSB first)
+                current_id = (current_id >> 1) | if tdo_val { 0x8000_0000 } else { 0 };
+                bit_count += 1;
+                consecutive_bypass = 0;
+            }
+            // Exit on 32 consecutive bypass bits
+            if consecutive_bypass == ID_LEN {
+                break;
+            }
+            // Store completed 32-bit IDCODE
+            if bit_count == ID_LEN {
+                // Terminate on invalid IDCODE (all 1s)
+                if current_id != u32::MAX {
+                    tdo_pins.push(i);
                 }
+                break;
             }
         }
-        Ok(tdo_pins)
     }
+    tdo_pins
 }
 
 pub struct JtagDetectTdi {
     /// Thread-safe handle to FTDI MPSSE controller
     mpsse: FtdiMpsse,
-    tck_mask: u8,
-    tdi_mask: u8,
-    tdo_mask: u8,
-    tms_mask: u8,
+    /// Whether the upper pins are driven and sampled too
+    upper: bool,
+    /// Pins the detection may use, see [`JtagDetectTdi::set_candidates`]
+    candidates: u16,
+    tck_mask: u16,
+    tdi_mask: u16,
+    tdo_mask: u16,
+    tms_mask: u16,
 }
 impl From<JtagDetectTdi> for FtdiMpsse {
     fn from(value: JtagDetectTdi) -> Self {
@@ -121,16 +185,35 @@ impl JtagDetectTdi {
     /// If you want to use the level translation chip, please use [`crate::FtdiOutputPin`] to control.
     /// Parameters:
     ///
-    /// tck & tdi & tdo & tms are all lower pins index
+    /// tck & tdi & tdo & tms are pin indexes, see [`JtagDetectTdi::include_upper`]
     pub fn new(mpsse: impl Into<FtdiMpsse>) -> Self {
         Self {
             mpsse: mpsse.into(),
+            upper: false,
+            candidates: u16::MAX,
             tck_mask: 0,
             tdi_mask: 0,
             tdo_mask: 0,
             tms_mask: 0,
         }
     }
+    /// Also use the upper pins, they are indexed from 8 up to [`JtagDetectTdi::pin_count`]
+    pub fn include_upper(&mut self, enable: bool) -> Result<(), FtdiError> {
+        self.upper = enable && check_upper(&self.mpsse)?;
+        Ok(())
+    }
+    /// Number of pin indexes the detector can use
+    pub fn pin_count(&self) -> usize {
+        pin_count(&self.mpsse, self.upper)
+    }
+    /// Restricts the pins driven during detection to the indexes in `pins`, all pins by default
+    pub fn set_candidates(&mut self, pins: &[usize]) -> Result<(), FtdiError> {
+        self.candidates = candidate_mask(pins)?;
+        Ok(())
+    }
+    fn usable(&self) -> u16 {
+        usable_mask(&self.mpsse, self.upper) & self.candidates
+    }
     pub fn set_pins(&mut self, tck: usize, tdi: usize, tdo: usize, tms: usize) {
         self.tck_mask = 1 << tck;
         self.tdi_mask = 1 << tdi;
@@ -138,16 +221,17 @@ impl JtagDetectTdi {
         self.tms_mask = 1 << tms;
     }
     fn shift_dr(&self, tdi_value: bool, len: usize) -> Result<Vec<bool>, FtdiError> {
-        let direction = !(self.tdo_mask); // all output except tdo
+        // all output except tdo
+        let direction = self.usable() & !self.tdo_mask;
         let tdi_mask = if tdi_value { self.tdi_mask } else { 0 };
         let mut cmd = MpsseCmdBuilder::new();
         for _ in 0..len {
-            cmd.set_gpio_lower(tdi_mask, direction) // TCK0,TMS0,
-                .set_gpio_lower(tdi_mask | self.tck_mask, direction) // TCK1,TMS0,
-                .gpio_lower();
+            set_gpio(&mut cmd, self.upper, tdi_mask, direction); // TCK0,TMS0,
+            set_gpio(&mut cmd, self.upper, tdi_mask | self.tck_mask, direction); // TCK1,TMS0,
+            get_gpio(&mut cmd, self.upper);
         }
         let response = self.mpsse.exec(cmd)?;
-        Ok(response
+        Ok(parse_gpio(&response, self.upper)
             .into_iter()
             .map(|x| x & (self.tdo_mask) != 0)
             .collect())
@@ -177,6 +261,7 @@ impl JtagDetectTdi {
                 "any one of tck/tms/tdi/tdo cannot be same to others.",
             ));
         }
+        check_pins(mask, self.usable())?;
         const ID_LEN: usize = 32;
         // Shift TDI value and read TDO until 32 consecutive 0s detected
         let mut idcodes = Vec::new();
@@ -184,7 +269,7 @@ impl JtagDetectTdi {
         let mut bit_count = 0;
         let mut consecutive_bypass = 0;
 
-        reset2dr(&self.mpsse, self.tck_mask, self.tms_mask)?;
+        reset2dr(&self.mpsse, self.upper, self.tck_mask, self.tms_mask)?;
 
         'outer: loop {
             let tdos: Vec<_> = self.shift_dr(tdi_val, ID_LEN * 2)?;
@@ -217,29 +302,110 @@ impl JtagDetectTdi {
         Ok(idcodes)
     }
 }
+fn check_upper(mpsse: &FtdiMpsse) -> Result<bool, FtdiError> {
+    if mpsse.chip_type.upper_pins() == 0 {
+        return Err(FtdiError::PinFault(format!(
+            "{:?} do not has upper pins",
+            mpsse.chip_type
+        )));
+    }
+    Ok(true)
+}
+fn pin_count(mpsse: &FtdiMpsse, upper: bool) -> usize {
+    if upper {
+        UPPER_OFFSET + mpsse.chip_type.upper_pins()
+    } else {
+        UPPER_OFFSET
+    }
+}
+// Pins that may be driven, upper pins reserved for another function are left alone.
+fn usable_mask(mpsse: &FtdiMpsse, upper: bool) -> u16 {
+    let mut mask = 0x00FF;
+    if upper {
+        for idx in 0..mpsse.chip_type.upper_pins() {
+            if mpsse.upper.is_free(idx) {
+                mask |= 1 << (UPPER_OFFSET + idx);
+            }
+        }
+    }
+    mask
+}
+fn candidate_mask(pins: &[usize]) -> Result<u16, FtdiError> {
+    pins.iter().try_fold(0, |mask, &pin| {
+        if pin >= u16::BITS as usize {
+            return Err(FtdiError::PinFault(format!(
+                "pin index {pin} is out of range 0 - 15"
+            )));
+        }
+        Ok(mask | 1 << pin)
+    })
+}
+fn check_pins(mask: u16, usable: u16) -> Result<(), FtdiError> {
+    if mask & !usable != 0 {
+        return Err(FtdiError::PinFault(format!(
+            "pin mask {:#06x} can not be used for detection",
+            mask & !usable
+        )));
+    }
+    Ok(())
+}
+fn set_gpio(cmd: &mut MpsseCmdBuilder, upper: bool, value: u16, direction: u16) {
+    let [value_lower, value_upper] = value.to_le_bytes();
+    let [direction_lower, direction_upper] = direction.to_le_bytes();
+    cmd.set_gpio_lower(value_lower, direction_lower);
+    if upper {
+        cmd.set_gpio_upper(value_upper, direction_upper);
+    }
+}
+fn get_gpio(cmd: &mut MpsseCmdBuilder, upper: bool) {
+    cmd.gpio_lower();
+    if upper {
+        cmd.gpio_upper();
+    }
+}
+fn parse_gpio(response: &[u8], upper: bool) -> Vec<u16> {
+    if upper {
+        response
+            .chunks_exact(2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+            .collect()
+    } else {
+        response.iter().map(|&x| x as u16).collect()
+    }
+}
 // 将JTAG状态机复位到Run-Test/Idle状态, 然后切换到shift-dr
-fn reset2dr(mpsse: &FtdiMpsse, tck_mask: u8, tms_mask: u8) -> Result<(), FtdiError> {
-    let direction = tck_mask | tms_mask;
+fn reset2dr(mpsse: &FtdiMpsse, upper: bool, tck_mask: u16, tms_mask: u16) -> Result<(), FtdiError> {
     let mut cmd = MpsseCmdBuilder::new();
-    cmd.set_gpio_lower(tck_mask, direction);
-    for _ in 0..5 {
-        cmd
-            // TMS1
-            .set_gpio_lower(tms_mask, direction) // TCK to low
-            .set_gpio_lower(tck_mask | tms_mask, direction); // TCK to high
-    }
-    cmd
-        // TMS0
-        .set_gpio_lower(0, direction) // TCK to low
-        .set_gpio_lower(tck_mask, direction) // TCK to high
-        // TMS1
-        .set_gpio_lower(tms_mask, direction) // TCK to low
-        .set_gpio_lower(tck_mask | tms_mask, direction) // TCK to high
-        // TMS0
-        .set_gpio_lower(0, direction) // TCK to low
-        .set_gpio_lower(tck_mask, direction) // TCK to high
-        .set_gpio_lower(0, direction) // TCK to low
-        .set_gpio_lower(tck_mask, direction); // TCK to high
+    push_reset2dr(&mut cmd, upper, tck_mask, tms_mask);
     mpsse.exec(cmd)?;
     Ok(())
 }
+fn push_reset2dr(cmd: &mut MpsseCmdBuilder, upper: bool, tck_mask: u16, tms_mask: u16) {
+    let direction = tck_mask | tms_mask;
+    set_gpio(cmd, upper, tck_mask, direction);
+    for _ in 0..5 {
+        // TMS1
+        set_gpio(cmd, upper, tms_mask, direction); // TCK to low
+        set_gpio(cmd, upper, tck_mask | tms_mask, direction); // TCK to high
+    }
+    // TMS0
+    set_gpio(cmd, upper, 0, direction); // TCK to low
+    set_gpio(cmd, upper, tck_mask, direction); // TCK to high
+    // TMS1
+    set_gpio(cmd, upper, tms_mask, direction); // TCK to low
+    set_gpio(cmd, upper, tck_mask | tms_mask, direction); // TCK to high
+    // TMS0
+    set_gpio(cmd, upper, 0, direction); // TCK to low
+    set_gpio(cmd, upper, tck_mask, direction); // TCK to high
+    set_gpio(cmd, upper, 0, direction); // TCK to low
+    set_gpio(cmd, upper, tck_mask, direction); // TCK to high
+}
+// Clocks `len` bits with TMS low, sampling all pins after each rising edge of TCK.
+fn push_shift_dr(cmd: &mut MpsseCmdBuilder, upper: bool, tck_mask: u16, tms_mask: u16, len: usize) {
+    let direction = tck_mask | tms_mask;
+    for _ in 0..len {
+        set_gpio(cmd, upper, 0, direction); // TCK0,TMS0,
+        set_gpio(cmd, upper, tck_mask, direction); // TCK1,TMS0,
+        get_gpio(cmd, upper);
+    }
+}
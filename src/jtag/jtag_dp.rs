@@ -0,0 +1,162 @@
+//! ADIv5 JTAG-DP, the debug port registers behind the DPACC and APACC scan chains.
+//!
+//! The DP must be the only TAP on the chain. Every scan shifts a 35 bit DR of RnW,
+//! A[3:2] and the data, and captures the ACK with the result of the previous read, so
+//! each read is collected by a following scan of DP RDBUFF.
+use super::FtdiJtag;
+use crate::FtdiError;
+
+const IR_LEN: usize = 4;
+const DPACC: u8 = 0b1010;
+const APACC: u8 = 0b1011;
+const DR_LEN: usize = 35;
+const RDBUFF: u8 = 0x0C;
+
+#[derive(Debug, thiserror::Error)]
+pub enum JtagDpError {
+    #[error("Ftdi inner error")]
+    FtdiInner(#[from] FtdiError),
+    #[error("Jtag-DP ack wait.")]
+    AckWait,
+    #[error("Jtag-DP unknown ack LSB[{0:#3b}].")]
+    UnknownAck(u8),
+}
+
+/// JTAG-DP on a [`FtdiJtag`] with a single TAP
+pub struct JtagDp<'a> {
+    jtag: &'a FtdiJtag,
+    /// Whether every scan is logged
+    trace: bool,
+    /// How often a scan is repeated after a WAIT
+    wait_retries: usize,
+}
+
+// JTAG-DP ACK (3 bits), FAULT is reported through CTRL/STAT instead
+const ACK_OK_FAULT: u8 = 0b010;
+const ACK_WAIT: u8 = 0b001;
+
+impl<'a> JtagDp<'a> {
+    pub fn new(jtag: &'a FtdiJtag) -> Self {
+        Self {
+            jtag,
+            trace: false,
+            wait_retries: 0,
+        }
+    }
+    /// Log every DPACC and APACC scan and every WAIT retry at info level
+    pub fn set_trace(&mut self, enable: bool) {
+        self.trace = enable;
+    }
+    /// Repeat a scan up to `retries` times while the DP answers WAIT
+    pub fn set_wait_retries(&mut self, retries: usize) {
+        self.wait_retries = retries;
+    }
+    pub fn read_dp(&self, addr: u8) -> Result<u32, JtagDpError> {
+        self.scan(DPACC, addr, None)?;
+        self.scan(DPACC, RDBUFF, None)
+    }
+    pub fn write_dp(&self, addr: u8, value: u32) -> Result<(), JtagDpError> {
+        self.scan(DPACC, addr, Some(value))?;
+        Ok(())
+    }
+    /// Reads a register of the AP and bank selected in DP SELECT
+    pub fn read_ap(&self, addr: u8) -> Result<u32, JtagDpError> {
+        self.scan(APACC, addr, None)?;
+        self.scan(DPACC, RDBUFF, None)
+    }
+    /// Writes a register of the AP and bank selected in DP SELECT
+    pub fn write_ap(&self, addr: u8, value: u32) -> Result<(), JtagDpError> {
+        self.scan(APACC, addr, Some(value))?;
+        Ok(())
+    }
+    /// One scan, a read when `write` is `None`, returns the data captured with the ACK
+    fn scan(&self, ir: u8, addr: u8, write: Option<u32>) -> Result<u32, JtagDpError> {
+        let dr = encode(addr, write);
+        let mut retry = 0;
+        loop {
+            let response = self.jtag.write_read(&[ir], IR_LEN, &dr, DR_LEN)?;
+            let (ack, data) = decode(&response);
+            self.trace(ir, addr, write, ack, data);
+            if check_ack(ack, retry, self.wait_retries)? {
+                return Ok(data);
+            }
+            retry += 1;
+            if self.trace {
+                log::info!("JTAG-DP WAIT, retry {retry} of {}", self.wait_retries);
+            }
+        }
+    }
+    fn trace(&self, ir: u8, addr: u8, write: Option<u32>, ack: u8, data: u32) {
+        if !self.trace {
+            return;
+        }
+        let port = if ir == APACC { "APACC" } else { "DPACC" };
+        let ack = match ack {
+            ACK_OK_FAULT => "OK/FAULT".to_string(),
+            ACK_WAIT => "WAIT".to_string(),
+            x => format!("{x:#05b}"),
+        };
+        match write {
+            Some(value) => {
+                log::info!("JTAG-DP W {port} {addr:#04x} data {value:#010x} ack {ack}")
+            }
+            None => log::info!("JTAG-DP R {port} {addr:#04x} ack {ack} previous {data:#010x}"),
+        }
+    }
+}
+
+// DR bits LSB first: [0] RnW, [2:1] A[3:2], [34:3] data
+fn encode(addr: u8, write: Option<u32>) -> [u8; 5] {
+    let bits = (u64::from(write.unwrap_or(0)) << 3)
+        | (u64::from((addr >> 2) & 0b11) << 1)
+        | u64::from(write.is_none());
+    let bytes = bits.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4]]
+}
+
+// `Ok(true)` when the scan completed, `Ok(false)` when a WAIT is retried after `retry` retries
+fn check_ack(ack: u8, retry: usize, wait_retries: usize) -> Result<bool, JtagDpError> {
+    match ack {
+        ACK_OK_FAULT => Ok(true),
+        ACK_WAIT if retry < wait_retries => Ok(false),
+        ACK_WAIT => Err(JtagDpError::AckWait),
+        x => Err(JtagDpError::UnknownAck(x)),
+    }
+}
+
+// Captured bits LSB first: [2:0] ACK, [34:3] data
+fn decode(response: &[u8]) -> (u8, u32) {
+    let mut bytes = [0; 8];
+    bytes[..5].copy_from_slice(&response[..5]);
+    let bits = u64::from_le_bytes(bytes);
+    ((bits & 0b111) as u8, (bits >> 3) as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JtagDpError, check_ack, decode, encode};
+
+    #[test]
+    fn dr_layout() {
+        // read of RDBUFF: RnW 1, A[3:2] 0b11
+        assert_eq!(encode(0x0C, None), [0b111, 0, 0, 0, 0]);
+        // write of 0x8000_0001 to 0x04: A[3:2] 0b01, data from bit 3
+        assert_eq!(encode(0x04, Some(0x8000_0001)), [0b1010, 0, 0, 0, 0b100]);
+        assert_eq!(decode(&[0b1010, 0, 0, 0, 0b100]), (0b010, 0x8000_0001));
+    }
+    #[test]
+    fn ack_wait_retries() {
+        assert!(matches!(check_ack(0b010, 0, 0), Ok(true)));
+        assert!(matches!(check_ack(0b001, 0, 2), Ok(false)));
+        assert!(matches!(check_ack(0b001, 1, 2), Ok(false)));
+        assert!(matches!(check_ack(0b001, 2, 2), Err(JtagDpError::AckWait)));
+        assert!(matches!(check_ack(0b001, 0, 0), Err(JtagDpError::AckWait)));
+        assert!(matches!(
+            check_ack(0b100, 0, 2),
+            Err(JtagDpError::UnknownAck(0b100))
+        ));
+        // the ACK sits in the low three bits of the capture
+        let (ack, _) = decode(&[0b1111_1001, 0, 0, 0, 0]);
+        assert!(matches!(check_ack(ack, 0, 1), Ok(false)));
+    }
+}
@@ -1,5 +1,9 @@
 mod hw_jtag;
+mod idcode;
 mod jtag_detect;
+mod jtag_dp;
 
-pub use hw_jtag::FtdiJtag;
+pub use hw_jtag::{FtdiJtag, JtagState};
+pub use idcode::{Jep106, JtagDevice};
 pub use jtag_detect::{JtagDetectTdi, JtagDetectTdo};
+pub use jtag_dp::{JtagDp, JtagDpError};
@@ -22,17 +22,41 @@
 
 #![forbid(unsafe_code)]
 
+pub mod at24;
+pub mod bitbang;
+#[cfg(feature = "config")]
+pub mod config;
 pub mod delay;
+pub mod dmx;
+pub mod eeprom;
+pub mod esp;
+pub mod fpga;
 mod ftdaye;
 pub mod gpio;
+pub mod heartbeat;
 pub mod i2c;
+pub mod image;
 pub mod jtag;
 mod list;
-pub use list::list_all_device;
+#[cfg(target_os = "linux")]
+pub use list::install_udev_rules;
+pub use list::{UDEV_RULES_PATH, list_all_device, register_device, udev_rules};
+pub mod microwire;
 pub mod mpsse;
 mod mpsse_cmd;
+pub use mpsse_cmd::MpsseCmdBuilder;
+pub mod pins;
+pub mod probe;
+#[cfg(feature = "config")]
+pub mod script;
+pub mod soft_spi;
 pub mod spi;
+mod stats;
+pub use stats::Stats;
+pub mod stream;
 pub mod swd;
+pub mod uart;
+pub mod ws2812;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChipType {
@@ -47,7 +71,8 @@ pub enum ChipType {
     Unknown,
 }
 impl ChipType {
-    pub(crate) const fn interface_list(self) -> &'static [Interface] {
+    /// Interfaces the chip has
+    pub const fn interface_list(self) -> &'static [Interface] {
         match self {
             ChipType::FT232H => &[Interface::A],
             ChipType::FT2232H | ChipType::FT2232D => &[Interface::A, Interface::B],
@@ -55,14 +80,16 @@ impl ChipType {
             _ => &[],
         }
     }
-    pub(crate) const fn mpsse_list(self) -> &'static [Interface] {
+    /// Interfaces with an MPSSE engine
+    pub const fn mpsse_list(self) -> &'static [Interface] {
         match self {
             ChipType::FT232H | ChipType::FT2232D => &[Interface::A],
             ChipType::FT2232H | ChipType::FT4232H => &[Interface::A, Interface::B],
             _ => &[],
         }
     }
-    pub(crate) const fn upper_pins(self) -> usize {
+    /// Upper bank GPIO pins of an MPSSE interface
+    pub const fn upper_pins(self) -> usize {
         match self {
             ChipType::FT232H | ChipType::FT2232H => 8,
             ChipType::FT2232D => 4,
@@ -70,14 +97,27 @@ impl ChipType {
             _ => 0,
         }
     }
-    pub(crate) const fn max_frequecny(self) -> (usize, Option<bool>) {
+    /// Highest MPSSE clock frequency in Hz, 0 for chips without MPSSE
+    pub const fn max_frequency(self) -> usize {
         match self {
-            ChipType::FT2232D => (6_000_000, None),
-            ChipType::FT232H | ChipType::FT2232H | ChipType::FT4232H => (30_000_000, Some(false)),
-            _ => (0, None),
+            ChipType::FT2232D => 6_000_000,
+            ChipType::FT232H | ChipType::FT2232H | ChipType::FT4232H => 30_000_000,
+            _ => 0,
         }
     }
-    pub(crate) const fn max_packet_size(self) -> usize {
+    /// Whether the 60 MHz master clock can be divided by 5, the crate only does so for clocks below 458 Hz
+    pub const fn has_divide_by5(self) -> bool {
+        matches!(
+            self,
+            ChipType::FT232H | ChipType::FT2232H | ChipType::FT4232H
+        )
+    }
+    /// Whether 3-phase data clocking, adaptive clocking and clock-only commands are available
+    pub const fn supports_three_phase(self) -> bool {
+        self.has_divide_by5()
+    }
+    /// Size of a USB bulk packet
+    pub const fn max_packet_size(self) -> usize {
         match self {
             ChipType::FT2232D => 64,
             ChipType::FT232H | ChipType::FT2232H | ChipType::FT4232H => 512,
@@ -87,6 +127,7 @@ impl ChipType {
 }
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
 pub enum Interface {
     A = 1,
     B = 2,
@@ -95,6 +136,9 @@ pub enum Interface {
 }
 
 impl Interface {
+    /// Every interface, in order
+    pub const ALL: [Interface; 4] = [Interface::A, Interface::B, Interface::C, Interface::D];
+
     pub(crate) const fn read_ep(self) -> u8 {
         match self {
             Interface::A => 0x81,
@@ -127,6 +171,12 @@ pub enum Pin {
     Upper(usize),
 }
 impl Pin {
+    /// Every GPIO pin `chip_type` has on an MPSSE interface, lower bank first
+    pub fn iter(chip_type: ChipType) -> impl Iterator<Item = Pin> {
+        (0..8)
+            .map(Pin::Lower)
+            .chain((0..chip_type.upper_pins()).map(Pin::Upper))
+    }
     pub(crate) const fn mask(self) -> u8 {
         match self {
             Pin::Lower(idx) => 1 << idx,
@@ -153,6 +203,43 @@ pub enum FtdiError {
     #[error("Pin fault: {0}")]
     PinFault(String),
 
+    #[error("Self test failed: {0}")]
+    /// The loopback readback did not match the shifted pattern.
+    SelfTestFailed(String),
+
+    #[error("FPGA configuration failed: {0}")]
+    /// The FPGA did not report a successful configuration.
+    FpgaConfigFailed(String),
+
+    #[error("EEPROM backup failed: {0}")]
+    /// The backup file could not be read, written or parsed.
+    EepromBackup(String),
+
+    #[error("IR capture of TAP {tap} is {captured:#b}, not ...01")]
+    /// The instruction register of TAP `tap`, counted from TDO, did not capture `...01`,
+    /// `captured` holds its first 64 bits LSB first.
+    IrCapture { tap: usize, captured: u64 },
+
+    #[error("Timed out waiting for the device")]
+    /// The expected response did not arrive before the deadline.
+    Timeout,
+
+    #[error("The device has been disconnected")]
+    /// The device was unplugged, every later call on the same handle fails with it too.
+    DeviceGone,
+
     #[error("{0}")]
     Other(&'static str),
 }
+
+impl From<FtdiError> for std::io::Error {
+    fn from(e: FtdiError) -> Self {
+        use std::io::ErrorKind;
+        match e {
+            FtdiError::Usb(e) => e,
+            FtdiError::Timeout => ErrorKind::TimedOut.into(),
+            FtdiError::DeviceGone => ErrorKind::NotConnected.into(),
+            e => std::io::Error::other(e),
+        }
+    }
+}
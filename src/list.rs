@@ -1,6 +1,12 @@
 use nusb::DeviceInfo;
+use std::sync::Mutex;
+#[cfg(target_os = "linux")]
+use std::{io, path::Path};
 
 use crate::{ChipType, Interface};
+
+/// Where [`install_udev_rules`] puts the rules.
+pub const UDEV_RULES_PATH: &str = "/etc/udev/rules.d/99-ftdi-tools.rules";
 /// Known properties associated to particular FTDI chip types.
 
 #[derive(Debug, Clone, Copy)]
@@ -62,14 +68,82 @@ static FTDI_COMPAT_DEVICES: &[FtdiDevice] = &[
     },
 ];
 
+/// VID/PID pairs added by [`register_device`].
+static CUSTOM_DEVICES: Mutex<Vec<FtdiDevice>> = Mutex::new(Vec::new());
+
+/// Known and registered devices.
+fn all_devices() -> Vec<FtdiDevice> {
+    let mut devices = FTDI_COMPAT_DEVICES.to_vec();
+    devices.extend(CUSTOM_DEVICES.lock().unwrap().iter());
+    devices
+}
+
+/// Makes [`list_all_device`] and [`udev_rules`] include an adapter with its own VID/PID
+///
+/// `chip_type` is used when the chip can not be told from its `bcdDevice`.
+pub fn register_device(vendor_id: u16, product_id: u16, chip_type: ChipType) {
+    let mut devices = CUSTOM_DEVICES.lock().unwrap();
+    if !devices
+        .iter()
+        .any(|device| device.id == (vendor_id, product_id))
+    {
+        devices.push(FtdiDevice {
+            id: (vendor_id, product_id),
+            fallback_chip_type: chip_type,
+        });
+    }
+}
+
+/// Chip type of a known or registered VID/PID, for chips `bcdDevice` does not identify
+pub(crate) fn fallback_chip_type(vendor_id: u16, product_id: u16) -> Option<ChipType> {
+    all_devices()
+        .into_iter()
+        .find(|device| device.id == (vendor_id, product_id))
+        .map(|device| device.fallback_chip_type)
+}
+
+/// udev rules giving the logged in user access to every known and registered device
+pub fn udev_rules() -> String {
+    let mut rules = String::from("# Generated by ftdi-tools\n");
+    for device in all_devices() {
+        rules += &format!(
+            "# {:?}\nSUBSYSTEM==\"usb\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", MODE=\"0660\", GROUP=\"plugdev\", TAG+=\"uaccess\"\n",
+            device.fallback_chip_type, device.id.0, device.id.1
+        );
+    }
+    rules
+}
+
+/// Writes [`udev_rules`] to [`UDEV_RULES_PATH`] and reloads udev, needs root
+///
+/// Adapters already plugged in get the new permissions once they are replugged.
+#[cfg(target_os = "linux")]
+pub fn install_udev_rules() -> io::Result<()> {
+    use std::{fs, os::unix::fs::PermissionsExt, process::Command};
+
+    let path = Path::new(UDEV_RULES_PATH);
+    fs::write(path, udev_rules())?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o644))?;
+    for args in [&["control", "--reload-rules"][..], &["trigger"]] {
+        match Command::new("udevadm").args(args).status() {
+            Ok(status) if status.success() => (),
+            Ok(status) => log::warn!("udevadm {} failed: {status}", args.join(" ")),
+            Err(e) => log::warn!("udevadm {} failed: {e}", args.join(" ")),
+        }
+    }
+    log::info!("Installed {}", path.display());
+    Ok(())
+}
+
 pub struct FtdiDeviceInfo {
     pub usb_device: DeviceInfo,
     pub interface: &'static [Interface],
 }
 
 pub fn list_all_device() -> Vec<FtdiDeviceInfo> {
-    fn filter_map(info: DeviceInfo) -> Option<FtdiDeviceInfo> {
-        for device in FTDI_COMPAT_DEVICES {
+    let known = all_devices();
+    let filter_map = |info: DeviceInfo| -> Option<FtdiDeviceInfo> {
+        for device in &known {
             if (info.vendor_id(), info.product_id()) == device.id {
                 log::info!(
                     "Find {:?}:[{:#06x?},{:#06x?}]",
@@ -84,9 +158,23 @@ pub fn list_all_device() -> Vec<FtdiDeviceInfo> {
             }
         }
         None
-    }
+    };
     match nusb::list_devices() {
         Err(_) => Vec::new(),
         Ok(devices) => devices.filter_map(filter_map).collect(),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{register_device, udev_rules};
+    use crate::ChipType;
+
+    #[test]
+    fn rules_include_registered_devices() {
+        register_device(0x1234, 0xabcd, ChipType::FT232H);
+        let rules = udev_rules();
+        assert!(rules.contains(r#"ATTRS{idVendor}=="0403", ATTRS{idProduct}=="6014""#));
+        assert!(rules.contains(r#"ATTRS{idVendor}=="1234", ATTRS{idProduct}=="abcd""#));
+    }
+}
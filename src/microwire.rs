@@ -0,0 +1,295 @@
+//! Microwire bus and 93Cxx EEPROMs.
+//!
+//! Microwire is a 3-wire bus close to SPI MODE0 with an active high CS. Every
+//! instruction starts with a 1 start bit, a 2 bit opcode and the address, so frames
+//! are rarely whole bytes. SK is AD0, DI of the device AD1 and DO of the device AD2.
+//!
+//! The device changes DO after the rising SK edge, the MPSSE samples it on the
+//! falling edge. A read answers with a 0 dummy bit on the last address bit, the data
+//! follows MSB first.
+use crate::{
+    FtdiError, Pin,
+    gpio::UsedPin,
+    mpsse::{FtdiMpsse, PinUsage},
+    mpsse_cmd::MpsseCmdBuilder,
+    spi::set_pin,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const SK: Pin = Pin::Lower(0);
+const DI: Pin = Pin::Lower(1);
+const DO: Pin = Pin::Lower(2);
+/// WRAL and ERAL of older parts take up to 30 ms, single words about 5 ms
+const WRITE_CYCLE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, thiserror::Error)]
+pub enum MicrowireError {
+    #[error(transparent)]
+    FtdiInner(#[from] FtdiError),
+    #[error("{len} words at {addr:#x} exceed the EEPROM size")]
+    OutOfRange { addr: usize, len: usize },
+    #[error("EEPROM still busy after the write cycle timeout")]
+    WriteTimeout,
+    #[error("No dummy 0 bit before the read data, is the EEPROM connected?")]
+    NoDummyBit,
+}
+
+/// Packs `(value, bits)` fields MSB first, returns the bytes and the number of bits
+pub fn pack(fields: &[(u32, usize)]) -> (Vec<u8>, usize) {
+    let total: usize = fields.iter().map(|&(_, bits)| bits).sum();
+    let mut bytes = vec![0; total.div_ceil(8)];
+    let mut pos = 0;
+    for &(value, bits) in fields {
+        for idx in (0..bits).rev() {
+            if (value >> idx) & 1 != 0 {
+                bytes[pos / 8] |= 0x80 >> (pos % 8);
+            }
+            pos += 1;
+        }
+    }
+    (bytes, total)
+}
+
+/// The `bits` bits from bit `start` of MSB first `bytes`, at most 32
+pub fn unpack(bytes: &[u8], start: usize, bits: usize) -> u32 {
+    (start..start + bits).fold(0, |value, pos| {
+        (value << 1) | ((bytes[pos / 8] >> (7 - pos % 8)) & 1) as u32
+    })
+}
+
+/// Microwire master on SK (AD0), DI (AD1), DO (AD2) and a CS on any pin
+pub struct Microwire {
+    _pins: [UsedPin; 3],
+    cs: UsedPin,
+    /// Thread-safe handle to FTDI MPSSE controller
+    mtx: Arc<Mutex<FtdiMpsse>>,
+}
+
+impl Microwire {
+    pub fn new(mtx: Arc<Mutex<FtdiMpsse>>, cs: Pin) -> Result<Self, FtdiError> {
+        let this = Self {
+            _pins: [
+                UsedPin::new(mtx.clone(), SK, PinUsage::Spi)?,
+                UsedPin::new(mtx.clone(), DI, PinUsage::Spi)?,
+                UsedPin::new(mtx.clone(), DO, PinUsage::Spi)?,
+            ],
+            cs: UsedPin::new(mtx.clone(), cs, PinUsage::Spi)?,
+            mtx: mtx.clone(),
+        };
+        let mut lock = FtdiMpsse::lock(&mtx)?;
+        // SK idles low, CS is released until the first instruction
+        lock.lower.direction |= SK.mask() | DI.mask();
+        lock.lower.direction &= !DO.mask();
+        lock.lower.value &= !(SK.mask() | DI.mask());
+        let bank = match cs {
+            Pin::Lower(_) => &mut lock.lower,
+            Pin::Upper(_) => &mut lock.upper,
+        };
+        bank.direction |= cs.mask();
+        bank.value &= !cs.mask();
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+        set_pin(&mut cmd, &lock, cs, false);
+        lock.exec(cmd)?;
+        drop(lock);
+        Ok(this)
+    }
+    /// Shifts the first `bits` bits of `write` with CS high, returns what DO sent meanwhile
+    ///
+    /// Both are MSB first, the response is laid out like `write`.
+    pub fn transfer(&self, write: &[u8], bits: usize) -> Result<Vec<u8>, FtdiError> {
+        let (whole, remain) = (bits / 8, bits % 8);
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.tdo_neg_read(true);
+        set_pin(&mut cmd, &lock, *self.cs, true);
+        cmd.shift_bytes(false, false, &write[..whole]);
+        if remain > 0 {
+            cmd.shift_bits(false, false, write[whole], remain);
+        }
+        set_pin(&mut cmd, &lock, *self.cs, false);
+        let mut response = lock.exec(cmd)?;
+        if remain > 0 {
+            // the bits of a partial byte arrive in its low end
+            response[whole] <<= 8 - remain;
+        }
+        Ok(response)
+    }
+    /// Waits until DO reports the write cycle done, it is low while the device is busy
+    pub fn wait_ready(&self, timeout: Duration) -> Result<(), MicrowireError> {
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let mut cmd = MpsseCmdBuilder::new();
+        set_pin(&mut cmd, &lock, *self.cs, true);
+        lock.exec(cmd)?;
+        let start = Instant::now();
+        let result = loop {
+            if lock.read_pins(&[DO])?[0] {
+                break Ok(());
+            }
+            if start.elapsed() > timeout {
+                break Err(MicrowireError::WriteTimeout);
+            }
+        };
+        let mut cmd = MpsseCmdBuilder::new();
+        set_pin(&mut cmd, &lock, *self.cs, false);
+        lock.exec(cmd)?;
+        result
+    }
+}
+
+/// Geometry of a 93Cxx EEPROM, the ORG pin selects 8 or 16 bit words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Eeprom93Part {
+    /// Capacity in words
+    pub words: usize,
+    pub addr_bits: usize,
+    /// 8 or 16
+    pub word_bits: usize,
+}
+
+impl Eeprom93Part {
+    pub const C46_X8: Self = Self::new(128, 7, 8);
+    pub const C46_X16: Self = Self::new(64, 6, 16);
+    pub const C56_X8: Self = Self::new(256, 9, 8);
+    pub const C56_X16: Self = Self::new(128, 8, 16);
+    pub const C66_X8: Self = Self::new(512, 9, 8);
+    pub const C66_X16: Self = Self::new(256, 8, 16);
+
+    pub const fn new(words: usize, addr_bits: usize, word_bits: usize) -> Self {
+        Self {
+            words,
+            addr_bits,
+            word_bits,
+        }
+    }
+}
+
+/// 93C46, 93C56 or 93C66 EEPROM on a [`Microwire`] bus
+pub struct Eeprom93 {
+    bus: Microwire,
+    part: Eeprom93Part,
+}
+
+impl Eeprom93 {
+    const READ: u32 = 0b10;
+    const WRITE: u32 = 0b01;
+    const ERASE: u32 = 0b11;
+    /// EWEN, EWDS, ERAL and WRAL, told apart by the two top address bits
+    const EXTENDED: u32 = 0b00;
+    const EWDS: u32 = 0b00;
+    const WRAL: u32 = 0b01;
+    const ERAL: u32 = 0b10;
+    const EWEN: u32 = 0b11;
+
+    pub fn new(bus: Microwire, part: Eeprom93Part) -> Self {
+        Self { bus, part }
+    }
+    pub fn part(&self) -> Eeprom93Part {
+        self.part
+    }
+    pub fn into_inner(self) -> Microwire {
+        self.bus
+    }
+    fn check_range(&self, addr: usize, len: usize) -> Result<(), MicrowireError> {
+        if addr + len > self.part.words {
+            return Err(MicrowireError::OutOfRange { addr, len });
+        }
+        Ok(())
+    }
+    /// Start bit, opcode and address, followed by `tail`
+    fn instruction(&self, opcode: u32, addr: u32, tail: &[(u32, usize)]) -> (Vec<u8>, usize) {
+        let mut fields = vec![(1, 1), (opcode, 2), (addr, self.part.addr_bits)];
+        fields.extend_from_slice(tail);
+        pack(&fields)
+    }
+    fn extended(&self, code: u32, tail: &[(u32, usize)]) -> (Vec<u8>, usize) {
+        self.instruction(Self::EXTENDED, code << (self.part.addr_bits - 2), tail)
+    }
+    /// Reads `buf.len()` words from `addr` in one sequential read
+    pub fn read(&mut self, addr: usize, buf: &mut [u16]) -> Result<(), MicrowireError> {
+        self.check_range(addr, buf.len())?;
+        let header = 3 + self.part.addr_bits;
+        let data_bits = buf.len() * self.part.word_bits;
+        let (mut write, _) = self.instruction(Self::READ, addr as u32, &[]);
+        write.resize((header + data_bits).div_ceil(8), 0);
+        let response = self.bus.transfer(&write, header + data_bits)?;
+        if unpack(&response, header - 1, 1) != 0 {
+            return Err(MicrowireError::NoDummyBit);
+        }
+        for (idx, word) in buf.iter_mut().enumerate() {
+            let start = header + idx * self.part.word_bits;
+            *word = unpack(&response, start, self.part.word_bits) as u16;
+        }
+        Ok(())
+    }
+    /// Allows or forbids the writing and erasing instructions, forbidden at power up
+    pub fn write_enable(&mut self, enable: bool) -> Result<(), MicrowireError> {
+        let code = if enable { Self::EWEN } else { Self::EWDS };
+        let (write, bits) = self.extended(code, &[]);
+        self.bus.transfer(&write, bits)?;
+        Ok(())
+    }
+    /// Sends a writing or erasing instruction and waits for its write cycle
+    fn program(&mut self, (write, bits): (Vec<u8>, usize)) -> Result<(), MicrowireError> {
+        self.bus.transfer(&write, bits)?;
+        self.bus.wait_ready(WRITE_CYCLE_TIMEOUT)
+    }
+    /// Writes `data` word by word, writing is enabled around it
+    pub fn write(&mut self, addr: usize, data: &[u16]) -> Result<(), MicrowireError> {
+        self.check_range(addr, data.len())?;
+        self.write_enable(true)?;
+        let word_bits = self.part.word_bits;
+        let result = data.iter().enumerate().try_for_each(|(idx, &word)| {
+            let frame = self.instruction(
+                Self::WRITE,
+                (addr + idx) as u32,
+                &[(word.into(), word_bits)],
+            );
+            self.program(frame)
+        });
+        self.write_enable(false)?;
+        result
+    }
+    /// Sets the word at `addr` to all ones
+    pub fn erase(&mut self, addr: usize) -> Result<(), MicrowireError> {
+        self.check_range(addr, 1)?;
+        self.write_enable(true)?;
+        let result = self.program(self.instruction(Self::ERASE, addr as u32, &[]));
+        self.write_enable(false)?;
+        result
+    }
+    /// Sets every word to all ones
+    pub fn erase_all(&mut self) -> Result<(), MicrowireError> {
+        self.write_enable(true)?;
+        let result = self.program(self.extended(Self::ERAL, &[]));
+        self.write_enable(false)?;
+        result
+    }
+    /// Sets every word to `word`
+    pub fn write_all(&mut self, word: u16) -> Result<(), MicrowireError> {
+        self.write_enable(true)?;
+        let frame = self.extended(Self::WRAL, &[(word.into(), self.part.word_bits)]);
+        let result = self.program(frame);
+        self.write_enable(false)?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pack, unpack};
+
+    #[test]
+    fn frame_bits() {
+        // 93C46 x16 WRITE of 0xBEEF to 0x2A: 1 01 101010 1011111011101111
+        let (bytes, bits) = pack(&[(1, 1), (0b01, 2), (0x2A, 6), (0xBEEF, 16)]);
+        assert_eq!(bits, 25);
+        assert_eq!(bytes, [0b1011_0101, 0b0101_1111, 0b0111_0111, 0b1000_0000]);
+        assert_eq!(unpack(&bytes, 0, 3), 0b101);
+        assert_eq!(unpack(&bytes, 3, 6), 0x2A);
+        assert_eq!(unpack(&bytes, 9, 16), 0xBEEF);
+    }
+}
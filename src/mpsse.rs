@@ -1,4 +1,24 @@
-use crate::{ChipType, FtdiError, Interface, Pin, ftdaye::FtdiContext, mpsse_cmd::MpsseCmdBuilder};
+use crate::{
+    ChipType, FtdiError, Interface, Pin,
+    eeprom::{self, AcbusFunction},
+    ftdaye::FtdiContext,
+    i2c::I2cBus,
+    list::{self, list_all_device},
+    mpsse_cmd::{EngineConfig, MpsseCmdBuilder},
+    spi::SharedSpiEngine,
+    stats::{Event, Stats},
+    stream::{StreamReader, StreamWriter},
+};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    sync::{Mutex, MutexGuard, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Interval between attempts to find the device again after it was unplugged.
+const RECONNECT_POLL: Duration = Duration::from_millis(200);
 /// State tracker for each pin on the FTDI chip.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PinUsage {
@@ -8,6 +28,9 @@ pub enum PinUsage {
     Spi,
     Jtag,
     Swd,
+    BitBang,
+    /// FT232H ACBUS pin given another function in the EEPROM.
+    Acbus(AcbusFunction),
 }
 /// Manages a bank of 8 GPIO pins
 /// Tracks direction, current value, and allocated protocol usage
@@ -20,12 +43,20 @@ pub(crate) struct GpioByte {
     /// Protocol allocation status for each pin (prevents conflicting usage)
     pins: [Option<PinUsage>; 8],
 }
+impl GpioByte {
+    /// Whether the pin is not allocated to anything
+    pub(crate) fn is_free(&self, idx: usize) -> bool {
+        self.pins[idx].is_none()
+    }
+}
 
 /// Main FTDI MPSSE (Multi-Protocol Synchronous Serial Engine) controller
 /// Manages FTDI device communication and protocol-specific pin configurations
 pub struct FtdiMpsse {
-    /// FTDI device context handle
-    ft: FtdiContext,
+    /// FTDI device context handle, replaced on reconnect
+    ft: RefCell<FtdiContext>,
+    /// Identifies the same device again after a replug
+    identity: DeviceIdentity,
     /// FTDI device interface
     interface: Interface,
     /// Type of FTDI chip (e.g., FT232H, FT2232H)
@@ -34,6 +65,293 @@ pub struct FtdiMpsse {
     pub(crate) lower: GpioByte,
     /// Upper GPIO pins state tracker (if supported by chip)
     pub(crate) upper: GpioByte,
+    /// Whether the typed pin tokens were handed out
+    pub(crate) pins_taken: bool,
+    /// I2C bus shared by every `FtdiI2c` of this interface
+    pub(crate) i2c_bus: Weak<I2cBus>,
+    /// SPI pins shared by every `FtdiSpiDevice` of this interface
+    pub(crate) spi_bus: Weak<SharedSpiEngine>,
+    /// Clock and mode settings sent so far, restored on reconnect
+    engine: Cell<EngineConfig>,
+    /// How long a command waits for an unplugged device to come back
+    auto_reconnect: Option<Duration>,
+    /// Settings the interface was opened with, reapplied on reconnect
+    config: OpenConfig,
+    /// Zero point of the transaction timestamps
+    epoch: Instant,
+    /// Whether transactions are timestamped
+    timestamps: bool,
+    last_timestamp: Cell<Option<Timestamp>>,
+}
+
+/// When a transaction was sent and when its USB transfer completed, see [`FtdiMpsse::set_timestamps`]
+///
+/// Both are monotonic offsets from [`FtdiMpsse::epoch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub start: Duration,
+    pub done: Duration,
+}
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.6}s +{}us",
+            self.start.as_secs_f64(),
+            (self.done - self.start).as_micros()
+        )
+    }
+}
+
+/// Settings applied while opening an interface, see [`FtdiMpsse::open_with_config`]
+///
+/// The defaults match [`FtdiMpsse::open`]: all pins inputs, full clock speed and every
+/// clocking mode off. Pins given a direction here are still free for protocols to take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenConfig {
+    latency_timer: u8,
+    /// Lower bank value and direction
+    lower: [u8; 2],
+    /// Upper bank value and direction
+    upper: [u8; 2],
+    frequency: Option<usize>,
+    loopback: bool,
+    three_phase: bool,
+    adaptive: bool,
+    detach_kernel_driver: bool,
+}
+impl Default for OpenConfig {
+    fn default() -> Self {
+        Self {
+            latency_timer: 16,
+            lower: [0, 0],
+            upper: [0, 0],
+            frequency: None,
+            loopback: false,
+            three_phase: false,
+            adaptive: false,
+            detach_kernel_driver: true,
+        }
+    }
+}
+impl OpenConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Milliseconds the chip holds back a short response, 16 by default
+    pub fn latency_timer(mut self, ms: u8) -> Self {
+        self.latency_timer = ms;
+        self
+    }
+    /// Initial levels and directions (`1` is output) of AD0 to AD7
+    pub fn lower_pins(mut self, value: u8, direction: u8) -> Self {
+        self.lower = [value, direction];
+        self
+    }
+    /// Initial levels and directions (`1` is output) of the upper bank
+    pub fn upper_pins(mut self, value: u8, direction: u8) -> Self {
+        self.upper = [value, direction];
+        self
+    }
+    /// See [`FtdiMpsse::set_frequency`]
+    pub fn frequency(mut self, frequency_hz: usize) -> Self {
+        self.frequency = Some(frequency_hz);
+        self
+    }
+    /// Internal TDI to TDO loopback
+    pub fn loopback(mut self, enable: bool) -> Self {
+        self.loopback = enable;
+        self
+    }
+    /// 3-phase data clocking, FTx232H only
+    pub fn three_phase(mut self, enable: bool) -> Self {
+        self.three_phase = enable;
+        self
+    }
+    /// Adaptive clocking on RTCK (AD7), FTx232H only
+    pub fn adaptive_clock(mut self, enable: bool) -> Self {
+        self.adaptive = enable;
+        self
+    }
+    /// Whether a kernel driver bound to the interface is detached, `true` by default
+    ///
+    /// With `false` opening fails while e.g. `ftdi_sio` holds the interface.
+    pub fn detach_kernel_driver(mut self, enable: bool) -> Self {
+        self.detach_kernel_driver = enable;
+        self
+    }
+}
+
+/// Pin and engine settings of an interface, see [`FtdiMpsse::save_state`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MpsseState {
+    /// Lower bank value and direction
+    lower: [u8; 2],
+    /// Upper bank value and direction
+    upper: [u8; 2],
+    engine: EngineConfig,
+}
+
+/// Value, direction and allocation of one GPIO bank, see [`DebugState`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankState {
+    pub value: u8,
+    /// 1 for outputs
+    pub direction: u8,
+    pub pins: [Option<PinUsage>; 8],
+}
+impl From<&GpioByte> for BankState {
+    fn from(bank: &GpioByte) -> Self {
+        Self {
+            value: bank.value,
+            direction: bank.direction,
+            pins: bank.pins,
+        }
+    }
+}
+
+/// Engine configuration of an interface, see [`FtdiMpsse::debug_state`]
+///
+/// `Display` prints it over several lines, one per pin, for logs and bug reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugState {
+    pub chip_type: ChipType,
+    pub interface: Interface,
+    /// `None` until a clock was set
+    pub divisor: Option<u16>,
+    /// `None` on chips without the divider or until a clock was set
+    pub clk_div_by5: Option<bool>,
+    /// Clock generated by `divisor` in Hertz, `None` until a clock was set
+    pub frequency: Option<usize>,
+    pub loopback: bool,
+    pub three_phase: bool,
+    pub adaptive: bool,
+    pub lower: BankState,
+    pub upper: BankState,
+}
+/// Outcome of [`FtdiMpsse::loopback_test`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopbackReport {
+    pub bytes: usize,
+    /// From sending the command to the last byte read back
+    pub elapsed: Duration,
+    /// Clock in Hertz while shifting, `None` if it was never set
+    pub frequency: Option<usize>,
+}
+impl LoopbackReport {
+    /// Bytes per second, USB transfers included
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+    /// Share of the time the clock was busy shifting, 1.0 without any USB overhead
+    ///
+    /// `None` if the clock is unknown.
+    pub fn efficiency(&self) -> Option<f64> {
+        let frequency = self.frequency?;
+        Some(self.throughput() * 8.0 / frequency as f64)
+    }
+}
+impl fmt::Display for LoopbackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes in {:?}, {:.2} MB/s",
+            self.bytes,
+            self.elapsed,
+            self.throughput() / 1e6,
+        )?;
+        match (self.efficiency(), self.frequency) {
+            (Some(efficiency), Some(frequency)) => {
+                write!(f, ", {:.0}% of the {frequency}Hz clock", efficiency * 100.0)
+            }
+            _ => write!(f, ", clock unknown"),
+        }
+    }
+}
+
+impl fmt::Display for DebugState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let on_off = |enable: bool| if enable { "on" } else { "off" };
+        writeln!(f, "{:?} interface {}", self.chip_type, self.interface)?;
+        match (self.frequency, self.divisor) {
+            (Some(frequency), Some(divisor)) => {
+                write!(f, "clock {frequency}Hz, divisor {divisor}")?
+            }
+            _ => write!(f, "clock unknown")?,
+        }
+        if let Some(clk_div_by5) = self.clk_div_by5 {
+            write!(f, ", divide by 5 {}", on_off(clk_div_by5))?;
+        }
+        writeln!(
+            f,
+            "\nloopback {}, 3-phase {}, adaptive {}",
+            on_off(self.loopback),
+            on_off(self.three_phase),
+            on_off(self.adaptive)
+        )?;
+        let banks = [
+            (&self.lower, 8, Pin::Lower as fn(usize) -> Pin),
+            (&self.upper, self.chip_type.upper_pins(), Pin::Upper),
+        ];
+        for (bank, count, pin) in banks {
+            for idx in 0..count {
+                let mask = pin(idx).mask();
+                let direction = if bank.direction & mask != 0 {
+                    "out"
+                } else {
+                    "in"
+                };
+                let level = if bank.value & mask != 0 {
+                    "high"
+                } else {
+                    "low"
+                };
+                write!(f, "{} {direction:<3} {level:<4}", pin(idx))?;
+                match bank.pins[idx] {
+                    Some(usage) => writeln!(f, " {usage:?}")?,
+                    None => writeln!(f, " free")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// USB identity of an opened device
+struct DeviceIdentity {
+    vendor_id: u16,
+    product_id: u16,
+    serial_number: Option<String>,
+    bus_number: u8,
+    /// Hub ports from the root hub down to the device
+    port_chain: Vec<u8>,
+}
+impl DeviceIdentity {
+    fn new(usb_device: &nusb::DeviceInfo) -> Self {
+        Self {
+            vendor_id: usb_device.vendor_id(),
+            product_id: usb_device.product_id(),
+            serial_number: usb_device.serial_number().map(str::to_string),
+            bus_number: usb_device.bus_number(),
+            port_chain: usb_device.port_chain().to_vec(),
+        }
+    }
+    /// Whether the device can be found again after it was unplugged
+    fn is_unique(&self) -> bool {
+        self.serial_number.is_some() || !self.port_chain.is_empty()
+    }
+    /// Devices without a serial number are told apart by the port they are plugged into.
+    fn matches(&self, other: &DeviceIdentity) -> bool {
+        (other.vendor_id, other.product_id) == (self.vendor_id, self.product_id)
+            && match &self.serial_number {
+                Some(_) => other.serial_number == self.serial_number,
+                None => {
+                    !self.port_chain.is_empty()
+                        && (other.bus_number, &other.port_chain)
+                            == (self.bus_number, &self.port_chain)
+                }
+            }
+    }
 }
 
 impl FtdiMpsse {
@@ -47,6 +365,26 @@ impl FtdiMpsse {
     /// # Returns
     /// Result containing FtdiMpsse instance or FtdiError
     pub fn open(usb_device: &nusb::DeviceInfo, interface: Interface) -> Result<Self, FtdiError> {
+        Self::open_with_config(usb_device, interface, OpenConfig::default())
+    }
+    /// Opens `interface` of the first device [`list_all_device`] finds
+    pub fn open_first(interface: Interface) -> Result<Self, FtdiError> {
+        Self::open_by_index(0, interface)
+    }
+    /// Opens `interface` of the device at `index` in the order of [`list_all_device`]
+    pub fn open_by_index(index: usize, interface: Interface) -> Result<Self, FtdiError> {
+        let devices = list_all_device();
+        let device = devices.get(index).ok_or_else(|| {
+            FtdiError::OpenFailed(format!("No FTDI device {index}, found {}", devices.len()))
+        })?;
+        Self::open(&device.usb_device, interface)
+    }
+    /// Like [`FtdiMpsse::open`], with pins and engine set up in the same transfer as the initialization
+    pub fn open_with_config(
+        usb_device: &nusb::DeviceInfo,
+        interface: Interface,
+        config: OpenConfig,
+    ) -> Result<Self, FtdiError> {
         let handle = usb_device.open()?;
         // let max_packet_size = handle
         //     .active_configuration()
@@ -62,52 +400,91 @@ impl FtdiMpsse {
         //         "Failed to get endpoint info".to_string(),
         //     ))?
         //     .max_packet_size();
-        let chip_type = match (
-            usb_device.device_version(),
-            usb_device.serial_number().unwrap_or(""),
-        ) {
-            (0x400, _) | (0x200, "") => return Err(FtdiError::UnsupportedChip(ChipType::Bm)),
-            (0x200, _) => return Err(FtdiError::UnsupportedChip(ChipType::Am)),
-            (0x500, _) => ChipType::FT2232D,
-            (0x600, _) => return Err(FtdiError::UnsupportedChip(ChipType::R)),
-            (0x700, _) => ChipType::FT2232H,
-            (0x800, _) => ChipType::FT4232H,
-            (0x900, _) => ChipType::FT232H,
-            (0x1000, _) => return Err(FtdiError::UnsupportedChip(ChipType::FT230X)),
-            _ => return Err(FtdiError::UnsupportedChip(ChipType::Unknown)),
-        };
+        let chip_type = chip_type(usb_device)?;
         if !chip_type.interface_list().contains(&interface) {
             return Err(FtdiError::OpenFailed(format!(
-                "{chip_type:?} do not support Interface::{interface:?}"
+                "{chip_type:?} do not support Interface::{interface}"
             )));
         }
+        if !chip_type.supports_three_phase() && (config.three_phase || config.adaptive) {
+            return Err(FtdiError::UnsupportedChip(chip_type));
+        }
 
-        let handle = handle.detach_and_claim_interface(interface.interface_number())?;
+        let handle = claim(&handle, interface, config.detach_kernel_driver)?;
 
-        let this = Self {
-            ft: FtdiContext::new(handle, interface, chip_type.max_packet_size()).into_mpsse(0)?,
+        let mut this = Self {
+            ft: RefCell::new(
+                FtdiContext::new(handle, interface, chip_type.max_packet_size())
+                    .into_mpsse(0, config.latency_timer)?,
+            ),
+            identity: DeviceIdentity::new(usb_device),
             interface,
             chip_type,
             lower: Default::default(),
             upper: Default::default(),
+            pins_taken: false,
+            i2c_bus: Weak::new(),
+            spi_bus: Weak::new(),
+            engine: Cell::default(),
+            auto_reconnect: None,
+            config,
+            epoch: Instant::now(),
+            timestamps: false,
+            last_timestamp: Cell::new(None),
         };
 
+        [this.lower.value, this.lower.direction] = config.lower;
+        [this.upper.value, this.upper.direction] = config.upper;
+        let (divisor, clk_div_by5) = match config.frequency {
+            Some(frequency_hz) => clock_setting(chip_type, frequency_hz),
+            None => (0, chip_type.has_divide_by5().then_some(false)),
+        };
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.set_gpio_lower(0, 0) // set all pin to input and value 0;
-            .set_gpio_upper(0, 0) // set all pin to input and value 0;
-            .enable_loopback(false);
-        if chip_type == ChipType::FT2232D {
-            cmd.set_clock(0, None);
-        } else {
-            cmd.enable_3phase_data_clocking(false)
-                .enable_adaptive_clocking(false)
-                .set_clock(0, Some(false));
+        cmd.set_gpio_lower(config.lower[0], config.lower[1])
+            .set_gpio_upper(config.upper[0], config.upper[1])
+            .enable_loopback(config.loopback);
+        if chip_type.supports_three_phase() {
+            cmd.enable_3phase_data_clocking(config.three_phase)
+                .enable_adaptive_clocking(config.adaptive);
         }
+        cmd.set_clock(divisor, clk_div_by5);
         this.exec(cmd)?;
 
+        if chip_type == ChipType::FT232H {
+            match this.acbus_functions() {
+                Ok(functions) => {
+                    for (idx, function) in functions.into_iter().take(8).enumerate() {
+                        if function.is_repurposed() {
+                            log::info!("{} is reserved for {function:?}", Pin::Upper(idx));
+                            this.upper.pins[idx] = Some(PinUsage::Acbus(function));
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Failed to read ACBUS functions: {e}"),
+            }
+        }
+
         Ok(this)
     }
 
+    /// Type of the opened chip
+    pub fn chip_type(&self) -> ChipType {
+        self.chip_type
+    }
+    /// Reads the FT232H ACBUS pin functions from the EEPROM.
+    ///
+    /// Pins with a function other than GPIO are reserved when the device is opened.
+    pub fn acbus_functions(&self) -> Result<[AcbusFunction; eeprom::ACBUS_PINS], FtdiError> {
+        if self.chip_type != ChipType::FT232H {
+            return Err(FtdiError::UnsupportedChip(self.chip_type));
+        }
+        let mut bytes = Vec::new();
+        for addr in eeprom::ACBUS_WORDS {
+            bytes.extend_from_slice(&self.read_eeprom_word(addr)?.to_le_bytes());
+        }
+        Ok(eeprom::acbus_functions(&bytes))
+    }
+
     /// Sets the MPSSE clock frequency
     ///
     /// # Arguments
@@ -119,45 +496,475 @@ impl FtdiMpsse {
     /// # Notes
     /// Actual frequency may differ from target due to hardware limitations
     /// FT2232D Supports frequencies from 92Hz to 6MHz.
-    /// FTx232H Supports frequencies from 92Hz to 30MHz but in this lib only 458Hz to 30MHz has been supported.
+    /// FTx232H Supports frequencies from 92Hz to 30MHz, below 458Hz the master clock is divided by 5.
     pub fn set_frequency(&self, frequency_hz: usize) -> Result<usize, FtdiError> {
-        let (max_frequency, clk_div_by5) = self.chip_type.max_frequecny();
-        let min_frequency = max_frequency / (u16::MAX as usize + 1) + 1;
-
-        let divisor = if frequency_hz > max_frequency {
-            log::warn!("frequency has out of range[{min_frequency}-{max_frequency}Hz]");
-            log::warn!("frequency set to {max_frequency}Hz]");
-            1
-        } else if frequency_hz < min_frequency {
-            log::warn!("frequency has out of range[{min_frequency}-{max_frequency}Hz]");
-            log::warn!("frequency set to {min_frequency}Hz]");
-            u16::MAX as usize + 1
-        } else if max_frequency % frequency_hz != 0 {
-            max_frequency / frequency_hz + 1
+        let (divisor, clk_div_by5) = clock_setting(self.chip_type, frequency_hz);
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.set_clock(divisor, clk_div_by5);
+        self.exec(cmd)?;
+        let frequency = clock_base(self.chip_type, clk_div_by5) / (divisor as usize + 1);
+        log::info!("Frequency set to {frequency}Hz");
+        Ok(frequency)
+    }
+    /// The clock frequency in Hertz the MPSSE runs at, as set by [`FtdiMpsse::set_frequency`]
+    ///
+    /// `None` until a clock was set.
+    pub fn frequency(&self) -> Option<usize> {
+        let (divisor, clk_div_by5) = self.engine.get().clock?;
+        Some(clock_base(self.chip_type, clk_div_by5) / (divisor as usize + 1))
+    }
+    /// Checks the USB link and the MPSSE engine without any target connected.
+    ///
+    /// Enables the internal TDI to TDO loopback, shifts known patterns through it and
+    /// compares the readback, then disables the loopback again. A failure here points
+    /// to the driver or the chip rather than to the wiring.
+    ///
+    /// The clock and data pins still toggle if they are configured as outputs,
+    /// so avoid calling this while a target is attached to them.
+    pub fn self_test(&self) -> Result<(), FtdiError> {
+        let walking: Vec<u8> = (0..8)
+            .map(|i| 1 << i)
+            .chain((0..8).map(|i| !(1 << i)))
+            .collect();
+        // Longer than a single USB packet to also cover response reassembly.
+        let counting: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        let patterns: [&[u8]; 4] = [&[0x00, 0xFF, 0x55, 0xAA], &walking, &counting, &[0xA5]];
+
+        let result = self.loopback_patterns(&patterns);
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.enable_loopback(false);
+        self.exec(cmd)?;
+        result
+    }
+    /// Shifts `len` pseudo-random bytes through the internal loopback and times them
+    ///
+    /// Needs no target either, see [`FtdiMpsse::self_test`] for the pins. The bytes go out
+    /// in one command at the current clock, the time includes the USB transfers, so
+    /// [`LoopbackReport::efficiency`] shows how much of it the USB overhead takes.
+    pub fn loopback_test(&self, len: usize) -> Result<LoopbackReport, FtdiError> {
+        let pattern = pseudo_random(len);
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.enable_loopback(true)
+            .shift_bytes(false, false, &pattern);
+        let start = Instant::now();
+        let response = self.exec(cmd);
+        let elapsed = start.elapsed();
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.enable_loopback(false);
+        self.exec(cmd)?;
+        check_loopback(&pattern, &response?)?;
+        let report = LoopbackReport {
+            bytes: len,
+            elapsed,
+            frequency: self.frequency(),
+        };
+        log::info!("Loopback test passed, {report}");
+        Ok(report)
+    }
+    fn loopback_patterns(&self, patterns: &[&[u8]]) -> Result<(), FtdiError> {
+        for is_lsb in [false, true] {
+            for pattern in patterns {
+                let mut cmd = MpsseCmdBuilder::new();
+                cmd.enable_loopback(true)
+                    .shift_bytes(false, is_lsb, pattern);
+                check_loopback(pattern, &self.exec(cmd)?)?;
+            }
+            // Bit mode sends from one end of the byte and shifts reads in from the other.
+            let data = if is_lsb { 0b101 } else { 0b101 << 5 };
+            let mut cmd = MpsseCmdBuilder::new();
+            cmd.enable_loopback(true).shift_bits(false, is_lsb, data, 3);
+            let response = self.exec(cmd)?;
+            let bits = if is_lsb {
+                response[0] >> 5
+            } else {
+                response[0] & 0b111
+            };
+            if bits != 0b101 {
+                return Err(FtdiError::SelfTestFailed(format!(
+                    "bit shift sent 0b101, read back {bits:#05b}"
+                )));
+            }
+        }
+        log::info!("Self test passed");
+        Ok(())
+    }
+    /// Reads the level of several pins with a single USB round trip
+    ///
+    /// Each bank is sampled once, so all pins of a bank are read at the same moment.
+    /// Pins do not need to be allocated.
+    pub fn read_pins(&self, pins: &[Pin]) -> Result<Vec<bool>, FtdiError> {
+        let mut need_lower = false;
+        let mut need_upper = false;
+        for &pin in pins {
+            match pin {
+                Pin::Lower(idx) if idx < 8 => need_lower = true,
+                Pin::Upper(idx) if idx < self.chip_type.upper_pins() => need_upper = true,
+                _ => {
+                    return Err(FtdiError::PinFault(format!(
+                        "{:?} Interface::{} do not has {pin}",
+                        self.chip_type, self.interface
+                    )));
+                }
+            }
+        }
+        let mut cmd = MpsseCmdBuilder::new();
+        if need_lower {
+            cmd.gpio_lower();
+        }
+        if need_upper {
+            cmd.gpio_upper();
+        }
+        let response = self.exec(cmd)?;
+        if response.len() != need_lower as usize + need_upper as usize {
+            return Err(FtdiError::Other("Gpio read length not correct"));
+        }
+        let lower = if need_lower { response[0] } else { 0 };
+        let upper = if need_upper {
+            response[need_lower as usize]
         } else {
-            max_frequency / frequency_hz
+            0
         };
-
+        Ok(pins
+            .iter()
+            .map(|&pin| match pin {
+                Pin::Lower(_) => lower & pin.mask() != 0,
+                Pin::Upper(_) => upper & pin.mask() != 0,
+            })
+            .collect())
+    }
+    /// Drives several output pins with one SetDataBits command per bank
+    ///
+    /// Pins of the same bank change at the same moment, the upper bank follows the lower
+    /// bank one command later. Every pin must be allocated as an output,
+    /// e.g. through [`FtdiOutputPin`](crate::gpio::FtdiOutputPin).
+    pub fn set_pins(&mut self, pins: &[(Pin, bool)]) -> Result<(), FtdiError> {
+        let mut lower = self.lower.value;
+        let mut upper = self.upper.value;
+        for &(pin, state) in pins {
+            let (byte, value, idx) = match pin {
+                Pin::Lower(idx) if idx < 8 => (&self.lower, &mut lower, idx),
+                Pin::Upper(idx) if idx < self.chip_type.upper_pins() => {
+                    (&self.upper, &mut upper, idx)
+                }
+                _ => {
+                    return Err(FtdiError::PinFault(format!(
+                        "{:?} Interface::{} do not has {pin}",
+                        self.chip_type, self.interface
+                    )));
+                }
+            };
+            if byte.pins[idx] != Some(PinUsage::Output) {
+                return Err(FtdiError::PinFault(format!(
+                    "{pin} is not allocated as output"
+                )));
+            }
+            if state {
+                *value |= pin.mask();
+            } else {
+                *value &= !pin.mask();
+            }
+        }
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.set_clock((divisor - 1) as u16, clk_div_by5);
+        if lower != self.lower.value {
+            cmd.set_gpio_lower(lower, self.lower.direction);
+        }
+        if upper != self.upper.value {
+            cmd.set_gpio_upper(upper, self.upper.direction);
+        }
         self.exec(cmd)?;
-        log::info!("Frequency set to {}Hz", max_frequency / divisor);
-        Ok(max_frequency / divisor)
+        self.lower.value = lower;
+        self.upper.value = upper;
+        Ok(())
+    }
+    /// Read one 16-bit word of the configuration EEPROM.
+    pub(crate) fn read_eeprom_word(&self, addr: u16) -> Result<u16, FtdiError> {
+        self.ft.borrow().read_eeprom_word(addr)
+    }
+    /// Write one 16-bit word of the configuration EEPROM.
+    pub(crate) fn write_eeprom_word(&self, addr: u16, value: u16) -> Result<(), FtdiError> {
+        self.ft.borrow().write_eeprom_word(addr, value)
     }
     /// Write mpsse command and read response
     pub(crate) fn exec(&self, cmd: impl Into<MpsseCmdBuilder>) -> Result<Vec<u8>, FtdiError> {
+        self.exec_until(cmd, None)
+    }
+    /// Like [`FtdiMpsse::exec`], but fails with [`FtdiError::Timeout`] once `deadline` has passed
+    ///
+    /// Call [`FtdiMpsse::purge`] after a timeout, the chip still holds the leftovers.
+    pub(crate) fn exec_until(
+        &self,
+        cmd: impl Into<MpsseCmdBuilder>,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<u8>, FtdiError> {
         let cmd = cmd.into();
+        let engine = cmd.engine();
         let (cmd, mut response) = cmd.destruct();
-        self.ft.write_read(cmd, &mut response)?;
+        let start = self.timestamps.then(Instant::now);
+        let result = self.ft.borrow().write_read(&cmd, &mut response, deadline);
+        match (result, self.auto_reconnect) {
+            (Err(FtdiError::DeviceGone), Some(timeout)) => {
+                self.reconnect(timeout)?;
+                self.ft.borrow().write_read(&cmd, &mut response, deadline)?;
+            }
+            (result, _) => result?,
+        }
+        if let Some(start) = start {
+            let stamp = Timestamp {
+                start: start - self.epoch,
+                done: self.epoch.elapsed(),
+            };
+            log::debug!(
+                "MPSSE transaction at {stamp}, {} bytes out, {} bytes in",
+                cmd.len(),
+                response.len()
+            );
+            self.last_timestamp.set(Some(stamp));
+        }
+        let mut current = self.engine.get();
+        current.update(engine);
+        self.engine.set(current);
         Ok(response)
     }
+    /// Lets commands wait up to `timeout` for an unplugged device to come back
+    ///
+    /// The device is found again by its serial number, or by the USB port it was plugged
+    /// into if it has none. Once it is back, pin directions and levels, clock and
+    /// clocking modes are restored and the failed command is sent again.
+    /// Targets still see the pins float while the device is gone, so a transaction
+    /// interrupted halfway may need to be repeated from its start.
+    ///
+    /// Off by default, every command then fails with [`FtdiError::DeviceGone`].
+    /// Fails for a device with neither a serial number nor a known port.
+    pub fn set_auto_reconnect(&mut self, timeout: Option<Duration>) -> Result<(), FtdiError> {
+        if timeout.is_some() && !self.identity.is_unique() {
+            return Err(FtdiError::Other(
+                "Device has neither a serial number nor a port path to reconnect by",
+            ));
+        }
+        self.auto_reconnect = timeout;
+        Ok(())
+    }
+    /// Waits up to `timeout` for the device to come back and restores its state
+    ///
+    /// See [`FtdiMpsse::set_auto_reconnect`], fails with [`FtdiError::DeviceGone`]
+    /// if the device does not show up in time.
+    pub fn reconnect(&self, timeout: Duration) -> Result<(), FtdiError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.reopen() {
+                Ok(()) => break,
+                Err(e) if Instant::now() >= deadline => {
+                    log::warn!("Device did not come back: {e}");
+                    return Err(FtdiError::DeviceGone);
+                }
+                Err(e) => log::debug!("Reopen failed: {e}"),
+            }
+            thread::sleep(RECONNECT_POLL);
+        }
+        self.record(Event::Reconnect);
+        log::info!(
+            "Reconnected {:?} Interface::{}",
+            self.chip_type,
+            self.interface
+        );
+        Ok(())
+    }
+    fn reopen(&self) -> Result<(), FtdiError> {
+        let usb_device = nusb::list_devices()?
+            .find(|usb_device| self.identity.matches(&DeviceIdentity::new(usb_device)))
+            .ok_or(FtdiError::DeviceGone)?;
+        let handle = claim(
+            &usb_device.open()?,
+            self.interface,
+            self.config.detach_kernel_driver,
+        )?;
+        let mut ft = self.ft.borrow_mut();
+        ft.reopen_mpsse(handle, 0, self.config.latency_timer)?;
+        let (cmd, mut response) = self.restore_cmd(&self.save_state()).destruct();
+        ft.write_read(&cmd, &mut response, None)
+    }
+    /// Clocked bits lasting at least `delay` with the current clock and clocking mode
+    pub(crate) fn clock_bits_for(&self, delay: Duration) -> usize {
+        let engine = self.engine.get();
+        let (divisor, clk_div_by5) = engine.clock.unwrap_or((0, None));
+        let base = clock_base(self.chip_type, clk_div_by5);
+        // A bit takes two half periods, three with 3-phase clocking
+        let half_periods = if engine.three_phase == Some(true) {
+            3
+        } else {
+            2
+        };
+        (delay.as_nanos() * 2 * base as u128)
+            .div_ceil(half_periods * (divisor as u128 + 1) * 1_000_000_000) as usize
+    }
+    /// Everything known about the engine, chip, clock, modes and both GPIO banks
+    ///
+    /// Nothing is read from the chip, output levels are the ones last written.
+    pub fn debug_state(&self) -> DebugState {
+        let engine = self.engine.get();
+        DebugState {
+            chip_type: self.chip_type,
+            interface: self.interface,
+            divisor: engine.clock.map(|(divisor, _)| divisor),
+            clk_div_by5: engine.clock.and_then(|(_, clk_div_by5)| clk_div_by5),
+            frequency: self.frequency(),
+            loopback: engine.loopback.unwrap_or(false),
+            three_phase: engine.three_phase.unwrap_or(false),
+            adaptive: engine.adaptive.unwrap_or(false),
+            lower: (&self.lower).into(),
+            upper: (&self.upper).into(),
+        }
+    }
+    /// Snapshot of pin directions and levels, clock and clocking modes
+    pub fn save_state(&self) -> MpsseState {
+        MpsseState {
+            lower: [self.lower.value, self.lower.direction],
+            upper: [self.upper.value, self.upper.direction],
+            engine: self.engine.get(),
+        }
+    }
+    /// Puts pins and engine back as they were when `state` was saved
+    ///
+    /// Pin allocations are left alone, pins allocated since then are driven
+    /// as saved too. Settings never changed before the snapshot go back to
+    /// the values set when the interface was opened.
+    pub fn restore_state(&mut self, state: &MpsseState) -> Result<(), FtdiError> {
+        self.exec(self.restore_cmd(state))?;
+        [self.lower.value, self.lower.direction] = state.lower;
+        [self.upper.value, self.upper.direction] = state.upper;
+        Ok(())
+    }
+    /// Programs every setting of `state`, also onto a freshly reset chip
+    fn restore_cmd(&self, state: &MpsseState) -> MpsseCmdBuilder {
+        let engine = state.engine;
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.set_gpio_lower(state.lower[0], state.lower[1])
+            .set_gpio_upper(state.upper[0], state.upper[1])
+            .enable_loopback(engine.loopback.unwrap_or(false));
+        if self.chip_type == ChipType::FT2232D {
+            let (divisor, _) = engine.clock.unwrap_or((0, None));
+            cmd.set_clock(divisor, None);
+        } else {
+            let (divisor, clk_div_by5) = engine.clock.unwrap_or((0, Some(false)));
+            cmd.enable_3phase_data_clocking(engine.three_phase.unwrap_or(false))
+                .enable_adaptive_clocking(engine.adaptive.unwrap_or(false))
+                .set_clock(divisor, clk_div_by5);
+        }
+        cmd
+    }
+    /// Discards pending commands and unread responses in the chip
+    pub(crate) fn purge(&mut self) -> Result<(), FtdiError> {
+        self.ft.get_mut().purge()
+    }
+    /// Timestamp every transaction and log it at debug level
+    ///
+    /// Protocol traces then carry the time too, for lining them up with scope captures or target logs.
+    pub fn set_timestamps(&mut self, enable: bool) {
+        self.timestamps = enable;
+        if !enable {
+            self.last_timestamp.set(None);
+        }
+    }
+    /// Zero point of the timestamps, the moment the interface was opened
+    pub fn epoch(&self) -> Instant {
+        self.epoch
+    }
+    /// Timestamp of the latest transaction, `None` unless timestamps are enabled
+    pub fn last_timestamp(&self) -> Option<Timestamp> {
+        self.last_timestamp.get()
+    }
+    /// Independent write and read halves of this interface, see [`crate::stream`]
+    ///
+    /// Only use the MPSSE through the halves while they exist, its own commands would
+    /// race them for the responses. They stop working after a reconnect.
+    pub fn split_stream(&self) -> (StreamWriter, StreamReader) {
+        let ft = self.ft.borrow();
+        (
+            StreamWriter::new(ft.bulk_writer()),
+            StreamReader::new(ft.bulk_reader()),
+        )
+    }
+    /// Recovery event counters of this interface
+    pub fn stats(&self) -> Stats {
+        self.ft.borrow().stats()
+    }
+    pub fn reset_stats(&self) {
+        self.ft.borrow().reset_stats();
+    }
+    pub(crate) fn record(&self, event: Event) {
+        self.ft.borrow().record(event);
+    }
+    /// Locks an interface shared by protocol drivers
+    ///
+    /// A driver panicking with the lock held may have left a command half sent, so a
+    /// poisoned lock is reported as [`FtdiError::DeviceGone`], like a device that was
+    /// unplugged and is not reconnected automatically.
+    pub(crate) fn lock(mtx: &Mutex<FtdiMpsse>) -> Result<MutexGuard<'_, FtdiMpsse>, FtdiError> {
+        let lock = mtx.lock().map_err(|_| FtdiError::DeviceGone)?;
+        if lock.auto_reconnect.is_none() && lock.ft.borrow().is_gone() {
+            return Err(FtdiError::DeviceGone);
+        }
+        Ok(lock)
+    }
+    /// Executes independent commands in one USB round trip and splits the response per command
+    ///
+    /// The commands are not checked against the pins claimed by the protocol drivers,
+    /// GPIO writes among them update the pin state the drivers start from.
+    pub fn exec_many(
+        &mut self,
+        cmds: impl IntoIterator<Item = impl Into<MpsseCmdBuilder>>,
+    ) -> Result<Vec<Vec<u8>>, FtdiError> {
+        let mut all = MpsseCmdBuilder::new();
+        let mut lens = Vec::new();
+        for cmd in cmds {
+            let cmd = cmd.into();
+            lens.push(cmd.read_len());
+            all.append(cmd);
+        }
+        let [lower, upper] = all.gpio_state();
+        let response = self.exec(all)?;
+        for (bank, state) in [(&mut self.lower, lower), (&mut self.upper, upper)] {
+            if let Some([value, direction]) = state {
+                bank.value = value;
+                bank.direction = direction;
+            }
+        }
+        let mut rest = response.as_slice();
+        Ok(lens
+            .into_iter()
+            .map(|len| {
+                let (head, tail) = rest.split_at(len);
+                rest = tail;
+                head.to_vec()
+            })
+            .collect())
+    }
+    /// Runs the commands `next` builds one after another and passes each response to `done`
+    ///
+    /// The next command is already sent while the response of the previous one is read,
+    /// for long transfers made of many commands.
+    pub(crate) fn exec_pipelined<E: From<FtdiError>>(
+        &self,
+        mut next: impl FnMut() -> Result<Option<MpsseCmdBuilder>, E>,
+        done: impl FnMut(Vec<u8>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.ft.borrow().pipelined(
+            || {
+                Ok(next()?.map(|cmd| {
+                    let (cmd, response) = cmd.destruct();
+                    (cmd, response.len())
+                }))
+            },
+            done,
+        )
+    }
     /// Allocate a pin for a specific use.
     pub(crate) fn alloc_pin(&mut self, pin: Pin, usage: PinUsage) -> Result<(), FtdiError> {
         if !self.chip_type.mpsse_list().contains(&self.interface)
             && (usage != PinUsage::Input || usage != PinUsage::Output)
         {
             return Err(FtdiError::PinFault(format!(
-                "{:?} Interface::{:?} can not be used for {usage:?}",
+                "{:?} Interface::{} can not be used for {usage:?}",
                 self.chip_type, self.interface
             )));
         };
@@ -165,7 +972,7 @@ impl FtdiMpsse {
             Pin::Lower(idx) => {
                 if idx >= 8 {
                     return Err(FtdiError::PinFault(format!(
-                        "{:?} Interface::{:?} do not has {pin:?}",
+                        "{:?} Interface::{} do not has {pin}",
                         self.chip_type, self.interface
                     )));
                 };
@@ -174,7 +981,7 @@ impl FtdiMpsse {
             Pin::Upper(idx) => {
                 if idx >= self.chip_type.upper_pins() {
                     return Err(FtdiError::PinFault(format!(
-                        "{:?} Interface::{:?} do not has {pin:?}",
+                        "{:?} Interface::{} do not has {pin}",
                         self.chip_type, self.interface
                     )));
                 }
@@ -183,17 +990,17 @@ impl FtdiMpsse {
         };
         if let Some(current) = byte.pins[idx] {
             return Err(FtdiError::PinFault(format!(
-                "Unable to allocate pin {pin:?} for {usage:?}, pin is already allocated for {current:?}"
+                "Unable to allocate pin {pin} for {usage:?}, pin is already allocated for {current:?}"
             )));
         } else {
-            log::trace!("pin {:?} has been alloced for {:?}", pin, usage);
+            log::trace!("pin {pin} has been alloced for {usage:?}");
             byte.pins[idx] = Some(usage)
         }
         Ok(())
     }
-    /// Allocate a pin for a specific use.
-    pub(crate) fn free_pin(&mut self, pin: Pin) {
-        log::trace!("pin {:?} has been released", pin);
+    /// Releases a pin to a low input, the pin is free again even when the command fails
+    pub(crate) fn free_pin(&mut self, pin: Pin) -> Result<(), FtdiError> {
+        log::trace!("pin {pin} has been released");
         match pin {
             Pin::Lower(idx) => {
                 assert!(idx < 8, "Pin index {idx} is out of range 0 - 7");
@@ -202,7 +1009,7 @@ impl FtdiMpsse {
                 self.lower.direction &= !pin.mask(); // set direction to input
                 let mut cmd = MpsseCmdBuilder::new();
                 cmd.set_gpio_lower(self.lower.value, self.lower.direction);
-                self.exec(cmd).unwrap();
+                self.exec(cmd)?;
             }
             Pin::Upper(idx) => {
                 assert!(idx < 8, "Pin index {idx} is out of range 0 - 7");
@@ -211,8 +1018,209 @@ impl FtdiMpsse {
                 self.upper.direction &= !pin.mask(); // set direction to input
                 let mut cmd = MpsseCmdBuilder::new();
                 cmd.set_gpio_upper(self.upper.value, self.upper.direction);
-                self.exec(cmd).unwrap();
+                self.exec(cmd)?;
             }
         };
+        Ok(())
+    }
+}
+/// Identifies the chip from the USB descriptor, only MPSSE capable chips are supported.
+pub(crate) fn chip_type(usb_device: &nusb::DeviceInfo) -> Result<ChipType, FtdiError> {
+    match identify(usb_device) {
+        chip_type @ (ChipType::FT2232D
+        | ChipType::FT2232H
+        | ChipType::FT4232H
+        | ChipType::FT232H) => Ok(chip_type),
+        chip_type => Err(FtdiError::UnsupportedChip(chip_type)),
+    }
+}
+/// Identifies any chip from the USB descriptor, see [`register_device`](crate::register_device)
+pub(crate) fn identify(usb_device: &nusb::DeviceInfo) -> ChipType {
+    match (
+        usb_device.device_version(),
+        usb_device.serial_number().unwrap_or(""),
+    ) {
+        (0x400, _) | (0x200, "") => ChipType::Bm,
+        (0x200, _) => ChipType::Am,
+        (0x500, _) => ChipType::FT2232D,
+        (0x600, _) => ChipType::R,
+        (0x700, _) => ChipType::FT2232H,
+        (0x800, _) => ChipType::FT4232H,
+        (0x900, _) => ChipType::FT232H,
+        (0x1000, _) => ChipType::FT230X,
+        _ => list::fallback_chip_type(usb_device.vendor_id(), usb_device.product_id())
+            .unwrap_or(ChipType::Unknown),
+    }
+}
+/// Fails at the first byte the loopback did not return unchanged
+fn check_loopback(pattern: &[u8], response: &[u8]) -> Result<(), FtdiError> {
+    match response.iter().zip(pattern).position(|(r, p)| r != p) {
+        Some(idx) => Err(FtdiError::SelfTestFailed(format!(
+            "byte {idx} of {} bytes, sent {:#04x}, read back {:#04x}",
+            pattern.len(),
+            pattern[idx],
+            response[idx]
+        ))),
+        None => Ok(()),
+    }
+}
+/// Xorshift bytes, the same for every call so failures can be reproduced
+fn pseudo_random(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_F491;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+/// Clock with a divisor of 0
+fn clock_base(chip_type: ChipType, clk_div_by5: Option<bool>) -> usize {
+    match clk_div_by5 {
+        Some(true) => chip_type.max_frequency() / 5,
+        _ => chip_type.max_frequency(),
+    }
+}
+/// Clock divisor closest to `frequency_hz` without exceeding it, with the divide by 5 setting
+///
+/// The master clock is only divided by 5 for clocks the undivided one can not reach.
+fn clock_setting(chip_type: ChipType, frequency_hz: usize) -> (u16, Option<bool>) {
+    let undivided_min = chip_type.max_frequency() / (u16::MAX as usize + 1) + 1;
+    let clk_div_by5 = chip_type
+        .has_divide_by5()
+        .then_some(frequency_hz < undivided_min);
+    let max_frequency = chip_type.max_frequency();
+    let min_frequency = clock_base(chip_type, chip_type.has_divide_by5().then_some(true))
+        / (u16::MAX as usize + 1)
+        + 1;
+    let base = clock_base(chip_type, clk_div_by5);
+
+    let divisor = if frequency_hz > max_frequency {
+        log::warn!("frequency has out of range[{min_frequency}-{max_frequency}Hz]");
+        log::warn!("frequency set to {max_frequency}Hz]");
+        1
+    } else if frequency_hz < min_frequency {
+        log::warn!("frequency has out of range[{min_frequency}-{max_frequency}Hz]");
+        log::warn!("frequency set to {min_frequency}Hz]");
+        u16::MAX as usize + 1
+    } else {
+        base.div_ceil(frequency_hz)
+    };
+    ((divisor - 1) as u16, clk_div_by5)
+}
+/// Claims `interface`, detaching a kernel driver first if asked to
+fn claim(
+    device: &nusb::Device,
+    interface: Interface,
+    detach_kernel_driver: bool,
+) -> Result<nusb::Interface, FtdiError> {
+    let number = interface.interface_number();
+    Ok(if detach_kernel_driver {
+        device.detach_and_claim_interface(number)?
+    } else {
+        device.claim_interface(number)?
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        BankState, DebugState, DeviceIdentity, LoopbackReport, PinUsage, check_loopback,
+        clock_setting, pseudo_random,
+    };
+    use crate::{ChipType, Interface};
+    use std::time::Duration;
+
+    #[test]
+    fn identity_without_serial() {
+        let identity = |serial_number: Option<&str>, port_chain: &[u8]| DeviceIdentity {
+            vendor_id: 0x0403,
+            product_id: 0x6010,
+            serial_number: serial_number.map(str::to_string),
+            bus_number: 1,
+            port_chain: port_chain.to_vec(),
+        };
+        let serial = identity(Some("FT1"), &[]);
+        assert!(serial.is_unique());
+        assert!(serial.matches(&identity(Some("FT1"), &[3])));
+        assert!(!serial.matches(&identity(Some("FT2"), &[])));
+        let port = identity(None, &[1, 2]);
+        assert!(port.is_unique());
+        assert!(port.matches(&identity(None, &[1, 2])));
+        // another adapter of the same kind on the same bus
+        assert!(!port.matches(&identity(None, &[1, 3])));
+        let unknown = identity(None, &[]);
+        assert!(!unknown.is_unique());
+        assert!(!unknown.matches(&identity(None, &[])));
+    }
+    #[test]
+    fn loopback_report() {
+        let pattern = pseudo_random(1000);
+        assert_eq!(pattern, pseudo_random(1000));
+        assert!(pattern.windows(2).any(|pair| pair[0] != pair[1]));
+        assert!(check_loopback(&pattern, &pattern).is_ok());
+        let mut response = pattern.clone();
+        response[7] ^= 1;
+        assert!(check_loopback(&pattern, &response).is_err());
+        let report = LoopbackReport {
+            bytes: 1_000_000,
+            elapsed: Duration::from_secs(1),
+            frequency: Some(10_000_000),
+        };
+        assert_eq!(report.throughput(), 1e6);
+        assert_eq!(report.efficiency(), Some(0.8));
+        let report = LoopbackReport {
+            frequency: None,
+            ..report
+        };
+        assert_eq!(report.efficiency(), None);
+        assert!(report.to_string().ends_with("clock unknown"));
+    }
+    #[test]
+    fn divide_by5_below_458hz() {
+        assert_eq!(
+            clock_setting(ChipType::FT232H, 1_000_000),
+            (29, Some(false))
+        );
+        assert_eq!(clock_setting(ChipType::FT232H, 458), (65502, Some(false)));
+        // 6 MHz / 60000
+        assert_eq!(clock_setting(ChipType::FT232H, 100), (59999, Some(true)));
+        assert_eq!(clock_setting(ChipType::FT232H, 10), (u16::MAX, Some(true)));
+        assert_eq!(clock_setting(ChipType::FT2232D, 100), (59999, None));
+    }
+    #[test]
+    fn debug_state_display() {
+        let mut pins = [None; 8];
+        pins[0] = Some(PinUsage::Spi);
+        let state = DebugState {
+            chip_type: ChipType::FT2232H,
+            interface: Interface::B,
+            divisor: Some(29),
+            clk_div_by5: Some(false),
+            frequency: Some(1_000_000),
+            loopback: false,
+            three_phase: true,
+            adaptive: false,
+            lower: BankState {
+                value: 0b1000,
+                direction: 0b1011,
+                pins,
+            },
+            upper: BankState {
+                value: 0,
+                direction: 0,
+                pins: [None; 8],
+            },
+        };
+        let text = state.to_string();
+        assert!(
+            text.starts_with("FT2232H interface B\nclock 1000000Hz, divisor 29, divide by 5 off\n")
+        );
+        assert!(text.contains("3-phase on"));
+        assert!(text.contains("AD0 out low  Spi\n"));
+        assert!(text.contains("AD3 out high free\n"));
+        assert!(text.contains("AC7 in  low  free\n"));
     }
 }
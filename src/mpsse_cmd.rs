@@ -36,6 +36,10 @@ enum MpsseCmd {
     Enable3PhaseClocking = 0x8C,
     /// Used by [`MpsseCmdBuilder::enable_3phase_data_clocking`].
     Disable3PhaseClocking = 0x8D,
+    /// Used by [`MpsseCmdBuilder::clock_bits`].
+    ClockBits = 0x8E,
+    /// Used by [`MpsseCmdBuilder::clock_bits`].
+    ClockBytes = 0x8F,
     /// Used by [`MpsseCmdBuilder::enable_adaptive_clocking`].
     EnableAdaptiveClocking = 0x96,
     /// Used by [`MpsseCmdBuilder::enable_adaptive_clocking`].
@@ -100,52 +104,167 @@ impl MpsseShiftCmd {
             .with_is_tms_write(true)
             .into()
     }
-    fn tms_shift(tdo_read: bool) -> u8 {
-        // tms only be used for jtag, so tck_init_value and tdo_neg_read only can be false.
-        Self::_tms_shift(false, false, tdo_read)
+    fn tms_shift(tdo_neg_read: bool, tdo_read: bool) -> u8 {
+        // tms only be used for jtag, so tck_init_value only can be false.
+        Self::_tms_shift(false, tdo_neg_read, tdo_read)
     }
 }
 
+/// Engine settings the commands change, `None` where they leave it as it is
+///
+/// Collected while a command is built, so [`FtdiMpsse`](crate::mpsse::FtdiMpsse) knows the
+/// clock and clocking modes once it ran, e.g. to restore them after a reconnect.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EngineConfig {
+    /// Clock divisor and divide by 5 setting
+    pub(crate) clock: Option<(u16, Option<bool>)>,
+    pub(crate) loopback: Option<bool>,
+    pub(crate) three_phase: Option<bool>,
+    pub(crate) adaptive: Option<bool>,
+}
+impl EngineConfig {
+    /// Applies the settings `later` commands change on top of these.
+    pub(crate) fn update(&mut self, later: EngineConfig) {
+        self.clock = later.clock.or(self.clock);
+        self.loopback = later.loopback.or(self.loopback);
+        self.three_phase = later.three_phase.or(self.three_phase);
+        self.adaptive = later.adaptive.or(self.adaptive);
+    }
+}
+
+const MAX_BYTES_SHIFT: usize = 65536;
+const MAX_BITS_SHIFT: usize = 8;
+const MAX_TMS_SHIFT: usize = 7;
+/// SetDataBits commands at the end of a command buffer
+#[derive(Default)]
+struct GpioRun {
+    /// End of the run in the buffer, any other command ends it
+    end: usize,
+    /// Last [state, direction] of each bank in the run
+    lower: Option<[u8; 2]>,
+    upper: Option<[u8; 2]>,
+    /// Opcode of the last command of the run, unless it was a hold
+    mergeable: Option<u8>,
+}
+
 /// FTDI Multi-Protocol Synchronous Serial Engine (MPSSE) command builder.
 ///
 /// For details about the MPSSE read the [FTDI MPSSE Basics].
 ///
 /// This structure is a `Vec<u8>` that the methods push bytewise commands onto.
-/// These commands can then be written to the device with the appropriate
-/// implementations of [`send`] and [`xfer`] methods.
+/// These commands can then be written to the device with
+/// [`FtdiMpsse::exec_many`](crate::mpsse::FtdiMpsse::exec_many).
 ///
 /// This is useful for creating commands that need to do multiple operations
 /// quickly, since individual write calls can be expensive. For example,
 /// this can be used to set a GPIO low and clock data out for SPI operations.
 ///
-/// If dynamic command layout is not required, the [`mpsse`] macro can build
-/// command `[u8; N]` arrays at compile-time.
-///
 /// [FTDI MPSSE Basics]: https://www.ftdichip.com/Support/Documents/AppNotes/AN_135_MPSSE_Basics.pdf
-const MAX_BYTES_SHIFT: usize = 65536;
-const MAX_BITS_SHIFT: usize = 8;
-const MAX_TMS_SHIFT: usize = 7;
 #[derive(Default)]
-pub(crate) struct MpsseCmdBuilder {
+pub struct MpsseCmdBuilder {
     cmd: Vec<u8>,
     read_len: usize,
+    /// Whether a SetDataBits identical to the current pin state is dropped
+    coalesce_gpio: bool,
+    /// Run of SetDataBits commands at the end of `cmd`
+    gpio_run: GpioRun,
+    /// Whether TDO is sampled on the falling TCK edge
+    tdo_neg_read: bool,
+    /// Engine settings changed by `cmd`
+    engine: EngineConfig,
 }
 impl MpsseCmdBuilder {
     /// Create a new command builder.
-    pub(crate) fn new() -> MpsseCmdBuilder {
+    pub fn new() -> MpsseCmdBuilder {
         Default::default()
     }
 
+    /// Shift opcode, see [`MpsseCmdBuilder::tdo_neg_read`].
+    fn shift_cmd(
+        &self,
+        tck_init_value: bool,
+        is_bit_mode: bool,
+        is_lsb: bool,
+        is_tdi_write: bool,
+        is_tdo_read: bool,
+    ) -> u8 {
+        let cmd = MpsseShiftCmd::from(MpsseShiftCmd::shift(
+            tck_init_value,
+            is_bit_mode,
+            is_lsb,
+            is_tdi_write,
+            is_tdo_read,
+        ));
+        cmd.with_is_tdo_neg_read(cmd.is_tdo_neg_read() || (self.tdo_neg_read && is_tdo_read))
+            .into()
+    }
+
     /// Destruct the MPSSE command.
     pub(crate) fn destruct(mut self) -> (Vec<u8>, Vec<u8>) {
         self.send_immediate();
         (self.cmd, vec![0; self.read_len])
     }
 
+    /// Drop SetDataBits commands that program the state the pins already have, and merge
+    /// one that directly follows a SetDataBits of the same bank into it.
+    ///
+    /// Only directly preceding SetDataBits count, with any other command in between
+    /// the GPIO command is kept. Repeats used as delay go through
+    /// [`MpsseCmdBuilder::hold_gpio_lower`] and [`MpsseCmdBuilder::hold_gpio_upper`].
+    pub(crate) fn coalesce_gpio(&mut self, enable: bool) -> &mut Self {
+        self.coalesce_gpio = enable;
+        self
+    }
+
+    /// Sample TDO on the falling edge instead of the rising edge, only for TCK idling low.
+    ///
+    /// Slow or level-shifted targets may not have TDO stable at the rising edge,
+    /// the falling edge gives them another half TCK period.
+    pub(crate) fn tdo_neg_read(&mut self, enable: bool) -> &mut Self {
+        self.tdo_neg_read = enable;
+        self
+    }
+
+    /// Appends the commands of `other`, its response follows the response of `self`.
+    pub fn append(&mut self, other: MpsseCmdBuilder) -> &mut Self {
+        self.cmd.extend_from_slice(&other.cmd);
+        self.read_len += other.read_len;
+        self.engine.update(other.engine);
+        self
+    }
+
+    /// Number of response bytes the commands produce.
+    pub fn read_len(&self) -> usize {
+        self.read_len
+    }
+
+    /// Engine settings in effect once the commands have run.
+    pub(crate) fn engine(&self) -> EngineConfig {
+        self.engine
+    }
+
+    /// Value and direction of the lower and upper bank once the commands have run,
+    /// `None` for a bank the commands do not set.
+    pub(crate) fn gpio_state(&self) -> [Option<[u8; 2]>; 2] {
+        let mut state = [None; 2];
+        let mut rest = self.cmd.as_slice();
+        while !rest.is_empty() {
+            let len = command_len(rest).min(rest.len());
+            match rest[..len] {
+                [0x80, value, direction] => state[0] = Some([value, direction]),
+                [0x82, value, direction] => state[1] = Some([value, direction]),
+                _ => {}
+            }
+            rest = &rest[len..];
+        }
+        state
+    }
+
     /// Set the MPSSE clock frequency using provided
     /// divisor value and clock divider configuration.
     /// Both parameters are device dependent.
     pub(crate) fn set_clock(&mut self, divisor: u16, clk_div_by5: Option<bool>) -> &mut Self {
+        self.engine.clock = Some((divisor, clk_div_by5));
         match clk_div_by5 {
             Some(true) => self.cmd.push(MpsseCmd::EnableClockDivideBy5 as u8),
             Some(false) => self.cmd.push(MpsseCmd::DisableClockDivideBy5 as u8),
@@ -161,6 +280,7 @@ impl MpsseCmdBuilder {
 
     /// MPSSE loopback state.
     pub(crate) fn enable_loopback(&mut self, state: bool) -> &mut Self {
+        self.engine.loopback = Some(state);
         if state {
             self.cmd.push(MpsseCmd::EnableLoopback as u8);
         } else {
@@ -192,6 +312,7 @@ impl MpsseCmdBuilder {
     /// 1. Data setup for 1/2 clock period
     /// 2. Pulse clock for 1/2 clock period
     pub(crate) fn enable_3phase_data_clocking(&mut self, state: bool) -> &mut Self {
+        self.engine.three_phase = Some(state);
         if state {
             self.cmd.push(MpsseCmd::Enable3PhaseClocking as u8);
         } else {
@@ -204,6 +325,7 @@ impl MpsseCmdBuilder {
     ///
     /// This is only available on FTx232H devices.
     pub(crate) fn enable_adaptive_clocking(&mut self, state: bool) -> &mut Self {
+        self.engine.adaptive = Some(state);
         if state {
             self.cmd.push(MpsseCmd::EnableAdaptiveClocking as u8);
         } else {
@@ -223,10 +345,14 @@ impl MpsseCmdBuilder {
     ///
     /// * `state` - GPIO state mask, `0` is low (or input pin), `1` is high.
     /// * `direction` - GPIO direction mask, `0` is input, `1` is output.
-    pub(crate) fn set_gpio_lower(&mut self, state: u8, direction: u8) -> &mut Self {
-        self.cmd
-            .extend_from_slice(&[MpsseCmd::SetDataBitsLowbyte as u8, state, direction]);
-        self
+    pub fn set_gpio_lower(&mut self, state: u8, direction: u8) -> &mut Self {
+        self.set_gpio(MpsseCmd::SetDataBitsLowbyte, state, direction, false)
+    }
+
+    /// Like [`MpsseCmdBuilder::set_gpio_lower`], but never coalesced, repeats of the same
+    /// state hold the pins for one command each.
+    pub(crate) fn hold_gpio_lower(&mut self, state: u8, direction: u8) -> &mut Self {
+        self.set_gpio(MpsseCmd::SetDataBitsLowbyte, state, direction, true)
     }
 
     /// Set the pin direction and state of the upper byte (8-15) GPIO pins on
@@ -246,15 +372,69 @@ impl MpsseCmdBuilder {
     /// On the FT232H only CBUS5, CBUS6, CBUS8, and CBUS9 can be controlled.
     /// These pins confusingly map to the first four bits in the direction and
     /// state masks.
-    pub(crate) fn set_gpio_upper(&mut self, state: u8, direction: u8) -> &mut Self {
-        self.cmd
-            .extend_from_slice(&[MpsseCmd::SetDataBitsHighbyte as u8, state, direction]);
+    pub fn set_gpio_upper(&mut self, state: u8, direction: u8) -> &mut Self {
+        self.set_gpio(MpsseCmd::SetDataBitsHighbyte, state, direction, false)
+    }
+
+    /// Like [`MpsseCmdBuilder::set_gpio_upper`], but never coalesced.
+    pub(crate) fn hold_gpio_upper(&mut self, state: u8, direction: u8) -> &mut Self {
+        self.set_gpio(MpsseCmd::SetDataBitsHighbyte, state, direction, true)
+    }
+
+    fn set_gpio(&mut self, opcode: MpsseCmd, state: u8, direction: u8, hold: bool) -> &mut Self {
+        let opcode = opcode as u8;
+        let run = &mut self.gpio_run;
+        if run.end != self.cmd.len() || self.cmd.is_empty() {
+            *run = GpioRun::default();
+        }
+        let bank = if opcode == MpsseCmd::SetDataBitsHighbyte as u8 {
+            &mut run.upper
+        } else {
+            &mut run.lower
+        };
+        let pins = Some([state, direction]);
+        if self.coalesce_gpio && !hold {
+            if *bank == pins {
+                return self;
+            }
+            if run.mergeable == Some(opcode) {
+                // the previous state would only last for a single command
+                let len = self.cmd.len();
+                self.cmd[len - 2..].copy_from_slice(&[state, direction]);
+                *bank = pins;
+                return self;
+            }
+        }
+        self.cmd.extend_from_slice(&[opcode, state, direction]);
+        *bank = pins;
+        run.end = self.cmd.len();
+        run.mergeable = (!hold).then_some(opcode);
+        self
+    }
+
+    /// Clock for `len` bits without transferring data.
+    ///
+    /// This is only available on FTx232H devices.
+    pub fn clock_bits(&mut self, mut len: usize) -> &mut Self {
+        while len >= 8 {
+            let bytes = (len / 8).min(MAX_BYTES_SHIFT);
+            self.cmd.extend_from_slice(&[
+                MpsseCmd::ClockBytes as u8,
+                ((bytes - 1) & 0xFF) as u8,
+                (((bytes - 1) >> 8) & 0xFF) as u8,
+            ]);
+            len -= bytes * 8;
+        }
+        if len > 0 {
+            self.cmd
+                .extend_from_slice(&[MpsseCmd::ClockBits as u8, (len - 1) as u8]);
+        }
         self
     }
 
     /// Get the pin state state of the lower byte (0-7) GPIO pins on the MPSSE
     /// interface.
-    pub(crate) fn gpio_lower(&mut self) -> &mut Self {
+    pub fn gpio_lower(&mut self) -> &mut Self {
         self.read_len += 1;
         self.cmd.push(MpsseCmd::GetDataBitsLowbyte as u8);
         self
@@ -267,7 +447,7 @@ impl MpsseCmdBuilder {
     /// mappings.
     ///
     /// [`set_gpio_upper`]: MpsseCmdBuilder::set_gpio_upper
-    pub(crate) fn gpio_upper(&mut self) -> &mut Self {
+    pub fn gpio_upper(&mut self) -> &mut Self {
         self.read_len += 1;
         self.cmd.push(MpsseCmd::GetDataBitsHighbyte as u8);
         self
@@ -307,7 +487,7 @@ impl MpsseCmdBuilder {
     /// No data is clocked into the device on TDO/DI.
     ///
     /// This will panic for data lengths greater than `u16::MAX + 1`.
-    pub(crate) fn shift_bytes_out(
+    pub fn shift_bytes_out(
         &mut self,
         tck_init_value: bool,
         is_lsb: bool,
@@ -334,7 +514,7 @@ impl MpsseCmdBuilder {
         );
         len -= 1;
         self.cmd.extend_from_slice(&[
-            MpsseShiftCmd::shift(tck_init_value, false, is_lsb, true, false),
+            self.shift_cmd(tck_init_value, false, is_lsb, true, false),
             (len & 0xFF) as u8,
             ((len >> 8) & 0xFF) as u8,
         ]);
@@ -352,7 +532,7 @@ impl MpsseCmdBuilder {
     /// * `mode` - Data clocking mode.
     /// * `len` - Number of bytes to clock in.
     ///   This will panic for values greater than `u16::MAX + 1`.
-    pub(crate) fn shift_bytes_in(
+    pub fn shift_bytes_in(
         &mut self,
         tck_init_value: bool,
         is_lsb: bool,
@@ -381,7 +561,7 @@ impl MpsseCmdBuilder {
         self.read_len += len;
         len -= 1;
         self.cmd.extend_from_slice(&[
-            MpsseShiftCmd::shift(tck_init_value, false, is_lsb, false, true),
+            self.shift_cmd(tck_init_value, false, is_lsb, false, true),
             (len & 0xFF) as u8,
             ((len >> 8) & 0xFF) as u8,
         ]);
@@ -391,12 +571,7 @@ impl MpsseCmdBuilder {
     /// Clock data in and out simultaneously.
     ///
     /// This will panic for data lengths greater than `u16::MAX + 1`.
-    pub(crate) fn shift_bytes(
-        &mut self,
-        tck_init_value: bool,
-        is_lsb: bool,
-        data: &[u8],
-    ) -> &mut Self {
+    pub fn shift_bytes(&mut self, tck_init_value: bool, is_lsb: bool, data: &[u8]) -> &mut Self {
         for slice in data.chunks(MAX_BYTES_SHIFT) {
             self.shift_bytes_limited(tck_init_value, is_lsb, slice);
         }
@@ -419,7 +594,7 @@ impl MpsseCmdBuilder {
         self.read_len += len;
         len -= 1;
         self.cmd.extend_from_slice(&[
-            MpsseShiftCmd::shift(tck_init_value, false, is_lsb, true, true),
+            self.shift_cmd(tck_init_value, false, is_lsb, true, true),
             (len & 0xFF) as u8,
             ((len >> 8) & 0xFF) as u8,
         ]);
@@ -435,7 +610,7 @@ impl MpsseCmdBuilder {
     /// * `data` - Data bits.
     /// * `len` - Number of bits to clock out.
     ///   This will panic for values greater than 8.
-    pub(crate) fn shift_bits_out(
+    pub fn shift_bits_out(
         &mut self,
         tck_init_value: bool,
         is_lsb: bool,
@@ -447,7 +622,7 @@ impl MpsseCmdBuilder {
         }
         assert!(len <= 8, "data length should be less than {MAX_BITS_SHIFT}");
         self.cmd.extend_from_slice(&[
-            MpsseShiftCmd::shift(tck_init_value, true, is_lsb, true, false),
+            self.shift_cmd(tck_init_value, true, is_lsb, true, false),
             (len - 1) as u8,
             data,
         ]);
@@ -461,19 +636,14 @@ impl MpsseCmdBuilder {
     /// * `mode` - Bit clocking mode.
     /// * `len` - Number of bits to clock in.
     ///   This will panic for values greater than 8.
-    pub(crate) fn shift_bits_in(
-        &mut self,
-        tck_init_value: bool,
-        is_lsb: bool,
-        len: usize,
-    ) -> &mut Self {
+    pub fn shift_bits_in(&mut self, tck_init_value: bool, is_lsb: bool, len: usize) -> &mut Self {
         if len == 0 {
             return self;
         }
         assert!(len <= 8, "data length should be less than {MAX_BITS_SHIFT}");
         self.read_len += 1;
         self.cmd.extend_from_slice(&[
-            MpsseShiftCmd::shift(tck_init_value, true, is_lsb, false, true),
+            self.shift_cmd(tck_init_value, true, is_lsb, false, true),
             (len - 1) as u8,
         ]);
         self
@@ -486,7 +656,7 @@ impl MpsseCmdBuilder {
     /// * `mode` - Bit clocking mode.
     /// * `len` - Number of bits to clock in.
     ///   This will panic for values greater than 8.
-    pub(crate) fn shift_bits(
+    pub fn shift_bits(
         &mut self,
         tck_init_value: bool,
         is_lsb: bool,
@@ -501,7 +671,7 @@ impl MpsseCmdBuilder {
 
         self.read_len += 1;
         self.cmd.extend_from_slice(&[
-            MpsseShiftCmd::shift(tck_init_value, true, is_lsb, true, true),
+            self.shift_cmd(tck_init_value, true, is_lsb, true, true),
             (len - 1) as u8,
             data,
         ]);
@@ -517,14 +687,17 @@ impl MpsseCmdBuilder {
     /// * `tdi` - Value to place on TDI while clocking.
     /// * `len` - Number of bits to clock out.
     ///   This will panic for values greater than 7.
-    pub(crate) fn clock_tms_out(&mut self, tdi: bool, data: u8, len: usize) -> &mut Self {
+    pub fn clock_tms_out(&mut self, tdi: bool, data: u8, len: usize) -> &mut Self {
         if len == 0 {
             return self;
         }
         assert!(len <= 7, "data length should be less than {MAX_TMS_SHIFT}");
         let data = if tdi { data | 0x80 } else { data };
-        self.cmd
-            .extend_from_slice(&[MpsseShiftCmd::tms_shift(false), (len - 1) as u8, data]);
+        self.cmd.extend_from_slice(&[
+            MpsseShiftCmd::tms_shift(false, false),
+            (len - 1) as u8,
+            data,
+        ]);
         self
     }
 
@@ -537,21 +710,77 @@ impl MpsseCmdBuilder {
     /// * `tdi` - Value to place on TDI while clocking.
     /// * `len` - Number of bits to clock out.
     ///   This will panic for values greater than 7.
-    pub(crate) fn clock_tms(&mut self, tdi: bool, data: u8, len: usize) -> &mut Self {
+    pub fn clock_tms(&mut self, tdi: bool, data: u8, len: usize) -> &mut Self {
         if len == 0 {
             return self;
         }
         assert!(len <= 7, "data length should be less than {MAX_TMS_SHIFT}");
         self.read_len += 1;
         let data = if tdi { data | 0x80 } else { data };
-        self.cmd
-            .extend_from_slice(&[MpsseShiftCmd::tms_shift(true), (len - 1) as u8, data]);
+        self.cmd.extend_from_slice(&[
+            MpsseShiftCmd::tms_shift(self.tdo_neg_read, true),
+            (len - 1) as u8,
+            data,
+        ]);
         self
     }
 }
+/// Splits a command stream into pieces of at most `max_len` bytes without cutting a command.
+///
+/// A single command longer than `max_len` becomes a piece of its own.
+pub(crate) fn split_commands(cmd: &[u8], max_len: usize) -> Vec<&[u8]> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    while end < cmd.len() {
+        let len = command_len(&cmd[end..]).min(cmd.len() - end);
+        if end > start && end + len - start > max_len {
+            pieces.push(&cmd[start..end]);
+            start = end;
+        }
+        end += len;
+    }
+    if end > start {
+        pieces.push(&cmd[start..end]);
+    }
+    pieces
+}
+/// Length of the command at the start of `cmd`, opcode included.
+fn command_len(cmd: &[u8]) -> usize {
+    let opcode = cmd[0];
+    if opcode & 0x80 == 0 {
+        let shift = MpsseShiftCmd::from(opcode);
+        return match (
+            shift.is_tms_write(),
+            shift.is_bit_mode(),
+            shift.is_tdi_write(),
+        ) {
+            // opcode, length, data
+            (true, _, _) | (false, true, true) => 3,
+            // opcode, length
+            (false, true, false) => 2,
+            // opcode, length low, length high
+            (false, false, false) => 3,
+            (false, false, true) => {
+                let len = cmd
+                    .get(1..3)
+                    .map_or(0, |len| u16::from_le_bytes([len[0], len[1]]));
+                3 + len as usize + 1
+            }
+        };
+    }
+    match opcode {
+        // opcode, value, direction / divisor low, divisor high / length low, length high
+        0x80 | 0x82 | 0x86 | 0x8F | 0x9C | 0x9D | 0x9E => 3,
+        // opcode, length
+        0x8E => 2,
+        _ => 1,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::MpsseShiftCmd;
+    use super::{MpsseCmdBuilder, MpsseShiftCmd, split_commands};
     #[test]
     fn mpsse_shift_cmd_write_box_test() {
         // AN108 3.3
@@ -618,11 +847,12 @@ mod test {
         assert_eq!(0x4au8, MpsseShiftCmd::_tms_shift(true, false, false)); // Not used.
         assert_eq!(0x4au8, MpsseShiftCmd::_tms_shift(true, true, false)); // Not used.
         assert_eq!(0x4bu8, MpsseShiftCmd::_tms_shift(false, false, false));
-        assert_eq!(0x4bu8, MpsseShiftCmd::tms_shift(false));
+        assert_eq!(0x4bu8, MpsseShiftCmd::tms_shift(false, false));
         assert_eq!(0x4bu8, MpsseShiftCmd::_tms_shift(false, true, false)); // Not used.
         assert_eq!(0x6au8, MpsseShiftCmd::_tms_shift(true, false, true)); // Not used.
         assert_eq!(0x6bu8, MpsseShiftCmd::_tms_shift(false, false, true));
-        assert_eq!(0x6bu8, MpsseShiftCmd::tms_shift(true));
+        assert_eq!(0x6bu8, MpsseShiftCmd::tms_shift(false, true));
+        assert_eq!(0x6fu8, MpsseShiftCmd::tms_shift(true, true));
         assert_eq!(0x6eu8, MpsseShiftCmd::_tms_shift(true, true, true)); // Not used.
         assert_eq!(0x6fu8, MpsseShiftCmd::_tms_shift(false, true, true)); // Not used.
     }
@@ -675,4 +905,97 @@ mod test {
             ]
         )
     }
+    #[test]
+    fn tdo_neg_read() {
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.tdo_neg_read(true)
+            .shift_bytes_in(false, true, 1)
+            .shift_bytes_out(false, true, &[0])
+            .clock_tms(false, 0, 1);
+        let (cmd, _) = cmd.destruct();
+        assert_eq!(cmd[0], 0x2c);
+        assert_eq!(cmd[3], 0x19);
+        assert_eq!(cmd[7], 0x6f);
+    }
+    #[test]
+    fn split_at_command_boundaries() {
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.set_gpio_lower(0, 0)
+            .shift_bytes_out(false, false, &[0; 4])
+            .shift_bits_in(false, false, 3)
+            .gpio_lower();
+        let (cmd, _) = cmd.destruct();
+        let pieces = split_commands(&cmd, 8);
+        // gpio | bytes | bits, read gpio, send immediate
+        assert_eq!(
+            pieces.iter().map(|x| x.len()).collect::<Vec<_>>(),
+            [3, 7, 4]
+        );
+        assert_eq!(pieces.concat(), cmd);
+        assert_eq!(split_commands(&cmd, 64).len(), 1);
+    }
+    #[test]
+    fn gpio_state_of_batch() {
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.set_gpio_lower(1, 3)
+            // data bytes that look like a SetDataBits opcode are skipped
+            .shift_bytes_out(false, false, &[0x82, 0xFF, 0xFF])
+            .set_gpio_lower(2, 3);
+        assert_eq!(cmd.gpio_state(), [Some([2, 3]), None]);
+        cmd.set_gpio_upper(4, 5);
+        assert_eq!(cmd.gpio_state(), [Some([2, 3]), Some([4, 5])]);
+        assert_eq!(MpsseCmdBuilder::new().gpio_state(), [None, None]);
+    }
+    #[test]
+    fn coalesce_identical_gpio() {
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.coalesce_gpio(true)
+            .set_gpio_lower(1, 3)
+            .set_gpio_upper(0, 0)
+            .set_gpio_lower(1, 3)
+            .set_gpio_upper(0, 0)
+            .shift_bits_in(false, false, 1)
+            .set_gpio_lower(1, 3);
+        let (cmd, _) = cmd.destruct();
+        assert_eq!(cmd, [0x80, 1, 3, 0x82, 0, 0, 0x22, 0, 0x80, 1, 3, 0x87]);
+    }
+    #[test]
+    fn hold_and_merge_gpio() {
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.coalesce_gpio(true)
+            .hold_gpio_lower(1, 3)
+            .hold_gpio_lower(1, 3)
+            .set_gpio_lower(1, 3)
+            .set_gpio_lower(2, 3)
+            .set_gpio_lower(0, 3)
+            .set_gpio_upper(1, 1)
+            .set_gpio_lower(2, 3);
+        let (cmd, _) = cmd.destruct();
+        assert_eq!(
+            cmd,
+            [
+                0x80, 1, 3, 0x80, 1, 3, 0x80, 0, 3, 0x82, 1, 1, 0x80, 2, 3, 0x87
+            ]
+        );
+    }
+    #[test]
+    fn engine_settings() {
+        let mut first = MpsseCmdBuilder::new();
+        first.set_clock(5, Some(false)).enable_loopback(true);
+        let mut second = MpsseCmdBuilder::new();
+        second.enable_loopback(false).enable_adaptive_clocking(true);
+        first.append(second);
+        let engine = first.engine();
+        assert_eq!(engine.clock, Some((5, Some(false))));
+        assert_eq!(engine.loopback, Some(false));
+        assert_eq!(engine.adaptive, Some(true));
+        assert_eq!(engine.three_phase, None);
+    }
+    #[test]
+    fn clock_without_data() {
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.clock_bits(65536 * 8 + 8 * 3 + 5);
+        let (cmd, _) = cmd.destruct();
+        assert_eq!(cmd, [0x8F, 0xFF, 0xFF, 0x8F, 2, 0, 0x8E, 4, 0x87]);
+    }
 }
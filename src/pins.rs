@@ -0,0 +1,251 @@
+//! Compile-time pin allocation.
+//!
+//! [`FtdiMpsse::take_pins`] hands out one token per pin, exactly once per device.
+//! Constructors taking tokens consume them by value, so using a pin twice is a
+//! compile error instead of a [`FtdiError::PinFault`] at runtime:
+//!
+//! ```ignore
+//! let mut mpsse = FtdiMpsse::open(&device.usb_device, device.interface[0])?;
+//! let pins = mpsse.take_pins().unwrap();
+//! let mtx = Arc::new(Mutex::new(mpsse));
+//! let spi = FtdiSpi::from_pins(mtx.clone(), (pins.ad0, pins.ad1, pins.ad2))?;
+//! let cs = FtdiOutputPin::from_pin(mtx.clone(), pins.ad3)?;
+//! // FtdiJtag::from_pins(mtx, (pins.ad0, ..)) no longer compiles, AD0 was moved.
+//! ```
+//!
+//! The dynamic constructors taking [`Pin`] remain for configuration driven tools,
+//! pins they allocate are still checked at runtime.
+use crate::{
+    FtdiError, Interface, Pin,
+    gpio::{FtdiInputPin, FtdiOutputPin},
+    i2c::{FtdiI2c, FtdiI2cError, I2cConfig},
+    jtag::FtdiJtag,
+    mpsse::FtdiMpsse,
+    spi::{
+        FtdiSpi, FtdiSpiDevice, FtdiSpiError, FtdiSpiHalfduplex, FtdiSpiRx, FtdiSpiTx,
+        SpiDeviceConfig,
+    },
+    swd::{FtdiSwd, FtdiSwdError},
+};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Token standing for one pin of an FTDI interface
+pub trait TypedPin {
+    const PIN: Pin;
+}
+
+macro_rules! typed_pins {
+    ($($field:ident: $name:ident = $pin:expr,)*) => {
+        $(
+            #[doc = concat!("Token for [`", stringify!($pin), "`]")]
+            pub struct $name(());
+            impl TypedPin for $name {
+                const PIN: Pin = $pin;
+            }
+        )*
+        /// Every pin token of one interface, see [`FtdiMpsse::take_pins`]
+        ///
+        /// Upper pins the chip does not have fail when they are allocated.
+        pub struct Pins {
+            $(pub $field: $name,)*
+        }
+        impl Pins {
+            const fn new() -> Self {
+                Pins {
+                    $($field: $name(()),)*
+                }
+            }
+        }
+    };
+}
+typed_pins! {
+    ad0: AD0 = Pin::Lower(0),
+    ad1: AD1 = Pin::Lower(1),
+    ad2: AD2 = Pin::Lower(2),
+    ad3: AD3 = Pin::Lower(3),
+    ad4: AD4 = Pin::Lower(4),
+    ad5: AD5 = Pin::Lower(5),
+    ad6: AD6 = Pin::Lower(6),
+    ad7: AD7 = Pin::Lower(7),
+    ac0: AC0 = Pin::Upper(0),
+    ac1: AC1 = Pin::Upper(1),
+    ac2: AC2 = Pin::Upper(2),
+    ac3: AC3 = Pin::Upper(3),
+    ac4: AC4 = Pin::Upper(4),
+    ac5: AC5 = Pin::Upper(5),
+    ac6: AC6 = Pin::Upper(6),
+    ac7: AC7 = Pin::Upper(7),
+}
+
+/// Parses datasheet pin names like `AD3` or `BC0`, case insensitive
+///
+/// The first letter names the channel and is not checked against the interface,
+/// `D` selects the lower bank (xDBUS) and `C` the upper bank (xCBUS).
+impl FromStr for Pin {
+    type Err = FtdiError;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let invalid = || FtdiError::PinFault(format!("{name:?} is not a pin name like AD3 or AC0"));
+        let upper = name.to_ascii_uppercase();
+        let [channel, bank, idx] = upper.as_bytes() else {
+            return Err(invalid());
+        };
+        if !(b'A'..=b'D').contains(channel) || !(b'0'..=b'7').contains(idx) {
+            return Err(invalid());
+        }
+        let idx = (idx - b'0') as usize;
+        match bank {
+            b'D' => Ok(Pin::Lower(idx)),
+            b'C' => Ok(Pin::Upper(idx)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Writes the datasheet name of channel A, `AD3` or `AC0`
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Lower(idx) => write!(f, "AD{idx}"),
+            Pin::Upper(idx) => write!(f, "AC{idx}"),
+        }
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Interface::A => "A",
+            Interface::B => "B",
+            Interface::C => "C",
+            Interface::D => "D",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Parses `A` to `D`, case insensitive
+impl FromStr for Interface {
+    type Err = FtdiError;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Interface::ALL
+            .into_iter()
+            .find(|interface| name.eq_ignore_ascii_case(&interface.to_string()))
+            .ok_or_else(|| FtdiError::OpenFailed(format!("{name:?} is not an interface A to D")))
+    }
+}
+
+impl FtdiMpsse {
+    /// Takes the pin tokens, only the first call returns them
+    pub fn take_pins(&mut self) -> Option<Pins> {
+        if self.pins_taken {
+            return None;
+        }
+        self.pins_taken = true;
+        Some(Pins::new())
+    }
+}
+
+impl FtdiOutputPin {
+    pub fn from_pin<P: TypedPin>(mtx: Arc<Mutex<FtdiMpsse>>, _pin: P) -> Result<Self, FtdiError> {
+        Self::new(mtx, P::PIN)
+    }
+}
+impl FtdiInputPin {
+    pub fn from_pin<P: TypedPin>(mtx: Arc<Mutex<FtdiMpsse>>, _pin: P) -> Result<Self, FtdiError> {
+        Self::new(mtx, P::PIN)
+    }
+}
+impl FtdiSpi {
+    /// SCK, MOSI, MISO
+    pub fn from_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        _pins: (AD0, AD1, AD2),
+    ) -> Result<Self, FtdiError> {
+        Self::new(mtx)
+    }
+}
+impl FtdiSpiHalfduplex {
+    /// SCK, MOSI, MISO
+    pub fn from_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        _pins: (AD0, AD1, AD2),
+    ) -> Result<Self, FtdiSpiError> {
+        Self::new(mtx)
+    }
+}
+impl FtdiSpiTx {
+    /// SCK, MOSI
+    pub fn from_pins(mtx: Arc<Mutex<FtdiMpsse>>, _pins: (AD0, AD1)) -> Result<Self, FtdiSpiError> {
+        Self::new(mtx)
+    }
+}
+impl FtdiSpiRx {
+    /// SCK, MISO
+    pub fn from_pins(mtx: Arc<Mutex<FtdiMpsse>>, _pins: (AD0, AD2)) -> Result<Self, FtdiSpiError> {
+        Self::new(mtx)
+    }
+}
+impl FtdiSpiDevice {
+    /// SCK, MOSI, MISO, CS, the pins of `config` are replaced by these
+    pub fn from_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        _pins: (AD0, AD1, AD2, AD3),
+        config: SpiDeviceConfig,
+    ) -> Result<Self, FtdiSpiError> {
+        Self::new(mtx, config.pins(AD0::PIN, AD1::PIN, AD2::PIN).cs(AD3::PIN))
+    }
+}
+impl FtdiI2c {
+    /// SCL, SDA out, SDA in
+    pub fn from_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        _pins: (AD0, AD1, AD2),
+        config: I2cConfig,
+    ) -> Result<Self, FtdiI2cError> {
+        Self::new(mtx, config)
+    }
+}
+impl FtdiJtag {
+    /// TCK, TDI, TDO, TMS
+    pub fn from_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        _pins: (AD0, AD1, AD2, AD3),
+    ) -> Result<Self, FtdiError> {
+        Self::new(mtx)
+    }
+}
+impl FtdiSwd {
+    /// SWCLK, SWDIO out, SWDIO in
+    pub fn from_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        _pins: (AD0, AD1, AD2),
+    ) -> Result<Self, FtdiSwdError> {
+        Self::new(mtx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ChipType, Interface, Pin};
+
+    #[test]
+    fn names_round_trip() {
+        for pin in Pin::iter(ChipType::FT232H) {
+            assert_eq!(pin.to_string().parse::<Pin>().unwrap(), pin);
+        }
+        assert_eq!("bc7".parse::<Pin>().unwrap(), Pin::Upper(7));
+        for interface in Interface::ALL {
+            assert_eq!(
+                interface.to_string().parse::<Interface>().unwrap(),
+                interface
+            );
+        }
+        assert_eq!("d".parse::<Interface>().unwrap(), Interface::D);
+        assert!("E".parse::<Interface>().is_err());
+        assert_eq!(Pin::iter(ChipType::FT4232H).count(), 8);
+    }
+}
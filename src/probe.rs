@@ -0,0 +1,152 @@
+//! Detect what is attached to the FTDI pins without knowing the protocol in advance.
+//!
+//! Each supported protocol is tried in turn with its default pin assignment,
+//! but only when all of its pins are in the candidate set:
+//!
+//! | Protocol  | Pins      | Detected by                   |
+//! |-----------|-----------|-------------------------------|
+//! | JTAG      | AD0 - AD3 | IDCODEs shifted out of DR     |
+//! | SWD       | AD0 - AD2 | DPIDR read after line reset   |
+//! | SPI flash | AD0 - AD3 | JEDEC ID (0x9F)               |
+//! | I2C       | AD0 - AD2 | Addresses acknowledging write |
+//!
+//! Probing drives the pins, so only pass pins whose target tolerates
+//! being clocked with any of the protocols above.
+use crate::{
+    FtdiError, Pin,
+    i2c::{FtdiI2c, FtdiI2cError, I2cConfig, I2cProbe},
+    jtag::FtdiJtag,
+    mpsse::FtdiMpsse,
+    spi::{FtdiSpiDevice, FtdiSpiError, SpiDeviceConfig},
+    swd::{FtdiSwd, FtdiSwdError},
+};
+use eh1::spi::{Operation, SpiDevice};
+use std::sync::{Arc, Mutex};
+
+const JTAG_PINS: [Pin; 4] = [Pin::Lower(0), Pin::Lower(1), Pin::Lower(2), Pin::Lower(3)];
+const SWD_PINS: [Pin; 3] = [Pin::Lower(0), Pin::Lower(1), Pin::Lower(2)];
+const SPI_PINS: [Pin; 4] = [Pin::Lower(0), Pin::Lower(1), Pin::Lower(2), Pin::Lower(3)];
+const I2C_PINS: [Pin; 3] = [Pin::Lower(0), Pin::Lower(1), Pin::Lower(2)];
+/// Flash command returning manufacturer, memory type and capacity.
+const JEDEC_READ_ID: u8 = 0x9F;
+
+/// Everything found by [`probe`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// IDCODEs of the JTAG chain, devices in BYPASS reported as 0.
+    pub jtag: Vec<u32>,
+    /// DPIDR of an SWD debug port.
+    pub swd: Option<u32>,
+    /// JEDEC ID of an SPI flash (manufacturer, memory type, capacity).
+    pub spi_flash: Option<[u8; 3]>,
+    /// I2C addresses that acknowledged.
+    pub i2c: Vec<u8>,
+}
+impl ProbeReport {
+    /// Whether nothing was detected.
+    pub fn is_empty(&self) -> bool {
+        self.jtag.is_empty()
+            && self.swd.is_none()
+            && self.spi_flash.is_none()
+            && self.i2c.is_empty()
+    }
+}
+
+/// Try every protocol whose pins are all in `candidates` and report what answered.
+///
+/// Protocol errors only mean nothing was found, USB errors are returned.
+pub fn probe(mtx: Arc<Mutex<FtdiMpsse>>, candidates: &[Pin]) -> Result<ProbeReport, FtdiError> {
+    let usable = |pins: &[Pin]| pins.iter().all(|pin| candidates.contains(pin));
+    let mut report = ProbeReport::default();
+    if usable(&JTAG_PINS) {
+        report.jtag = transport_only(probe_jtag(mtx.clone()), "JTAG")?.unwrap_or_default();
+    }
+    if usable(&SWD_PINS) {
+        report.swd = transport_only(probe_swd(mtx.clone()).map_err(swd_inner), "SWD")?;
+    }
+    if usable(&SPI_PINS) {
+        report.spi_flash =
+            transport_only(probe_spi_flash(mtx.clone()).map_err(spi_inner), "SPI flash")?.flatten();
+    }
+    if usable(&I2C_PINS) {
+        report.i2c = transport_only(probe_i2c(mtx).map_err(i2c_inner), "I2C")?.unwrap_or_default();
+    }
+    log::info!("Probe result: {report:?}");
+    Ok(report)
+}
+
+fn probe_jtag(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Vec<u32>, FtdiError> {
+    let mut jtag = FtdiJtag::new(mtx)?;
+    // Shifting ones ends the scan on an all ones IDCODE, a low TDO yields zeros only.
+    let mut idcodes = jtag.scan_with(true)?;
+    if !idcodes.iter().any(|idcode| idcode & 1 == 1) {
+        idcodes.clear();
+    }
+    while idcodes.last() == Some(&0) {
+        idcodes.pop();
+    }
+    Ok(idcodes)
+}
+
+fn probe_swd(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<u32, FtdiSwdError> {
+    FtdiSwd::new(mtx)?.detect()
+}
+
+fn probe_spi_flash(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Option<[u8; 3]>, FtdiSpiError> {
+    let mut spi = FtdiSpiDevice::new(mtx, SpiDeviceConfig::new())?;
+    let mut id = [0; 3];
+    spi.transaction(&mut [Operation::Write(&[JEDEC_READ_ID]), Operation::Read(&mut id)])?;
+    // A floating or grounded MISO reads back as all ones or all zeros.
+    Ok((id != [0x00; 3] && id != [0xFF; 3]).then_some(id))
+}
+
+fn probe_i2c(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Vec<u8>, FtdiI2cError> {
+    let mut i2c = FtdiI2c::new(mtx, I2cConfig::new())?;
+    // A zero-length write does not leave EEPROMs waiting to send data.
+    let found = i2c.scan(0..0x80, I2cProbe::Write)?;
+    Ok(found.into_iter().map(|(addr, _)| addr).collect())
+}
+
+fn swd_inner(e: FtdiSwdError) -> Option<FtdiError> {
+    match e {
+        FtdiSwdError::FtdiInner(e) => Some(e),
+        e => {
+            log::debug!("SWD: {e}");
+            None
+        }
+    }
+}
+fn spi_inner(e: FtdiSpiError) -> Option<FtdiError> {
+    match e {
+        FtdiSpiError::FtdiInner(e) => Some(e),
+        e => {
+            log::debug!("SPI flash: {e}");
+            None
+        }
+    }
+}
+fn i2c_inner(e: FtdiI2cError) -> Option<FtdiError> {
+    match e {
+        FtdiI2cError::FtdiInner(e) => Some(e),
+        e => {
+            log::debug!("I2C: {e}");
+            None
+        }
+    }
+}
+
+/// Turns protocol level failures into "not found", keeping transport failures.
+fn transport_only<T, E: Into<Option<FtdiError>>>(
+    result: Result<T, E>,
+    protocol: &str,
+) -> Result<Option<T>, FtdiError> {
+    match result.map_err(Into::into) {
+        Ok(value) => Ok(Some(value)),
+        Err(Some(FtdiError::Usb(e))) => Err(FtdiError::Usb(e)),
+        Err(Some(e)) => {
+            log::debug!("{protocol}: {e}");
+            Ok(None)
+        }
+        Err(None) => Ok(None),
+    }
+}
@@ -0,0 +1,296 @@
+//! Transaction scripts run against a [`Bench`].
+//!
+//! A script is a list of steps, each reported as passed or failed:
+//!
+//! ```json
+//! {"steps": [
+//!     {"op": "gpio_set", "pin": "reset", "level": false},
+//!     {"op": "delay", "ms": 10},
+//!     {"op": "gpio_set", "pin": "reset", "level": true},
+//!     {"name": "who am i", "op": "i2c_read", "addr": 104, "write": [117], "expect": [104]},
+//!     {"op": "spi_xfer", "cs": "flash_cs", "data": [159, 0, 0, 0], "expect": [255, 239, 64, 24]},
+//!     {"op": "gpio_expect", "pin": "ready", "level": true}
+//! ]}
+//! ```
+//!
+//! TOML scripts use `[[steps]]` tables, YAML scripts a `steps` sequence of the same maps. Pins are the names from the [`BenchConfig`](crate::config::BenchConfig).
+//! Needs the `config` feature.
+use crate::config::{Bench, ConfigError};
+use eh1::{
+    digital::{InputPin, OutputPin},
+    i2c::I2c,
+    spi::SpiBus,
+};
+use serde::Deserialize;
+use std::{fmt, path::Path, thread::sleep, time::Duration};
+
+/// One operation of a script
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Step {
+    I2cWrite {
+        addr: u8,
+        data: Vec<u8>,
+    },
+    /// Writes `write` if not empty, then reads as many bytes as `expect` holds
+    I2cRead {
+        addr: u8,
+        #[serde(default)]
+        write: Vec<u8>,
+        expect: Vec<u8>,
+    },
+    /// Full duplex transfer, `cs` is an output pin driven low around it
+    SpiXfer {
+        cs: Option<String>,
+        data: Vec<u8>,
+        expect: Option<Vec<u8>>,
+    },
+    GpioSet {
+        pin: String,
+        level: bool,
+    },
+    GpioExpect {
+        pin: String,
+        level: bool,
+    },
+    Delay {
+        ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ScriptStep {
+    /// Shown in the report instead of the operation
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub step: Step,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Script {
+    pub steps: Vec<ScriptStep>,
+    /// Whether the steps after a failed one still run
+    #[serde(default)]
+    pub keep_going: bool,
+}
+
+/// Result of one step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    /// The device answered, but not as expected
+    Fail(String),
+    /// The step could not be carried out
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct StepReport {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+/// Outcome of every step that ran, in order
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub steps: Vec<StepReport>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.outcome == Outcome::Pass)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, step) in self.steps.iter().enumerate() {
+            match &step.outcome {
+                Outcome::Pass => writeln!(f, "{:>3} PASS  {}", idx + 1, step.name)?,
+                Outcome::Fail(why) => writeln!(f, "{:>3} FAIL  {}: {why}", idx + 1, step.name)?,
+                Outcome::Error(why) => writeln!(f, "{:>3} ERROR {}: {why}", idx + 1, step.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Script {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(text)?)
+    }
+    pub fn from_json(text: &str) -> Result<Self, ConfigError> {
+        Ok(serde_json::from_str(text)?)
+    }
+    pub fn from_yaml(text: &str) -> Result<Self, ConfigError> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+    /// Reads a file, its extension selects TOML, JSON or YAML
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            Some("yaml" | "yml") => Self::from_yaml(&text),
+            _ => Err(ConfigError::UnknownFormat),
+        }
+    }
+    /// Runs the steps in order, stopping at the first one that does not pass unless `keep_going` is set
+    pub fn run(&self, bench: &mut Bench) -> Report {
+        let mut report = Report::default();
+        for ScriptStep { name, step } in &self.steps {
+            let outcome = step.run(bench);
+            log::info!("{step:?}: {outcome:?}");
+            let stop = outcome != Outcome::Pass && !self.keep_going;
+            report.steps.push(StepReport {
+                name: name.clone().unwrap_or_else(|| format!("{step:?}")),
+                outcome,
+            });
+            if stop {
+                break;
+            }
+        }
+        report
+    }
+}
+
+impl Step {
+    fn run(&self, bench: &mut Bench) -> Outcome {
+        match self.try_run(bench) {
+            Ok(None) => Outcome::Pass,
+            Ok(Some(why)) => Outcome::Fail(why),
+            Err(why) => Outcome::Error(why),
+        }
+    }
+    /// `Ok(Some(_))` describes a mismatch
+    fn try_run(&self, bench: &mut Bench) -> Result<Option<String>, String> {
+        match self {
+            Step::I2cWrite { addr, data } => {
+                let i2c = bench.i2c.as_mut().ok_or("no i2c in the bench config")?;
+                i2c.write(*addr, data).map_err(|e| e.to_string())?;
+                Ok(None)
+            }
+            Step::I2cRead {
+                addr,
+                write,
+                expect,
+            } => {
+                let i2c = bench.i2c.as_mut().ok_or("no i2c in the bench config")?;
+                let mut read = vec![0; expect.len()];
+                if write.is_empty() {
+                    i2c.read(*addr, &mut read)
+                } else {
+                    i2c.write_read(*addr, write, &mut read)
+                }
+                .map_err(|e| e.to_string())?;
+                Ok(mismatch(expect, &read))
+            }
+            Step::SpiXfer { cs, data, expect } => {
+                if let Some(cs) = cs {
+                    set_output(bench, cs, false)?;
+                }
+                let spi = bench.spi.as_mut().ok_or("no spi in the bench config")?;
+                let mut read = vec![0; data.len()];
+                let result = spi
+                    .transfer(&mut read, data)
+                    .and_then(|_| SpiBus::<u8>::flush(spi))
+                    .map_err(|e| e.to_string());
+                if let Some(cs) = cs {
+                    set_output(bench, cs, true)?;
+                }
+                result?;
+                Ok(expect.as_ref().and_then(|expect| mismatch(expect, &read)))
+            }
+            Step::GpioSet { pin, level } => {
+                set_output(bench, pin, *level)?;
+                Ok(None)
+            }
+            Step::GpioExpect { pin, level } => {
+                let input = bench
+                    .input(pin)
+                    .ok_or_else(|| format!("no input pin {pin:?}"))?;
+                let high = input.is_high().map_err(|e| e.to_string())?;
+                Ok((high != *level)
+                    .then(|| format!("{pin} is {}", if high { "high" } else { "low" })))
+            }
+            Step::Delay { ms } => {
+                sleep(Duration::from_millis(*ms));
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn set_output(bench: &mut Bench, pin: &str, level: bool) -> Result<(), String> {
+    bench
+        .output(pin)
+        .ok_or_else(|| format!("no output pin {pin:?}"))?
+        .set_state(level.into())
+        .map_err(|e| e.to_string())
+}
+
+fn mismatch(expect: &[u8], read: &[u8]) -> Option<String> {
+    (expect != read).then(|| format!("expected {expect:02x?}, read {read:02x?}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Outcome, Report, Script, Step, StepReport};
+
+    #[test]
+    fn parse_steps() {
+        let json = Script::from_json(
+            r#"{"steps": [
+                {"op": "i2c_read", "addr": 72, "write": [0], "expect": [1, 2]},
+                {"name": "settle", "op": "delay", "ms": 5}
+            ]}"#,
+        )
+        .unwrap();
+        let toml = Script::from_toml(
+            r#"
+            [[steps]]
+            op = "i2c_read"
+            addr = 72
+            write = [0]
+            expect = [1, 2]
+            [[steps]]
+            name = "settle"
+            op = "delay"
+            ms = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(json.steps, toml.steps);
+        assert_eq!(
+            json.steps[0].step,
+            Step::I2cRead {
+                addr: 72,
+                write: vec![0],
+                expect: vec![1, 2]
+            }
+        );
+        assert_eq!(json.steps[1].name.as_deref(), Some("settle"));
+        assert!(!json.keep_going);
+    }
+    #[test]
+    fn report() {
+        let report = Report {
+            steps: vec![
+                StepReport {
+                    name: "reset".into(),
+                    outcome: Outcome::Pass,
+                },
+                StepReport {
+                    name: "id".into(),
+                    outcome: Outcome::Fail("expected [68], read [00]".into()),
+                },
+            ],
+        };
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "  1 PASS  reset\n  2 FAIL  id: expected [68], read [00]\n"
+        );
+    }
+}
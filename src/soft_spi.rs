@@ -0,0 +1,127 @@
+//! Bit-banged SPI master on any GPIO pins.
+//!
+//! Every bit takes a few GPIO commands, so it runs far slower than [`FtdiSpi`](crate::spi::FtdiSpi),
+//! but it is free in its pinout, upper bank included, and supports all four SPI modes.
+use crate::{
+    FtdiError, Pin,
+    bitbang::{BitBang, Sequence},
+    mpsse::FtdiMpsse,
+    spi::{FtdiSpiError, padded},
+};
+use eh1::spi::{ErrorType, Mode, Phase, Polarity, SpiBus};
+use std::sync::{Arc, Mutex};
+
+/// SPI master shifting bits with GPIO commands
+pub struct FtdiSoftSpi {
+    bitbang: BitBang,
+    sck: Pin,
+    mosi: Pin,
+    miso: Pin,
+    /// SCK level while idle
+    cpol: bool,
+    /// Whether MISO is sampled on the second SCK edge
+    cpha: bool,
+    /// Whether data is transferred least significant bit (LSB) first
+    is_lsb: bool,
+    /// Sent after `write` when a transfer reads more bytes than it writes
+    fill: u8,
+}
+
+impl FtdiSoftSpi {
+    /// Drives SCK and MOSI, MISO becomes an input. Starts in MODE0, MSB first.
+    pub fn new(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        sck: Pin,
+        mosi: Pin,
+        miso: Pin,
+    ) -> Result<Self, FtdiError> {
+        Ok(Self {
+            bitbang: BitBang::new(mtx, &[sck, mosi], &[miso])?,
+            sck,
+            mosi,
+            miso,
+            cpol: false,
+            cpha: false,
+            is_lsb: false,
+            fill: 0,
+        })
+    }
+    /// Sets SPI mode and bit order, SCK moves to its new idle level at once
+    pub fn set_mode(&mut self, mode: Mode, is_lsb: bool) -> Result<(), FtdiError> {
+        self.cpol = mode.polarity == Polarity::IdleHigh;
+        self.cpha = mode.phase == Phase::CaptureOnSecondTransition;
+        self.is_lsb = is_lsb;
+        self.bitbang
+            .run(Sequence::new().edge(self.sck, self.cpol))?;
+        Ok(())
+    }
+    /// Sets the byte clocked out after `write` when a transfer reads more, 0 by default
+    pub fn set_fill_byte(&mut self, fill: u8) {
+        self.fill = fill;
+    }
+    /// Shifts `write` out and returns what was read at the same time
+    pub(crate) fn shift(&self, write: &[u8]) -> Result<Vec<u8>, FtdiError> {
+        self.shift_bits(write, write.len() * 8)
+    }
+    /// Shifts the first `bits` bits of `write`, see [`FtdiSpi::transfer_bits`](crate::spi::FtdiSpi::transfer_bits)
+    pub(crate) fn shift_bits(&self, write: &[u8], bits: usize) -> Result<Vec<u8>, FtdiError> {
+        let mut seq = Sequence::new();
+        for (byte_idx, &byte) in write.iter().enumerate() {
+            for idx in 0..8.min(bits - byte_idx * 8) {
+                let shift = if self.is_lsb { idx } else { 7 - idx };
+                let bit = (byte >> shift) & 1 != 0;
+                if self.cpha {
+                    seq.set(self.mosi, bit)
+                        .edge(self.sck, !self.cpol)
+                        .edge(self.sck, self.cpol)
+                        .sample(self.miso);
+                } else {
+                    seq.set(self.mosi, bit)
+                        .commit()
+                        .edge(self.sck, !self.cpol)
+                        .sample(self.miso)
+                        .edge(self.sck, self.cpol);
+                }
+            }
+        }
+        let samples = self.bitbang.run(&seq)?;
+        Ok(samples
+            .chunks(8)
+            .map(|bits| {
+                bits.iter().enumerate().fold(0, |byte, (idx, &bit)| {
+                    let shift = if self.is_lsb { idx } else { 7 - idx };
+                    byte | ((bit as u8) << shift)
+                })
+            })
+            .collect())
+    }
+}
+
+impl ErrorType for FtdiSoftSpi {
+    type Error = FtdiSpiError;
+}
+
+impl SpiBus<u8> for FtdiSoftSpi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let response = self.shift(&vec![0; words.len()])?;
+        words.copy_from_slice(&response);
+        Ok(())
+    }
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.shift(words)?;
+        Ok(())
+    }
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        let response = self.shift(&padded(read.len(), write, self.fill))?;
+        read.copy_from_slice(&response[..read.len()]);
+        Ok(())
+    }
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let response = self.shift(words)?;
+        words.copy_from_slice(&response);
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
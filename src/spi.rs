@@ -1,23 +1,63 @@
 use crate::{
     FtdiError, Pin,
+    delay::{Delay, SpinDelay},
     gpio::UsedPin,
     mpsse::{FtdiMpsse, PinUsage},
     mpsse_cmd::MpsseCmdBuilder,
+    soft_spi::FtdiSoftSpi,
+};
+use eh1::{
+    delay::DelayNs,
+    spi::{
+        Error, ErrorKind, ErrorType, MODE_0, Mode, Operation, Phase, Polarity, SpiBus, SpiDevice,
+    },
+};
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+    ops::Range,
+    sync::{Arc, Mutex, MutexGuard},
+    thread::sleep,
+    time::{Duration, Instant},
 };
-use eh1::spi::{Error, ErrorKind, ErrorType, MODE_0, MODE_2, Mode, Operation, SpiBus, SpiDevice};
-use std::sync::{Arc, Mutex};
 
 const SCK_MASK: u8 = Pin::Lower(0).mask();
 const MOSI_MASK: u8 = Pin::Lower(1).mask();
 #[allow(unused)]
 const MISO_MASK: u8 = Pin::Lower(2).mask();
-const CS_MASK: u8 = Pin::Lower(3).mask();
+/// Lower bound of the time one SetDataBits command takes, 3 cycles of the 60 MHz engine clock.
+const GPIO_CMD_TIME: Duration = Duration::from_nanos(50);
+/// Bytes shifted per MPSSE command by the streaming transfers, the most one shift command takes
+const STREAM_CHUNK: usize = 65536;
+/// Longest delay operation made of pin commands inside the MPSSE command, about 3 KiB of them
+const INLINE_DELAY: Duration = Duration::from_micros(50);
 
-// Spi only support mode0 and mode2
+// The shift commands only come in the edges of mode0 and mode2
 // TDI(AD1) can only can output on second edge.
 // TDO(AD2) can only can sample on first edge.
 // according to AN108-2.2.
 // https://ftdichip.com/Support/Documents/AppNotes/AN_108_Command_Processor_for_MPSSE_and_MCU_Host_Bus_Emulation_Modes.pdf
+// mode1 and mode3 reuse them with SCK idling at the other level, see `mpsse_mode`.
+
+/// SCK idle level and the `tck_init_value` of the shift commands for `mode`
+///
+/// MODE_1 shifts out on the rising and samples on the falling edge like MODE_2, and
+/// MODE_3 the other way round like MODE_0, only SCK idles at the other level. The
+/// clock then starts with the edge that shifts out, as the second phase needs.
+fn mpsse_mode(mode: Mode) -> (bool, bool) {
+    let idle_high = mode.polarity == Polarity::IdleHigh;
+    let second_edge = mode.phase == Phase::CaptureOnSecondTransition;
+    (idle_high, idle_high != second_edge)
+}
+
+/// Drives SCK(AD0) to its idle level
+fn idle_sck(lock: &mut FtdiMpsse, idle_high: bool) {
+    if idle_high {
+        lock.lower.value |= SCK_MASK;
+    } else {
+        lock.lower.value &= !SCK_MASK;
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum FtdiSpiError {
@@ -25,12 +65,121 @@ pub enum FtdiSpiError {
     FtdiInner(#[from] FtdiError),
     #[error("embedded-hal::spi::SpiBus {0} is not supported.")]
     NotSupported(&'static str),
+    #[error("Stream source or sink failed: {0}")]
+    Stream(std::io::Error),
 }
 impl Error for FtdiSpiError {
     fn kind(&self) -> ErrorKind {
         ErrorKind::Other
     }
 }
+/// How a full-duplex SPI bus shifts its data
+enum SpiEngine {
+    /// SCK, MOSI and MISO on AD0 to AD2, shifted by the MPSSE
+    Mpsse { _pins: [UsedPin; 3] },
+    /// Any other pins, bit-banged
+    Soft(FtdiSoftSpi),
+}
+
+impl SpiEngine {
+    fn new(mtx: &Arc<Mutex<FtdiMpsse>>, sck: Pin, mosi: Pin, miso: Pin) -> Result<Self, FtdiError> {
+        if [sck, mosi, miso] != [Pin::Lower(0), Pin::Lower(1), Pin::Lower(2)] {
+            log::info!("SPI on {sck}, {mosi}, {miso} is bit-banged");
+            return Ok(SpiEngine::Soft(FtdiSoftSpi::new(
+                mtx.clone(),
+                sck,
+                mosi,
+                miso,
+            )?));
+        }
+        let pins = [
+            UsedPin::new(mtx.clone(), sck, PinUsage::Spi)?,
+            UsedPin::new(mtx.clone(), mosi, PinUsage::Spi)?,
+            UsedPin::new(mtx.clone(), miso, PinUsage::Spi)?,
+        ];
+        let mut lock = FtdiMpsse::lock(mtx)?;
+        // set SCK(AD0) and MOSI (AD1) as output pins
+        lock.lower.direction |= SCK_MASK | MOSI_MASK;
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+        lock.exec(cmd)?;
+        Ok(SpiEngine::Mpsse { _pins: pins })
+    }
+}
+
+/// SCK, MOSI and MISO shared by every [`FtdiSpiDevice`] of an interface
+pub(crate) struct SharedSpiEngine {
+    mtx: Arc<Mutex<FtdiMpsse>>,
+    /// SCK, MOSI, MISO
+    pins: [Pin; 3],
+    state: Mutex<SharedSpiState>,
+}
+
+/// Engine of a [`SharedSpiEngine`] with the mode and bit order the last transaction left it in
+struct SharedSpiState {
+    engine: SpiEngine,
+    mode: Option<(Mode, bool)>,
+    /// Clock last set by a device, `None` until one asks for a frequency
+    frequency: Option<usize>,
+}
+
+impl SharedSpiEngine {
+    /// The engine already open on `pins`, or a new one
+    fn open(mtx: &Arc<Mutex<FtdiMpsse>>, pins: [Pin; 3]) -> Result<Arc<Self>, FtdiError> {
+        let shared = FtdiMpsse::lock(mtx)?.spi_bus.upgrade();
+        if let Some(shared) = shared {
+            if shared.pins != pins {
+                let [sck, mosi, miso] = shared.pins;
+                return Err(FtdiError::PinFault(format!(
+                    "SPI devices already use {sck}, {mosi}, {miso}"
+                )));
+            }
+            return Ok(shared);
+        }
+        let [sck, mosi, miso] = pins;
+        let shared = Arc::new(Self {
+            mtx: mtx.clone(),
+            pins,
+            state: Mutex::new(SharedSpiState {
+                engine: SpiEngine::new(mtx, sck, mosi, miso)?,
+                mode: None,
+                frequency: None,
+            }),
+        });
+        FtdiMpsse::lock(mtx)?.spi_bus = Arc::downgrade(&shared);
+        Ok(shared)
+    }
+    /// Locks the engine, switched to `mode`, `is_lsb` and `frequency` if another device changed them
+    fn select(
+        &self,
+        mode: Mode,
+        is_lsb: bool,
+        frequency: Option<usize>,
+    ) -> Result<MutexGuard<'_, SharedSpiState>, FtdiError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(hz) = frequency.filter(|_| state.frequency != frequency) {
+            if let SpiEngine::Mpsse { .. } = state.engine {
+                FtdiMpsse::lock(&self.mtx)?.set_frequency(hz)?;
+            }
+            state.frequency = frequency;
+        }
+        if state.mode != Some((mode, is_lsb)) {
+            match &mut state.engine {
+                SpiEngine::Soft(soft) => soft.set_mode(mode, is_lsb)?,
+                SpiEngine::Mpsse { .. } => {
+                    let mut lock = FtdiMpsse::lock(&self.mtx)?;
+                    idle_sck(&mut lock, mpsse_mode(mode).0);
+                    let mut cmd = MpsseCmdBuilder::new();
+                    cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+                    lock.exec(cmd)?;
+                }
+            }
+            state.mode = Some((mode, is_lsb));
+        }
+        Ok(state)
+    }
+}
+
 /// FTDI SPI bus.
 ///
 /// In embedded-hal version 1 this represents an exclusive SPI bus.
@@ -38,62 +187,186 @@ impl Error for FtdiSpiError {
 ///
 /// Implements full-duplex synchronous serial communication with configurable mode
 pub struct FtdiSpi {
-    _pins: [UsedPin; 3],
+    engine: SpiEngine,
     /// Thread-safe handle to FTDI MPSSE controller
     mtx: Arc<Mutex<FtdiMpsse>>,
-    /// Initial value of SCK line (clock polarity) - determines idle state
+    /// Edges of the shift commands, see [`mpsse_mode`]
     tck_init_value: bool,
     /// Whether data is transferred least significant bit (LSB) first
     is_lsb: bool,
+    /// Sent after `write` when a transfer reads more bytes than it writes
+    fill: u8,
+    /// Pause after every byte but the last of a shift
+    byte_gap: Duration,
 }
 
 impl FtdiSpi {
     pub fn new(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Self, FtdiError> {
-        let this = Self {
-            _pins: [
-                UsedPin::new(mtx.clone(), Pin::Lower(0), PinUsage::Spi)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(1), PinUsage::Spi)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(2), PinUsage::Spi)?,
-            ],
-            mtx: mtx.clone(),
+        Self::new_with_pins(mtx, Pin::Lower(0), Pin::Lower(1), Pin::Lower(2))
+    }
+    /// SPI bus on any pins
+    ///
+    /// The MPSSE only shifts on AD0 to AD2, on any other pins the bus is bit-banged
+    /// like [`FtdiSoftSpi`]: much slower, but all four modes are supported.
+    pub fn new_with_pins(
+        mtx: Arc<Mutex<FtdiMpsse>>,
+        sck: Pin,
+        mosi: Pin,
+        miso: Pin,
+    ) -> Result<Self, FtdiError> {
+        // default msb mode0, SCK default 0
+        Ok(Self {
+            engine: SpiEngine::new(&mtx, sck, mosi, miso)?,
+            mtx,
             tck_init_value: false,
             is_lsb: false,
+            fill: 0,
+            byte_gap: Duration::ZERO,
+        })
+    }
+    /// Sets the byte clocked out after `write` when a transfer reads more, 0 by default
+    ///
+    /// Applies to the devices on this bus too. Some chips want 0xFF while they answer.
+    pub fn set_fill_byte(&mut self, fill: u8) {
+        self.fill = fill;
+    }
+    /// Pauses SCK for at least `gap` between the bytes of a shift, for slow peripherals
+    ///
+    /// The pause is made of repeated pin commands like the CS timing of [`CsDelays`].
+    /// Applies to the devices on this bus too. Streams and bit-banged buses ignore it.
+    pub fn set_byte_gap(&mut self, gap: Duration) {
+        self.byte_gap = gap;
+    }
+    /// Device on this bus with CS on `cs`, see [`FtdiGpioCsDevice`]
+    pub fn device(&self, cs: Pin) -> Result<FtdiGpioCsDevice<'_>, FtdiSpiError> {
+        FtdiGpioCsDevice::new(self, cs)
+    }
+    /// One device per CS pin, `let [flash, display] = spi.devices([Pin::Lower(3), Pin::Lower(4)])?;`
+    pub fn devices<const N: usize>(
+        &self,
+        cs: [Pin; N],
+    ) -> Result<[FtdiGpioCsDevice<'_>; N], FtdiSpiError> {
+        let devices = cs
+            .into_iter()
+            .map(|cs| self.device(cs))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Ok(devices) = devices.try_into() else {
+            unreachable!("one device per CS pin")
         };
-
-        let mut lock = mtx.lock().unwrap();
-        // default MODE0, SCK(AD0) default 0
-        // set SCK(AD0) and MOSI (AD1) as output pins
-        lock.lower.direction |= SCK_MASK | MOSI_MASK;
-        let mut cmd = MpsseCmdBuilder::new();
-        cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
-        lock.exec(cmd)?;
-
-        // default msb mode0
-        Ok(this)
+        Ok(devices)
     }
     /// set spi mode and bitorder
     pub fn set_mode(&mut self, mode: Mode, is_lsb: bool) -> Result<(), FtdiSpiError> {
-        let mut lock = self.mtx.lock().unwrap();
-        // set SCK polarity
-        match mode {
-            MODE_0 => {
-                lock.lower.value &= !SCK_MASK; // set SCK(AD0) to 0
-                self.tck_init_value = false;
-            }
-            MODE_2 => {
-                lock.lower.value |= SCK_MASK; // set SCK(AD0) to 1
-                self.tck_init_value = true;
-            }
-            _ => {
-                return Err(FtdiSpiError::NotSupported("MODE_1&MODE_3"));
-            }
+        if let SpiEngine::Soft(soft) = &mut self.engine {
+            soft.set_mode(mode, is_lsb)?;
+            self.tck_init_value = mode.polarity == Polarity::IdleHigh;
+            self.is_lsb = is_lsb;
+            return Ok(());
         }
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        let (idle_high, tck_init_value) = mpsse_mode(mode);
+        idle_sck(&mut lock, idle_high);
+        self.tck_init_value = tck_init_value;
         self.is_lsb = is_lsb;
         let mut cmd = MpsseCmdBuilder::new();
         cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
         lock.exec(cmd)?;
         Ok(())
     }
+    /// Sets the SCK frequency in Hertz and returns the one achieved
+    ///
+    /// The clock belongs to the MPSSE, other protocols on this interface run at it too.
+    /// A bit-banged bus runs as fast as USB allows and ignores it.
+    pub fn set_frequency(&mut self, frequency_hz: usize) -> Result<usize, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.set_frequency(frequency_hz)?)
+    }
+    /// The SCK frequency in Hertz, `None` until a clock was set, see [`FtdiSpi::set_frequency`]
+    pub fn frequency(&self) -> Result<Option<usize>, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.frequency())
+    }
+    /// Shifts the first `bits` bits of `write` and returns the bits read meanwhile
+    ///
+    /// For frames that are not whole bytes, like 12 or 18 bit ADC conversions. Whole bytes
+    /// go first, the bits left over are taken from the high end of the last byte when MSB
+    /// first and from its low end when LSB first. The response is laid out the same way.
+    pub fn transfer_bits(&mut self, write: &[u8], bits: usize) -> Result<Vec<u8>, FtdiSpiError> {
+        assert!(
+            write.len() * 8 >= bits,
+            "write should have at least {bits} bits"
+        );
+        let write = &write[..bits.div_ceil(8)];
+        if let SpiEngine::Soft(soft) = &self.engine {
+            return Ok(soft.shift_bits(write, bits)?);
+        }
+        let (bytes, remain) = (bits / 8, bits % 8);
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.shift_bytes(self.tck_init_value, self.is_lsb, &write[..bytes]);
+        if remain != 0 {
+            cmd.shift_bits(self.tck_init_value, self.is_lsb, write[bytes], remain);
+        }
+        let mut response = FtdiMpsse::lock(&self.mtx)?.exec(cmd)?;
+        if remain != 0 {
+            // The MPSSE shifts bits in from the end opposite to where they leave
+            let last = response.len() - 1;
+            if self.is_lsb {
+                response[last] >>= 8 - remain;
+            } else {
+                response[last] <<= 8 - remain;
+            }
+        }
+        Ok(response)
+    }
+    /// Writes everything `source` yields, returns the number of bytes
+    ///
+    /// Only a few chunks of 64 KiB are in memory at a time, for flash images or display
+    /// frames too large to build a single command from. The next chunk is sent while the
+    /// previous one is shifted.
+    pub fn write_stream(&mut self, source: impl Read) -> Result<usize, FtdiSpiError> {
+        self.stream(source, None)
+    }
+    /// Like [`FtdiSpi::write_stream`], what MISO returns goes to `sink` chunk by chunk
+    pub fn transfer_stream(
+        &mut self,
+        source: impl Read,
+        mut sink: impl Write,
+    ) -> Result<usize, FtdiSpiError> {
+        self.stream(source, Some(&mut sink))
+    }
+    fn stream(
+        &mut self,
+        source: impl Read,
+        sink: Option<&mut dyn Write>,
+    ) -> Result<usize, FtdiSpiError> {
+        if let SpiEngine::Soft(soft) = &self.engine {
+            return for_each_chunk(source, sink, |chunk, _| soft.shift(chunk));
+        }
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        mpsse_stream(
+            &lock,
+            self.tck_init_value,
+            self.is_lsb,
+            source,
+            sink,
+            |_| (),
+            |_| (),
+        )
+    }
+}
+
+impl FtdiSpi {
+    /// [`shift_gapped`] holding all pins while pausing
+    fn shift_gapped(
+        &self,
+        cmd: &mut MpsseCmdBuilder,
+        lock: &FtdiMpsse,
+        len: usize,
+        shift: impl FnMut(&mut MpsseCmdBuilder, Range<usize>),
+    ) {
+        let hold = |cmd: &mut MpsseCmdBuilder| {
+            cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+        };
+        shift_gapped(cmd, len, gpio_repeats(self.byte_gap), &hold, shift);
+    }
 }
 
 impl ErrorType for FtdiSpi {
@@ -102,10 +375,15 @@ impl ErrorType for FtdiSpi {
 
 impl SpiBus<u8> for FtdiSpi {
     fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        if let SpiEngine::Soft(soft) = &mut self.engine {
+            return soft.read(words);
+        }
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.shift_bytes_in(self.tck_init_value, self.is_lsb, words.len());
+        self.shift_gapped(&mut cmd, &lock, words.len(), |cmd, range| {
+            cmd.shift_bytes_in(self.tck_init_value, self.is_lsb, range.len());
+        });
 
-        let lock = self.mtx.lock().unwrap();
         let response = lock.exec(cmd)?;
         words.copy_from_slice(&response);
 
@@ -113,10 +391,15 @@ impl SpiBus<u8> for FtdiSpi {
     }
 
     fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        if let SpiEngine::Soft(soft) = &mut self.engine {
+            return soft.write(words);
+        }
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.shift_bytes_out(self.tck_init_value, self.is_lsb, words);
+        self.shift_gapped(&mut cmd, &lock, words.len(), |cmd, range| {
+            cmd.shift_bytes_out(self.tck_init_value, self.is_lsb, &words[range]);
+        });
 
-        let lock = self.mtx.lock().unwrap();
         lock.exec(cmd)?;
 
         Ok(())
@@ -127,10 +410,14 @@ impl SpiBus<u8> for FtdiSpi {
     }
 
     fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        if let SpiEngine::Soft(soft) = &mut self.engine {
+            return soft.transfer_in_place(words);
+        }
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.shift_bytes(self.tck_init_value, self.is_lsb, words);
-
-        let lock = self.mtx.lock().unwrap();
+        self.shift_gapped(&mut cmd, &lock, words.len(), |cmd, range| {
+            cmd.shift_bytes(self.tck_init_value, self.is_lsb, &words[range]);
+        });
 
         let response = lock.exec(cmd)?;
         words.copy_from_slice(&response);
@@ -139,17 +426,87 @@ impl SpiBus<u8> for FtdiSpi {
     }
 
     fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        let write = padded(read.len(), write, self.fill);
+        if let SpiEngine::Soft(soft) = &mut self.engine {
+            let response = soft.shift(&write)?;
+            read.copy_from_slice(&response[..read.len()]);
+            return Ok(());
+        }
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.shift_bytes(self.tck_init_value, self.is_lsb, write);
+        self.shift_gapped(&mut cmd, &lock, write.len(), |cmd, range| {
+            cmd.shift_bytes(self.tck_init_value, self.is_lsb, &write[range]);
+        });
 
-        let lock = self.mtx.lock().unwrap();
         let response = lock.exec(cmd)?;
-        read.copy_from_slice(&response);
+        read.copy_from_slice(&response[..read.len()]);
 
         Ok(())
     }
 }
 
+/// Words wider than a byte, MSB first buses send their high byte first and LSB first buses their low byte
+trait WideWord: Copy + Default {
+    fn put(self, is_lsb: bool, bytes: &mut [u8]);
+    fn get(is_lsb: bool, bytes: &[u8]) -> Self;
+}
+macro_rules! wide_words {
+    ($($word:ty),*) => {$(
+        impl WideWord for $word {
+            fn put(self, is_lsb: bool, bytes: &mut [u8]) {
+                let word = if is_lsb { self.to_le_bytes() } else { self.to_be_bytes() };
+                bytes.copy_from_slice(&word);
+            }
+            fn get(is_lsb: bool, bytes: &[u8]) -> Self {
+                let bytes = bytes.try_into().expect("one word of bytes");
+                if is_lsb { Self::from_le_bytes(bytes) } else { Self::from_be_bytes(bytes) }
+            }
+        }
+    )*};
+}
+wide_words!(u16, u32);
+
+fn words_to_bytes<W: WideWord>(words: &[W], is_lsb: bool) -> Vec<u8> {
+    let mut bytes = vec![0; size_of_val(words)];
+    for (word, chunk) in words.iter().zip(bytes.chunks_exact_mut(size_of::<W>())) {
+        word.put(is_lsb, chunk);
+    }
+    bytes
+}
+fn bytes_to_words<W: WideWord>(bytes: &[u8], is_lsb: bool, words: &mut [W]) {
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(size_of::<W>())) {
+        *word = W::get(is_lsb, chunk);
+    }
+}
+
+/// 16 and 32 bit words, shifted as bytes in the order of [`WideWord`]
+impl<W: WideWord + 'static> SpiBus<W> for FtdiSpi {
+    fn read(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
+        let mut bytes = vec![0; size_of_val(words)];
+        SpiBus::<u8>::read(self, &mut bytes)?;
+        bytes_to_words(&bytes, self.is_lsb, words);
+        Ok(())
+    }
+    fn write(&mut self, words: &[W]) -> Result<(), Self::Error> {
+        SpiBus::<u8>::write(self, &words_to_bytes(words, self.is_lsb))
+    }
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn transfer_in_place(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
+        let mut bytes = words_to_bytes(words, self.is_lsb);
+        SpiBus::<u8>::transfer_in_place(self, &mut bytes)?;
+        bytes_to_words(&bytes, self.is_lsb, words);
+        Ok(())
+    }
+    fn transfer(&mut self, read: &mut [W], write: &[W]) -> Result<(), Self::Error> {
+        let mut bytes = vec![0; size_of_val(read)];
+        SpiBus::<u8>::transfer(self, &mut bytes, &words_to_bytes(write, self.is_lsb))?;
+        bytes_to_words(&bytes, self.is_lsb, read);
+        Ok(())
+    }
+}
+
 /// FTDI SPI bus.
 ///
 /// In embedded-hal version 1 this represents an exclusive SPI bus.
@@ -160,10 +517,12 @@ pub struct FtdiSpiHalfduplex {
     _pins: [UsedPin; 3],
     /// Thread-safe handle to FTDI MPSSE controller
     mtx: Arc<Mutex<FtdiMpsse>>,
-    /// Initial value of SCK line (clock polarity) - determines idle state
+    /// Edges of the shift commands, see [`mpsse_mode`]
     tck_init_value: bool,
     /// Whether data is transferred least significant bit (LSB) first
     is_lsb: bool,
+    /// Optional direction pin for an external SDIO buffer, high while MOSI drives SDIO
+    direction_pin: Option<UsedPin>,
 }
 
 impl FtdiSpiHalfduplex {
@@ -177,9 +536,10 @@ impl FtdiSpiHalfduplex {
             mtx: mtx.clone(),
             tck_init_value: false,
             is_lsb: false,
+            direction_pin: None,
         };
 
-        let mut lock = mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&mtx)?;
         // default MODE0, SCK(AD0) default 0
         // set SCK(AD0) and MOSI (AD1) as output pins
         lock.lower.direction |= SCK_MASK | MOSI_MASK;
@@ -192,38 +552,88 @@ impl FtdiSpiHalfduplex {
     }
     /// set spi mode and bitorder
     pub fn set_mode(&mut self, mode: Mode, is_lsb: bool) -> Result<(), FtdiSpiError> {
-        let mut lock = self.mtx.lock().unwrap();
-        // set SCK polarity
-        match mode {
-            MODE_0 => {
-                lock.lower.value &= !SCK_MASK; // set SCK(AD0) to 0
-                self.tck_init_value = false;
-            }
-            MODE_2 => {
-                lock.lower.value |= SCK_MASK; // set SCK(AD0) to 1
-                self.tck_init_value = true;
-            }
-            _ => {
-                return Err(FtdiSpiError::NotSupported("MODE_1&MODE_3"));
-            }
-        }
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        let (idle_high, tck_init_value) = mpsse_mode(mode);
+        idle_sck(&mut lock, idle_high);
+        self.tck_init_value = tck_init_value;
         self.is_lsb = is_lsb;
         let mut cmd = MpsseCmdBuilder::new();
         cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
         lock.exec(cmd)?;
         Ok(())
     }
+    /// Pin switching an external SDIO buffer, driven high while writing and low while reading
+    ///
+    /// The buffer starts out receiving, with MOSI released.
+    pub fn set_direction_pin(&mut self, pin: Pin) -> Result<(), FtdiSpiError> {
+        self.direction_pin = Some(UsedPin::new(self.mtx.clone(), pin, PinUsage::Spi)?);
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        match pin {
+            Pin::Lower(_) => lock.lower.direction |= pin.mask(),
+            Pin::Upper(_) => lock.upper.direction |= pin.mask(),
+        }
+        let mut cmd = MpsseCmdBuilder::new();
+        self.sdio(&mut cmd, &mut lock, false);
+        lock.exec(cmd)?;
+        Ok(())
+    }
+    /// Appends the pin commands turning SDIO around, `out` when MOSI drives it
+    ///
+    /// An upper bank direction pin switches the buffer before MOSI drives and after
+    /// MOSI is released, so the two never drive SDIO against each other.
+    fn sdio(&self, cmd: &mut MpsseCmdBuilder, lock: &mut FtdiMpsse, out: bool) {
+        let apply = |bits: &mut u8, mask: u8| {
+            if out {
+                *bits |= mask;
+            } else {
+                *bits &= !mask;
+            }
+        };
+        apply(&mut lock.lower.direction, MOSI_MASK);
+        match self.direction_pin.as_deref() {
+            Some(&pin @ Pin::Lower(_)) => {
+                apply(&mut lock.lower.value, pin.mask());
+                cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+            }
+            Some(&pin @ Pin::Upper(_)) => {
+                apply(&mut lock.upper.value, pin.mask());
+                let lower = [lock.lower.value, lock.lower.direction];
+                let upper = [lock.upper.value, lock.upper.direction];
+                if out {
+                    cmd.set_gpio_upper(upper[0], upper[1]);
+                    cmd.set_gpio_lower(lower[0], lower[1]);
+                } else {
+                    cmd.set_gpio_lower(lower[0], lower[1]);
+                    cmd.set_gpio_upper(upper[0], upper[1]);
+                }
+            }
+            None => {
+                cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+            }
+        }
+    }
+    /// Sets the SCK frequency in Hertz and returns the one achieved
+    ///
+    /// The clock belongs to the MPSSE, other protocols on this interface run at it too.
+    pub fn set_frequency(&mut self, frequency_hz: usize) -> Result<usize, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.set_frequency(frequency_hz)?)
+    }
+    /// The SCK frequency in Hertz, `None` until a clock was set
+    pub fn frequency(&self) -> Result<Option<usize>, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.frequency())
+    }
 }
 
 impl ErrorType for FtdiSpiHalfduplex {
     type Error = FtdiSpiError;
 }
 
+/// Transfers write first and read afterwards, the command-then-response order of 3-wire devices
 impl SpiBus for FtdiSpiHalfduplex {
     fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
-        let lock = self.mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.set_gpio_lower(lock.lower.value, lock.lower.direction & (!MOSI_MASK)); // set tdi to input
+        self.sdio(&mut cmd, &mut lock, false); // set tdi to input
         cmd.shift_bytes_in(self.tck_init_value, self.is_lsb, words.len());
 
         let response = lock.exec(cmd)?;
@@ -232,9 +642,9 @@ impl SpiBus for FtdiSpiHalfduplex {
         Ok(())
     }
     fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
-        let lock = self.mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
-        cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+        self.sdio(&mut cmd, &mut lock, true);
         cmd.shift_bytes_out(self.tck_init_value, self.is_lsb, words);
 
         lock.exec(cmd)?;
@@ -244,11 +654,24 @@ impl SpiBus for FtdiSpiHalfduplex {
     fn flush(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
-    fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), Self::Error> {
-        Err(FtdiSpiError::NotSupported("transfer"))
+    /// Writes all of `write`, then turns SDIO around and reads `read`
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        let mut cmd = MpsseCmdBuilder::new();
+        self.sdio(&mut cmd, &mut lock, true);
+        cmd.shift_bytes_out(self.tck_init_value, self.is_lsb, write);
+        self.sdio(&mut cmd, &mut lock, false);
+        cmd.shift_bytes_in(self.tck_init_value, self.is_lsb, read.len());
+
+        let response = lock.exec(cmd)?;
+        read.copy_from_slice(&response);
+
+        Ok(())
     }
-    fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
-        Err(FtdiSpiError::NotSupported("transfer_in_place"))
+    /// Writes `words`, then reads as many bytes back into it
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let write = words.to_vec();
+        self.transfer(words, &write)
     }
 }
 /// FTDI SPI bus.
@@ -261,7 +684,7 @@ pub struct FtdiSpiTx {
     _pins: [UsedPin; 2],
     /// Thread-safe handle to FTDI MPSSE controller
     mtx: Arc<Mutex<FtdiMpsse>>,
-    /// Initial value of SCK line (clock polarity) - determines idle state
+    /// Edges of the shift commands, see [`mpsse_mode`]
     tck_init_value: bool,
     /// Whether data is transferred least significant bit (LSB) first
     is_lsb: bool,
@@ -279,7 +702,7 @@ impl FtdiSpiTx {
             is_lsb: false,
         };
 
-        let mut lock = mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&mtx)?;
         // default MODE0, SCK(AD0) default 0
         // set SCK(AD0) and MOSI (AD1) as output pins
         lock.lower.direction |= SCK_MASK | MOSI_MASK;
@@ -292,27 +715,26 @@ impl FtdiSpiTx {
     }
     /// set spi mode and bitorder
     pub fn set_mode(&mut self, mode: Mode, is_lsb: bool) -> Result<(), FtdiSpiError> {
-        let mut lock = self.mtx.lock().unwrap();
-        // set SCK polarity
-        match mode {
-            MODE_0 => {
-                lock.lower.value &= !SCK_MASK; // set SCK(AD0) to 0
-                self.tck_init_value = false;
-            }
-            MODE_2 => {
-                lock.lower.value |= SCK_MASK; // set SCK(AD0) to 1
-                self.tck_init_value = true;
-            }
-            _ => {
-                return Err(FtdiSpiError::NotSupported("MODE_1&MODE_3"));
-            }
-        }
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        let (idle_high, tck_init_value) = mpsse_mode(mode);
+        idle_sck(&mut lock, idle_high);
+        self.tck_init_value = tck_init_value;
         self.is_lsb = is_lsb;
         let mut cmd = MpsseCmdBuilder::new();
         cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
         lock.exec(cmd)?;
         Ok(())
     }
+    /// Sets the SCK frequency in Hertz and returns the one achieved
+    ///
+    /// The clock belongs to the MPSSE, other protocols on this interface run at it too.
+    pub fn set_frequency(&mut self, frequency_hz: usize) -> Result<usize, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.set_frequency(frequency_hz)?)
+    }
+    /// The SCK frequency in Hertz, `None` until a clock was set
+    pub fn frequency(&self) -> Result<Option<usize>, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.frequency())
+    }
 }
 
 impl ErrorType for FtdiSpiTx {
@@ -321,7 +743,7 @@ impl ErrorType for FtdiSpiTx {
 
 impl SpiBus for FtdiSpiTx {
     fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
-        let lock = self.mtx.lock().unwrap();
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = MpsseCmdBuilder::new();
         cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
         cmd.shift_bytes_out(self.tck_init_value, self.is_lsb, words);
@@ -343,40 +765,386 @@ impl SpiBus for FtdiSpiTx {
         Err(FtdiSpiError::NotSupported("transfer_in_place"))
     }
 }
-pub struct FtdiSpiDevice {
-    _pins: [UsedPin; 4],
+
+/// Read-only SPI bus on SCK (AD0) and MISO (AD2)
+///
+/// Clocks data in without driving MOSI, AD1 stays free for other uses.
+/// Suits ADCs and other devices that only ever talk back.
+pub struct FtdiSpiRx {
+    _pins: [UsedPin; 2],
     /// Thread-safe handle to FTDI MPSSE controller
     mtx: Arc<Mutex<FtdiMpsse>>,
-    /// Initial value of SCK line (clock polarity) - determines idle state
+    /// Edges of the shift commands, see [`mpsse_mode`]
     tck_init_value: bool,
     /// Whether data is transferred least significant bit (LSB) first
     is_lsb: bool,
 }
 
-impl FtdiSpiDevice {
+impl FtdiSpiRx {
     pub fn new(mtx: Arc<Mutex<FtdiMpsse>>) -> Result<Self, FtdiSpiError> {
         let this = Self {
             _pins: [
                 UsedPin::new(mtx.clone(), Pin::Lower(0), PinUsage::Spi)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(1), PinUsage::Spi)?,
                 UsedPin::new(mtx.clone(), Pin::Lower(2), PinUsage::Spi)?,
-                UsedPin::new(mtx.clone(), Pin::Lower(3), PinUsage::Spi)?,
             ],
             mtx: mtx.clone(),
             tck_init_value: false,
             is_lsb: false,
         };
-        let mut lock = mtx.lock().unwrap();
+
+        let mut lock = FtdiMpsse::lock(&mtx)?;
         // default MODE0, SCK(AD0) default 0
-        // set SCK(AD0) and MOSI (AD1) as output pins
-        lock.lower.direction |= SCK_MASK | MOSI_MASK | CS_MASK;
-        lock.lower.value |= CS_MASK;
+        // set SCK(AD0) as output pin, MISO(AD2) as input pin
+        lock.lower.direction |= SCK_MASK;
+        lock.lower.direction &= !MISO_MASK;
         let mut cmd = MpsseCmdBuilder::new();
         cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
         lock.exec(cmd)?;
+
         // default msb mode0
         Ok(this)
     }
+    /// set spi mode and bitorder
+    pub fn set_mode(&mut self, mode: Mode, is_lsb: bool) -> Result<(), FtdiSpiError> {
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
+        let (idle_high, tck_init_value) = mpsse_mode(mode);
+        idle_sck(&mut lock, idle_high);
+        self.tck_init_value = tck_init_value;
+        self.is_lsb = is_lsb;
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+        lock.exec(cmd)?;
+        Ok(())
+    }
+    /// Sets the SCK frequency in Hertz and returns the one achieved
+    ///
+    /// The clock belongs to the MPSSE, other protocols on this interface run at it too.
+    pub fn set_frequency(&mut self, frequency_hz: usize) -> Result<usize, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.set_frequency(frequency_hz)?)
+    }
+    /// The SCK frequency in Hertz, `None` until a clock was set
+    pub fn frequency(&self) -> Result<Option<usize>, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.frequency())
+    }
+    /// Clocks `len` bytes in and writes them to `sink` as they arrive
+    ///
+    /// Only a few chunks of 64 KiB are in memory at a time, for long ADC captures.
+    pub fn read_stream(&mut self, len: usize, mut sink: impl Write) -> Result<usize, FtdiSpiError> {
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let mut total = 0;
+        pipeline_chunks(
+            &lock,
+            || {
+                if total == len {
+                    return Ok(None);
+                }
+                let chunk = STREAM_CHUNK.min(len - total);
+                let mut cmd = MpsseCmdBuilder::new();
+                cmd.shift_bytes_in(self.tck_init_value, self.is_lsb, chunk);
+                total += chunk;
+                Ok(Some(cmd))
+            },
+            Some(&mut sink),
+        )?;
+        Ok(len)
+    }
+}
+
+impl ErrorType for FtdiSpiRx {
+    type Error = FtdiSpiError;
+}
+
+impl SpiBus for FtdiSpiRx {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.shift_bytes_in(self.tck_init_value, self.is_lsb, words.len());
+
+        let response = lock.exec(cmd)?;
+        words.copy_from_slice(&response);
+
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn write(&mut self, _words: &[u8]) -> Result<(), Self::Error> {
+        Err(FtdiSpiError::NotSupported("write"))
+    }
+    fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), Self::Error> {
+        Err(FtdiSpiError::NotSupported("transfer"))
+    }
+    fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+        Err(FtdiSpiError::NotSupported("transfer_in_place"))
+    }
+}
+/// Chip select timing of [`FtdiSpiDevice`], all zero by default
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CsDelays {
+    /// From CS assertion to the first SCK edge
+    pub setup: Duration,
+    /// From the last SCK edge to CS release
+    pub hold: Duration,
+    /// From CS release to the next CS assertion
+    pub between: Duration,
+}
+
+pub struct FtdiSpiDevice {
+    engine: Arc<SharedSpiEngine>,
+    cs: UsedPin,
+    /// Level of CS while the device is selected
+    cs_active_high: bool,
+    /// Thread-safe handle to FTDI MPSSE controller
+    mtx: Arc<Mutex<FtdiMpsse>>,
+    mode: Mode,
+    /// Edges of the shift commands, see [`mpsse_mode`]
+    tck_init_value: bool,
+    /// Whether data is transferred least significant bit (LSB) first
+    is_lsb: bool,
+    /// SCK of this device, `None` keeps whatever the MPSSE runs at
+    frequency: Option<usize>,
+    /// Sent after `write` when a transfer reads more bytes than it writes
+    fill: u8,
+    /// Pause after every byte but the last of a shift
+    byte_gap: Duration,
+    cs_delays: CsDelays,
+    /// When CS was last released
+    cs_released: Option<Instant>,
+    /// Waits for the delay operations of a transaction
+    delay: SpinDelay,
+}
+
+/// Settings of an [`FtdiSpiDevice`], checked by [`FtdiSpiDevice::new`]
+///
+/// The defaults are SCK, MOSI and MISO on AD0 to AD2, CS on AD3 and active low without
+/// extra timing, MODE0, MSB first and the clock the MPSSE already runs at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiDeviceConfig {
+    sck: Pin,
+    mosi: Pin,
+    miso: Pin,
+    cs: Pin,
+    cs_active_high: bool,
+    cs_delays: CsDelays,
+    mode: Mode,
+    is_lsb: bool,
+    frequency: Option<usize>,
+    fill: u8,
+    byte_gap: Duration,
+}
+impl Default for SpiDeviceConfig {
+    fn default() -> Self {
+        Self {
+            sck: Pin::Lower(0),
+            mosi: Pin::Lower(1),
+            miso: Pin::Lower(2),
+            cs: Pin::Lower(3),
+            cs_active_high: false,
+            cs_delays: CsDelays::default(),
+            mode: MODE_0,
+            is_lsb: false,
+            frequency: None,
+            fill: 0,
+            byte_gap: Duration::ZERO,
+        }
+    }
+}
+impl SpiDeviceConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// SCK, MOSI and MISO anywhere but AD0 to AD2 are bit-banged, see [`FtdiSpi::new_with_pins`]
+    pub fn pins(mut self, sck: Pin, mosi: Pin, miso: Pin) -> Self {
+        self.sck = sck;
+        self.mosi = mosi;
+        self.miso = miso;
+        self
+    }
+    /// Any free pin of either bank
+    pub fn cs(mut self, pin: Pin) -> Self {
+        self.cs = pin;
+        self
+    }
+    /// Selects the device with CS high instead of low
+    pub fn cs_active_high(mut self, active_high: bool) -> Self {
+        self.cs_active_high = active_high;
+        self
+    }
+    /// See [`FtdiSpiDevice::set_cs_delays`]
+    pub fn cs_delays(mut self, delays: CsDelays) -> Self {
+        self.cs_delays = delays;
+        self
+    }
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn lsb_first(mut self, is_lsb: bool) -> Self {
+        self.is_lsb = is_lsb;
+        self
+    }
+    /// SCK frequency in Hertz, see [`FtdiMpsse::set_frequency`], bit-banged pins ignore it
+    pub fn frequency(mut self, frequency_hz: usize) -> Self {
+        self.frequency = Some(frequency_hz);
+        self
+    }
+    /// See [`FtdiSpiDevice::set_fill_byte`]
+    pub fn fill_byte(mut self, fill: u8) -> Self {
+        self.fill = fill;
+        self
+    }
+    /// See [`FtdiSpiDevice::set_byte_gap`]
+    pub fn byte_gap(mut self, gap: Duration) -> Self {
+        self.byte_gap = gap;
+        self
+    }
+}
+
+impl FtdiSpiDevice {
+    /// Opens a device, devices on the same SCK, MOSI and MISO share them
+    ///
+    /// Every device has its own CS, mode and bit order, the bus is switched over when
+    /// a transaction of another device follows. The pins are released with the last device.
+    pub fn new(mtx: Arc<Mutex<FtdiMpsse>>, config: SpiDeviceConfig) -> Result<Self, FtdiSpiError> {
+        let engine = SharedSpiEngine::open(&mtx, [config.sck, config.mosi, config.miso])?;
+        drop(engine.select(config.mode, config.is_lsb, config.frequency)?);
+        let this = Self {
+            engine,
+            cs: UsedPin::new(mtx.clone(), config.cs, PinUsage::Spi)?,
+            cs_active_high: config.cs_active_high,
+            mtx: mtx.clone(),
+            mode: config.mode,
+            tck_init_value: mpsse_mode(config.mode).1,
+            is_lsb: config.is_lsb,
+            frequency: config.frequency,
+            fill: config.fill,
+            byte_gap: config.byte_gap,
+            cs_delays: config.cs_delays,
+            cs_released: None,
+            delay: Delay.into(),
+        };
+        let mut lock = FtdiMpsse::lock(&mtx)?;
+        let mut cmd = MpsseCmdBuilder::new();
+        // CS is released until the first transaction
+        let bank = match *this.cs {
+            Pin::Lower(_) => &mut lock.lower,
+            Pin::Upper(_) => &mut lock.upper,
+        };
+        bank.direction |= this.cs.mask();
+        if this.cs_active_high {
+            bank.value &= !this.cs.mask();
+        } else {
+            bank.value |= this.cs.mask();
+        }
+        set_pin(&mut cmd, &lock, *this.cs, !this.cs_active_high);
+        lock.exec(cmd)?;
+        drop(lock);
+        Ok(this)
+    }
+    /// Sets the chip select timing for slow peripherals
+    ///
+    /// Setup and hold are made of repeated pin commands, they take at least
+    /// the given time but may take several times longer.
+    /// Each transaction is its own USB transfer, so transactions are always
+    /// some ten microseconds apart, `between` is only waited for when longer.
+    pub fn set_cs_delays(&mut self, delays: CsDelays) {
+        self.cs_delays = delays;
+    }
+    /// Sets the byte clocked out after `write` when a transfer reads more, 0 by default
+    pub fn set_fill_byte(&mut self, fill: u8) {
+        self.fill = fill;
+    }
+    /// Pauses SCK for at least `gap` between the bytes of a shift, CS stays asserted
+    ///
+    /// Streams and bit-banged pins ignore it.
+    pub fn set_byte_gap(&mut self, gap: Duration) {
+        self.byte_gap = gap;
+    }
+    /// Sets the SCK frequency of this device and returns the one achieved
+    ///
+    /// Devices sharing the bus may ask for different clocks, it is switched over when a
+    /// transaction of another device follows. Bit-banged pins ignore it.
+    pub fn set_frequency(&mut self, frequency_hz: usize) -> Result<usize, FtdiSpiError> {
+        drop(
+            self.engine
+                .select(self.mode, self.is_lsb, Some(frequency_hz))?,
+        );
+        self.frequency = Some(frequency_hz);
+        Ok(self
+            .frequency()?
+            .ok_or(FtdiError::Other("SPI clock not set"))?)
+    }
+    /// The SCK frequency in Hertz the MPSSE currently runs at, `None` until a clock was set
+    pub fn frequency(&self) -> Result<Option<usize>, FtdiSpiError> {
+        Ok(FtdiMpsse::lock(&self.mtx)?.frequency())
+    }
+    /// Sets how delay operations wait, [`Delay`] by default
+    pub fn set_delay(&mut self, delay: impl Into<SpinDelay>) {
+        self.delay = delay.into();
+    }
+    /// Writes everything `source` yields with CS asserted throughout, see [`FtdiSpi::write_stream`]
+    pub fn write_stream(&mut self, source: impl Read) -> Result<usize, FtdiSpiError> {
+        self.stream(source, None)
+    }
+    /// Like [`FtdiSpiDevice::write_stream`], what MISO returns goes to `sink` chunk by chunk
+    pub fn transfer_stream(
+        &mut self,
+        source: impl Read,
+        mut sink: impl Write,
+    ) -> Result<usize, FtdiSpiError> {
+        self.stream(source, Some(&mut sink))
+    }
+    fn stream(
+        &mut self,
+        source: impl Read,
+        sink: Option<&mut dyn Write>,
+    ) -> Result<usize, FtdiSpiError> {
+        self.wait_between();
+        let state = self.engine.select(self.mode, self.is_lsb, self.frequency)?;
+        let (cs, active) = (*self.cs, self.cs_active_high);
+        let result = if let SpiEngine::Soft(soft) = &state.engine {
+            drive_pin(&self.mtx, cs, active)?;
+            let result = for_each_chunk(source, sink, |chunk, _| soft.shift(chunk));
+            drive_pin(&self.mtx, cs, !active)?;
+            result
+        } else {
+            let lock = FtdiMpsse::lock(&self.mtx)?;
+            mpsse_stream(
+                &lock,
+                self.tck_init_value,
+                self.is_lsb,
+                source,
+                sink,
+                |cmd| {
+                    for _ in 0..=gpio_repeats(self.cs_delays.setup) {
+                        set_pin(cmd, &lock, cs, active);
+                    }
+                },
+                |cmd| {
+                    for _ in 0..gpio_repeats(self.cs_delays.hold) {
+                        set_pin(cmd, &lock, cs, active);
+                    }
+                    set_pin(cmd, &lock, cs, !active);
+                },
+            )
+        };
+        self.cs_released = Some(Instant::now());
+        result
+    }
+    /// Waits out the rest of `between` since CS was last released
+    fn wait_between(&self) {
+        if let Some(released) = self.cs_released {
+            let elapsed = released.elapsed();
+            if elapsed < self.cs_delays.between {
+                sleep(self.cs_delays.between - elapsed);
+            }
+        }
+    }
+}
+
+/// Repeats of a SetDataBits command lasting at least `delay`
+fn gpio_repeats(delay: Duration) -> u32 {
+    delay.as_nanos().div_ceil(GPIO_CMD_TIME.as_nanos()) as u32
 }
 
 impl ErrorType for FtdiSpiDevice {
@@ -388,49 +1156,575 @@ impl SpiDevice<u8> for FtdiSpiDevice {
         &mut self,
         operations: &mut [eh1::spi::Operation<'_, u8>],
     ) -> Result<(), Self::Error> {
-        let lock = self.mtx.lock().unwrap();
-        // send request
-        let mut cmd = MpsseCmdBuilder::new();
-        cmd.set_gpio_lower(
-            lock.lower.value & !Pin::Lower(3).mask(),
-            lock.lower.direction,
+        self.wait_between();
+        let state = self.engine.select(self.mode, self.is_lsb, self.frequency)?;
+        if let SpiEngine::Soft(soft) = &state.engine {
+            // setup and hold are far shorter than the USB round trips around the data
+            let result = soft_transaction(
+                soft,
+                &self.mtx,
+                *self.cs,
+                self.cs_active_high,
+                self.fill,
+                operations,
+                &mut self.delay,
+            );
+            self.cs_released = Some(Instant::now());
+            return result;
+        }
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let (cs, active) = (*self.cs, self.cs_active_high);
+        let result = run_operations(
+            &lock,
+            self.tck_init_value,
+            self.is_lsb,
+            self.fill,
+            gpio_repeats(self.byte_gap),
+            operations,
+            |cmd| {
+                for _ in 0..=gpio_repeats(self.cs_delays.setup) {
+                    set_pin(cmd, &lock, cs, active);
+                }
+            },
+            |cmd| set_pin(cmd, &lock, cs, active),
+            |cmd| {
+                for _ in 0..gpio_repeats(self.cs_delays.hold) {
+                    set_pin(cmd, &lock, cs, active);
+                }
+                set_pin(cmd, &lock, cs, !active);
+            },
+            &mut self.delay,
         );
-        operations.iter().for_each(|op| match op {
+        self.cs_released = Some(Instant::now());
+        Ok(result?)
+    }
+}
+
+/// Appends a pin command driving `pin` to `level`, the other pins of its bank keep their state
+pub(crate) fn set_pin(cmd: &mut MpsseCmdBuilder, lock: &FtdiMpsse, pin: Pin, level: bool) {
+    let apply = |value: u8| {
+        if level {
+            value | pin.mask()
+        } else {
+            value & !pin.mask()
+        }
+    };
+    match pin {
+        Pin::Lower(_) => cmd.set_gpio_lower(apply(lock.lower.value), lock.lower.direction),
+        Pin::Upper(_) => cmd.set_gpio_upper(apply(lock.upper.value), lock.upper.direction),
+    };
+}
+
+/// Shifts `operations` framed by the commands of `select` and `release`
+///
+/// Delays up to [`INLINE_DELAY`] repeat the pin command of `hold` inside the MPSSE command.
+/// Every longer one ends the MPSSE command and waits on `delay` before the next one,
+/// so it lasts a USB round trip longer than asked for.
+#[allow(clippy::too_many_arguments)]
+fn run_operations(
+    lock: &FtdiMpsse,
+    tck_init_value: bool,
+    is_lsb: bool,
+    fill: u8,
+    gap: u32,
+    operations: &mut [Operation<'_, u8>],
+    select: impl FnOnce(&mut MpsseCmdBuilder),
+    hold: impl Fn(&mut MpsseCmdBuilder),
+    release: impl FnOnce(&mut MpsseCmdBuilder),
+    delay: &mut impl DelayNs,
+) -> Result<(), FtdiError> {
+    let mut cmd = MpsseCmdBuilder::new();
+    select(&mut cmd);
+    let mut rest = operations;
+    loop {
+        let end = rest
+            .iter()
+            .position(|op| matches!(op, Operation::DelayNs(ns) if !is_inline(*ns)))
+            .unwrap_or(rest.len());
+        let (shifts, tail) = std::mem::take(&mut rest).split_at_mut(end);
+        shift_operations(&mut cmd, tck_init_value, is_lsb, fill, gap, shifts, &hold);
+        let Some((Operation::DelayNs(ns), tail)) = tail.split_first_mut() else {
+            release(&mut cmd);
+            copy_responses(shifts, &lock.exec(cmd)?);
+            return Ok(());
+        };
+        copy_responses(shifts, &lock.exec(cmd)?);
+        delay.delay_ns(*ns);
+        cmd = MpsseCmdBuilder::new();
+        rest = tail;
+    }
+}
+
+/// Feeds `source` to `shift` in chunks of [`STREAM_CHUNK`], writing the responses to `sink`
+///
+/// `shift` is told whether the response is needed. Returns the number of bytes shifted.
+fn for_each_chunk(
+    mut source: impl Read,
+    mut sink: Option<&mut dyn Write>,
+    mut shift: impl FnMut(&[u8], bool) -> Result<Vec<u8>, FtdiError>,
+) -> Result<usize, FtdiSpiError> {
+    let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+    let mut total = 0;
+    loop {
+        read_chunk(&mut source, &mut chunk)?;
+        if chunk.is_empty() {
+            return Ok(total);
+        }
+        let response = shift(&chunk, sink.is_some())?;
+        if let Some(sink) = &mut sink {
+            sink.write_all(&response).map_err(FtdiSpiError::Stream)?;
+        }
+        total += chunk.len();
+    }
+}
+
+/// Replaces `chunk` with the next [`STREAM_CHUNK`] bytes of `source`, empty at its end
+fn read_chunk(source: &mut impl Read, chunk: &mut Vec<u8>) -> Result<(), FtdiSpiError> {
+    chunk.clear();
+    source
+        .take(STREAM_CHUNK as u64)
+        .read_to_end(chunk)
+        .map_err(FtdiSpiError::Stream)?;
+    Ok(())
+}
+
+/// Runs the commands `next` builds with the following one already on its way, see
+/// [`FtdiMpsse::exec_pipelined`], and writes the responses to `sink`
+fn pipeline_chunks(
+    lock: &FtdiMpsse,
+    next: impl FnMut() -> Result<Option<MpsseCmdBuilder>, FtdiSpiError>,
+    mut sink: Option<&mut dyn Write>,
+) -> Result<(), FtdiSpiError> {
+    lock.exec_pipelined(next, |response| {
+        if let Some(sink) = &mut sink {
+            sink.write_all(&response).map_err(FtdiSpiError::Stream)?;
+        }
+        Ok(())
+    })
+}
+
+/// Streams `source` through the MPSSE, one command per chunk
+///
+/// `select` goes in front of the first chunk and `release` after the last, the lock keeps
+/// everything else off the pins in between. While a chunk is shifted the next one is
+/// already read from `source` and sent.
+fn mpsse_stream(
+    lock: &FtdiMpsse,
+    tck_init_value: bool,
+    is_lsb: bool,
+    mut source: impl Read,
+    sink: Option<&mut dyn Write>,
+    select: impl FnOnce(&mut MpsseCmdBuilder),
+    release: impl FnOnce(&mut MpsseCmdBuilder),
+) -> Result<usize, FtdiSpiError> {
+    let read = sink.is_some();
+    let mut cmd = MpsseCmdBuilder::new();
+    select(&mut cmd);
+    let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+    let mut total = 0;
+    let result = pipeline_chunks(
+        lock,
+        || {
+            read_chunk(&mut source, &mut chunk)?;
+            if chunk.is_empty() {
+                return Ok(None);
+            }
+            let mut cmd = std::mem::take(&mut cmd);
+            if read {
+                cmd.shift_bytes(tck_init_value, is_lsb, &chunk);
+            } else {
+                cmd.shift_bytes_out(tck_init_value, is_lsb, &chunk);
+            }
+            total += chunk.len();
+            Ok(Some(cmd))
+        },
+        sink,
+    );
+    let mut cmd = MpsseCmdBuilder::new();
+    release(&mut cmd);
+    lock.exec(cmd)?;
+    result.map(|()| total)
+}
+
+/// Drives `pin` to `level` in a USB round trip of its own
+fn drive_pin(mtx: &Mutex<FtdiMpsse>, pin: Pin, level: bool) -> Result<(), FtdiError> {
+    let lock = FtdiMpsse::lock(mtx)?;
+    let mut cmd = MpsseCmdBuilder::new();
+    set_pin(&mut cmd, &lock, pin, level);
+    lock.exec(cmd)?;
+    Ok(())
+}
+
+/// Runs `operations` on a bit-banged bus with `cs` at `active`, one USB round trip each
+fn soft_transaction(
+    soft: &FtdiSoftSpi,
+    mtx: &Mutex<FtdiMpsse>,
+    cs: Pin,
+    active: bool,
+    fill: u8,
+    operations: &mut [Operation<'_, u8>],
+    delay: &mut impl DelayNs,
+) -> Result<(), FtdiSpiError> {
+    let select = |level: bool| drive_pin(mtx, cs, level);
+    select(active)?;
+    let result = operations.iter_mut().try_for_each(|op| {
+        match op {
             Operation::Read(read) => {
-                cmd.shift_bytes_in(self.tck_init_value, self.is_lsb, read.len());
+                let response = soft.shift(&vec![0; read.len()])?;
+                read.copy_from_slice(&response);
             }
             Operation::Write(write) => {
-                cmd.shift_bytes_out(self.tck_init_value, self.is_lsb, write);
+                soft.shift(write)?;
             }
-            Operation::Transfer(_, write) => {
-                cmd.shift_bytes(self.tck_init_value, self.is_lsb, write);
+            Operation::Transfer(read, write) => {
+                let response = soft.shift(&padded(read.len(), write, fill))?;
+                read.copy_from_slice(&response[..read.len()]);
             }
-            Operation::TransferInPlace(write) => {
-                cmd.shift_bytes(self.tck_init_value, self.is_lsb, write);
+            Operation::TransferInPlace(words) => {
+                let response = soft.shift(words)?;
+                words.copy_from_slice(&response);
             }
-            Operation::DelayNs(_) => (),
-        });
-        cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
-        let response = lock.exec(cmd)?;
-        // parse response
-        let mut len = 0;
-        operations.iter_mut().for_each(|op| {
-            len += match op {
-                Operation::Read(x) => {
-                    x.copy_from_slice(&response[len..len + x.len()]);
-                    x.len()
-                }
-                Operation::Transfer(x, _) => {
-                    x.copy_from_slice(&response[len..len + x.len()]);
-                    x.len()
-                }
-                Operation::TransferInPlace(x) => {
-                    x.copy_from_slice(&response[len..len + x.len()]);
-                    x.len()
-                }
-                _ => 0,
+            Operation::DelayNs(ns) => delay.delay_ns(*ns),
+        }
+        Ok::<_, FtdiError>(())
+    });
+    select(!active)?;
+    Ok(result?)
+}
+
+/// Whether a delay of `ns` is made of pin commands, see [`run_operations`]
+fn is_inline(ns: u32) -> bool {
+    Duration::from_nanos(ns.into()) <= INLINE_DELAY
+}
+
+/// Appends the shifts of `operations` and their short delays, longer ones are left to [`run_operations`]
+fn shift_operations(
+    cmd: &mut MpsseCmdBuilder,
+    tck_init_value: bool,
+    is_lsb: bool,
+    fill: u8,
+    gap: u32,
+    operations: &[Operation<'_, u8>],
+    hold: impl Fn(&mut MpsseCmdBuilder),
+) {
+    operations.iter().for_each(|op| match op {
+        Operation::Read(read) => {
+            shift_gapped(cmd, read.len(), gap, &hold, |cmd, range| {
+                cmd.shift_bytes_in(tck_init_value, is_lsb, range.len());
+            });
+        }
+        Operation::Write(write) => {
+            shift_gapped(cmd, write.len(), gap, &hold, |cmd, range| {
+                cmd.shift_bytes_out(tck_init_value, is_lsb, &write[range]);
+            });
+        }
+        Operation::Transfer(read, write) => {
+            let write = padded(read.len(), write, fill);
+            shift_gapped(cmd, write.len(), gap, &hold, |cmd, range| {
+                cmd.shift_bytes(tck_init_value, is_lsb, &write[range]);
+            });
+        }
+        Operation::TransferInPlace(write) => {
+            shift_gapped(cmd, write.len(), gap, &hold, |cmd, range| {
+                cmd.shift_bytes(tck_init_value, is_lsb, &write[range]);
+            });
+        }
+        Operation::DelayNs(ns) if is_inline(*ns) => {
+            for _ in 0..gpio_repeats(Duration::from_nanos((*ns).into())) {
+                hold(cmd);
             }
-        });
+        }
+        Operation::DelayNs(_) => (),
+    });
+}
+
+/// Calls `shift` for the bytes `0..len`, one at a time with `gap` repeats of `hold` in between
+///
+/// Without a gap the whole range goes to a single call.
+fn shift_gapped(
+    cmd: &mut MpsseCmdBuilder,
+    len: usize,
+    gap: u32,
+    hold: &impl Fn(&mut MpsseCmdBuilder),
+    mut shift: impl FnMut(&mut MpsseCmdBuilder, Range<usize>),
+) {
+    if gap == 0 {
+        shift(cmd, 0..len);
+        return;
+    }
+    for idx in 0..len {
+        if idx > 0 {
+            for _ in 0..gap {
+                hold(cmd);
+            }
+        }
+        shift(cmd, idx..idx + 1);
+    }
+}
+
+/// `write` with `fill` appended up to `read_len`, a transfer clocks as many bytes as its longer side
+pub(crate) fn padded(read_len: usize, write: &[u8], fill: u8) -> Cow<'_, [u8]> {
+    if read_len <= write.len() {
+        return Cow::Borrowed(write);
+    }
+    let mut words = write.to_vec();
+    words.resize(read_len, fill);
+    Cow::Owned(words)
+}
+
+/// Hands the response of [`shift_operations`] out to the reading operations
+fn copy_responses(operations: &mut [Operation<'_, u8>], response: &[u8]) {
+    let mut len = 0;
+    operations.iter_mut().for_each(|op| {
+        len += match op {
+            Operation::Read(x) => {
+                x.copy_from_slice(&response[len..len + x.len()]);
+                x.len()
+            }
+            Operation::Transfer(x, write) => {
+                x.copy_from_slice(&response[len..len + x.len()]);
+                x.len().max(write.len())
+            }
+            Operation::TransferInPlace(x) => {
+                x.copy_from_slice(&response[len..len + x.len()]);
+                x.len()
+            }
+            _ => 0,
+        }
+    });
+}
+
+/// [`SpiDevice`] on an [`FtdiSpi`] bus with CS on any GPIO pin
+///
+/// `embedded_hal_bus::spi::RefCellDevice` with an [`FtdiOutputPin`](crate::gpio::FtdiOutputPin)
+/// takes a USB round trip each for CS assertion, data and CS release.
+/// This sends all three in one MPSSE command, unless the bus is bit-banged or the
+/// transaction has delays longer than 50 us. Any number of devices can share a bus, they all use its mode,
+/// see [`FtdiSpi::devices`].
+pub struct FtdiGpioCsDevice<'a> {
+    bus: &'a FtdiSpi,
+    cs: UsedPin,
+    /// Waits for the delay operations of a transaction
+    delay: SpinDelay,
+}
+
+impl<'a> FtdiGpioCsDevice<'a> {
+    /// Takes `cs` as an output, released (high) until the first transaction
+    pub fn new(bus: &'a FtdiSpi, cs: Pin) -> Result<Self, FtdiSpiError> {
+        let cs = UsedPin::new(bus.mtx.clone(), cs, PinUsage::Spi)?;
+        let mut lock = FtdiMpsse::lock(&bus.mtx)?;
+        let mut cmd = MpsseCmdBuilder::new();
+        match *cs {
+            Pin::Lower(_) => {
+                lock.lower.value |= cs.mask();
+                lock.lower.direction |= cs.mask();
+                cmd.set_gpio_lower(lock.lower.value, lock.lower.direction);
+            }
+            Pin::Upper(_) => {
+                lock.upper.value |= cs.mask();
+                lock.upper.direction |= cs.mask();
+                cmd.set_gpio_upper(lock.upper.value, lock.upper.direction);
+            }
+        }
+        lock.exec(cmd)?;
+        drop(lock);
+        Ok(Self {
+            bus,
+            cs,
+            delay: Delay.into(),
+        })
+    }
+    /// Sets how delay operations wait, [`Delay`] by default
+    pub fn set_delay(&mut self, delay: impl Into<SpinDelay>) {
+        self.delay = delay.into();
+    }
+}
+
+impl ErrorType for FtdiGpioCsDevice<'_> {
+    type Error = FtdiSpiError;
+}
+
+impl SpiDevice<u8> for FtdiGpioCsDevice<'_> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        if let SpiEngine::Soft(soft) = &self.bus.engine {
+            return soft_transaction(
+                soft,
+                &self.bus.mtx,
+                *self.cs,
+                false,
+                self.bus.fill,
+                operations,
+                &mut self.delay,
+            );
+        }
+        let lock = FtdiMpsse::lock(&self.bus.mtx)?;
+        let cs = *self.cs;
+        run_operations(
+            &lock,
+            self.bus.tck_init_value,
+            self.bus.is_lsb,
+            self.bus.fill,
+            gpio_repeats(self.bus.byte_gap),
+            operations,
+            |cmd| set_pin(cmd, &lock, cs, false),
+            |cmd| set_pin(cmd, &lock, cs, false),
+            |cmd| set_pin(cmd, &lock, cs, true),
+            &mut self.delay,
+        )?;
         Ok(())
     }
 }
+
+/// Cascaded 74HC595 style shift registers on an [`FtdiSpi`] bus with the latch on any GPIO pin
+///
+/// Output `n` is bit `n % 8` of register `n / 8`, register 0 being the one MOSI feeds.
+/// The bus should be MSB first so that bit 7 ends up on Q7. The whole chain is shifted
+/// and latched in one MPSSE command, the outputs change together on the latch edge.
+pub struct ShiftRegisterChain<'a> {
+    bus: &'a FtdiSpi,
+    /// Storage register clock (RCLK), latches on the rising edge
+    latch: UsedPin,
+    /// Last written outputs, one byte per register
+    outputs: Vec<u8>,
+}
+
+impl<'a> ShiftRegisterChain<'a> {
+    /// Takes `latch` as an output idling low, the outputs are unknown until the first write
+    pub fn new(bus: &'a FtdiSpi, latch: Pin, registers: usize) -> Result<Self, FtdiSpiError> {
+        let latch = UsedPin::new(bus.mtx.clone(), latch, PinUsage::Spi)?;
+        let mut lock = FtdiMpsse::lock(&bus.mtx)?;
+        let bank = match *latch {
+            Pin::Lower(_) => &mut lock.lower,
+            Pin::Upper(_) => &mut lock.upper,
+        };
+        bank.value &= !latch.mask();
+        bank.direction |= latch.mask();
+        let mut cmd = MpsseCmdBuilder::new();
+        set_pin(&mut cmd, &lock, *latch, false);
+        lock.exec(cmd)?;
+        drop(lock);
+        Ok(Self {
+            bus,
+            latch,
+            outputs: vec![0; registers],
+        })
+    }
+    /// The outputs last written, one byte per register
+    pub fn outputs(&self) -> &[u8] {
+        &self.outputs
+    }
+    /// Drives output `idx` to `level`, the other outputs keep their state
+    ///
+    /// # Panics
+    /// If `idx` is beyond the last register.
+    pub fn set_bit(&mut self, idx: usize, level: bool) -> Result<(), FtdiSpiError> {
+        let mask = 1 << (idx % 8);
+        if level {
+            self.outputs[idx / 8] |= mask;
+        } else {
+            self.outputs[idx / 8] &= !mask;
+        }
+        self.latch_outputs()
+    }
+    /// Drives every output, one byte per register
+    ///
+    /// # Panics
+    /// If `outputs` has not one byte per register.
+    pub fn write_all(&mut self, outputs: &[u8]) -> Result<(), FtdiSpiError> {
+        self.outputs.copy_from_slice(outputs);
+        self.latch_outputs()
+    }
+    fn latch_outputs(&self) -> Result<(), FtdiSpiError> {
+        // the byte of the last register goes first and travels the whole chain
+        let data: Vec<u8> = self.outputs.iter().rev().copied().collect();
+        let latch = *self.latch;
+        if let SpiEngine::Soft(soft) = &self.bus.engine {
+            soft.shift(&data)?;
+            drive_pin(&self.bus.mtx, latch, true)?;
+            drive_pin(&self.bus.mtx, latch, false)?;
+            return Ok(());
+        }
+        let lock = FtdiMpsse::lock(&self.bus.mtx)?;
+        let mut cmd = MpsseCmdBuilder::new();
+        cmd.shift_bytes_out(self.bus.tck_init_value, self.bus.is_lsb, &data);
+        set_pin(&mut cmd, &lock, latch, true);
+        set_pin(&mut cmd, &lock, latch, false);
+        lock.exec(cmd)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        STREAM_CHUNK, bytes_to_words, copy_responses, for_each_chunk, mpsse_mode, padded,
+        shift_gapped, words_to_bytes,
+    };
+    use crate::mpsse_cmd::MpsseCmdBuilder;
+    use eh1::spi::{MODE_0, MODE_1, MODE_2, MODE_3, Operation};
+    use std::cell::Cell;
+
+    #[test]
+    fn unequal_transfer_lengths() {
+        assert_eq!(*padded(4, &[1, 2], 0), [1, 2, 0, 0]);
+        assert_eq!(*padded(4, &[1, 2], 0xFF), [1, 2, 0xFF, 0xFF]);
+        assert_eq!(*padded(1, &[1, 2], 0xFF), [1, 2]);
+        let (mut short, mut long, mut read) = ([0; 1], [0; 3], [0; 1]);
+        let mut operations = [
+            Operation::Transfer(&mut short, &[0xA, 0xB]),
+            Operation::Transfer(&mut long, &[0xC]),
+            Operation::Read(&mut read),
+        ];
+        copy_responses(&mut operations, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!((short, long, read), ([1], [3, 4, 5], [6]));
+    }
+    #[test]
+    fn gaps_between_bytes() {
+        let holds = Cell::new(0);
+        let hold = |_: &mut MpsseCmdBuilder| holds.set(holds.get() + 1);
+        let mut ranges = Vec::new();
+        let mut cmd = MpsseCmdBuilder::new();
+        shift_gapped(&mut cmd, 3, 0, &hold, |_, range| ranges.push(range));
+        assert_eq!((ranges.len(), ranges[0].clone(), holds.get()), (1, 0..3, 0));
+        let mut ranges = Vec::new();
+        shift_gapped(&mut cmd, 3, 2, &hold, |_, range| ranges.push(range));
+        assert_eq!((ranges, holds.get()), (vec![0..1, 1..2, 2..3], 4));
+    }
+    #[test]
+    fn mode_edges() {
+        // (SCK idle high, shift commands of MODE_2)
+        assert_eq!(mpsse_mode(MODE_0), (false, false));
+        assert_eq!(mpsse_mode(MODE_1), (false, true));
+        assert_eq!(mpsse_mode(MODE_2), (true, true));
+        assert_eq!(mpsse_mode(MODE_3), (true, false));
+    }
+    #[test]
+    fn stream_in_chunks() {
+        let source: Vec<u8> = (0..STREAM_CHUNK * 2 + 100).map(|idx| idx as u8).collect();
+        let mut lens = Vec::new();
+        let mut sink = Vec::new();
+        let total = for_each_chunk(&source[..], Some(&mut sink), |chunk, read| {
+            assert!(read);
+            lens.push(chunk.len());
+            Ok(chunk.to_vec())
+        })
+        .unwrap();
+        assert_eq!(total, source.len());
+        assert_eq!(lens, [STREAM_CHUNK, STREAM_CHUNK, 100]);
+        assert_eq!(sink, source);
+    }
+    #[test]
+    fn wide_word_order() {
+        assert_eq!(
+            words_to_bytes(&[0x1234u16, 0xABCD], false),
+            [0x12, 0x34, 0xAB, 0xCD]
+        );
+        assert_eq!(
+            words_to_bytes(&[0x1234_5678u32], true),
+            [0x78, 0x56, 0x34, 0x12]
+        );
+        let mut words = [0u16; 2];
+        bytes_to_words(&[0x12, 0x34, 0xAB, 0xCD], true, &mut words);
+        assert_eq!(words, [0x3412, 0xCDAB]);
+    }
+}
@@ -0,0 +1,60 @@
+use std::cell::Cell;
+
+/// Counters of recovery events since the interface was opened
+///
+/// Rising counts on a rig that used to run clean point to a degrading adapter,
+/// cable or target.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// USB buffer purges
+    pub purges: u64,
+    /// SWD line resets after a failed batch
+    pub resyncs: u64,
+    /// Commands the MPSSE rejected as invalid
+    pub bad_commands: u64,
+    /// I2C transactions ended by a missing ACK
+    pub i2c_nacks: u64,
+    /// SWD transactions answered with WAIT
+    pub swd_waits: u64,
+    /// Operations that gave up waiting for a response
+    pub timeouts: u64,
+    /// Reopens after the device was unplugged
+    pub reconnects: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Event {
+    Purge,
+    Resync,
+    BadCommand,
+    I2cNack,
+    SwdWait,
+    Timeout,
+    Reconnect,
+}
+
+/// Stats of one interface, only touched while its owner is locked
+#[derive(Default)]
+pub(crate) struct StatsCell(Cell<Stats>);
+impl StatsCell {
+    pub(crate) fn record(&self, event: Event) {
+        let mut stats = self.0.get();
+        let counter = match event {
+            Event::Purge => &mut stats.purges,
+            Event::Resync => &mut stats.resyncs,
+            Event::BadCommand => &mut stats.bad_commands,
+            Event::I2cNack => &mut stats.i2c_nacks,
+            Event::SwdWait => &mut stats.swd_waits,
+            Event::Timeout => &mut stats.timeouts,
+            Event::Reconnect => &mut stats.reconnects,
+        };
+        *counter += 1;
+        self.0.set(stats);
+    }
+    pub(crate) fn get(&self) -> Stats {
+        self.0.get()
+    }
+    pub(crate) fn reset(&self) {
+        self.0.set(Stats::default());
+    }
+}
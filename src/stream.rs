@@ -0,0 +1,122 @@
+//! Independent write and read halves of an interface.
+//!
+//! [`FtdiMpsse`](crate::mpsse::FtdiMpsse) waits for the response of every command it sends.
+//! Continuous transfers like FIFO capture or SWO need one thread that keeps feeding
+//! commands while another one drains whatever comes back, the halves here have their own
+//! USB requests and can be moved to different threads.
+//!
+//! The halves bypass pin tracking, reconnects and stats, and they know nothing about the
+//! protocol: the writer takes raw bytes, MPSSE commands or UART data, and the reader returns
+//! raw response data with the modem status bytes of the USB packets stripped.
+use crate::{
+    FtdiError,
+    ftdaye::{BulkReader, BulkWriter},
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Sending half of an interface
+pub struct StreamWriter {
+    writer: BulkWriter,
+}
+
+impl StreamWriter {
+    pub(crate) fn new(writer: BulkWriter) -> Self {
+        Self { writer }
+    }
+    /// Sends all bytes
+    pub fn write(&mut self, data: &[u8]) -> Result<(), FtdiError> {
+        self.writer.write(data)
+    }
+}
+
+/// Receiving half of an interface
+pub struct StreamReader {
+    reader: BulkReader,
+    /// Data received but not returned yet
+    pending: VecDeque<u8>,
+}
+
+impl StreamReader {
+    pub(crate) fn new(reader: BulkReader) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+    /// Waits for the next USB response and returns the number of bytes read, possibly 0
+    ///
+    /// The chip answers at least every latency timer period, so this does not block
+    /// for longer than that even when nothing arrives.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FtdiError> {
+        if self.pending.is_empty() {
+            self.pending.extend(self.reader.next()?.data);
+        }
+        let len = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+    /// Fills `buf` completely, fails with [`FtdiError::Timeout`] if that takes longer than `timeout`
+    ///
+    /// `buf` is only written once all of it has arrived, a failed call leaves the data
+    /// for the next read.
+    pub fn read_exact(&mut self, buf: &mut [u8], timeout: Duration) -> Result<(), FtdiError> {
+        let len = buf.len();
+        wait_buffered(len, timeout, |_| {
+            if self.pending.len() < len {
+                self.pending.extend(self.reader.next()?.data);
+            }
+            Ok(self.pending.len())
+        })?;
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *dst = src;
+        }
+        Ok(())
+    }
+}
+
+/// Waits until at least `len` bytes are buffered, fails with [`FtdiError::Timeout`] after `timeout`
+///
+/// `wait` blocks for new data no longer than the time it gets, and returns the number of
+/// bytes buffered then. Nothing is taken out of the buffer here, so a read running into
+/// the timeout loses no data.
+pub(crate) fn wait_buffered(
+    len: usize,
+    timeout: Duration,
+    mut wait: impl FnMut(Duration) -> Result<usize, FtdiError>,
+) -> Result<(), FtdiError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if wait(remaining)? >= len {
+            return Ok(());
+        }
+        if remaining.is_zero() {
+            return Err(FtdiError::Timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::wait_buffered;
+    use crate::FtdiError;
+    use std::time::Duration;
+
+    #[test]
+    fn buffered_until_timeout() {
+        let mut buffered = 0;
+        let result = wait_buffered(5, Duration::from_millis(50), |_| {
+            buffered += 2;
+            Ok(buffered)
+        });
+        assert!(result.is_ok());
+        assert_eq!(buffered, 6);
+        let result = wait_buffered(5, Duration::ZERO, |_| Ok(3));
+        assert!(matches!(result, Err(FtdiError::Timeout)));
+    }
+}
@@ -0,0 +1,109 @@
+use super::{FtdiSwd, FtdiSwdError, SwdAddr};
+use crate::jtag::Jep106;
+
+// DP registers
+const ABORT: u8 = 0x00;
+const SELECT: u8 = 0x08;
+/// Identification register, last register of bank 0xF
+const IDR: u8 = 0xFC;
+/// STKCMPCLR | STKERRCLR | WDERRCLR | ORUNERRCLR
+const ABORT_CLEAR_ERRORS: u32 = 0x1E;
+/// ARM Ltd., bank 4 identity 0x3B
+const ARM: Jep106 = Jep106 { bank: 4, id: 0x3B };
+
+/// Kind of an Access Port, from its IDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApKind {
+    /// Memory Access Port, usable with [`FtdiSwd::mem_ap`]
+    MemAp,
+    /// JTAG Access Port driving JTAG TAPs behind the debug port
+    JtagAp,
+    /// Vendor defined port, e.g. a CTRL-AP or MDM-AP
+    Vendor,
+}
+
+/// An Access Port found by [`FtdiSwd::scan_aps`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApInfo {
+    /// APSEL of the port
+    pub apsel: u8,
+    /// Raw IDR value
+    pub idr: u32,
+    pub kind: ApKind,
+    /// Designer of the port
+    pub designer: Jep106,
+}
+impl ApInfo {
+    fn decode(apsel: u8, idr: u32) -> Self {
+        // IDR: [31:28] revision, [27:24] bank, [23:17] identity, [16:13] class, [7:4] variant, [3:0] type
+        const CLASS_MEM_AP: u32 = 0b1000;
+        let designer = Jep106 {
+            bank: ((idr >> 24) & 0x0F) as u8,
+            id: ((idr >> 17) & 0x7F) as u8,
+        };
+        let class = (idr >> 13) & 0x0F;
+        let ap_type = idr & 0x0F;
+        let kind = if class == CLASS_MEM_AP {
+            ApKind::MemAp
+        } else if designer == ARM && class == 0 && ap_type == 0 {
+            ApKind::JtagAp
+        } else {
+            ApKind::Vendor
+        };
+        ApInfo {
+            apsel,
+            idr,
+            kind,
+            designer,
+        }
+    }
+}
+
+impl FtdiSwd {
+    /// Reads the IDR of every APSEL and returns the ports present
+    ///
+    /// An IDR of zero means there is no port. Ports answering with FAULT are skipped
+    /// after clearing the sticky errors, so [`FtdiSwd::enable`] and a power-up request
+    /// in DP CTRL/STAT should come first.
+    pub fn scan_aps(&self) -> Result<Vec<ApInfo>, FtdiSwdError> {
+        let mut aps = Vec::new();
+        for apsel in 0..=u8::MAX {
+            self.write(
+                SwdAddr::Dp(SELECT),
+                (apsel as u32) << 24 | (IDR as u32 & 0xF0),
+            )?;
+            let mut idr = [0];
+            match self.read_ap_posted(IDR, &mut idr) {
+                Ok(()) => (),
+                Err(FtdiSwdError::AckFailed) => {
+                    log::debug!("AP{apsel} answered FAULT");
+                    self.write(SwdAddr::Dp(ABORT), ABORT_CLEAR_ERRORS)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            if idr[0] != 0 {
+                let ap = ApInfo::decode(apsel, idr[0]);
+                log::info!("Found AP{apsel}: {:?} IDR {:#010x}", ap.kind, ap.idr);
+                aps.push(ap);
+            }
+        }
+        self.write(SwdAddr::Dp(SELECT), 0)?;
+        Ok(aps)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ApInfo, ApKind};
+
+    #[test]
+    fn decode_idr() {
+        // Cortex-M4 AHB-AP
+        assert_eq!(ApInfo::decode(0, 0x2477_0011).kind, ApKind::MemAp);
+        // Kinetis MDM-AP
+        assert_eq!(ApInfo::decode(1, 0x001C_0000).kind, ApKind::Vendor);
+        // CoreSight JTAG-AP
+        assert_eq!(ApInfo::decode(2, 0x0476_0010).kind, ApKind::JtagAp);
+    }
+}
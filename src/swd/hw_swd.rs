@@ -1,10 +1,11 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use self::cmd::SwdCmdBuilder;
 use crate::{
     FtdiError, Pin,
     gpio::UsedPin,
     mpsse::{FtdiMpsse, PinUsage},
+    stats::Event,
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -19,6 +20,10 @@ pub enum FtdiSwdError {
     UnknownAck(u8),
     #[error("Swd parity error.")]
     ParityError,
+    #[error("Swd target not present.")]
+    NotPresent,
+    #[error("Rtt {0}.")]
+    Rtt(&'static str),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -47,6 +52,10 @@ pub struct FtdiSwd {
     mtx: Arc<Mutex<FtdiMpsse>>,
     /// Optional direction control pin for SWDIO signal (half-duplex mode)
     direction_pin: Option<UsedPin>,
+    /// Whether every transaction is logged
+    trace: bool,
+    /// How often a single read or write is repeated after a WAIT
+    wait_retries: usize,
 }
 impl FtdiSwd {
     // Swd ACK (3 bits)
@@ -68,12 +77,68 @@ impl FtdiSwd {
             ],
             mtx,
             direction_pin: None,
+            trace: false,
+            wait_retries: 0,
         };
         Ok(this)
     }
+    /// Log every transaction at info level
+    ///
+    /// Each line holds the request byte, the decoded port and register,
+    /// the ACK, and for completed transactions the data and its parity check.
+    pub fn set_trace(&mut self, enable: bool) {
+        self.trace = enable;
+    }
+    /// Repeat [`FtdiSwd::read`] and [`FtdiSwd::write`] up to `retries` times while the target
+    /// answers WAIT, 0 reports the first WAIT as [`FtdiSwdError::AckWait`]
+    pub fn set_wait_retries(&mut self, retries: usize) {
+        self.wait_retries = retries;
+    }
+    fn retry_wait<T>(
+        &self,
+        mut transaction: impl FnMut() -> Result<T, FtdiSwdError>,
+    ) -> Result<T, FtdiSwdError> {
+        let mut retry = 0;
+        loop {
+            match transaction() {
+                Err(FtdiSwdError::AckWait) if retry < self.wait_retries => {
+                    retry += 1;
+                    if self.trace {
+                        log::info!("SWD WAIT, retry {retry} of {}", self.wait_retries);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+    fn trace(&self, lock: &FtdiMpsse, request: u8, ack: u8, data: Option<(u32, bool)>) {
+        if !self.trace {
+            return;
+        }
+        let at = lock
+            .last_timestamp()
+            .map(|stamp| format!(" at {stamp}"))
+            .unwrap_or_default();
+        let rw = if request & (1 << 2) != 0 { "R" } else { "W" };
+        let port = if request & (1 << 1) != 0 { "AP" } else { "DP" };
+        let addr = ((request >> 3) & 0b11) << 2;
+        let ack = match ack {
+            Self::REPONSE_SUCCESS => "OK".to_string(),
+            Self::REPONSE_WAIT => "WAIT".to_string(),
+            Self::REPONSE_FAILED => "FAULT".to_string(),
+            x => format!("{x:#05b}"),
+        };
+        match data {
+            Some((value, parity_ok)) => log::info!(
+                "SWD {rw} {port} {addr:#04x} req {request:#04x} ack {ack} data {value:#010x} parity {}{at}",
+                if parity_ok { "ok" } else { "error" }
+            ),
+            None => log::info!("SWD {rw} {port} {addr:#04x} req {request:#04x} ack {ack}{at}"),
+        }
+    }
     pub fn set_direction_pin(&mut self, pin: Pin) -> Result<(), FtdiSwdError> {
         self.direction_pin = Some(UsedPin::new(self.mtx.clone(), pin, PinUsage::Swd)?);
-        let mut lock = self.mtx.lock().unwrap();
+        let mut lock = FtdiMpsse::lock(&self.mtx)?;
         match self.direction_pin.as_deref().unwrap() {
             Pin::Lower(_) => {
                 lock.lower.direction |= pin.mask();
@@ -87,13 +152,35 @@ impl FtdiSwd {
     /// Send SWD activation sequence
     /// Sequence: >50 ones + 0x79E7 (MSB first) + >50 ones
     pub fn enable(&self) -> Result<(), FtdiSwdError> {
-        let lock = self.mtx.lock().unwrap();
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let mut cmd = SwdCmdBuilder::new(&lock, self.direction_pin.as_deref());
         cmd.swd_enable();
 
         lock.exec(cmd)?;
+        if self.trace {
+            log::info!("SWD JTAG-to-SWD sequence and line reset");
+        }
         Ok(())
     }
+    /// Check whether an SWD target is connected and powered
+    ///
+    /// Sends the activation sequence, which ends with a line reset, then reads DPIDR.
+    /// Any protocol level failure is reported as [`FtdiSwdError::NotPresent`],
+    /// so this can be polled while waiting for a board to be attached.
+    ///
+    /// # Returns
+    /// The DPIDR of the target
+    pub fn detect(&self) -> Result<u32, FtdiSwdError> {
+        let result = self.enable().and_then(|_| self.read(SwdAddr::Dp(0)));
+        match result {
+            Ok(idcode) => Ok(idcode),
+            Err(FtdiSwdError::FtdiInner(e)) => Err(FtdiSwdError::FtdiInner(e)),
+            Err(e) => {
+                log::trace!("Swd target not detected: {e}");
+                Err(FtdiSwdError::NotPresent)
+            }
+        }
+    }
     // Build SWD request packet (lsb 8 bits)
     // Timing Sequence: [Start(1), APnDP, RnW, A[2:3], Parity, Stop(0), Park(1)]
     // LSB Format: [Park(1), Stop(0), Parity, A[3:2], RnW, APnDP, Start(1)]
@@ -128,7 +215,10 @@ impl FtdiSwd {
     /// Implements SWD read transaction including request, ACK check, data reception,
     /// and parity verification as defined in ARM Debug Interface Architecture Specification
     pub fn read(&self, addr: SwdAddr) -> Result<u32, FtdiSwdError> {
-        let lock = self.mtx.lock().unwrap();
+        self.retry_wait(|| self.read_once(addr))
+    }
+    fn read_once(&self, addr: SwdAddr) -> Result<u32, FtdiSwdError> {
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let request = Self::build_request(true, addr);
         // Send request (8 bits)
         let mut cmd = SwdCmdBuilder::new(&lock, self.direction_pin.as_deref());
@@ -138,15 +228,12 @@ impl FtdiSwd {
         // Read ACK (3 bits)
         let ack = response[0] >> 5;
         if ack != Self::REPONSE_SUCCESS {
+            self.trace(&lock, request, ack, None);
             let mut cmd = SwdCmdBuilder::new(&lock, self.direction_pin.as_deref());
             cmd.trn();
             lock.exec(cmd)?;
-            return match ack {
-                Self::REPONSE_WAIT => Err(FtdiSwdError::AckWait),
-                Self::REPONSE_FAILED => Err(FtdiSwdError::AckFailed),
-                x => Err(FtdiSwdError::UnknownAck(x)),
-            };
         }
+        Self::check_ack(&lock, ack)?;
 
         // Read data (32 bits) + parity (1 bit) = 33 bits
         // 33 bits = 5 bytes
@@ -158,6 +245,7 @@ impl FtdiSwd {
         let value = u32::from_le_bytes([response[0], response[1], response[2], response[3]]);
         let parity = (response[4] >> 7) & 0x01;
         let calc_parity = value.count_ones() as u8 & 0x01;
+        self.trace(&lock, request, ack, Some((value, parity == calc_parity)));
 
         if parity != calc_parity {
             return Err(FtdiSwdError::ParityError);
@@ -165,8 +253,138 @@ impl FtdiSwd {
         Ok(value)
     }
 
+    /// Reads the same AP register `values.len()` times using posted reads
+    ///
+    /// An AP read returns the result of the previous AP read, and DP RDBUFF returns the
+    /// last one. All requests are therefore sent in a single MPSSE command with one
+    /// transaction per word, instead of a request and a data phase for each word.
+    ///
+    /// The ACKs are only checked once the command completed. On any failure the line is
+    /// reset, so the next transaction starts in sync, and none of the values are valid.
+    pub fn read_ap_posted(&self, addr: u8, values: &mut [u32]) -> Result<(), FtdiSwdError> {
+        const RDBUFF: u8 = 0x0C;
+        // ACK byte, 4 data bytes and the parity byte
+        const TRANSACTION_LEN: usize = 6;
+        const READS_PER_CMD: usize = 128;
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let ap_request = Self::build_request(true, SwdAddr::Ap(addr));
+        let rdbuff_request = Self::build_request(true, SwdAddr::Dp(RDBUFF));
+        for chunk in values.chunks_mut(READS_PER_CMD) {
+            let mut cmd = SwdCmdBuilder::new(&lock, self.direction_pin.as_deref());
+            for request in std::iter::repeat_n(ap_request, chunk.len()).chain([rdbuff_request]) {
+                cmd.swd_send_request(request)
+                    .trn()
+                    .swd_read_response()
+                    .swd_read_data()
+                    .trn();
+            }
+            let response = lock.exec(cmd)?;
+            let requests = std::iter::repeat_n(ap_request, chunk.len()).chain([rdbuff_request]);
+            let result = response
+                .chunks_exact(TRANSACTION_LEN)
+                .zip(requests)
+                .map(|(transaction, request)| {
+                    let ack = transaction[0] >> 5;
+                    let value = u32::from_le_bytes([
+                        transaction[1],
+                        transaction[2],
+                        transaction[3],
+                        transaction[4],
+                    ]);
+                    let parity = (transaction[5] >> 7) & 0x01;
+                    let parity_ok = parity == value.count_ones() as u8 & 0x01;
+                    if ack == Self::REPONSE_SUCCESS {
+                        self.trace(&lock, request, ack, Some((value, parity_ok)));
+                    } else {
+                        self.trace(&lock, request, ack, None);
+                    }
+                    Self::check_ack(&lock, ack)?;
+                    if !parity_ok {
+                        return Err(FtdiSwdError::ParityError);
+                    }
+                    Ok(value)
+                })
+                .collect::<Result<Vec<_>, _>>();
+            match result {
+                // the first AP read returns a stale value
+                Ok(posted) => chunk.copy_from_slice(&posted[1..]),
+                Err(e) => {
+                    self.resync(&lock)?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Writes `values` one after the other to the same AP register
+    ///
+    /// All transactions are sent in a single MPSSE command and the ACKs are checked
+    /// once it completed. On any failure the line is reset and an unknown number of
+    /// values have been written.
+    pub fn write_ap_repeated(&self, addr: u8, values: &[u32]) -> Result<(), FtdiSwdError> {
+        const WRITES_PER_CMD: usize = 128;
+        let lock = FtdiMpsse::lock(&self.mtx)?;
+        let request = Self::build_request(false, SwdAddr::Ap(addr));
+        for chunk in values.chunks(WRITES_PER_CMD) {
+            let mut cmd = SwdCmdBuilder::new(&lock, self.direction_pin.as_deref());
+            for &value in chunk {
+                cmd.swd_send_request(request)
+                    .trn()
+                    .swd_read_response()
+                    .trn()
+                    .swd_write_data(value);
+            }
+            let response = lock.exec(cmd)?;
+            if let Err(e) = response.iter().zip(chunk).try_for_each(|(ack, &value)| {
+                let ack = ack >> 5;
+                self.trace(
+                    &lock,
+                    request,
+                    ack,
+                    Some((value, true)).filter(|_| ack == Self::REPONSE_SUCCESS),
+                );
+                Self::check_ack(&lock, ack)
+            }) {
+                self.resync(&lock)?;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+    // A failed ACK in a batch leaves the target waiting for a request in the middle of
+    // our data phase, a line reset followed by a DPIDR read resynchronizes.
+    fn resync(&self, lock: &MutexGuard<FtdiMpsse>) -> Result<(), FtdiError> {
+        if self.trace {
+            log::info!("SWD line reset to resynchronize");
+        }
+        lock.record(Event::Resync);
+        let mut cmd = SwdCmdBuilder::new(lock, self.direction_pin.as_deref());
+        cmd.swd_line_reset()
+            .swd_send_request(Self::build_request(true, SwdAddr::Dp(0)))
+            .trn()
+            .swd_read_response()
+            .swd_read_data()
+            .trn();
+        lock.exec(cmd)?;
+        Ok(())
+    }
+    fn check_ack(lock: &FtdiMpsse, ack: u8) -> Result<(), FtdiSwdError> {
+        match ack {
+            Self::REPONSE_SUCCESS => Ok(()),
+            Self::REPONSE_WAIT => {
+                lock.record(Event::SwdWait);
+                Err(FtdiSwdError::AckWait)
+            }
+            Self::REPONSE_FAILED => Err(FtdiSwdError::AckFailed),
+            x => Err(FtdiSwdError::UnknownAck(x)),
+        }
+    }
+
     pub fn write(&self, addr: SwdAddr, value: u32) -> Result<(), FtdiSwdError> {
-        let lock = self.mtx.lock().unwrap();
+        self.retry_wait(|| self.write_once(addr, value))
+    }
+    fn write_once(&self, addr: SwdAddr, value: u32) -> Result<(), FtdiSwdError> {
+        let lock = FtdiMpsse::lock(&self.mtx)?;
         let request = Self::build_request(false, addr);
         let mut cmd = SwdCmdBuilder::new(&lock, self.direction_pin.as_deref());
         cmd.swd_send_request(request)
@@ -178,16 +396,14 @@ impl FtdiSwd {
         // Read ACK (3 bits)
         let ack = response[0] >> 5;
         if ack != Self::REPONSE_SUCCESS {
-            return match ack {
-                Self::REPONSE_WAIT => Err(FtdiSwdError::AckWait),
-                Self::REPONSE_FAILED => Err(FtdiSwdError::AckFailed),
-                x => Err(FtdiSwdError::UnknownAck(x)),
-            };
+            self.trace(&lock, request, ack, None);
         }
+        Self::check_ack(&lock, ack)?;
         // Send data (33 bits)
         let mut cmd = SwdCmdBuilder::new(&lock, self.direction_pin.as_deref());
         cmd.swd_write_data(value);
         lock.exec(cmd)?;
+        self.trace(&lock, request, ack, Some((value, true)));
         Ok(())
     }
 }
@@ -0,0 +1,172 @@
+use super::{FtdiSwd, FtdiSwdError, SwdAddr};
+
+// DP register
+const SELECT: u8 = 0x08;
+// MEM-AP registers, all in bank 0
+const CSW: u8 = 0x00;
+const TAR: u8 = 0x04;
+const DRW: u8 = 0x0C;
+
+// CSW fields
+const CSW_SIZE_MASK: u32 = 0b111;
+const CSW_ADDRINC_MASK: u32 = 0b11 << 4;
+const CSW_ADDRINC_SINGLE: u32 = 0b01 << 4;
+
+/// TAR auto-increment is only guaranteed within a 1 KiB block (ADIv5.2-C2.2.2).
+const AUTO_INCREMENT_WRAP: u32 = 0x400;
+
+/// Memory Access Port reached through an [`FtdiSwd`]
+///
+/// Block transfers set CSW to auto-increment TAR and stream DRW accesses,
+/// rewriting TAR at every 1 KiB boundary. Byte and halfword transfers are
+/// for peripherals that only accept accesses of that size.
+pub struct MemAp<'a> {
+    swd: &'a FtdiSwd,
+    /// APSEL of the MEM-AP
+    ap: u8,
+}
+impl FtdiSwd {
+    /// Access the MEM-AP with the given APSEL
+    pub fn mem_ap(&self, ap: u8) -> MemAp<'_> {
+        MemAp { swd: self, ap }
+    }
+}
+impl MemAp<'_> {
+    /// Reads consecutive words starting at the word aligned `addr`
+    pub fn read_block(&self, addr: u32, data: &mut [u32]) -> Result<(), FtdiSwdError> {
+        self.read_sized(addr, AccessSize::Word, data)
+    }
+    /// Writes consecutive words starting at the word aligned `addr`
+    pub fn write_block(&self, addr: u32, data: &[u32]) -> Result<(), FtdiSwdError> {
+        self.write_sized(addr, AccessSize::Word, data)
+    }
+    /// Reads consecutive halfwords with 16-bit accesses, `addr` must be halfword aligned
+    pub fn read_block_u16(&self, addr: u32, data: &mut [u16]) -> Result<(), FtdiSwdError> {
+        let mut values = vec![0; data.len()];
+        self.read_sized(addr, AccessSize::Halfword, &mut values)?;
+        data.iter_mut()
+            .zip(values)
+            .for_each(|(data, value)| *data = value as u16);
+        Ok(())
+    }
+    /// Writes consecutive halfwords with 16-bit accesses, `addr` must be halfword aligned
+    pub fn write_block_u16(&self, addr: u32, data: &[u16]) -> Result<(), FtdiSwdError> {
+        let values: Vec<u32> = data.iter().map(|&x| x as u32).collect();
+        self.write_sized(addr, AccessSize::Halfword, &values)
+    }
+    /// Reads consecutive bytes with 8-bit accesses, `addr` may have any alignment
+    pub fn read_block_u8(&self, addr: u32, data: &mut [u8]) -> Result<(), FtdiSwdError> {
+        let mut values = vec![0; data.len()];
+        self.read_sized(addr, AccessSize::Byte, &mut values)?;
+        data.iter_mut()
+            .zip(values)
+            .for_each(|(data, value)| *data = value as u8);
+        Ok(())
+    }
+    /// Writes consecutive bytes with 8-bit accesses, `addr` may have any alignment
+    pub fn write_block_u8(&self, addr: u32, data: &[u8]) -> Result<(), FtdiSwdError> {
+        let values: Vec<u32> = data.iter().map(|&x| x as u32).collect();
+        self.write_sized(addr, AccessSize::Byte, &values)
+    }
+    // Values are right aligned, DRW holds them in the byte lanes of their address.
+    fn read_sized(
+        &self,
+        addr: u32,
+        size: AccessSize,
+        data: &mut [u32],
+    ) -> Result<(), FtdiSwdError> {
+        self.prepare(addr, size)?;
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            let len = Self::transfers_before_wrap(addr, size, data.len());
+            let (head, tail) = data.split_at_mut(len);
+            self.swd.write(SwdAddr::Ap(TAR), addr)?;
+            self.swd.read_ap_posted(DRW, head)?;
+            for value in head.iter_mut() {
+                *value = (*value >> size.lane_shift(addr)) & size.mask();
+                addr += size.bytes();
+            }
+            data = tail;
+        }
+        Ok(())
+    }
+    fn write_sized(&self, addr: u32, size: AccessSize, data: &[u32]) -> Result<(), FtdiSwdError> {
+        self.prepare(addr, size)?;
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            let len = Self::transfers_before_wrap(addr, size, data.len());
+            let (head, tail) = data.split_at(len);
+            self.swd.write(SwdAddr::Ap(TAR), addr)?;
+            let lanes: Vec<u32> = head
+                .iter()
+                .map(|&value| {
+                    let value = (value & size.mask()) << size.lane_shift(addr);
+                    addr += size.bytes();
+                    value
+                })
+                .collect();
+            self.swd.write_ap_repeated(DRW, &lanes)?;
+            data = tail;
+        }
+        Ok(())
+    }
+    // Selects this AP in bank 0 and sets the access size with auto-increment.
+    fn prepare(&self, addr: u32, size: AccessSize) -> Result<(), FtdiSwdError> {
+        assert!(
+            addr.is_multiple_of(size.bytes()),
+            "address {addr:#010x} is not aligned to {size:?}"
+        );
+        self.swd
+            .write(SwdAddr::Dp(SELECT), (self.ap as u32) << 24)?;
+        // AP reads are posted, the value arrives with RDBUFF
+        let mut csw = [0];
+        self.swd.read_ap_posted(CSW, &mut csw)?;
+        let [csw] = csw;
+        let csw = (csw & !(CSW_SIZE_MASK | CSW_ADDRINC_MASK)) | size as u32 | CSW_ADDRINC_SINGLE;
+        self.swd.write(SwdAddr::Ap(CSW), csw)?;
+        Ok(())
+    }
+    fn transfers_before_wrap(addr: u32, size: AccessSize, len: usize) -> usize {
+        let to_wrap = (AUTO_INCREMENT_WRAP - (addr % AUTO_INCREMENT_WRAP)) / size.bytes();
+        len.min(to_wrap as usize)
+    }
+}
+
+/// CSW.SIZE of a MEM-AP access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AccessSize {
+    Byte = 0b000,
+    Halfword = 0b001,
+    Word = 0b010,
+}
+impl AccessSize {
+    const fn bytes(self) -> u32 {
+        1 << self as u32
+    }
+    const fn mask(self) -> u32 {
+        match self {
+            AccessSize::Byte => 0xFF,
+            AccessSize::Halfword => 0xFFFF,
+            AccessSize::Word => 0xFFFF_FFFF,
+        }
+    }
+    // Narrow accesses use the byte lanes matching the address (ADIv5.2-C2.2.6).
+    const fn lane_shift(self, addr: u32) -> u32 {
+        (addr & 0b11 & !(self.bytes() - 1)) * 8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AccessSize;
+
+    #[test]
+    fn byte_lanes() {
+        assert_eq!(AccessSize::Byte.lane_shift(0x2000_0003), 24);
+        assert_eq!(AccessSize::Halfword.lane_shift(0x2000_0002), 16);
+        assert_eq!(AccessSize::Halfword.lane_shift(0x2000_0004), 0);
+        assert_eq!(AccessSize::Word.lane_shift(0x2000_0004), 0);
+    }
+}
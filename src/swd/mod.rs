@@ -0,0 +1,9 @@
+mod ap;
+mod hw_swd;
+mod mem_ap;
+mod rtt;
+
+pub use ap::{ApInfo, ApKind};
+pub use hw_swd::{FtdiSwd, FtdiSwdError, SwdAddr};
+pub use mem_ap::MemAp;
+pub use rtt::{Rtt, RttChannel};
@@ -0,0 +1,199 @@
+//! SEGGER Real Time Transfer over SWD.
+//!
+//! The firmware keeps a control block in RAM, starting with the string `"SEGGER RTT"`,
+//! followed by the number of up (target to host) and down (host to target) buffers and
+//! their descriptors. Each descriptor is a ring buffer with a write and a read offset,
+//! the host only ever moves the read offset of up buffers and the write offset of
+//! down buffers.
+use super::{FtdiSwdError, MemAp};
+use std::ops::Range;
+
+const ID: &[u8] = b"SEGGER RTT\0";
+/// acID[16], MaxNumUpBuffers, MaxNumDownBuffers
+const HEADER_LEN: u32 = 24;
+/// sName, pBuffer, SizeOfBuffer, WrOff, RdOff, Flags
+const DESCRIPTOR_LEN: u32 = 24;
+const WR_OFF: u32 = 12;
+const RD_OFF: u32 = 16;
+/// Bytes of target memory read at once while looking for the control block
+const SEARCH_CHUNK: u32 = 0x1000;
+/// More buffers than any firmware configures, larger counts mean a corrupt block
+const MAX_CHANNELS: u32 = 64;
+
+/// One RTT ring buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RttChannel {
+    /// Address of the buffer descriptor
+    descriptor: u32,
+    /// Address of the data
+    buffer: u32,
+    /// Size of the data in bytes
+    size: u32,
+}
+
+impl RttChannel {
+    /// Size of the ring buffer in bytes
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    /// Reads `[WrOff, RdOff]`, the target may have left the descriptor uninitialized or
+    /// overwritten it, so both must lie within the buffer
+    fn offsets(&self, mem: &MemAp) -> Result<[u32; 2], FtdiSwdError> {
+        let mut offsets = [0; 2];
+        mem.read_block(self.descriptor + WR_OFF, &mut offsets)?;
+        check_offsets(self.size, offsets)?;
+        Ok(offsets)
+    }
+}
+
+fn check_offsets(size: u32, [write, read]: [u32; 2]) -> Result<(), FtdiSwdError> {
+    if size == 0 {
+        return Err(FtdiSwdError::Rtt("channel has no buffer"));
+    }
+    if write >= size || read >= size {
+        return Err(FtdiSwdError::Rtt("buffer offsets out of range"));
+    }
+    Ok(())
+}
+
+/// RTT control block found in target RAM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rtt {
+    /// Address of the control block
+    address: u32,
+    up: Vec<RttChannel>,
+    down: Vec<RttChannel>,
+}
+impl Rtt {
+    /// Searches `range` of target RAM for the control block
+    ///
+    /// The firmware must have initialized RTT before, usually its first log call does.
+    pub fn attach(mem: &MemAp, range: Range<u32>) -> Result<Self, FtdiSwdError> {
+        let mut addr = range.start & !0b11;
+        while addr < range.end {
+            // overlap the chunks so an ID across the boundary is not missed
+            let len = (SEARCH_CHUNK + 16).min(range.end - addr) & !0b11;
+            let mut data = vec![0; len as usize];
+            read_bytes(mem, addr, &mut data)?;
+            if let Some(offset) = data.windows(ID.len()).position(|x| x == ID) {
+                return Self::read_control_block(mem, addr + offset as u32);
+            }
+            addr += SEARCH_CHUNK;
+        }
+        Err(FtdiSwdError::Rtt("control block not found"))
+    }
+    fn read_control_block(mem: &MemAp, address: u32) -> Result<Self, FtdiSwdError> {
+        let mut count = [0; 2];
+        mem.read_block(address + 16, &mut count)?;
+        let [up_count, down_count] = count;
+        log::info!("Rtt control block at {address:#010x}, {up_count} up and {down_count} down");
+        if up_count > MAX_CHANNELS || down_count > MAX_CHANNELS {
+            return Err(FtdiSwdError::Rtt("control block corrupt"));
+        }
+        let mut descriptor = address + HEADER_LEN;
+        let mut channels = Vec::new();
+        for _ in 0..up_count + down_count {
+            let mut words = [0; 3];
+            mem.read_block(descriptor, &mut words)?;
+            channels.push(RttChannel {
+                descriptor,
+                buffer: words[1],
+                size: words[2],
+            });
+            descriptor += DESCRIPTOR_LEN;
+        }
+        let down = channels.split_off(up_count as usize);
+        Ok(Rtt {
+            address,
+            up: channels,
+            down,
+        })
+    }
+    /// Address of the control block
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+    /// Up buffers, target to host
+    pub fn up_channels(&self) -> &[RttChannel] {
+        &self.up
+    }
+    /// Down buffers, host to target
+    pub fn down_channels(&self) -> &[RttChannel] {
+        &self.down
+    }
+    /// Reads what the target wrote to up channel `channel`
+    ///
+    /// Returns 0 when there is nothing new, so it can be polled.
+    pub fn read(&self, mem: &MemAp, channel: usize, buf: &mut [u8]) -> Result<usize, FtdiSwdError> {
+        let channel = self
+            .up
+            .get(channel)
+            .ok_or(FtdiSwdError::Rtt("up channel not exist"))?;
+        let [write, read] = channel.offsets(mem)?;
+        // Only read up to the end of the ring, the rest comes with the next call.
+        let available = if write >= read {
+            write - read
+        } else {
+            channel.size - read
+        };
+        let len = (available as usize).min(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        read_bytes(mem, channel.buffer + read, &mut buf[..len])?;
+        let read = (read + len as u32) % channel.size;
+        mem.write_block(channel.descriptor + RD_OFF, &[read])?;
+        Ok(len)
+    }
+    /// Writes `data` to down channel `channel`
+    ///
+    /// Returns the number of bytes written, less than `data.len()` if the buffer is full.
+    pub fn write(&self, mem: &MemAp, channel: usize, data: &[u8]) -> Result<usize, FtdiSwdError> {
+        let channel = self
+            .down
+            .get(channel)
+            .ok_or(FtdiSwdError::Rtt("down channel not exist"))?;
+        let [write, read] = channel.offsets(mem)?;
+        // One byte stays free to tell a full buffer from an empty one.
+        let free = if read > write {
+            read - write - 1
+        } else if read == 0 {
+            channel.size - write - 1
+        } else {
+            channel.size - write
+        };
+        let len = (free as usize).min(data.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        mem.write_block_u8(channel.buffer + write, &data[..len])?;
+        let write = (write + len as u32) % channel.size;
+        mem.write_block(channel.descriptor + WR_OFF, &[write])?;
+        Ok(len)
+    }
+}
+
+// Reads bytes at any address with word accesses.
+fn read_bytes(mem: &MemAp, addr: u32, buf: &mut [u8]) -> Result<(), FtdiSwdError> {
+    let start = addr & !0b11;
+    let end = (addr + buf.len() as u32).next_multiple_of(4);
+    let mut words = vec![0; ((end - start) / 4) as usize];
+    mem.read_block(start, &mut words)?;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let offset = (addr - start) as usize;
+    buf.copy_from_slice(&bytes[offset..offset + buf.len()]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_offsets;
+
+    #[test]
+    fn corrupt_descriptor() {
+        assert!(check_offsets(1024, [0, 1023]).is_ok());
+        assert!(check_offsets(0, [0, 0]).is_err());
+        assert!(check_offsets(1024, [1024, 0]).is_err());
+        assert!(check_offsets(1024, [0, 0xFFFF_FFFF]).is_err());
+    }
+}
@@ -0,0 +1,657 @@
+use crate::{
+    ChipType, FtdiError, Interface,
+    ftdaye::{self, BulkReader, FtdiContext, RxChunk},
+    mpsse,
+    stats::{Event, Stats},
+    stream::{StreamWriter, wait_buffered},
+};
+use futures_lite::future::{or, poll_fn};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Poll, Waker},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Base clock of the baud rate generator, 48MHz / 16.
+const BAUD_CLOCK: u32 = 3_000_000;
+/// Base clock of the H series baud rate generator, 120MHz / 10.
+const H_BAUD_CLOCK: u32 = 12_000_000;
+/// Encoded divisor bit selecting [`H_BAUD_CLOCK`].
+const H_CLOCK_SELECT: u32 = 1 << 17;
+/// Largest integer part of the divisor (14 bits).
+const MAX_DIVISOR: u32 = 0x3FFF;
+/// Encoding of the fractional part of the divisor in eighths (AN232B-05).
+const FRAC_CODE: [u32; 8] = [0, 3, 2, 4, 1, 5, 6, 7];
+/// Received bytes kept until they are read, the oldest are dropped beyond this.
+const RX_CAPACITY: usize = 0x1_0000;
+/// Line property bit holding TXD in the break (low) state.
+const LINE_BREAK: u16 = 1 << 14;
+/// Longest single wait of a blocking [`io::Read`] without timeout.
+const IO_POLL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Seven = 7,
+    Eight = 8,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None = 0,
+    Odd = 1,
+    Even = 2,
+    /// Parity bit always 1
+    Mark = 3,
+    /// Parity bit always 0
+    Space = 4,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One = 0,
+    OneAndHalf = 1,
+    Two = 2,
+}
+
+/// Baud rate set by [`FtdiUart::set_baud_rate`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaudRate {
+    /// Rate actually generated by the chip
+    pub actual: u32,
+    /// Deviation of `actual` from the requested rate, in percent
+    pub error: f32,
+}
+
+/// Handshake between the chip and the other end of the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// The chip stops sending while CTS is released and releases RTS when its buffer fills
+    RtsCts,
+    /// Same as [`FlowControl::RtsCts`] with DSR and DTR
+    DtrDsr,
+    /// The chip stops sending on an XOFF character and resumes on XON
+    XonXoff {
+        xon: u8,
+        xoff: u8,
+    },
+}
+
+/// Levels of the modem status inputs, `true` is asserted (low level)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModemStatus {
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub dcd: bool,
+}
+
+/// Receive errors reported by the chip for a chunk of received data
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineStatus {
+    /// Data was lost because the chip buffer was full
+    pub overrun: bool,
+    pub parity_error: bool,
+    /// A stop bit was missing
+    pub framing_error: bool,
+    /// RXD was held low for longer than a character
+    pub break_received: bool,
+}
+impl LineStatus {
+    fn from_bits(line: u8) -> Self {
+        Self {
+            overrun: line & 0x02 != 0,
+            parity_error: line & 0x04 != 0,
+            framing_error: line & 0x08 != 0,
+            break_received: line & 0x10 != 0,
+        }
+    }
+    /// Whether no error was reported
+    pub fn is_ok(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Received data shared with the reader thread
+#[derive(Default)]
+struct RxRing {
+    data: VecDeque<u8>,
+    /// Bytes removed from the front of `data` so far, read or dropped
+    consumed: u64,
+    /// End of every chunk received with errors, counted like `consumed`, and its status
+    errors: VecDeque<(u64, LineStatus)>,
+    /// Error that stopped the reader thread, returned by the next read and by all of them once the device is gone
+    error: Option<FtdiError>,
+    /// Whether the reader thread has stopped
+    stopped: bool,
+    /// Task of a pending `read_async`
+    waker: Option<Waker>,
+}
+impl RxRing {
+    fn push(&mut self, RxChunk { data, errors }: RxChunk) {
+        let end = self.consumed + self.data.len() as u64;
+        self.errors.extend(
+            errors
+                .into_iter()
+                .map(|(len, line)| (end + len as u64, LineStatus::from_bits(line))),
+        );
+        self.data.extend(data);
+        let overflow = self.data.len().saturating_sub(RX_CAPACITY);
+        if overflow > 0 {
+            log::warn!("Uart rx buffer full, drop {overflow} bytes");
+            self.drop_front(overflow);
+        }
+    }
+    fn drop_front(&mut self, len: usize) {
+        self.data.drain(..len);
+        self.consumed += len as u64;
+        while self
+            .errors
+            .front()
+            .is_some_and(|&(end, _)| end <= self.consumed)
+        {
+            self.errors.pop_front();
+        }
+    }
+    /// Error of the stopped reader thread, the next reads return it once the device is gone
+    fn stopped_error(&mut self) -> FtdiError {
+        let error = self
+            .error
+            .take()
+            .unwrap_or(FtdiError::Other("Uart reader stopped"));
+        if let FtdiError::DeviceGone = error {
+            self.error = Some(FtdiError::DeviceGone);
+        }
+        error
+    }
+    /// Whether a read would return without waiting
+    fn is_ready(&self, split: bool) -> bool {
+        !self.data.is_empty() || (split && !self.errors.is_empty()) || self.stopped
+    }
+    /// Moves received data into `buf`, `None` when there is nothing to return yet.
+    ///
+    /// With `split` set, stops at the end of the first chunk received with errors
+    /// and returns its status along with the data.
+    fn take(
+        &mut self,
+        buf: &mut [u8],
+        split: bool,
+    ) -> Option<Result<(usize, LineStatus), FtdiError>> {
+        if !buf.is_empty() && !self.is_ready(split) {
+            return None;
+        }
+        let mut len = buf.len().min(self.data.len());
+        let mut status = LineStatus::default();
+        if split
+            && let Some(&(end, chunk_status)) = self.errors.front()
+            && end - self.consumed <= len as u64
+        {
+            len = (end - self.consumed) as usize;
+            status = chunk_status;
+            self.errors.pop_front();
+        } else if len == 0 && !buf.is_empty() {
+            // Only a stopped reader is left
+            return Some(Err(self.stopped_error()));
+        }
+        for (dst, src) in buf.iter_mut().zip(self.data.drain(..len)) {
+            *dst = src;
+        }
+        self.consumed += len as u64;
+        if !split {
+            // Plain reads skip the status of what they returned
+            self.drop_front(0);
+        }
+        Some(Ok((len, status)))
+    }
+}
+#[derive(Default)]
+struct Rx {
+    ring: Mutex<RxRing>,
+    ready: Condvar,
+    stop: AtomicBool,
+}
+
+/// Drains the chip continuously, so nothing is lost between reads.
+fn rx_thread(mut reader: BulkReader, rx: Arc<Rx>) {
+    while !rx.stop.load(Ordering::Relaxed) {
+        let result = reader.next();
+        let mut ring = rx.ring.lock().unwrap();
+        match result {
+            Ok(chunk) if chunk.data.is_empty() && chunk.errors.is_empty() => continue,
+            Ok(chunk) => ring.push(chunk),
+            Err(e) => {
+                ring.error = Some(e);
+                ring.stopped = true;
+            }
+        }
+        if let Some(waker) = ring.waker.take() {
+            waker.wake();
+        }
+        rx.ready.notify_all();
+        if ring.stopped {
+            break;
+        }
+    }
+}
+
+/// FTDI interface used as an asynchronous serial port
+///
+/// Opens the interface in its default UART mode instead of MPSSE,
+/// so the same interface can not be used for GPIO or MPSSE protocols at the same time.
+/// The line starts as 8N1, see [`FtdiUart::set_framing`].
+///
+/// A background thread keeps reading into a buffer of 64 KiB,
+/// data arriving while nobody reads is not lost unless the buffer overflows.
+pub struct FtdiUart {
+    /// FTDI device context handle
+    ft: FtdiContext,
+    /// Type of FTDI chip (e.g., FT232H, FT2232H)
+    chip_type: ChipType,
+    /// Baud rate actually generated by the chip
+    baud_rate: u32,
+    /// Received bytes not yet returned by `read`
+    rx: Arc<Rx>,
+    rx_thread: Option<JoinHandle<()>>,
+    /// Data bits, parity, stop bits and break as sent with SIO_SET_DATA
+    line_property: u16,
+    /// Longest wait of [`io::Read`], `None` waits forever
+    io_timeout: Option<Duration>,
+}
+
+impl FtdiUart {
+    /// Opens an FTDI interface in UART mode at 115200 baud
+    pub fn open(usb_device: &nusb::DeviceInfo, interface: Interface) -> Result<Self, FtdiError> {
+        let chip_type = mpsse::chip_type(usb_device)?;
+        if !chip_type.interface_list().contains(&interface) {
+            return Err(FtdiError::OpenFailed(format!(
+                "{chip_type:?} do not support Interface::{interface}"
+            )));
+        }
+        let handle = usb_device
+            .open()?
+            .detach_and_claim_interface(interface.interface_number())?;
+        let ft = FtdiContext::new(handle, interface, chip_type.max_packet_size()).into_uart()?;
+        let rx = Arc::new(Rx::default());
+        let rx_thread = {
+            let reader = ft.bulk_reader();
+            let rx = rx.clone();
+            thread::spawn(move || rx_thread(reader, rx))
+        };
+        let mut this = Self {
+            ft,
+            chip_type,
+            baud_rate: 0,
+            rx,
+            rx_thread: Some(rx_thread),
+            line_property: line_property(DataBits::Eight, Parity::None, StopBits::One),
+            io_timeout: None,
+        };
+        this.set_baud_rate(115_200)?;
+        Ok(this)
+    }
+    /// Type of the opened chip
+    pub fn chip_type(&self) -> ChipType {
+        self.chip_type
+    }
+    /// Sets the baud rate and returns the rate actually generated
+    ///
+    /// The divisor has a fractional part in eighths, so most non-standard rates
+    /// are met closely. Rates from 183 baud to 3Mbaud are supported,
+    /// above 1.5Mbaud only 2Mbaud and 3Mbaud.
+    /// H series chips reach 12Mbaud, above 6Mbaud only 8Mbaud and 12Mbaud.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<BaudRate, FtdiError> {
+        let h_series = matches!(
+            self.chip_type,
+            ChipType::FT232H | ChipType::FT2232H | ChipType::FT4232H
+        );
+        // The faster clock has a smaller divisor range, it can not reach the lowest rates
+        let (encoded, actual) = if h_series && baud_rate > H_BAUD_CLOCK / MAX_DIVISOR {
+            let (encoded, actual) = baud_divisor(baud_rate, H_BAUD_CLOCK)?;
+            (encoded | H_CLOCK_SELECT, actual)
+        } else {
+            baud_divisor(baud_rate, BAUD_CLOCK)?
+        };
+        self.ft.set_baud_divisor(encoded)?;
+        let error = (actual as f32 - baud_rate as f32) / baud_rate as f32 * 100.0;
+        if actual != baud_rate {
+            log::info!("Baud rate {baud_rate} set to {actual} ({error:+.2}%)");
+        }
+        self.baud_rate = actual;
+        Ok(BaudRate { actual, error })
+    }
+    /// Baud rate actually generated by the chip
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+    /// Asserts (drives low) or releases DTR
+    pub fn set_dtr(&mut self, asserted: bool) -> Result<(), FtdiError> {
+        self.ft.set_modem_ctrl(Some(asserted), None)
+    }
+    /// Asserts (drives low) or releases RTS
+    pub fn set_rts(&mut self, asserted: bool) -> Result<(), FtdiError> {
+        self.ft.set_modem_ctrl(None, Some(asserted))
+    }
+    /// Holds TXD low (break) until called again with `false`
+    pub fn set_break(&mut self, enable: bool) -> Result<(), FtdiError> {
+        let line_property = if enable {
+            self.line_property | LINE_BREAK
+        } else {
+            self.line_property & !LINE_BREAK
+        };
+        self.ft.set_line_property(line_property)?;
+        self.line_property = line_property;
+        Ok(())
+    }
+    /// Sets the character format, the default is 8N1
+    ///
+    /// Received characters with a wrong parity or stop bit are reported by
+    /// [`FtdiUart::read_with_status`].
+    pub fn set_framing(
+        &mut self,
+        data_bits: DataBits,
+        parity: Parity,
+        stop_bits: StopBits,
+    ) -> Result<(), FtdiError> {
+        let line_property =
+            line_property(data_bits, parity, stop_bits) | (self.line_property & LINE_BREAK);
+        self.ft.set_line_property(line_property)?;
+        self.line_property = line_property;
+        Ok(())
+    }
+    /// Sets the flow control, the default is [`FlowControl::None`]
+    ///
+    /// With [`FlowControl::RtsCts`] or [`FlowControl::DtrDsr`] the chip drives RTS or DTR
+    /// itself, [`FtdiUart::set_rts`] or [`FtdiUart::set_dtr`] should not be used then.
+    pub fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<(), FtdiError> {
+        let (mode, value) = match flow_control {
+            FlowControl::None => (0, 0),
+            FlowControl::RtsCts => (1, 0),
+            FlowControl::DtrDsr => (2, 0),
+            FlowControl::XonXoff { xon, xoff } => (4, u16::from_le_bytes([xon, xoff])),
+        };
+        self.ft.set_flow_ctrl(mode, value)
+    }
+    /// Reads CTS, DSR, RI and DCD
+    pub fn modem_status(&self) -> Result<ModemStatus, FtdiError> {
+        let [modem, _] = self.ft.poll_modem_status()?;
+        Ok(ModemStatus {
+            cts: modem & 0x10 != 0,
+            dsr: modem & 0x20 != 0,
+            ri: modem & 0x40 != 0,
+            dcd: modem & 0x80 != 0,
+        })
+    }
+    /// Sends received data to the host as soon as `char` arrives, `None` disables it
+    ///
+    /// Without it data waits in the chip for the latency timer,
+    /// a delimiter ending each response makes request/response protocols faster.
+    pub fn set_event_char(&mut self, char: Option<u8>) -> Result<(), FtdiError> {
+        self.ft.set_event_char(char)
+    }
+    /// Replaces characters received with a parity error by `char`, `None` disables it
+    pub fn set_error_char(&mut self, char: Option<u8>) -> Result<(), FtdiError> {
+        self.ft.set_error_char(char)
+    }
+    /// Sends all bytes
+    pub fn write(&mut self, data: &[u8]) -> Result<(), FtdiError> {
+        self.ft.write(data.to_vec())
+    }
+    /// Second sending half for another thread, so one thread can write while another reads
+    pub fn writer(&self) -> StreamWriter {
+        StreamWriter::new(self.ft.bulk_writer())
+    }
+    /// Waits up to `timeout` for data and returns the number of bytes read, 0 on timeout
+    ///
+    /// Returns as soon as anything has arrived, without waiting for `buf` to fill.
+    pub fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, FtdiError> {
+        Ok(self.read_split(buf, timeout, false)?.0)
+    }
+    /// Same as [`FtdiUart::read`], also returns the receive errors of the returned data
+    ///
+    /// The data is returned up to the end of the first USB packet that reported an error,
+    /// so the status applies to the last bytes returned, a break may come without any data.
+    pub fn read_with_status(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(usize, LineStatus), FtdiError> {
+        self.read_split(buf, timeout, true)
+    }
+    fn read_split(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+        split: bool,
+    ) -> Result<(usize, LineStatus), FtdiError> {
+        let ring = self.rx.ring.lock().unwrap();
+        let (mut ring, _) = self
+            .rx
+            .ready
+            .wait_timeout_while(ring, timeout, |ring| !ring.is_ready(split))
+            .unwrap();
+        ring.take(buf, split)
+            .unwrap_or(Ok((0, LineStatus::default())))
+    }
+    /// Fills `buf` completely, fails with [`FtdiError::Timeout`] if that takes longer than `timeout`
+    ///
+    /// Nothing is consumed on a timeout, the next read returns the bytes that did arrive.
+    pub fn read_exact(&mut self, buf: &mut [u8], timeout: Duration) -> Result<(), FtdiError> {
+        let len = buf.len();
+        wait_buffered(len, timeout, |remaining| {
+            let ring = self.rx.ring.lock().unwrap();
+            let (mut ring, _) = self
+                .rx
+                .ready
+                .wait_timeout_while(ring, remaining, |ring| {
+                    ring.data.len() < len && !ring.stopped
+                })
+                .unwrap();
+            if ring.data.len() < len && ring.stopped {
+                return Err(ring.stopped_error());
+            }
+            Ok(ring.data.len())
+        })?;
+        let mut ring = self.rx.ring.lock().unwrap();
+        ring.take(buf, false)
+            .unwrap_or(Ok((0, LineStatus::default())))?;
+        Ok(())
+    }
+    /// Same as [`FtdiUart::read`] for async code, the reader thread wakes the task
+    pub async fn read_async(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, FtdiError> {
+        let deadline = Instant::now() + timeout;
+        let read = poll_fn(|cx| {
+            let mut ring = self.rx.ring.lock().unwrap();
+            match ring.take(buf, false) {
+                Some(result) => Poll::Ready(result.map(|(len, _)| len)),
+                None => {
+                    ring.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        });
+        or(read, async {
+            ftdaye::wait_until(deadline).await;
+            Ok(0)
+        })
+        .await
+    }
+    /// Returns the bytes that have already arrived without waiting for more
+    ///
+    /// Returns 0 when nothing was received.
+    pub fn read_available(&mut self, buf: &mut [u8]) -> Result<usize, FtdiError> {
+        let mut ring = self.rx.ring.lock().unwrap();
+        let read = ring
+            .take(buf, false)
+            .unwrap_or(Ok((0, LineStatus::default())));
+        Ok(read?.0)
+    }
+    /// Recovery event counters of this interface
+    pub fn stats(&self) -> Stats {
+        self.ft.stats()
+    }
+    pub fn reset_stats(&self) {
+        self.ft.reset_stats();
+    }
+    pub(crate) fn record(&self, event: Event) {
+        self.ft.record(event);
+    }
+    /// Sets how long [`io::Read`] waits for data before it fails with [`io::ErrorKind::TimedOut`]
+    ///
+    /// `None`, the default, blocks until data arrives like a serial port without timeout.
+    pub fn set_io_timeout(&mut self, timeout: Option<Duration>) {
+        self.io_timeout = timeout;
+    }
+    /// Discards everything received so far
+    pub fn clear_rx(&mut self) -> Result<(), FtdiError> {
+        self.ft.purge_rx()?;
+        let mut ring = self.rx.ring.lock().unwrap();
+        let len = ring.data.len();
+        ring.drop_front(len);
+        Ok(())
+    }
+}
+
+/// Blocks until data arrives, see [`FtdiUart::set_io_timeout`]
+impl io::Read for FtdiUart {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.io_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let wait = deadline.map_or(IO_POLL, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            let len = FtdiUart::read(self, buf, wait)?;
+            if len > 0 {
+                return Ok(len);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+    }
+}
+
+/// Writes complete before they return, so flushing has nothing to do
+impl io::Write for FtdiUart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        FtdiUart::write(self, buf)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for FtdiUart {
+    fn drop(&mut self) {
+        self.rx.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.rx_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Value of SIO_SET_DATA
+fn line_property(data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> u16 {
+    data_bits as u16 | (parity as u16) << 8 | (stop_bits as u16) << 11
+}
+
+/// Returns the encoded divisor of `clock` and the baud rate it generates.
+fn baud_divisor(baud_rate: u32, clock: u32) -> Result<(u32, u32), FtdiError> {
+    if baud_rate == 0 {
+        return Err(FtdiError::Other("Baud rate can not be 0"));
+    }
+    // divisor in eighths
+    let divisor = ((clock as u64 * 8 + baud_rate as u64 / 2) / baud_rate as u64) as u32;
+    // Divisors 0 and 1 are special and mean clock and 2/3 clock, 1 < divisor < 2 is invalid.
+    if divisor < 10 {
+        return Ok((0, clock));
+    }
+    if divisor < 14 {
+        return Ok((1, clock * 2 / 3));
+    }
+    let divisor = divisor.max(16);
+    if divisor >> 3 > MAX_DIVISOR {
+        return Err(FtdiError::Other("Baud rate is too low"));
+    }
+    let encoded = (divisor >> 3) | (FRAC_CODE[(divisor & 0b111) as usize] << 14);
+    Ok((encoded, (clock * 8 + divisor / 2) / divisor))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BAUD_CLOCK, H_BAUD_CLOCK, LineStatus, RxChunk, RxRing, baud_divisor};
+
+    #[test]
+    fn baud_divisors() {
+        // Examples from AN232B-05
+        assert_eq!(baud_divisor(3_000_000, BAUD_CLOCK).unwrap(), (0, 3_000_000));
+        assert_eq!(baud_divisor(2_000_000, BAUD_CLOCK).unwrap(), (1, 2_000_000));
+        assert_eq!(baud_divisor(9600, BAUD_CLOCK).unwrap(), (0x4138, 9600));
+        // 26.041 -> 26 + 0/8
+        assert_eq!(baud_divisor(115_200, BAUD_CLOCK).unwrap(), (26, 115_385));
+        assert!(baud_divisor(100, BAUD_CLOCK).is_err());
+    }
+    #[test]
+    fn h_baud_divisors() {
+        assert_eq!(
+            baud_divisor(12_000_000, H_BAUD_CLOCK).unwrap(),
+            (0, 12_000_000)
+        );
+        // 104.17 -> 104 + 1/8
+        assert_eq!(
+            baud_divisor(115_200, H_BAUD_CLOCK).unwrap(),
+            (104 | 3 << 14, 115_246)
+        );
+        // 160.26 -> 160 + 2/8
+        assert_eq!(
+            baud_divisor(74_880, H_BAUD_CLOCK).unwrap(),
+            (160 | 2 << 14, 74_883)
+        );
+        // MIDI, exact
+        assert_eq!(baud_divisor(31_250, H_BAUD_CLOCK).unwrap(), (384, 31_250));
+    }
+    #[test]
+    fn status_split() {
+        let mut ring = RxRing::default();
+        // parity error in the packet ending after "ab", break in the one ending after "cd"
+        ring.push(RxChunk {
+            data: b"abcdef".to_vec(),
+            errors: vec![(2, 0x04), (4, 0x10)],
+        });
+        let mut buf = [0; 8];
+        let parity = LineStatus {
+            parity_error: true,
+            ..Default::default()
+        };
+        let brk = LineStatus {
+            break_received: true,
+            ..Default::default()
+        };
+        assert_eq!(ring.take(&mut buf, true).unwrap().unwrap(), (2, parity));
+        assert_eq!(ring.take(&mut buf, true).unwrap().unwrap(), (2, brk));
+        assert_eq!(&buf[..2], b"cd");
+        assert_eq!(
+            ring.take(&mut buf, true).unwrap().unwrap(),
+            (2, LineStatus::default())
+        );
+        assert!(ring.take(&mut buf, true).is_none());
+        // plain reads skip the status
+        ring.push(RxChunk {
+            data: b"gh".to_vec(),
+            errors: vec![(1, 0x08)],
+        });
+        assert_eq!(ring.take(&mut buf, false).unwrap().unwrap().0, 2);
+        assert!(ring.errors.is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+//! WS2812 (NeoPixel) LED strips on the MOSI pin of an [`FtdiSpiTx`].
+//!
+//! Every data bit of the strip becomes three SPI bits, `100` for 0 and `110` for 1, so at
+//! about 2.4 MHz SCK the high times match the 0.4 us and 0.8 us of the datasheet.
+//! Only MOSI (AD1) goes to DIN of the strip, SCK is left unconnected.
+//!
+//! The FTx232H reach 2.31 MHz, the FT2232D only 2 MHz, which stretches the high time of a 1
+//! to 1 us, at the edge of what most strips accept.
+use crate::spi::{FtdiSpiError, FtdiSpiTx};
+use eh1::spi::{MODE_0, SpiBus};
+use std::time::Duration;
+
+/// SCK asked for, the closest clock below it is used
+pub const SPI_FREQUENCY: usize = 2_400_000;
+/// Low time latching the frame, newer strips need 280 us instead of the 50 us of the WS2812
+const RESET_TIME: Duration = Duration::from_micros(300);
+
+/// Strip of WS2812 LEDs, colors are `[red, green, blue]`
+pub struct Ws2812 {
+    spi: FtdiSpiTx,
+    /// SCK achieved, sets the length of the reset gap
+    frequency: usize,
+}
+
+impl Ws2812 {
+    /// Sets the bus to MODE0, MSB first and [`SPI_FREQUENCY`]
+    pub fn new(mut spi: FtdiSpiTx) -> Result<Self, FtdiSpiError> {
+        spi.set_mode(MODE_0, false)?;
+        let frequency = spi.set_frequency(SPI_FREQUENCY)?;
+        Ok(Self { spi, frequency })
+    }
+    /// The SCK frequency in Hertz the waveform is shifted at
+    pub fn frequency(&self) -> usize {
+        self.frequency
+    }
+    pub fn into_inner(self) -> FtdiSpiTx {
+        self.spi
+    }
+    /// Sends one color per LED, starting at the one DIN is connected to, then the reset gap
+    ///
+    /// The frame is a single MPSSE command, the LEDs show it once the gap has passed.
+    pub fn write(&mut self, colors: &[[u8; 3]]) -> Result<(), FtdiSpiError> {
+        let reset_bytes = (RESET_TIME.as_secs_f64() * self.frequency as f64 / 8.0).ceil() as usize;
+        self.spi.write(&encode(colors, reset_bytes))
+    }
+}
+
+/// SPI bytes of `colors` in the GRB order of the strip, followed by `reset_bytes` zeros
+fn encode(colors: &[[u8; 3]], reset_bytes: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(colors.len() * 9 + reset_bytes);
+    for &[red, green, blue] in colors {
+        for byte in [green, red, blue] {
+            data.extend_from_slice(&encode_byte(byte));
+        }
+    }
+    data.resize(data.len() + reset_bytes, 0);
+    data
+}
+
+/// Three SPI bits per bit of `byte`, MSB first
+fn encode_byte(byte: u8) -> [u8; 3] {
+    let bits = (0..8).rev().fold(0u32, |bits, idx| {
+        let symbol = if byte & (1 << idx) != 0 { 0b110 } else { 0b100 };
+        (bits << 3) | symbol
+    });
+    let [_, high, mid, low] = bits.to_be_bytes();
+    [high, mid, low]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, encode_byte};
+
+    #[test]
+    fn waveform() {
+        assert_eq!(encode_byte(0x00), [0x92, 0x49, 0x24]);
+        assert_eq!(encode_byte(0xFF), [0xDB, 0x6D, 0xB6]);
+        // 0x80: 110 then seven times 100
+        assert_eq!(encode_byte(0x80), [0xD2, 0x49, 0x24]);
+        let data = encode(&[[0xFF, 0x00, 0x80]], 2);
+        assert_eq!(
+            data,
+            [0x92, 0x49, 0x24, 0xDB, 0x6D, 0xB6, 0xD2, 0x49, 0x24, 0, 0]
+        );
+    }
+}